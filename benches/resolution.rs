@@ -0,0 +1,49 @@
+// Compares the backends, encodings and metrics on the synthetic instances of the
+// predefined profiles. Run with `cargo bench --features bench-internals`
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use libresolv::{
+    bench::{Instance, Workload, ENCODINGS},
+    generate::Profile,
+    Goal,
+};
+
+fn instances() -> Vec<Instance> {
+    // the large profile takes minutes per sample with some encodings
+    vec![
+        Instance::from_profile("small", &Profile::small()),
+        Instance::from_profile("medium", &Profile::medium()),
+    ]
+}
+
+fn bench_workloads(c: &mut Criterion) {
+    for instance in instances() {
+        let mut group = c.benchmark_group(format!("workloads/{}", instance.name));
+        group.sample_size(10);
+        for workload in Workload::all() {
+            group.bench_function(workload.name(), |b| {
+                b.iter(|| instance.run(workload, Default::default()).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_encodings(c: &mut Criterion) {
+    for instance in instances() {
+        let mut group = c.benchmark_group(format!("encodings/{}", instance.name));
+        group.sample_size(10);
+        for encoding in ENCODINGS {
+            for workload in [Workload::Solve, Workload::Optimize(Goal::Newest)] {
+                group.bench_with_input(
+                    BenchmarkId::new(workload.name(), format!("{encoding:?}")),
+                    &encoding,
+                    |b, &encoding| b.iter(|| instance.run(workload, encoding).unwrap()),
+                );
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_workloads, bench_encodings);
+criterion_main!(benches);
@@ -0,0 +1,219 @@
+// Command-line front-end, for trying the resolver on a repository without writing code.
+// Built with the `cli` feature:
+//
+//   resolv [--simple | --newest | --minimal] [--json] [--color] [--interactive]
+//          REPOSITORY [REQUIREMENTS]
+//
+// where `--newest` is the default, `--simple` looks for any plan and `--interactive`
+// starts the interactive mode of `repl` with the requirements as the initial ones. The
+// repository is a CUDF document if its file name ends in `.cudf`, an archive of
+// `Repository::to_archive` if it ends in `.rkyv`, otherwise it is in the JSON schema of
+// `Repository::from_json`. The requirements file holds requirements in the syntax of
+// `RequirementSet`, one or more per line, lines starting with `#` being ignored. It may be
+// left out for CUDF documents, the requirements of their request stanza being resolved,
+// and in the interactive mode. Outside of the interactive mode, exits with 0 if the
+// requirements are satisfiable, 1 if they are not and 2 on errors
+#![forbid(unsafe_code)]
+
+mod repl;
+
+use std::{fs, io, process::ExitCode};
+
+use libresolv::{
+    optimize_with_options, render, simple_solve, CudfDocument, Goal, RenderOptions, Repository,
+    RequirementSet, ResolutionError, ResolutionResult, SolveOptions,
+};
+
+use crate::repl::Repl;
+
+const USAGE: &str = "usage: resolv [--simple | --newest | --minimal] [--json] [--color] \
+                     [--interactive] REPOSITORY [REQUIREMENTS]";
+
+struct Args {
+    // None for any plan
+    goal: Option<Goal>,
+    json: bool,
+    color: bool,
+    interactive: bool,
+    repository: String,
+    requirements: Option<String>,
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut goal = Some(Goal::Newest);
+    let (mut json, mut color, mut interactive) = (false, false, false);
+    let mut files = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--simple" => goal = None,
+            "--newest" => goal = Some(Goal::Newest),
+            "--minimal" => goal = Some(Goal::Minimal),
+            "--json" => json = true,
+            "--color" => color = true,
+            "--interactive" => interactive = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown flag `{arg}`")),
+            _ => files.push(arg),
+        }
+    }
+    let mut files = files.into_iter();
+    let repository = files.next().ok_or("no repository given")?;
+    let requirements = files.next();
+    if files.next().is_some() {
+        return Err("too many arguments".to_string());
+    }
+    Ok(Args {
+        goal,
+        json,
+        color,
+        interactive,
+        repository,
+        requirements,
+    })
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))
+}
+
+// The repository and the requirements, if any were given
+fn load(args: &Args) -> Result<(Repository, Option<RequirementSet>), String> {
+    let (repo, requirements) = if args.repository.ends_with(".cudf") {
+        let doc = CudfDocument::parse(&read(&args.repository)?).map_err(|e| e.to_string())?;
+        (doc.repository, Some(doc.requirements))
+    } else if args.repository.ends_with(".rkyv") {
+        let bytes = fs::read(&args.repository).map_err(|e| format!("{}: {e}", args.repository))?;
+        let repo = Repository::from_archive(&bytes).map_err(|e| e.to_string())?;
+        (repo, None)
+    } else {
+        let value = serde_json::from_str(&read(&args.repository)?).map_err(|e| e.to_string())?;
+        let repo = Repository::from_json(&value).map_err(|e| e.to_string())?;
+        (repo, None)
+    };
+    let requirements = match (&args.requirements, requirements) {
+        (Some(path), _) => Some(
+            read(path)?
+                .lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .collect::<Vec<_>>()
+                .join(";")
+                .parse()
+                .map_err(|e: ResolutionError| format!("{path}: {e}"))?,
+        ),
+        (None, requirements) => requirements,
+    };
+    Ok((repo, requirements))
+}
+
+fn run(args: &Args) -> Result<ResolutionResult, String> {
+    let (repo, requirements) = load(args)?;
+    let requirements = requirements.ok_or("no requirements given")?;
+    match args.goal {
+        None => simple_solve(&repo, &requirements),
+        Some(goal) => optimize_with_options(&repo, &requirements, goal, &SolveOptions::default()),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn interactive(args: &Args) -> Result<(), String> {
+    let (repo, requirements) = load(args)?;
+    let render = RenderOptions::new().color(args.color);
+    let mut repl = Repl::new(&repo, args.goal, render, requirements.unwrap_or_default());
+    repl.run(io::stdin().lock(), io::stdout())
+        .map_err(|e| e.to_string())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    if args.interactive {
+        return match interactive(&args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("resolv: {e}");
+                ExitCode::from(2)
+            }
+        };
+    }
+    match run(&args) {
+        Ok(result) => {
+            let sat = result.is_sat();
+            if args.json {
+                println!("{}", result.to_json());
+            } else {
+                let options = RenderOptions::new().color(args.color);
+                println!("{}", render(result, &options));
+            }
+            ExitCode::from(if sat { 0 } else { 1 })
+        }
+        Err(e) => {
+            eprintln!("resolv: {e}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use libresolv::{Goal, Repository, Requirement, RequirementSet};
+
+    use super::{load, parse_args, Args};
+
+    fn args(args: &[&str]) -> Result<Args, String> {
+        parse_args(args.iter().map(ToString::to_string))
+    }
+
+    fn write(name: &str, contents: &[u8]) -> String {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("resolv-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_load() {
+        let parsed = args(&["--minimal", "--json", "repo.json", "reqs"]).unwrap();
+        assert_eq!(parsed.goal, Some(Goal::Minimal));
+        assert!(parsed.json && !parsed.color && !parsed.interactive);
+        assert_eq!(parsed.requirements.as_deref(), Some("reqs"));
+        assert_eq!(args(&["--simple", "repo.json"]).unwrap().goal, None);
+        assert!(args(&["--fast", "repo.json"]).is_err());
+        assert!(args(&["--newest"]).is_err());
+        assert!(args(&["a", "b", "c"]).is_err());
+
+        let json = r#"{ "packages": [["pkg1"], [""]], "masks": [] }"#;
+        let repo = Repository::from_json(&serde_json::from_str(json).unwrap()).unwrap();
+        let reqs = write("reqs", b"# the application\npkg0\n!pkg1 in {2}\n");
+        let expected = RequirementSet {
+            dependencies: vec![Requirement::any_version(0)],
+            conflicts: vec![Requirement::single_version(1, 2)],
+            formula: None,
+        };
+        let mut paths = vec![
+            write("repo.json", json.as_bytes()),
+            write("repo.rkyv", &repo.to_archive()),
+        ];
+        for path in &paths {
+            let (loaded, requirements) = load(&args(&[path, &reqs]).unwrap()).unwrap();
+            assert_eq!(loaded, repo);
+            assert_eq!(requirements, Some(expected.clone()));
+        }
+
+        let cudf = "package: a\nversion: 1\n\nrequest: r\ninstall: a\n";
+        paths.push(write("repo.cudf", cudf.as_bytes()));
+        let (_, requirements) = load(&args(&[&paths[2]]).unwrap()).unwrap();
+        assert_eq!(requirements.unwrap().dependencies.len(), 1);
+        assert!(load(&args(&[&paths[1][..paths[1].len() - 5]]).unwrap()).is_err());
+
+        paths.push(reqs);
+        for path in paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,251 @@
+// The interactive mode of `resolv --interactive`, loading the repository once and then
+// editing the toplevel requirements between resolutions, all of them being resolved by
+// one session. Commands, one per line:
+//
+//   add REQS                    add requirements in the syntax of `RequirementSet`
+//   remove N                    remove the requirement numbered N by `list`, the formula
+//                               of the requirements being numbered as a whole
+//   pin pkgN V                  replace the dependencies on package N by one on version V
+//   list                        the current requirements, dependencies first and the
+//                               formula last
+//   solve                       resolve the current requirements
+//   why pkgN                    the requirements through which package N is reached
+//   why                         the unsat core of the last resolution
+//   quit                        leave, as does the end of the input
+use std::io::{self, BufRead, Write};
+
+use libresolv::{
+    compute_closure, render, ClosureOptions, EdgeKind, Goal, PackageId, RenderOptions, Repository,
+    Requirement, RequirementSet, ResolutionError, ResolutionResult, SolverSession, Version,
+};
+
+const HELP: &str = "commands: add REQS, remove N, pin pkgN V, list, solve, why [pkgN], quit";
+
+pub struct Repl<'r> {
+    repo: &'r Repository,
+    session: SolverSession<'r, Repository>,
+    // None for any plan
+    goal: Option<Goal>,
+    render: RenderOptions,
+    requirements: RequirementSet,
+    last: Option<ResolutionResult>,
+}
+
+fn package_of(word: &str) -> Result<PackageId, String> {
+    word.strip_prefix("pkg")
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| format!("expected a package like `pkg3`, got `{word}`"))
+}
+
+impl<'r> Repl<'r> {
+    pub fn new(
+        repo: &'r Repository,
+        goal: Option<Goal>,
+        render: RenderOptions,
+        requirements: RequirementSet,
+    ) -> Self {
+        Self {
+            repo,
+            session: SolverSession::new(repo),
+            goal,
+            render,
+            requirements,
+            last: None,
+        }
+    }
+
+    fn list(&self) -> String {
+        let deps = self
+            .requirements
+            .dependencies
+            .iter()
+            .map(|req| req.to_string());
+        let conflicts = self
+            .requirements
+            .conflicts
+            .iter()
+            .map(|req| format!("!{req}"));
+        let formula = self.requirements.formula.iter().map(|f| f.to_string());
+        deps.chain(conflicts)
+            .chain(formula)
+            .enumerate()
+            .map(|(i, req)| format!("{i}: {req}\n"))
+            .collect()
+    }
+
+    fn remove(&mut self, index: usize) -> Result<(), String> {
+        let deps = self.requirements.dependencies.len();
+        let conflicts = self.requirements.conflicts.len();
+        if index < deps {
+            self.requirements.dependencies.remove(index);
+        } else if index - deps < conflicts {
+            self.requirements.conflicts.remove(index - deps);
+        } else if index - deps == conflicts && self.requirements.formula.is_some() {
+            self.requirements.formula = None;
+        } else {
+            return Err(format!("no requirement numbered {index}"));
+        }
+        Ok(())
+    }
+
+    fn pin(&mut self, package: PackageId, version: Version) {
+        self.requirements
+            .dependencies
+            .retain(|req| req.package != package);
+        self.requirements
+            .add_dep(Requirement::single_version(package, version));
+    }
+
+    fn solve(&mut self) -> Result<String, ResolutionError> {
+        let result = match self.goal {
+            None => self.session.solve(&self.requirements)?,
+            Some(goal) => self.session.optimize(&self.requirements, goal)?,
+        };
+        let rendered = render(result.clone(), &self.render);
+        self.last = Some(result);
+        Ok(rendered)
+    }
+
+    fn why(&self, package: PackageId) -> Result<String, ResolutionError> {
+        let closure = compute_closure(self.repo, &self.requirements, &ClosureOptions::new())?;
+        if !closure.contains(package) {
+            return Ok(format!(
+                "pkg{package} is not reachable from the requirements\n"
+            ));
+        }
+        let path = closure.path_to(package);
+        let Some(first) = path.first() else {
+            return Ok(format!("pkg{package} is required directly\n"));
+        };
+        let mut out = format!("pkg{} is required directly\n", first.parent);
+        for (i, edge) in path.iter().enumerate() {
+            let kind = match edge.kind {
+                EdgeKind::Dependency => "depends on",
+                EdgeKind::Conflict => "conflicts with",
+            };
+            let target = path.get(i + 1).map_or(package, |next| next.parent);
+            out.push_str(&format!(
+                "pkg{} {} {kind} pkg{target}\n",
+                edge.parent, edge.version
+            ));
+        }
+        Ok(out)
+    }
+
+    fn core(&self) -> String {
+        match &self.last {
+            Some(ResolutionResult::UnsatWithCore { core }) => {
+                render(core.clone(), &self.render) + "\n"
+            }
+            Some(ResolutionResult::Unsat) => "no core was computed\n".to_string(),
+            Some(ResolutionResult::Sat { .. }) => "the requirements are satisfiable\n".to_string(),
+            None => "nothing was resolved yet\n".to_string(),
+        }
+    }
+
+    // The output of a command, None to leave
+    fn command(&mut self, line: &str) -> Option<Result<String, String>> {
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        Some(match command {
+            "add" => rest
+                .parse::<RequirementSet>()
+                .map(|reqs| {
+                    self.requirements.dependencies.extend(reqs.dependencies);
+                    self.requirements.conflicts.extend(reqs.conflicts);
+                    if let Some(formula) = reqs.formula {
+                        self.requirements.add_formula(formula);
+                    }
+                    String::new()
+                })
+                .map_err(|e| e.to_string()),
+            "remove" => rest
+                .parse()
+                .map_err(|_| format!("expected a number, got `{rest}`"))
+                .and_then(|index| self.remove(index))
+                .map(|()| String::new()),
+            "pin" => match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                [package, version] => package_of(package).and_then(|package| {
+                    let version = version
+                        .parse()
+                        .map_err(|_| format!("malformed version `{version}`"))?;
+                    self.pin(package, version);
+                    Ok(String::new())
+                }),
+                _ => Err("usage: pin pkgN V".to_string()),
+            },
+            "list" => Ok(self.list()),
+            "solve" => self.solve().map_err(|e| e.to_string()),
+            "why" if rest.is_empty() => Ok(self.core()),
+            "why" => {
+                package_of(rest).and_then(|package| self.why(package).map_err(|e| e.to_string()))
+            }
+            "quit" | "exit" => return None,
+            "help" => Ok(format!("{HELP}\n")),
+            "" => Ok(String::new()),
+            _ => Err(format!("unknown command `{command}`, {HELP}")),
+        })
+    }
+
+    pub fn run(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        write!(output, "> ")?;
+        output.flush()?;
+        for line in input.lines() {
+            match self.command(line?.trim()) {
+                None => break,
+                Some(Ok(out)) => write!(output, "{out}")?,
+                Some(Err(e)) => writeln!(output, "error: {e}")?,
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use libresolv::{Goal, Package, PackageVer, RenderOptions, Repository, RequirementSet};
+
+    use super::Repl;
+
+    #[test]
+    fn test_repl() {
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: "pkg1".parse().unwrap(),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: (0..2)
+                    .map(|_| PackageVer {
+                        requirements: RequirementSet::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        let input = "add pkg0\npin pkg1 1\nlist\nwhy pkg1\nsolve\nadd !pkg1\nsolve\nwhy\n\
+                     remove 7\nremove 2\nadd ¬pkg1 ∨ pkg0\nlist\nremove 2\nlist\n\
+                     frobnicate\nquit\nsolve\n";
+        let mut output = Vec::new();
+        let mut repl = Repl::new(
+            &repo,
+            Some(Goal::Newest),
+            RenderOptions::plain(),
+            RequirementSet::default(),
+        );
+        repl.run(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        println!("{output}");
+        assert!(output.contains("0: pkg0\n1: pkg1 in {1}\n"));
+        assert!(output.contains("pkg0 is required directly\npkg0 1 depends on pkg1\n"));
+        assert!(output.contains("error: no requirement numbered 7"));
+        // the formula is listed last and removed as a whole
+        assert!(output.contains("2: ¬pkg1 ∨ pkg0\n> > 0: pkg0\n1: pkg1 in {1}\n> "));
+        assert!(output.contains("error: unknown command `frobnicate`"));
+        assert!(output.ends_with("> "));
+    }
+}
@@ -0,0 +1,192 @@
+// Analyses of the relations between packages for repository QA and interfaces, answered
+// by one solver holding the constraints of a dependency closure and queried under
+// assumptions, so that the queries share what z3 learned. A strong dependency of a
+// package is installed by every plan installing it, two packages form an impossible
+// combination if no plan installs both of them. Both only depend on the repository, the
+// toplevel requirements play no part
+use bumpalo::Bump;
+use z3::{ast::Bool, Context, SatResult, Solver};
+
+use crate::internals::{
+    constraints::{add_all_constraints_within, find_closure},
+    encoding::Encoder,
+    options::SolveOptions,
+    types::*,
+    utils::default_config,
+};
+
+pub struct DependencyAnalyzer<'c> {
+    solver: Solver<'c>,
+    encoder: Encoder<'c>,
+    pids: Vec<PackageId>,
+}
+
+impl<'c> DependencyAnalyzer<'c> {
+    // An analyzer of the packages in the closure of some packages
+    pub fn new<S: PackageSource + ?Sized>(
+        ctx: &'c Context,
+        repo: &S,
+        packages: impl IntoIterator<Item = PackageId>,
+        options: &SolveOptions,
+    ) -> Result<Self, ResolutionError> {
+        let solver = options.solver(ctx);
+        let allocator = Bump::new();
+
+        let roots = packages
+            .into_iter()
+            .map(Requirement::any_version)
+            .collect::<Vec<_>>();
+        let closure = find_closure(repo, roots.iter())?;
+        options.limits.check_closure(closure.len())?;
+        let pids = options.ordered_pids(&closure);
+        let encoder = Encoder::new(ctx, options.encoding, repo, pids.iter().copied());
+        for domain_constraint in encoder.domain_constraints() {
+            solver.assert(&domain_constraint);
+        }
+        add_all_constraints_within(
+            &allocator,
+            &encoder,
+            repo,
+            pids.iter().copied(),
+            &RequirementSet::default(),
+            &options.limits,
+            |_| options.check_cancelled(),
+            |expr, _, _| solver.assert(&expr.simplify()),
+        )?;
+        Ok(Self {
+            solver,
+            encoder,
+            pids,
+        })
+    }
+
+    fn check(&self, assumptions: &[Bool<'c>]) -> Result<bool, ResolutionError> {
+        match self.solver.check_assumptions(assumptions) {
+            SatResult::Sat => Ok(true),
+            SatResult::Unsat => Ok(false),
+            SatResult::Unknown => Err(ResolutionError::unknown(self.solver.get_reason_unknown())),
+        }
+    }
+
+    fn known(&self, pid: PackageId) -> Result<(), ResolutionError> {
+        if self.pids.contains(&pid) {
+            Ok(())
+        } else {
+            InvalidInputSnafu {
+                message: format!("package {pid} is not in the closure of the analyzer"),
+            }
+            .fail()
+        }
+    }
+
+    // Whether some plan installs the package
+    pub fn is_installable(&self, pid: PackageId) -> Result<bool, ResolutionError> {
+        self.known(pid)?;
+        self.check(&[self.encoder.installed(pid)])
+    }
+
+    // The packages installed by every plan installing the package, in increasing order.
+    // Empty if no plan installs the package
+    pub fn strong_deps(&self, pid: PackageId) -> Result<Vec<PackageId>, ResolutionError> {
+        self.known(pid)?;
+        let installed = self.encoder.installed(pid);
+        if !self.check(&[installed.clone()])? {
+            return Ok(Vec::new());
+        }
+        // only the packages installed by every model found so far are candidates
+        let mut candidates = self.installed_by_model();
+        candidates.retain(|&candidate| candidate != pid);
+        let mut strong = Vec::new();
+        while let Some(candidate) = candidates.pop() {
+            if self.check(&[installed.clone(), self.encoder.not_installed(candidate)])? {
+                let model = self.installed_by_model();
+                candidates.retain(|c| model.contains(c));
+            } else {
+                strong.push(candidate);
+            }
+        }
+        strong.sort_unstable();
+        Ok(strong)
+    }
+
+    // Whether no plan installs both packages
+    pub fn impossible_combinations(
+        &self,
+        pid_a: PackageId,
+        pid_b: PackageId,
+    ) -> Result<bool, ResolutionError> {
+        self.known(pid_a)?;
+        self.known(pid_b)?;
+        let both = [self.encoder.installed(pid_a), self.encoder.installed(pid_b)];
+        Ok(!self.check(&both)?)
+    }
+
+    fn installed_by_model(&self) -> Vec<PackageId> {
+        let model = self
+            .solver
+            .get_model()
+            .expect("Impossible: satisfiable but failed to generate a model");
+        self.encoder
+            .installation_status(&model, self.pids.iter().copied())
+            .0
+    }
+}
+
+// The packages installed by every plan installing a package, in increasing order
+pub fn strong_deps<S: PackageSource + ?Sized>(
+    repo: &S,
+    pid: PackageId,
+) -> Result<Vec<PackageId>, ResolutionError> {
+    let ctx = Context::new(&default_config());
+    DependencyAnalyzer::new(&ctx, repo, [pid], &SolveOptions::default())?.strong_deps(pid)
+}
+
+// Whether no plan installs both packages
+pub fn impossible_combinations<S: PackageSource + ?Sized>(
+    repo: &S,
+    pid_a: PackageId,
+    pid_b: PackageId,
+) -> Result<bool, ResolutionError> {
+    let ctx = Context::new(&default_config());
+    DependencyAnalyzer::new(&ctx, repo, [pid_a, pid_b], &SolveOptions::default())?
+        .impossible_combinations(pid_a, pid_b)
+}
+
+#[cfg(test)]
+mod test {
+    use z3::Context;
+
+    use crate::internals::{
+        options::SolveOptions, testing::repository_from_requirements, utils::default_config,
+    };
+
+    use super::{impossible_combinations, strong_deps, DependencyAnalyzer};
+
+    #[test]
+    fn test_dependency_analyzer() {
+        // package 0 requires packages 1 and 2, version 1 of package 2 requires package 3,
+        // package 3 conflicts with package 4, package 5 requires a missing version of
+        // package 1
+        let versions: [&[&str]; 6] = [
+            &["pkg1; pkg2"],
+            &[""],
+            &["pkg3", ""],
+            &["!pkg4"],
+            &[""],
+            &["pkg1 in {2}"],
+        ];
+        let repo = repository_from_requirements(&versions).unwrap();
+        assert_eq!(strong_deps(&repo, 0).unwrap(), vec![1, 2]);
+        assert_eq!(strong_deps(&repo, 4).unwrap(), vec![]);
+        assert!(impossible_combinations(&repo, 3, 4).unwrap());
+        assert!(!impossible_combinations(&repo, 0, 4).unwrap());
+
+        let ctx = Context::new(&default_config());
+        let analyzer =
+            DependencyAnalyzer::new(&ctx, &repo, 0..6, &SolveOptions::default()).unwrap();
+        assert!(!analyzer.is_installable(5).unwrap());
+        assert_eq!(analyzer.strong_deps(5).unwrap(), vec![]);
+        assert!(analyzer.impossible_combinations(5, 2).unwrap());
+        assert!(analyzer.strong_deps(6).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+// Audit trails of resolutions, for pipelines that have to account for how every plan was
+// decided. A hook set with `SolveOptions::audit` receives a record of every resolution of
+// the plain solver and of the optimizers run with these options, including those run by
+// sessions: the normalized requirements and fingerprints identifying the input, the
+// options, the time taken and the outcome. Records are built after the resolution, their
+// cost is only paid when a hook is set
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::internals::{options::SolveOptions, types::*};
+
+// What a resolution looked for
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ResolutionKind {
+    // any plan
+    Solve,
+    // an optimal plan for a goal
+    Goal(Goal),
+    // an optimal plan for a custom objective
+    Objective,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AuditRecord<'a> {
+    // the fingerprint of the package source, None if it has none, see
+    // `PackageSource::source_fingerprint`
+    pub repository: Option<u64>,
+    // the requirements normalized against the source and their fingerprint, so that
+    // equivalent requirements are recorded alike
+    pub requirements: &'a RequirementSet,
+    pub fingerprint: u64,
+    pub kind: ResolutionKind,
+    pub options: &'a SolveOptions,
+    pub elapsed: Duration,
+    pub outcome: &'a Res,
+}
+
+// Implementations must not block for long, records are delivered on the thread of the
+// resolution before its result is returned
+pub trait AuditHook: Send + Sync {
+    fn record(&self, record: &AuditRecord<'_>);
+}
+
+impl<F> AuditHook for F
+where
+    F: Fn(&AuditRecord<'_>) + Send + Sync,
+{
+    fn record(&self, record: &AuditRecord<'_>) {
+        self(record)
+    }
+}
+
+// A hook shared by clones of the options. Options compare equal if they share the same
+// hook
+#[derive(Clone)]
+pub struct Audit(Arc<dyn AuditHook>);
+
+impl Audit {
+    pub fn new(hook: impl AuditHook + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl PartialEq for Audit {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Audit {}
+
+impl Debug for Audit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Audit(..)")
+    }
+}
+
+// Run a resolution, recording it if the options carry a hook
+pub(crate) fn audited<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    kind: ResolutionKind,
+    options: &SolveOptions,
+    resolve: impl FnOnce() -> Res,
+) -> Res {
+    let Some(Audit(hook)) = &options.audit else {
+        return resolve();
+    };
+    let start = Instant::now();
+    let outcome = resolve();
+    let elapsed = start.elapsed();
+    let mut normalized = requirements.clone();
+    normalized.normalize(repo);
+    hook.record(&AuditRecord {
+        repository: repo.source_fingerprint(),
+        requirements: &normalized,
+        fingerprint: normalized.fingerprint(),
+        kind,
+        options,
+        elapsed,
+        outcome: &outcome,
+    });
+    outcome
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use crate::internals::{
+        options::SolveOptions,
+        session::SolverSession,
+        solver::{optimize_with_options, simple_solve_with_options},
+        types::{Goal, Package, PackageVer, Repository, Requirement, RequirementSet},
+    };
+
+    use super::{AuditRecord, ResolutionKind};
+
+    #[test]
+    fn test_audit() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..2)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let log = records.clone();
+        let options = SolveOptions::new().audit(move |record: &AuditRecord<'_>| {
+            log.lock().unwrap().push((
+                record.repository,
+                record.fingerprint,
+                record.kind,
+                record.outcome.as_ref().map(|r| r.is_sat()).ok(),
+            ))
+        });
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(0),
+            Requirement::any_version(0),
+        ]);
+        simple_solve_with_options(&repo, &reqs, &options).unwrap();
+        optimize_with_options(&repo, &reqs, Goal::Newest, &options).unwrap();
+        let mut session = SolverSession::with_options(&repo, options.clone());
+        session
+            .solve(&RequirementSet::from_antidep(Requirement::any_version(0)))
+            .unwrap();
+
+        let records = records.lock().unwrap();
+        println!("{records:?}");
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].0, Some(repo.fingerprint()));
+        let mut normalized = RequirementSet::from_dep(Requirement::any_version(0));
+        normalized.normalize(&repo);
+        assert_eq!(records[0].1, normalized.fingerprint());
+        assert_eq!(records[1].2, ResolutionKind::Goal(Goal::Newest));
+        assert_eq!(records[2].2, ResolutionKind::Solve);
+        assert_eq!(records[2].3, Some(true));
+        assert_eq!(options, options.clone());
+        assert_ne!(options, SolveOptions::new());
+    }
+}
@@ -0,0 +1,316 @@
+// Batch resolution of many requirement sets against the same repository. The
+// constraints of all the packages involved are encoded once, each behind a guard, and
+// each requirement set is then solved in its own solver scope assuming the guards of the
+// packages in its closure only, see `ScopedSolver`. If they outgrow the limits of the
+// options together, the requirement sets are solved one by one.
+use std::thread;
+
+use bumpalo::Bump;
+use tinyset::SetU32;
+use z3::{
+    ast::{Ast, Bool},
+    Context, Solver,
+};
+
+use crate::internals::{
+    audit::{audited, ResolutionKind},
+    constraints::{add_all_constraints_within, add_toplevel_constraints, find_closure, Origin},
+    encoding::Encoder,
+    options::SolveOptions,
+    solver::{check_and_extract_in, simple_solve_with_options, Hooks},
+    tracking::AssertionRegistry,
+    types::*,
+    utils::default_config,
+};
+
+pub fn solve_batch<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirement_sets: &[RequirementSet],
+) -> Vec<Res> {
+    solve_batch_with_options(repo, requirement_sets, &SolveOptions::default())
+}
+
+pub fn solve_batch_with_options<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirement_sets: &[RequirementSet],
+    options: &SolveOptions,
+) -> Vec<Res> {
+    let cfg = default_config();
+    let ctx = Context::new(&cfg);
+    let allocator = Bump::new();
+
+    let effective_sets = requirement_sets
+        .iter()
+        .map(|requirements| options.effective_requirements(requirements))
+        .collect::<Vec<_>>();
+    let closure = match find_closure(repo, effective_sets.iter().flat_map(|r| &**r)) {
+        Ok(closure) => closure,
+        // some set refers to an unknown package, the closure is made of the other sets
+        // only and the offending ones fail on their own
+        Err(_) => {
+            let mut closure = SetU32::new();
+            for requirements in &effective_sets {
+                if let Ok(set_closure) = find_closure(repo, requirements.into_iter()) {
+                    for pid in set_closure.iter() {
+                        closure.insert(pid);
+                    }
+                }
+            }
+            closure
+        }
+    };
+    // the closure of every set together may outgrow limits the closure of each set stays
+    // within, each set is then solved on its own
+    let scoped = match ScopedSolver::new(&ctx, &allocator, repo, &closure, options) {
+        Ok(scoped) => scoped,
+        Err(_) => {
+            return requirement_sets
+                .iter()
+                .map(|requirements| simple_solve_with_options(repo, requirements, options))
+                .collect()
+        }
+    };
+
+    let mut stats = SolveStats::default();
+    let mut hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut stats,
+    };
+    effective_sets
+        .iter()
+        .zip(requirement_sets)
+        .map(|(effective, requirements)| {
+            audited(repo, requirements, ResolutionKind::Solve, options, || {
+                options.interruptible(&ctx, || scoped.solve(repo, effective, options, &mut hooks))
+            })
+        })
+        .collect()
+}
+
+// The constraints of the packages of a closure asserted once into a solver, requirement
+// sets within the closure then being solved each in its own solver scope. The assertions
+// of the packages are guarded, a resolution only assumes the guards of the packages in
+// the closure of its requirements, so that its cost does not grow with the packages
+// outside of it. Options affecting how the constraints are tracked, e.g.
+// `untracked_first`, do not apply: the guards track every assertion once for all
+// resolutions
+pub(crate) struct ScopedSolver<'c, 'b> {
+    ctx: &'c Context,
+    solver: Solver<'c>,
+    encoder: Encoder<'c>,
+    // every assertion is guarded, see `AssertionRegistry::guard`
+    registry: AssertionRegistry<'c, (Expr<'b>, Origin)>,
+    // the guards of the assertions of each package, those of essential packages included
+    guards: PackageMap<Vec<usize>>,
+}
+
+impl<'c, 'b> ScopedSolver<'c, 'b> {
+    pub(crate) fn new<S: PackageSource + ?Sized>(
+        ctx: &'c Context,
+        allocator: &'b Bump,
+        repo: &'b S,
+        closure: &SetU32,
+        options: &SolveOptions,
+    ) -> Result<Self, ResolutionError> {
+        options.limits.check_closure(closure.len())?;
+        let solver = options.solver(ctx);
+        let pids = options.ordered_pids(closure);
+        let encoder = Encoder::new(ctx, options.encoding, repo, pids.iter().copied());
+        for domain_constraint in encoder.domain_constraints() {
+            solver.assert(&domain_constraint);
+        }
+
+        let mut registry = AssertionRegistry::new(ctx);
+        let mut guards = PackageMap::new();
+        add_all_constraints_within(
+            allocator,
+            &encoder,
+            repo,
+            pids.iter().copied(),
+            &RequirementSet::default(),
+            &options.limits,
+            |_| options.check_cancelled(),
+            |expr: Bool, sym_expr, origin| {
+                let id = registry.guard(&solver, &expr.simplify(), (sym_expr, origin));
+                if let Origin::Package(pid) | Origin::Essential(pid) = origin {
+                    guards.get_or_insert_with(pid, Vec::new).push(id);
+                }
+            },
+        )?;
+        Ok(Self {
+            ctx,
+            solver,
+            encoder,
+            registry,
+            guards,
+        })
+    }
+
+    // Solve requirements, with the options already applied to them, in a scope of the
+    // solver
+    pub(crate) fn solve<S: PackageSource + ?Sized>(
+        &self,
+        repo: &S,
+        requirements: &RequirementSet,
+        options: &SolveOptions,
+        hooks: &mut Hooks,
+    ) -> Res {
+        options.check_cancelled()?;
+        // packages outside of the closure of the requirements are irrelevant to them
+        let closure = find_closure(repo, requirements.into_iter())?;
+        options.limits.check_closure(closure.len())?;
+        if let Some(core) = requirements.contradiction() {
+            return Ok(ResolutionResult::UnsatWithCore { core });
+        }
+        self.solver.push();
+        let res = self.solve_scoped(repo, requirements, &closure, options, hooks);
+        self.solver.pop(1);
+        res
+    }
+
+    fn solve_scoped<S: PackageSource + ?Sized>(
+        &self,
+        repo: &S,
+        requirements: &RequirementSet,
+        closure: &SetU32,
+        options: &SolveOptions,
+        hooks: &mut Hooks,
+    ) -> Res {
+        let assumptions = closure
+            .iter()
+            .filter_map(|pid| self.guards.get(pid))
+            .flatten()
+            .map(|&id| self.registry.var(id))
+            .collect::<Vec<_>>();
+
+        // the toplevel and option constraints only live as long as the scope, their
+        // tracking variables are named alike in every scope
+        let allocator = Bump::new();
+        let mut toplevel = AssertionRegistry::with_prefix(self.ctx, "toplevel");
+        for (option_constraint, restriction) in options.option_constraints(&self.encoder, closure) {
+            let origin = Origin::Restriction(restriction);
+            toplevel.track(&self.solver, &option_constraint, (Expr::Top, origin));
+        }
+        add_toplevel_constraints(
+            &allocator,
+            &self.encoder,
+            repo,
+            requirements,
+            |expr, sym_expr, origin| {
+                toplevel.track(&self.solver, &expr.simplify(), (sym_expr, origin));
+            },
+        );
+        // the arena of the packages was checked against the limits when they were encoded
+        options.limits.check_constraints(
+            assumptions.len() + toplevel.len(),
+            allocator.allocated_bytes(),
+        )?;
+
+        let pids = options.ordered_pids(closure);
+        check_and_extract_in(
+            &self.encoder,
+            &self.solver,
+            repo,
+            &pids,
+            &[&self.registry, &toplevel],
+            &assumptions,
+            hooks,
+        )
+    }
+}
+
+// Solve the requirement sets on `threads` worker threads, each thread solving a
+// contiguous chunk of the requirement sets as a batch
+pub fn solve_batch_parallel<S: PackageSource + Sync + ?Sized>(
+    repo: &S,
+    requirement_sets: &[RequirementSet],
+    options: &SolveOptions,
+    threads: usize,
+) -> Vec<Res> {
+    if requirement_sets.is_empty() {
+        return Vec::new();
+    }
+    let chunk_size = requirement_sets.len().div_ceil(threads.max(1));
+
+    thread::scope(|scope| {
+        let handles = requirement_sets
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || solve_batch_with_options(repo, chunk, options)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Impossible: worker thread panicked"))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        options::SolveOptions,
+        solver::simple_solve,
+        types::{
+            Package, PackageVer, Repository, Requirement, RequirementSet, ResolutionError,
+            ResolutionResult,
+        },
+    };
+
+    use super::{solve_batch, solve_batch_parallel};
+
+    #[test]
+    fn test_solve_batch() {
+        // package i depends on package i + 1, package 3 conflicts with package 0
+        let packages = (0..4)
+            .map(|id| {
+                let requirements = if id < 3 {
+                    RequirementSet::from_dep(Requirement::any_version(id + 1))
+                } else {
+                    RequirementSet::from_antidep(Requirement::any_version(0))
+                };
+                Package {
+                    id,
+                    versions: vec![PackageVer { requirements }],
+                }
+            })
+            .collect();
+        let repo = Repository::new(packages);
+        let sets = (0..4)
+            .map(|id| RequirementSet::from_dep(Requirement::any_version(id)))
+            .collect::<Vec<_>>();
+
+        let batch = solve_batch(&repo, &sets);
+        let parallel = solve_batch_parallel(&repo, &sets, &SolveOptions::default(), 2);
+        for ((reqs, r1), r2) in sets.iter().zip(&batch).zip(parallel) {
+            let expected = simple_solve(&repo, reqs).unwrap();
+            assert_eq!(r1.as_ref().unwrap(), &expected);
+            assert_eq!(r2.unwrap(), expected);
+        }
+
+        // a set with an unknown package fails on its own
+        let mut with_unknown = sets.clone();
+        with_unknown.push(RequirementSet::from_dep(Requirement::any_version(7)));
+        let results = solve_batch(&repo, &with_unknown);
+        assert_eq!(&results[..4], &batch[..]);
+        assert_eq!(results[4], Err(ResolutionError::unknown_package(7)));
+
+        // the whole cycle is in the core of the first set
+        match batch[0].as_ref().unwrap() {
+            ResolutionResult::UnsatWithCore { core } => {
+                assert_eq!(core.involved_packages(), vec![0, 1, 2, 3])
+            }
+            r => panic!("unexpected result {r:?}"),
+        }
+        match batch[1].as_ref().unwrap() {
+            ResolutionResult::Sat { plans, .. } => {
+                let installed = plans
+                    .first()
+                    .iter()
+                    .copied()
+                    .filter(|(_, v)| *v != 0)
+                    .collect::<Vec<_>>();
+                assert_eq!(installed, vec![(1, 1), (2, 1), (3, 1)]);
+            }
+            r => panic!("unexpected result {r:?}"),
+        }
+    }
+}
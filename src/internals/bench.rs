@@ -0,0 +1,168 @@
+// Benchmark instances and a timing harness, shared by the benchmarks in `benches/` and
+// downstream crates comparing their own setups against the same synthetic profiles. Each
+// run resolves one instance with one workload and one encoding and reports the statistics
+// of the resolution
+use std::time::{Duration, Instant};
+
+use crate::internals::{
+    encoding::Encoding,
+    generate::{synthetic, Profile},
+    metrics::{DistanceFromNewest, InstalledPackages, Metric},
+    options::SolveOptions,
+    solver::{optimize_with_metrics, optimize_with_stats, simple_solve_with_stats},
+    types::*,
+};
+
+pub const ENCODINGS: [Encoding; 3] = [Encoding::Int, Encoding::Bool, Encoding::BitVec];
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Workload {
+    Solve,
+    Optimize(Goal),
+    // a single metric through `optimize_with_metrics`
+    MinimizeDistance,
+    MinimizeInstalled,
+}
+
+impl Workload {
+    pub fn all() -> [Workload; 5] {
+        [
+            Workload::Solve,
+            Workload::Optimize(Goal::Newest),
+            Workload::Optimize(Goal::Minimal),
+            Workload::MinimizeDistance,
+            Workload::MinimizeInstalled,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Workload::Solve => "solve",
+            Workload::Optimize(Goal::Newest) => "newest",
+            Workload::Optimize(Goal::Minimal) => "minimal",
+            Workload::Optimize(Goal::Preferred) => "preferred",
+            Workload::MinimizeDistance => "distance",
+            Workload::MinimizeInstalled => "installed",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Instance {
+    pub name: String,
+    pub repository: Repository,
+    pub requirements: RequirementSet,
+}
+
+impl Instance {
+    pub fn from_profile(name: impl Into<String>, profile: &Profile) -> Self {
+        let (repository, requirements) = synthetic(profile);
+        Self {
+            name: name.into(),
+            repository,
+            requirements,
+        }
+    }
+
+    // The instances of the predefined profiles, from small to large
+    pub fn standard() -> Vec<Instance> {
+        vec![
+            Self::from_profile("small", &Profile::small()),
+            Self::from_profile("medium", &Profile::medium()),
+            Self::from_profile("large", &Profile::large()),
+        ]
+    }
+
+    pub fn run(
+        &self,
+        workload: Workload,
+        encoding: Encoding,
+    ) -> Result<(ResolutionResult, SolveStats), ResolutionError> {
+        let options = SolveOptions {
+            encoding,
+            ..SolveOptions::deterministic(0)
+        };
+        let (repo, reqs) = (&self.repository, &self.requirements);
+        let metric: &dyn Metric<Repository> = match workload {
+            Workload::Solve => return simple_solve_with_stats(repo, reqs, &options),
+            Workload::Optimize(goal) => return optimize_with_stats(repo, reqs, goal, &options),
+            Workload::MinimizeDistance => &DistanceFromNewest,
+            Workload::MinimizeInstalled => &InstalledPackages,
+        };
+        // the metric entry point reports no statistics, only the time is measured
+        let start = Instant::now();
+        let result = optimize_with_metrics(repo, reqs, &[metric], &options)?;
+        let stats = SolveStats {
+            elapsed: start.elapsed(),
+            ..SolveStats::default()
+        };
+        Ok((result, stats))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub instance: String,
+    pub workload: Workload,
+    pub encoding: Encoding,
+    pub sat: bool,
+    // the median of the elapsed times of the repetitions
+    pub median: Duration,
+    // the statistics of the first repetition
+    pub stats: SolveStats,
+}
+
+// Run every combination of instance, workload and encoding `repetitions` times
+pub fn measure(
+    instances: &[Instance],
+    workloads: &[Workload],
+    encodings: &[Encoding],
+    repetitions: usize,
+) -> Result<Vec<Measurement>, ResolutionError> {
+    let mut measurements = Vec::new();
+    for instance in instances {
+        for &workload in workloads {
+            for &encoding in encodings {
+                let mut runs = (0..repetitions.max(1))
+                    .map(|_| instance.run(workload, encoding))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut times = runs
+                    .iter()
+                    .map(|(_, stats)| stats.elapsed)
+                    .collect::<Vec<_>>();
+                times.sort_unstable();
+                let (result, stats) = runs.swap_remove(0);
+                measurements.push(Measurement {
+                    instance: instance.name.clone(),
+                    workload,
+                    encoding,
+                    sat: result.is_sat(),
+                    median: times[times.len() / 2],
+                    stats,
+                });
+            }
+        }
+    }
+    Ok(measurements)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::generate::Profile;
+
+    use super::{measure, Instance, Workload, ENCODINGS};
+
+    #[test]
+    fn test_measure() {
+        let profile = Profile {
+            packages: 10,
+            ..Profile::small()
+        };
+        let instances = [Instance::from_profile("tiny", &profile)];
+        let measurements = measure(&instances, &Workload::all(), &ENCODINGS, 2).unwrap();
+        println!("{measurements:#?}");
+        assert_eq!(measurements.len(), Workload::all().len() * ENCODINGS.len());
+        // every workload and encoding agrees on satisfiability
+        assert!(measurements.iter().all(|m| m.sat == measurements[0].sat));
+    }
+}
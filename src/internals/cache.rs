@@ -0,0 +1,477 @@
+// Memoization of resolutions, keyed by the fingerprint of the repository and of the
+// normalized requirements, so that repeated identical resolutions in CI or a daemon are
+// answered without invoking the solver. Entries are stored as text through a `CacheStore`,
+// one entry per key:
+//
+//   sat                         followed by one `plan` line per plan, `sat optimal` if
+//                               the plans were proven optimal
+//   objective 4 2               the distance from newest and the number of installed
+//                               packages, only for optimized plans, before the plans
+//   plan 0=1 3=2                the versions of a plan, in order
+//   unsat                       unsatisfiable without a core
+//   core                        unsatisfiable, followed by the core
+//   top                         the following requirements are toplevel ones
+//   version 3 2                 the following requirements are those of version 2 of 3
+//   dep 1 2,4-5,*               a dependency on the points, intervals or all versions
+//   conflict 1 2                a conflict, in the same format
+//   restriction not-allowed 4   a restriction of the solve options in the core, also
+//                               `no-downgrade 4 2` and `max-installed 10`
+//   index dep 9f0c..            a toplevel requirement of the core, by fingerprint
+//
+// Equivalent requirements may be written in another order, the indices of the toplevel
+// requirements in cores are therefore mapped back through the fingerprints of the
+// requirements. Entries that fail to parse or cannot be mapped back are ignored
+use std::{collections::HashMap, fs, io::ErrorKind, path::PathBuf, sync::Mutex};
+
+use snafu::ResultExt;
+
+use crate::internals::{
+    options::SolveOptions,
+    solver::{optimize_with_options, simple_solve},
+    types::*,
+    utils::{format_ranges, parse_requirement},
+};
+
+pub trait CacheStore {
+    fn load(&self, key: &str) -> Result<Option<String>, ResolutionError>;
+
+    fn store(&self, key: &str, entry: &str) -> Result<(), ResolutionError>;
+}
+
+// A store keeping one file per key in a directory
+#[derive(Debug, Clone)]
+pub struct DirectoryStore {
+    dir: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, ResolutionError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir).context(IoSnafu)?;
+        Ok(Self { dir })
+    }
+}
+
+impl CacheStore for DirectoryStore {
+    fn load(&self, key: &str) -> Result<Option<String>, ResolutionError> {
+        match fs::read_to_string(self.dir.join(key)) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).context(IoSnafu),
+        }
+    }
+
+    // written to a temporary file first, so that concurrent readers never see partial
+    // entries
+    fn store(&self, key: &str, entry: &str) -> Result<(), ResolutionError> {
+        let tmp = self.dir.join(format!("{key}.{}.tmp", std::process::id()));
+        fs::write(&tmp, entry).context(IoSnafu)?;
+        fs::rename(&tmp, self.dir.join(key)).context(IoSnafu)
+    }
+}
+
+// A store in memory, e.g. for tests or as a per-process cache
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("poisoned cache").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn load(&self, key: &str) -> Result<Option<String>, ResolutionError> {
+        Ok(self
+            .entries
+            .lock()
+            .expect("poisoned cache")
+            .get(key)
+            .cloned())
+    }
+
+    fn store(&self, key: &str, entry: &str) -> Result<(), ResolutionError> {
+        self.entries
+            .lock()
+            .expect("poisoned cache")
+            .insert(key.to_string(), entry.to_string());
+        Ok(())
+    }
+}
+
+pub struct SolveCache<St: CacheStore> {
+    store: St,
+    hits: usize,
+    misses: usize,
+}
+
+impl<St: CacheStore> SolveCache<St> {
+    pub fn new(store: St) -> Self {
+        Self {
+            store,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn store(&self) -> &St {
+        &self.store
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn solve(
+        &mut self,
+        repo: &Repository,
+        requirements: &RequirementSet,
+    ) -> Result<ResolutionResult, ResolutionError> {
+        self.memoize(repo, requirements, "solve", || {
+            simple_solve(repo, requirements)
+        })
+    }
+
+    pub fn optimize(
+        &mut self,
+        repo: &Repository,
+        requirements: &RequirementSet,
+        goal: Goal,
+    ) -> Result<ResolutionResult, ResolutionError> {
+        let mode = match goal {
+            Goal::Newest => "newest",
+            Goal::Minimal => "minimal",
+            Goal::Preferred => "preferred",
+        };
+        self.memoize(repo, requirements, mode, || {
+            optimize_with_options(repo, requirements, goal, &SolveOptions::default())
+        })
+    }
+
+    fn memoize(
+        &mut self,
+        repo: &Repository,
+        requirements: &RequirementSet,
+        mode: &str,
+        solve: impl FnOnce() -> Result<ResolutionResult, ResolutionError>,
+    ) -> Result<ResolutionResult, ResolutionError> {
+        let mut normalized = requirements.clone();
+        normalized.normalize(repo);
+        let key = format!(
+            "{:016x}-{:016x}-{mode}",
+            repo.fingerprint(),
+            normalized.fingerprint()
+        );
+
+        let cached = self.store.load(&key)?;
+        if let Some(result) = cached.and_then(|entry| parse_entry(&entry, requirements)) {
+            self.hits += 1;
+            return Ok(result);
+        }
+        self.misses += 1;
+        let result = solve()?;
+        // the entries have no syntax for formulas, cores with formulas are not stored
+        if !has_formulas(&result) {
+            self.store
+                .store(&key, &write_entry(&result, requirements))?;
+        }
+        Ok(result)
+    }
+}
+
+fn has_formulas(result: &ResolutionResult) -> bool {
+    let ResolutionResult::UnsatWithCore { core } = result else {
+        return false;
+    };
+    core.toplevel_reqs.formula.is_some()
+        || core
+            .package_reqs
+            .values()
+            .flat_map(|versions| versions.values())
+            .any(|reqs| reqs.formula.is_some())
+}
+
+fn toplevel_requirement(
+    requirements: &RequirementSet,
+    index: usize,
+) -> Option<(bool, &Requirement)> {
+    let deps = requirements.dependencies.len();
+    if index < deps {
+        Some((false, &requirements.dependencies[index]))
+    } else {
+        requirements
+            .conflicts
+            .get(index - deps)
+            .map(|req| (true, req))
+    }
+}
+
+fn kind(conflict: bool) -> &'static str {
+    if conflict {
+        "conflict"
+    } else {
+        "dep"
+    }
+}
+
+fn write_requirements(entry: &mut String, reqs: &RequirementSet) {
+    let reqs = reqs
+        .dependencies
+        .iter()
+        .map(|req| (false, req))
+        .chain(reqs.conflicts.iter().map(|req| (true, req)));
+    for (conflict, req) in reqs {
+        entry.push_str(&format!(
+            "{} {} {}\n",
+            kind(conflict),
+            req.package,
+            format_ranges(&req.versions)
+        ));
+    }
+}
+
+fn write_entry(result: &ResolutionResult, requirements: &RequirementSet) -> String {
+    let mut entry = String::new();
+    match result {
+        ResolutionResult::Sat {
+            plans,
+            objective,
+            optimal,
+        } => {
+            entry.push_str(if *optimal { "sat optimal\n" } else { "sat\n" });
+            if let Some(objective) = objective {
+                entry.push_str(&format!(
+                    "objective {} {}\n",
+                    objective.distance_from_newest, objective.installed
+                ));
+            }
+            for plan in plans.as_vec() {
+                entry.push_str("plan");
+                for (pid, v) in plan {
+                    entry.push_str(&format!(" {pid}={v}"));
+                }
+                entry.push('\n');
+            }
+        }
+        ResolutionResult::Unsat => entry.push_str("unsat\n"),
+        ResolutionResult::UnsatWithCore { core } => {
+            entry.push_str("core\ntop\n");
+            write_requirements(&mut entry, &core.toplevel_reqs);
+            for (pid, reqs) in core.package_reqs.iter() {
+                for (v, reqs) in reqs.iter() {
+                    entry.push_str(&format!("version {pid} {v}\n"));
+                    write_requirements(&mut entry, reqs);
+                }
+            }
+            for restriction in core.restrictions() {
+                let words = match *restriction {
+                    Restriction::NoDowngrade { package, version } => {
+                        format!("no-downgrade {package} {version}")
+                    }
+                    Restriction::MaxInstalled(n) => format!("max-installed {n}"),
+                    Restriction::NotAllowed(package) => format!("not-allowed {package}"),
+                };
+                entry.push_str(&format!("restriction {words}\n"));
+            }
+            for &index in core.toplevel_indices() {
+                if let Some((conflict, req)) = toplevel_requirement(requirements, index) {
+                    entry.push_str(&format!(
+                        "index {} {:016x}\n",
+                        kind(conflict),
+                        req.fingerprint()
+                    ));
+                }
+            }
+        }
+    }
+    entry
+}
+
+// The cached result, None if the entry is malformed or the toplevel requirements of its
+// core are not among the requirements
+fn parse_entry(entry: &str, requirements: &RequirementSet) -> Option<ResolutionResult> {
+    let mut lines = entry.lines();
+    match lines.next()? {
+        "unsat" => Some(ResolutionResult::Unsat),
+        status @ ("sat" | "sat optimal") => {
+            let optimal = status == "sat optimal";
+            let mut lines = lines.peekable();
+            let objective = match lines.peek()?.strip_prefix("objective ") {
+                Some(values) => {
+                    let (distance, installed) = values.split_once(' ')?;
+                    lines.next();
+                    Some(ObjectiveValues {
+                        distance_from_newest: distance.parse().ok()?,
+                        installed: installed.parse().ok()?,
+                    })
+                }
+                None => None,
+            };
+            let plans = lines
+                .map(|line| {
+                    let mut words = line.split(' ');
+                    (words.next()? == "plan").then_some(())?;
+                    words
+                        .map(|word| {
+                            let (pid, v) = word.split_once('=')?;
+                            Some((pid.parse().ok()?, v.parse().ok()?))
+                        })
+                        .collect::<Option<Plan>>()
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(ResolutionResult::Sat {
+                plans: Vec1::try_from(plans).ok()?,
+                objective,
+                optimal,
+            })
+        }
+        "core" => {
+            let mut toplevel_reqs = RequirementSet::default();
+            let mut package_reqs = PackageMap::<VersionMap<RequirementSet>>::new();
+            let mut toplevel_labels = Vec::new();
+            let mut restrictions = Vec::new();
+            let mut current = None;
+            for line in lines {
+                let words = line.split(' ').collect::<Vec<_>>();
+                match words[..] {
+                    ["top"] => current = None,
+                    ["version", pid, v] => {
+                        let (pid, v) = (pid.parse::<PackageId>().ok()?, v.parse::<Version>().ok()?);
+                        package_reqs
+                            .get_or_insert_with(pid, VersionMap::new)
+                            .insert(v, RequirementSet::default());
+                        current = Some((pid, v));
+                    }
+                    [kind @ ("dep" | "conflict"), pid, ranges] => {
+                        let req = parse_requirement(pid, ranges)?;
+                        let reqs = match current {
+                            None => &mut toplevel_reqs,
+                            Some((pid, v)) => package_reqs.get_mut(pid)?.get_mut(v)?,
+                        };
+                        if kind == "dep" {
+                            reqs.add_dep(req);
+                        } else {
+                            reqs.add_antidep(req);
+                        }
+                    }
+                    ["restriction", "no-downgrade", pid, v] => {
+                        restrictions.push(Restriction::NoDowngrade {
+                            package: pid.parse().ok()?,
+                            version: v.parse().ok()?,
+                        });
+                    }
+                    ["restriction", "max-installed", n] => {
+                        restrictions.push(Restriction::MaxInstalled(n.parse().ok()?));
+                    }
+                    ["restriction", "not-allowed", pid] => {
+                        restrictions.push(Restriction::NotAllowed(pid.parse().ok()?));
+                    }
+                    ["index", kind @ ("dep" | "conflict"), fingerprint] => {
+                        let fingerprint = u64::from_str_radix(fingerprint, 16).ok()?;
+                        let (index, (_, req)) = (0..)
+                            .map_while(|i| toplevel_requirement(requirements, i).map(|r| (i, r)))
+                            .find(|&(_, (conflict, req))| {
+                                (conflict == (kind == "conflict"))
+                                    && req.fingerprint() == fingerprint
+                            })?;
+                        toplevel_labels.push((index, vec![req.package]));
+                    }
+                    _ => return None,
+                }
+            }
+            // the indices follow the order of the requirements, which may differ from
+            // the order of the cached ones
+            toplevel_labels.sort_unstable();
+            let (toplevel_indices, toplevel_packages) = toplevel_labels.into_iter().unzip();
+            Some(ResolutionResult::UnsatWithCore {
+                core: ConstraintSet {
+                    package_reqs,
+                    toplevel_reqs,
+                    toplevel_indices,
+                    toplevel_packages,
+                    restrictions,
+                },
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{
+        Goal, Package, PackageVer, Repository, Requirement, RequirementSet, ResolutionResult,
+    };
+
+    use super::{MemoryStore, SolveCache};
+
+    #[test]
+    fn test_solve_cache() {
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_antidep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: (0..2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        let mut cache = SolveCache::new(MemoryStore::new());
+
+        let reqs = RequirementSet::from_dep(Requirement::any_version(1));
+        let fresh = cache.optimize(&repo, &reqs, Goal::Newest).unwrap();
+        let cached = cache.optimize(&repo, &reqs, Goal::Newest).unwrap();
+        assert_eq!(fresh, cached);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // the same requirements in another order share the entry, the indices of the
+        // core follow the order of the requirements
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(0),
+            Requirement::any_version(1),
+        ]);
+        let fresh = cache.solve(&repo, &reqs).unwrap();
+        println!("{fresh:?}");
+        assert!(fresh.is_unsat());
+        let reversed = RequirementSet::from_deps(vec![
+            Requirement::any_version(1),
+            Requirement::any_version(0),
+        ]);
+        let cached = cache.solve(&repo, &reversed).unwrap();
+        assert_eq!(cache.hits(), 2);
+        let (
+            ResolutionResult::UnsatWithCore { core: fresh },
+            ResolutionResult::UnsatWithCore { core: cached },
+        ) = (fresh, cached)
+        else {
+            panic!("expected unsat cores")
+        };
+        assert_eq!(fresh.toplevel_reqs, cached.toplevel_reqs);
+        assert_eq!(fresh.package_reqs, cached.package_reqs);
+        let mut indices = cached.toplevel_indices().to_vec();
+        indices.sort_unstable();
+        assert_eq!(indices, vec![0, 1]);
+        assert_eq!(cache.store().len(), 2);
+    }
+}
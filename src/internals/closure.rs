@@ -0,0 +1,710 @@
+// Dependency closure computation with configurable traversal, recording enough
+// information about the traversal to explain why a package ended up in the closure
+use std::{borrow::Cow, collections::VecDeque};
+
+use intmap::IntMap;
+use tinyset::SetU32;
+
+use crate::internals::{
+    constraints::{find_closure, load_closure},
+    types::*,
+};
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum EdgeKind {
+    Dependency,
+    Conflict,
+}
+
+// The edge through which a package was first discovered: version `version` of
+// package `parent` depends on or conflicts with the package
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct ParentEdge {
+    pub parent: PackageId,
+    pub version: Version,
+    pub kind: EdgeKind,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ClosureOptions {
+    // do not expand packages more than `max_depth` edges away from the requirements
+    pub max_depth: Option<usize>,
+    // do not follow conflict edges
+    pub skip_conflicts: bool,
+    // only follow the edges of the newest version of each package
+    pub newest_only: bool,
+    // only follow the edges of the versions the toplevel requirements leave possible, see
+    // `version_restrictions`
+    pub restrict_versions: bool,
+}
+
+impl ClosureOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    pub fn skip_conflicts(mut self) -> Self {
+        self.skip_conflicts = true;
+        self
+    }
+
+    pub fn newest_only(mut self) -> Self {
+        self.newest_only = true;
+        self
+    }
+
+    pub fn restrict_versions(mut self) -> Self {
+        self.restrict_versions = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Closure {
+    pub packages: SetU32,
+    // packages in the order they were discovered (breadth first)
+    pub order: Vec<PackageId>,
+    // packages required directly by the toplevel requirements have no parent edge
+    pub parents: PackageMap<ParentEdge>,
+}
+
+impl Closure {
+    pub fn contains(&self, pid: PackageId) -> bool {
+        self.packages.contains(pid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.order.iter().copied()
+    }
+
+    pub fn to_vec(&self) -> Vec<PackageId> {
+        self.order.clone()
+    }
+
+    pub fn parent_of(&self, pid: PackageId) -> Option<&ParentEdge> {
+        self.parents.get(pid)
+    }
+
+    // The chain of edges leading from a toplevel requirement to `pid`, empty if `pid`
+    // is required directly or is not in the closure
+    pub fn path_to(&self, pid: PackageId) -> Vec<ParentEdge> {
+        let mut path = Vec::new();
+        let mut cur = pid;
+        while let Some(edge) = self.parent_of(cur) {
+            path.push(*edge);
+            cur = edge.parent;
+        }
+        path.reverse();
+        path
+    }
+}
+
+impl From<Closure> for Vec<PackageId> {
+    fn from(closure: Closure) -> Self {
+        closure.order
+    }
+}
+
+// The packages a resolution encodes, see `closure`. Unlike `Closure` it records no
+// traversal, so there is no path to explain why a package is in it
+#[derive(Debug, Clone)]
+pub struct EncodedClosure {
+    packages: SetU32,
+    // in increasing order
+    order: Vec<PackageId>,
+}
+
+impl EncodedClosure {
+    pub fn contains(&self, pid: PackageId) -> bool {
+        self.packages.contains(pid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.order.iter().copied()
+    }
+
+    pub fn to_vec(&self) -> Vec<PackageId> {
+        self.order.clone()
+    }
+}
+
+impl From<EncodedClosure> for Vec<PackageId> {
+    fn from(closure: EncodedClosure) -> Self {
+        closure.order
+    }
+}
+
+// The packages a resolution of the requirements encodes: the closure of the requirements
+// through the dependencies, conflicts and formulas of every version, together with the
+// essential packages. Cheaper than `compute_closure`, no traversal is recorded
+pub fn closure<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Result<EncodedClosure, ResolutionError> {
+    let packages = find_closure(repo, requirements.into_iter())?;
+    let mut order = packages.iter().collect::<Vec<_>>();
+    order.sort_unstable();
+    Ok(EncodedClosure { packages, order })
+}
+
+// The versions of a package left possible by the toplevel requirements, and whether they
+// force it to be installed
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub(crate) struct Restriction {
+    pub versions: RangeSet,
+    pub forced: bool,
+}
+
+// Restrict a package to the versions within some ranges, or out of them if it is not
+// `forced`. True if the package is forced and its restriction changed, i.e. if it has to
+// be propagated
+fn restrict<S: PackageSource + ?Sized>(
+    restrictions: &mut PackageMap<Restriction>,
+    repo: &S,
+    pid: PackageId,
+    ranges: &RangeSet,
+    forced: bool,
+) -> Result<bool, ResolutionError> {
+    let newest = repo
+        .newest_ver_of(pid)
+        .ok_or_else(|| ResolutionError::unknown_package(pid))?;
+    let restriction = restrictions.get_or_insert_with(pid, || Restriction {
+        versions: RangeSet::interval(1, newest),
+        forced: false,
+    });
+    let restricted = Restriction {
+        versions: if forced {
+            restriction.versions.intersection(ranges)
+        } else {
+            restriction.versions.difference(ranges)
+        },
+        forced: restriction.forced || forced,
+    };
+    let changed = *restriction != restricted;
+    *restriction = restricted;
+    Ok(changed && restriction.forced)
+}
+
+// The restrictions the toplevel requirements impose on the packages, accumulated along
+// the requirements of forced packages: a package required by every possible version of a
+// forced package is forced to the union of the versions they require, and the versions
+// of a package that every possible version of a forced package conflicts with are
+// excluded. Packages without a restriction may take any version
+pub(crate) fn version_restrictions<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Result<PackageMap<Restriction>, ResolutionError> {
+    let mut restrictions = PackageMap::new();
+    let mut pending = Vec::new();
+    for dep in &requirements.dependencies {
+        let required = RangeSet::from(&dep.versions);
+        if restrict(&mut restrictions, repo, dep.package, &required, true)? {
+            pending.push(dep.package);
+        }
+    }
+    for antidep in &requirements.conflicts {
+        let excluded = RangeSet::from(&antidep.versions);
+        if restrict(&mut restrictions, repo, antidep.package, &excluded, false)? {
+            pending.push(antidep.package);
+        }
+    }
+    for &pid in repo.essential_packages().iter() {
+        if restrict(&mut restrictions, repo, pid, &RangeSet::all(), true)? {
+            pending.push(pid);
+        }
+    }
+
+    while let Some(pid) = pending.pop() {
+        let package = repo.try_fetch_package(pid)?;
+        let possible = restrictions
+            .get(pid)
+            .map(|restriction| restriction.versions.clone())
+            .expect("Impossible: pending package has no restriction");
+        let versions = (1..)
+            .zip(&package.versions)
+            .filter(|&(v, _)| possible.contains(v))
+            .map(|(_, ver)| ver)
+            .collect::<Vec<_>>();
+        let Some(first) = versions.first() else {
+            continue;
+        };
+
+        // the versions of a package a version requires, None if it does not require it
+        let required_by = |ver: &PackageVer, target: PackageId| {
+            ver.deps()
+                .filter(|dep| dep.package == target)
+                .map(|dep| RangeSet::from(&dep.versions))
+                .reduce(|a, b| a.intersection(&b))
+        };
+        let excluded_by = |ver: &PackageVer, target: PackageId| {
+            ver.antideps()
+                .filter(|antidep| antidep.package == target)
+                .fold(RangeSet::empty(), |a, antidep| {
+                    a.union(&RangeSet::from(&antidep.versions))
+                })
+        };
+
+        let mut targets = first.deps().map(|dep| dep.package).collect::<Vec<_>>();
+        targets.sort_unstable();
+        targets.dedup();
+        for target in targets {
+            let required = versions
+                .iter()
+                .map(|ver| required_by(ver, target))
+                .reduce(|a, b| Some(a?.union(&b?)))
+                .flatten();
+            if let Some(required) = required {
+                if restrict(&mut restrictions, repo, target, &required, true)? {
+                    pending.push(target);
+                }
+            }
+        }
+
+        let mut targets = first
+            .antideps()
+            .map(|antidep| antidep.package)
+            .collect::<Vec<_>>();
+        targets.sort_unstable();
+        targets.dedup();
+        for target in targets {
+            let excluded = versions
+                .iter()
+                .map(|ver| excluded_by(ver, target))
+                .reduce(|a, b| a.intersection(&b))
+                .unwrap_or_else(RangeSet::empty);
+            if excluded.is_empty() {
+                continue;
+            }
+            if restrict(&mut restrictions, repo, target, &excluded, false)? {
+                pending.push(target);
+            }
+        }
+    }
+    Ok(restrictions)
+}
+
+// A source whose versions left impossible by the restrictions conflict with their own
+// package instead of carrying their requirements, like the versions pruned by `preprocess`.
+// Resolutions against it find the same plans, while its closure leaves out the packages
+// only those versions refer to
+struct RestrictedSource<'s, S: ?Sized> {
+    source: &'s S,
+    restrictions: PackageMap<Restriction>,
+}
+
+impl<S: PackageSource + ?Sized> PackageSource for RestrictedSource<'_, S> {
+    fn fetch_package(&self, id: PackageId) -> Option<Cow<'_, Package>> {
+        self.try_fetch_package(id).ok()
+    }
+
+    fn try_fetch_package(&self, id: PackageId) -> Result<Cow<'_, Package>, ResolutionError> {
+        let package = self.source.try_fetch_package(id)?;
+        let Some(restriction) = self.restrictions.get(id) else {
+            return Ok(package);
+        };
+        let mut package = package.into_owned();
+        for (v, ver) in (1..).zip(&mut package.versions) {
+            if !restriction.versions.contains(v) {
+                ver.requirements = RequirementSet::from_antidep(Requirement::any_version(id));
+            }
+        }
+        Ok(Cow::Owned(package))
+    }
+
+    fn preferred_versions(&self, id: PackageId) -> Option<Cow<'_, [Version]>> {
+        self.source.preferred_versions(id)
+    }
+
+    fn is_meta_package(&self, id: PackageId) -> bool {
+        self.source.is_meta_package(id)
+    }
+
+    fn essential_packages(&self) -> Cow<'_, [PackageId]> {
+        self.source.essential_packages()
+    }
+
+    fn masks_of(&self, id: PackageId) -> Cow<'_, [Requirement]> {
+        self.source.masks_of(id)
+    }
+}
+
+// The closure of the requirements following only the versions they leave possible, loaded
+// as a repository in which the other versions conflict with their own package. This is
+// what the solver encodes with `SolveOptions::restrict_versions`
+pub fn restricted_closure<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Result<PartialRepository, ResolutionError> {
+    let restricted = RestrictedSource {
+        source: repo,
+        restrictions: version_restrictions(repo, requirements)?,
+    };
+    load_closure(&restricted, requirements.into_iter())
+}
+
+pub fn compute_closure<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &ClosureOptions,
+) -> Result<Closure, ResolutionError> {
+    let mut packages = SetU32::new();
+    let mut order = Vec::new();
+    let mut parents = PackageMap::new();
+    let mut queue = VecDeque::new();
+    let restrictions = if options.restrict_versions {
+        version_restrictions(repo, requirements)?
+    } else {
+        PackageMap::new()
+    };
+
+    let toplevel_conflicts = if options.skip_conflicts {
+        &[][..]
+    } else {
+        &requirements.conflicts[..]
+    };
+    for req in requirements.dependencies.iter().chain(toplevel_conflicts) {
+        if packages.insert(req.package) {
+            order.push(req.package);
+            queue.push_back((req.package, 0));
+        }
+    }
+
+    while let Some((pid, depth)) = queue.pop_front() {
+        if options.max_depth.is_some_and(|max| depth >= max) {
+            continue;
+        }
+
+        let package = repo.try_fetch_package(pid)?;
+        let newest = package.newest_version_number();
+        if newest == 0 {
+            continue;
+        }
+        let first = if options.newest_only { newest } else { 1 };
+
+        let possible = restrictions
+            .get(pid)
+            .map(|restriction| &restriction.versions);
+        for version in first..=newest {
+            if possible.is_some_and(|versions| !versions.contains(version)) {
+                continue;
+            }
+            let ver = &package.versions[version as usize - 1];
+            let deps = ver
+                .requirements
+                .dependencies
+                .iter()
+                .map(|req| (req, EdgeKind::Dependency));
+            let antideps = ver
+                .requirements
+                .conflicts
+                .iter()
+                .filter(|_| !options.skip_conflicts)
+                .map(|req| (req, EdgeKind::Conflict));
+            for (req, kind) in deps.chain(antideps) {
+                if packages.insert(req.package) {
+                    order.push(req.package);
+                    parents.insert(
+                        req.package,
+                        ParentEdge {
+                            parent: pid,
+                            version,
+                            kind,
+                        },
+                    );
+                    queue.push_back((req.package, depth + 1));
+                }
+            }
+        }
+    }
+
+    Ok(Closure {
+        packages,
+        order,
+        parents,
+    })
+}
+
+// The versions of every transitive dependency that a package version may end up
+// selecting, without solving: for each package reachable through dependencies, the union
+// of the existing versions required of it by the versions reachable before it. An empty
+// set means that no existing version satisfies some requirement on the package. Only
+// dependencies are followed, conflicts and formulas are not, and the package itself is
+// absent unless a dependency cycles back to it
+pub fn flatten_requirements<S: PackageSource + ?Sized>(
+    repo: &S,
+    pid: PackageId,
+    version: Version,
+) -> Result<PackageMap<RangeSet>, ResolutionError> {
+    let package = repo.try_fetch_package(pid)?;
+    if version == 0 || version > package.newest_version_number() {
+        return InvalidInputSnafu {
+            message: format!("version {version} of package {pid} does not exist"),
+        }
+        .fail();
+    }
+
+    let mut flattened: PackageMap<RangeSet> = PackageMap::new();
+    let mut pending = vec![(pid, RangeSet::point(version))];
+    while let Some((pid, versions)) = pending.pop() {
+        let package = repo.try_fetch_package(pid)?;
+        for range in versions.iter() {
+            let (lower, upper) = match range {
+                Range::Point(v) => (v, v),
+                Range::Interval { lower, upper } => (lower, upper),
+                Range::All => unreachable!("Impossible: clamped versions are bounded"),
+            };
+            for v in lower..=upper {
+                let ver = &package.versions[v as usize - 1];
+                for req in &ver.requirements.dependencies {
+                    let newest = repo
+                        .newest_ver_of(req.package)
+                        .ok_or_else(|| ResolutionError::unknown_package(req.package))?;
+                    let required = RangeSet::clamped(&req.versions, newest);
+                    let known = flattened.get_or_insert_with(req.package, RangeSet::empty);
+                    // only the versions not reached before have requirements to follow
+                    let new = required.difference(known);
+                    *known = known.union(&required);
+                    if !new.is_empty() {
+                        pending.push((req.package, new));
+                    }
+                }
+            }
+        }
+    }
+    Ok(flattened)
+}
+
+// The installed packages of a plan that are not reachable from the explicitly requested
+// packages through the dependencies of the installed versions, i.e. the packages an
+// autoremove would uninstall. Only dependencies satisfied by the installed version of
+// their target are followed. The orphans are returned sorted
+pub fn compute_orphans<S: PackageSource + ?Sized>(
+    repo: &S,
+    plan: &Plan,
+    explicit: &[PackageId],
+) -> Result<Vec<PackageId>, ResolutionError> {
+    let mut installed = IntMap::new();
+    for (pid, v) in plan.installed() {
+        installed.insert(pid as u64, v);
+    }
+
+    let mut reachable = SetU32::new();
+    let mut pending = explicit
+        .iter()
+        .copied()
+        .filter(|&pid| installed.contains_key(pid as u64))
+        .collect::<Vec<_>>();
+    while let Some(pid) = pending.pop() {
+        if !reachable.insert(pid) {
+            continue;
+        }
+        let version = *installed
+            .get(pid as u64)
+            .expect("Impossible: reachable package is not installed");
+        let package = repo.try_fetch_package(pid)?;
+        let ver = package.versions.get(version as usize - 1).ok_or_else(|| {
+            InvalidInputSnafu {
+                message: format!("version {version} of package {pid} does not exist"),
+            }
+            .build()
+        })?;
+        for req in &ver.requirements.dependencies {
+            let satisfied = installed
+                .get(req.package as u64)
+                .is_some_and(|&v| RangeSet::from(&req.versions).contains(v));
+            if satisfied && !reachable.contains(req.package) {
+                pending.push(req.package);
+            }
+        }
+    }
+
+    let mut orphans = installed
+        .iter()
+        .map(|(&pid, _)| pid as PackageId)
+        .filter(|&pid| !reachable.contains(pid))
+        .collect::<Vec<_>>();
+    orphans.sort_unstable();
+    Ok(orphans)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        options::SolveOptions,
+        solver::optimize_with_stats,
+        testing::repository_from_requirements,
+        types::{Goal, Package, PackageVer, RangeSet, Repository, Requirement, RequirementSet},
+    };
+
+    use super::{
+        closure, compute_closure, compute_orphans, flatten_requirements, restricted_closure,
+        version_restrictions, ClosureOptions, EdgeKind,
+    };
+
+    #[test]
+    fn test_closure_options() {
+        // 0 depends on 1, 1 conflicts with 2, 2 depends on 3
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_antidep(Requirement::any_version(2)),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(3)),
+                }],
+            },
+            Package {
+                id: 3,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+
+        let full = compute_closure(&repo, &reqs, &ClosureOptions::new()).unwrap();
+        assert_eq!(full.order, vec![0, 1, 2, 3]);
+        let encoded = closure(&repo, &reqs).unwrap();
+        assert!(encoded.contains(3) && encoded.len() == 4);
+        assert_eq!(Vec::from(encoded), vec![0, 1, 2, 3]);
+        let path = full.path_to(3);
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[1].kind, EdgeKind::Conflict);
+
+        let shallow = compute_closure(&repo, &reqs, &ClosureOptions::new().max_depth(1)).unwrap();
+        assert_eq!(shallow.order, vec![0, 1]);
+
+        let deps_only =
+            compute_closure(&repo, &reqs, &ClosureOptions::new().skip_conflicts()).unwrap();
+        assert_eq!(deps_only.order, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_restricted_closure() {
+        // version 2 of 0 requires version 1 of 1 and conflicts with version 2 of 3, which
+        // leaves version 1 of 3 required by version 1 of 1
+        let repo = repository_from_requirements(&[
+            &["pkg2", "pkg1 in {1}; !pkg3 in {2}"],
+            &["pkg3", "pkg4"],
+            &[""],
+            &["", "pkg5"],
+            &[""],
+            &[""],
+        ])
+        .unwrap();
+        let reqs: RequirementSet = "pkg0 in {2}".parse().unwrap();
+        let full = compute_closure(&repo, &reqs, &ClosureOptions::new()).unwrap();
+        assert_eq!(full.order, vec![0, 2, 1, 3, 4, 5]);
+        let options = ClosureOptions::new().restrict_versions();
+        let restricted = compute_closure(&repo, &reqs, &options).unwrap();
+        assert_eq!(restricted.order, vec![0, 1, 3]);
+
+        let restrictions = version_restrictions(&repo, &reqs).unwrap();
+        println!("{restrictions:?}");
+        let restriction = restrictions.get(3).unwrap();
+        assert!(restriction.forced);
+        assert_eq!(restriction.versions, RangeSet::point(1));
+        assert!(restrictions.get(2).is_none());
+
+        // the solver encodes the same packages and finds the same plan
+        let loaded = restricted_closure(&repo, &reqs).unwrap();
+        assert_eq!(loaded.packages.keys(), vec![0, 1, 3]);
+        let (expected, full_stats) =
+            optimize_with_stats(&repo, &reqs, Goal::Newest, &SolveOptions::default()).unwrap();
+        let options = SolveOptions::default().restrict_versions();
+        let (res, stats) = optimize_with_stats(&repo, &reqs, Goal::Newest, &options).unwrap();
+        assert_eq!(res, expected);
+        assert_eq!((full_stats.closure_size, stats.closure_size), (6, 3));
+    }
+
+    #[test]
+    fn test_orphans() {
+        // 0 depends on version 2 of 1, 2 depends on 3
+        let empty = || PackageVer {
+            requirements: Default::default(),
+        };
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::single_version(1, 2)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![empty(), empty()],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(3)),
+                }],
+            },
+            Package {
+                id: 3,
+                versions: vec![empty()],
+            },
+        ]);
+        let plan = vec![(0, 1), (1, 2), (2, 1), (3, 1)];
+        assert_eq!(compute_orphans(&repo, &plan, &[0]).unwrap(), vec![2, 3]);
+        assert_eq!(compute_orphans(&repo, &plan, &[0, 2]).unwrap(), vec![]);
+        // the installed version of 1 does not satisfy the dependency of 0
+        let plan = vec![(0, 1), (1, 1)];
+        assert_eq!(compute_orphans(&repo, &plan, &[0]).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_flatten_requirements() {
+        // version 3 of 1 is out of the range required by 0, 0 requires a missing version
+        // of 3
+        let repo = repository_from_requirements(&[
+            &["pkg1 in [1, 2]; pkg3 in {5}"],
+            &["pkg2 in {1}", "pkg2 in {2}", "pkg3"],
+            &["", ""],
+            &[""],
+        ])
+        .unwrap();
+        let flattened = flatten_requirements(&repo, 0, 1).unwrap();
+        println!("{flattened:?}");
+        assert_eq!(flattened.keys(), vec![1, 2, 3]);
+        assert_eq!(flattened.get(1), Some(&RangeSet::interval(1, 2)));
+        assert_eq!(flattened.get(2), Some(&RangeSet::interval(1, 2)));
+        assert!(flattened.get(3).unwrap().is_empty());
+        assert_eq!(
+            flatten_requirements(&repo, 1, 3).unwrap().get(3),
+            Some(&RangeSet::point(1))
+        );
+        assert!(flatten_requirements(&repo, 0, 2).is_err());
+    }
+}
@@ -0,0 +1,141 @@
+// Configuration of the z3 solvers. Unlike z3's global parameters, a configuration only
+// affects the resolutions it is passed to
+use std::thread;
+
+use z3::{Context, Params, Solver};
+
+use crate::internals::encoding::Encoding;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct SolverConfig {
+    // number of threads z3 may use, z3's own configuration is left alone if unset
+    pub threads: Option<u32>,
+    // minimize unsat cores, yields smaller explanations of failures at some cost
+    pub core_minimization: bool,
+    // SMT-LIB logic of the solver, overriding the one implied by the encoding
+    pub logic: Option<String>,
+    // memory limit in megabytes
+    pub memory_limit: Option<u32>,
+    // seed for z3's random number generators
+    pub seed: Option<u32>,
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        Self {
+            threads: None,
+            core_minimization: true,
+            logic: None,
+            memory_limit: None,
+            seed: None,
+        }
+    }
+}
+
+impl SolverConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn threads(mut self, threads: u32) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    // as many threads as the machine has cores
+    pub fn autodetect_threads(self) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| u32::try_from(n.get()).unwrap_or(u32::MAX))
+            .unwrap_or(1);
+        self.threads(threads)
+    }
+
+    pub fn core_minimization(mut self, enable: bool) -> Self {
+        self.core_minimization = enable;
+        self
+    }
+
+    pub fn logic(mut self, logic: impl Into<String>) -> Self {
+        self.logic = Some(logic.into());
+        self
+    }
+
+    pub fn memory_limit(mut self, megabytes: u32) -> Self {
+        self.memory_limit = Some(megabytes);
+        self
+    }
+
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // A solver for the configured logic, falling back to the logic of the encoding if
+    // z3 does not know the configured one
+    pub(crate) fn solver(&self, ctx: &Context, encoding: Encoding) -> Solver<'_> {
+        self.logic
+            .as_deref()
+            .and_then(|logic| Solver::new_for_logic(ctx, logic))
+            .unwrap_or_else(|| encoding.solver(ctx))
+    }
+
+    // Parameters for a solver tracking unsat cores
+    pub(crate) fn solver_params<'ctx>(&self, ctx: &'ctx Context) -> Params<'ctx> {
+        let mut p = Params::new(ctx);
+        p.set_bool("unsat_core", true);
+        p.set_bool("core.minimize", self.core_minimization);
+        self.apply_params(&mut p);
+        p
+    }
+
+    // Parameters for an optimizer, which does not produce unsat cores
+    pub(crate) fn optimizer_params<'ctx>(&self, ctx: &'ctx Context) -> Params<'ctx> {
+        let mut p = Params::new(ctx);
+        self.apply_params(&mut p);
+        p
+    }
+
+    fn apply_params(&self, p: &mut Params) {
+        if let Some(threads) = self.threads {
+            p.set_u32("threads", threads);
+        }
+        if let Some(megabytes) = self.memory_limit {
+            p.set_u32("max_memory", megabytes);
+        }
+        if let Some(seed) = self.seed {
+            p.set_u32("random_seed", seed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        options::SolveOptions,
+        solver::simple_solve_with_options,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet},
+    };
+
+    use super::SolverConfig;
+
+    #[test]
+    fn test_solver_config() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: vec![PackageVer {
+                requirements: RequirementSet::from_antidep(Requirement::any_version(0)),
+            }],
+        }]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let config = SolverConfig::new()
+            .threads(2)
+            .core_minimization(false)
+            .logic("QF_LIA")
+            .memory_limit(1024)
+            .seed(42);
+        let r =
+            simple_solve_with_options(&repo, &reqs, &SolveOptions::new().config(config)).unwrap();
+        println!("{r:?}");
+        assert!(r.is_unsat());
+    }
+}
@@ -1,62 +1,115 @@
 use crate::internals::{
-    types::*,
-    utils::{merge_and_sort_ranges, zero},
+    encoding::Encoder, options::Limits, types::*, utils::merge_and_sort_ranges,
 };
 use bumpalo::Bump;
+use std::{borrow::Cow, cell::Cell};
 use tinyset::SetU32;
 use z3::ast::{Ast, Bool, Int};
 use z3::Context;
 
-pub fn find_closure<'a, T>(repo: &'a Repository, iter: T) -> SetU32
+pub fn find_closure<'a, S, T>(repo: &S, iter: T) -> Result<SetU32, ResolutionError>
 where
+    S: PackageSource + ?Sized,
     T: Iterator<Item = &'a Requirement>,
 {
-    fn go<'a, 'b, T>(repo: &'a Repository, iter: T, acc: &'b mut SetU32)
-    where
-        T: Iterator<Item = &'a Requirement>,
-    {
-        for req in iter {
-            let not_present = acc.insert(req.package);
-            if not_present {
-                let package = repo.packages.get(req.package as usize).unwrap_or_else(|| {
-                    panic!("Illegal index: index {} is out of bound", req.package)
-                });
-                for ver in &package.versions {
-                    go(repo, (&ver.requirements).into_iter(), acc);
-                }
+    walk_closure(repo, iter, |_| ())
+}
+
+// Load the dependency closure of the requirements from a package source, fetching
+// every package in the closure exactly once
+pub fn load_closure<'a, S, T>(source: &S, iter: T) -> Result<PartialRepository, ResolutionError>
+where
+    S: PackageSource + ?Sized,
+    T: Iterator<Item = &'a Requirement>,
+{
+    let mut loaded = PartialRepository::new();
+    loaded.essential_packages = source.essential_packages().into_owned();
+    walk_closure(source, iter, |package| {
+        loaded.insert_from(source, package.into_owned())
+    })?;
+    Ok(loaded)
+}
+
+// Visit every package of the closure, the essential packages included, exactly once
+// and return their ids
+fn walk_closure<'a, 'r, S, T>(
+    repo: &'r S,
+    iter: T,
+    mut visit: impl FnMut(Cow<'r, Package>),
+) -> Result<SetU32, ResolutionError>
+where
+    S: PackageSource + ?Sized,
+    T: Iterator<Item = &'a Requirement>,
+{
+    // an explicit work list instead of recursion, since dependency chains can be
+    // arbitrarily deep
+    let mut s = SetU32::new();
+    let mut pending = iter.map(|req| req.package).collect::<Vec<_>>();
+    pending.extend(repo.essential_packages().iter());
+
+    while let Some(pid) = pending.pop() {
+        if s.insert(pid) {
+            let package = repo.try_fetch_package(pid)?;
+            for ver in &package.versions {
+                pending.extend(
+                    (&ver.requirements)
+                        .into_iter()
+                        .map(|req| req.package)
+                        .filter(|p| !s.contains(*p)),
+                );
             }
+            visit(package);
         }
     }
 
-    let mut s = SetU32::new();
-    go(repo, iter, &mut s);
-    s
+    Ok(s)
 }
 
+// Symbolic constraints are generated first and then translated to z3, this way
+// the symbolic constraints can be cached and replayed without regenerating them
 pub trait AsConstraints {
-    fn add_constraints<'a, 'b>(
-        &self,
-        b: &'b Bump,
-        ctx: &'a Context,
-        expr_cont: impl FnMut(Bool<'a>, Expr<'b>),
-    );
-}
+    fn add_symbolic_constraints<'b>(&self, b: &'b Bump, expr_cont: impl FnMut(Expr<'b>));
 
-impl AsConstraints for Requirement {
     fn add_constraints<'a, 'b>(
         &self,
         b: &'b Bump,
         ctx: &'a Context,
         mut expr_cont: impl FnMut(Bool<'a>, Expr<'b>),
     ) {
-        let v = Int::new_const(ctx, self.package);
-        let mut expr = Bool::from_bool(ctx, false);
+        self.add_symbolic_constraints(b, |sym_expr| {
+            expr_cont(expr_to_z3(ctx, &sym_expr), sym_expr)
+        })
+    }
+}
+
+// Translate a symbolic expression into a z3 expression
+pub fn expr_to_z3<'a>(ctx: &'a Context, expr: &Expr<'_>) -> Bool<'a> {
+    match expr {
+        Expr::Atom(AtomicExpr::VerEq { pid, version }) => {
+            Int::new_const(ctx, *pid)._eq(&Int::from_u64(ctx, *version))
+        }
+        Expr::Atom(AtomicExpr::VerLE { pid, version }) => {
+            Int::new_const(ctx, *pid).le(&Int::from_u64(ctx, *version))
+        }
+        Expr::Atom(AtomicExpr::VerGE { pid, version }) => {
+            Int::new_const(ctx, *pid).ge(&Int::from_u64(ctx, *version))
+        }
+        Expr::Not(e) => expr_to_z3(ctx, e).not(),
+        Expr::And(l, r) => expr_to_z3(ctx, l) & expr_to_z3(ctx, r),
+        Expr::Or(l, r) => expr_to_z3(ctx, l) | expr_to_z3(ctx, r),
+        Expr::Implies(l, r) => expr_to_z3(ctx, l).implies(&expr_to_z3(ctx, r)),
+        Expr::Bot => Bool::from_bool(ctx, false),
+        Expr::Top => Bool::from_bool(ctx, true),
+    }
+}
+
+impl AsConstraints for Requirement {
+    fn add_symbolic_constraints<'b>(&self, b: &'b Bump, mut expr_cont: impl FnMut(Expr<'b>)) {
         let mut sym_expr = Expr::bot();
 
         for r in merge_and_sort_ranges(self.versions.as_vec()) {
             match r {
                 Range::Interval { lower, upper } => {
-                    expr |= v.ge(&Int::from_u64(ctx, lower)) & v.le(&Int::from_u64(ctx, upper));
                     let range_expr = Expr::and(
                         b,
                         Expr::Atom(AtomicExpr::ver_ge(self.package, lower)),
@@ -70,7 +123,6 @@ impl AsConstraints for Requirement {
                     }
                 }
                 Range::Point(v2) => {
-                    expr |= v._eq(&Int::from_u64(ctx, v2));
                     let point_expr = Expr::Atom(AtomicExpr::ver_eq(self.package, v2));
 
                     if sym_expr == Expr::Bot {
@@ -80,84 +132,356 @@ impl AsConstraints for Requirement {
                     }
                 }
                 Range::All => {
-                    expr = v._eq(&zero(ctx)).not();
                     sym_expr = Expr::not(b, Expr::Atom(AtomicExpr::ver_eq(self.package, 0)));
                     break;
                 }
             }
         }
 
-        expr_cont(expr, sym_expr)
+        expr_cont(sym_expr)
     }
 }
 
 impl AsConstraints for RequirementSet {
-    fn add_constraints<'a, 'b>(
-        &self,
-        b: &'b Bump,
-        ctx: &'a Context,
-        mut expr_cont: impl FnMut(Bool<'a>, Expr<'b>),
-    ) {
+    fn add_symbolic_constraints<'b>(&self, b: &'b Bump, mut expr_cont: impl FnMut(Expr<'b>)) {
         for dep in &self.dependencies {
-            dep.add_constraints(b, ctx, &mut expr_cont)
+            dep.add_symbolic_constraints(b, &mut expr_cont)
         }
-        let mut reversed_cont =
-            |expr: Bool<'a>, sym_expr| expr_cont(expr.not(), Expr::not(b, sym_expr));
+        let mut reversed_cont = |sym_expr| expr_cont(Expr::not(b, sym_expr));
         for antidep in &self.conflicts {
-            antidep.add_constraints(b, ctx, &mut reversed_cont)
+            antidep.add_symbolic_constraints(b, &mut reversed_cont)
+        }
+        if let Some(formula) = &self.formula {
+            expr_cont(formula.to_expr(b))
         }
     }
 }
 
 impl AsConstraints for Package {
-    fn add_constraints<'a, 'b>(
-        &self,
-        b: &'b Bump,
-        ctx: &'a Context,
-        mut expr_cont: impl FnMut(Bool<'a>, Expr<'b>),
-    ) {
-        let package = Int::new_const(ctx, self.id);
-        expr_cont(
-            package.ge(&zero(ctx)),
-            Expr::Atom(AtomicExpr::ver_ge(self.id, 0)),
-        );
-
-        let mut ver_counter = 0;
-        for ver in &self.versions {
-            ver_counter += 1;
-            let ver_number = Int::from_u64(ctx, ver_counter);
-            let eq_expr = package._eq(&ver_number);
-            let mut modified_cont = |expr, sym_expr| {
-                expr_cont(
-                    eq_expr.implies(&expr),
-                    Expr::implies(
-                        b,
-                        Expr::Atom(AtomicExpr::ver_eq(self.id, ver_counter)),
-                        sym_expr,
-                    ),
+    fn add_symbolic_constraints<'b>(&self, b: &'b Bump, mut expr_cont: impl FnMut(Expr<'b>)) {
+        expr_cont(Expr::Atom(AtomicExpr::ver_ge(self.id, 0)));
+
+        // runs of consecutive versions with the same requirements share a single guard
+        // over the interval they span, instead of one implication per version
+        let mut lower = 1;
+        for (upper, ver) in (1..).zip(&self.versions) {
+            let next = self.versions.get(upper as usize);
+            if next.is_some_and(|next| next.requirements == ver.requirements) {
+                continue;
+            }
+            let guard = if lower == upper {
+                Expr::Atom(AtomicExpr::ver_eq(self.id, upper))
+            } else {
+                Expr::and(
+                    b,
+                    Expr::Atom(AtomicExpr::ver_ge(self.id, lower)),
+                    Expr::Atom(AtomicExpr::ver_le(self.id, upper)),
                 )
             };
-            ver.requirements.add_constraints(b, ctx, &mut modified_cont);
+            let mut modified_cont = |sym_expr| expr_cont(Expr::implies(b, guard.clone(), sym_expr));
+            ver.requirements
+                .add_symbolic_constraints(b, &mut modified_cont);
+            lower = upper + 1;
         }
 
-        expr_cont(
-            package.le(&Int::from_u64(ctx, ver_counter)),
-            Expr::Atom(AtomicExpr::ver_le(self.id, ver_counter)),
-        );
+        expr_cont(Expr::Atom(AtomicExpr::ver_le(
+            self.id,
+            self.versions.len() as Version,
+        )));
     }
 }
 
-pub fn add_all_constraints<'a, 'b>(
+// Where a constraint comes from, toplevel requirements are numbered with the
+// dependencies first, then the conflicts, then the formula
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Origin {
+    Package(PackageId),
+    Toplevel(usize),
+    // the requirement of an essential package
+    Essential(PackageId),
+    // a constraint of the solve options, tracked along with the placeholder `Expr::Top`
+    Restriction(Restriction),
+}
+
+pub fn add_all_constraints<'a, 'b, S: PackageSource + ?Sized>(
     b: &'b Bump,
-    ctx: &'a Context,
-    repo: &Repository,
+    encoder: &Encoder<'a>,
+    repo: &'b S,
     pids: impl Iterator<Item = u32>,
     requirements: &RequirementSet,
-    mut expr_cont: impl FnMut(Bool<'a>, Expr<'b>),
+    expr_cont: impl FnMut(Bool<'a>, Expr<'b>, Origin),
 ) {
+    add_all_constraints_within(
+        b,
+        encoder,
+        repo,
+        pids,
+        requirements,
+        &Limits::default(),
+        |_| Ok(()),
+        expr_cont,
+    )
+    .expect("Impossible: there are no limits to exceed")
+}
+
+// The packages encoded between two calls of the chunk callback of
+// `add_all_constraints_within`
+pub const ENCODING_CHUNK: usize = 512;
+
+// The same within limits, failing as soon as an assertion takes the constraints beyond
+// them. Nothing is translated past that assertion. The arena only counts what it
+// allocates from the call on, so that an arena reused across resolutions is not charged
+// for the earlier ones. `after_chunk` is called with the number of packages encoded so
+// far after each chunk of `ENCODING_CHUNK` packages, generation stops at the first error
+// it returns, e.g. when the resolution was cancelled
+#[allow(clippy::too_many_arguments)]
+pub fn add_all_constraints_within<'a, 'b, S: PackageSource + ?Sized>(
+    b: &'b Bump,
+    encoder: &Encoder<'a>,
+    repo: &'b S,
+    pids: impl Iterator<Item = u32>,
+    requirements: &RequirementSet,
+    limits: &Limits,
+    mut after_chunk: impl FnMut(usize) -> Result<(), ResolutionError>,
+    mut expr_cont: impl FnMut(Bool<'a>, Expr<'b>, Origin),
+) -> Result<(), ResolutionError> {
+    let baseline = b.allocated_bytes();
+    let exceeded = Cell::new(None);
+    let mut assertions = 0;
+    let mut stopped = false;
+    let mut add = |sym_expr: Expr<'b>, origin| {
+        if stopped {
+            return;
+        }
+        assertions += 1;
+        match limits.check_constraints(assertions, b.allocated_bytes() - baseline) {
+            Ok(()) => expr_cont(encoder.translate(&sym_expr), sym_expr, origin),
+            Err(e) => {
+                stopped = true;
+                exceeded.set(Some(e));
+            }
+        }
+    };
+    let mut encoded = 0;
     for pid in pids {
-        let package = repo.get_package_unchecked(pid);
-        package.add_constraints(b, ctx, &mut expr_cont);
+        repo.package_constraints(b, pid, &mut |sym_expr| add(sym_expr, Origin::Package(pid)));
+        if let Some(e) = exceeded.take() {
+            return Err(e);
+        }
+        encoded += 1;
+        if encoded % ENCODING_CHUNK == 0 {
+            after_chunk(encoded)?;
+        }
+    }
+    if encoded % ENCODING_CHUNK != 0 {
+        after_chunk(encoded)?;
+    }
+    add_essential_constraints(b, repo, |sym_expr, pid| {
+        add(sym_expr, Origin::Essential(pid))
+    });
+    add_toplevel_symbolic_constraints(b, repo, requirements, &mut add);
+    exceeded.take().map_or(Ok(()), Err)
+}
+
+// One constraint per essential package, requiring some version of it
+pub fn add_essential_constraints<'b, S: PackageSource + ?Sized>(
+    b: &'b Bump,
+    repo: &S,
+    mut expr_cont: impl FnMut(Expr<'b>, PackageId),
+) {
+    for &pid in repo.essential_packages().iter() {
+        Requirement::any_version(pid)
+            .add_symbolic_constraints(b, |sym_expr| expr_cont(sym_expr, pid));
+    }
+}
+
+// The requirements are normalized against the source one at a time, so that the index
+// of each constraint is still the index of its requirement
+pub fn add_toplevel_constraints<'a, 'b, S: PackageSource + ?Sized>(
+    b: &'b Bump,
+    encoder: &Encoder<'a>,
+    repo: &S,
+    requirements: &RequirementSet,
+    mut expr_cont: impl FnMut(Bool<'a>, Expr<'b>, Origin),
+) {
+    add_toplevel_symbolic_constraints(b, repo, requirements, |sym_expr, origin| {
+        expr_cont(encoder.translate(&sym_expr), sym_expr, origin)
+    });
+}
+
+pub(crate) fn add_toplevel_symbolic_constraints<'b, S: PackageSource + ?Sized>(
+    b: &'b Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    mut expr_cont: impl FnMut(Expr<'b>, Origin),
+) {
+    // every requirement generates exactly one constraint
+    let mut index = 0;
+    requirements
+        .normalized_each(repo)
+        .add_symbolic_constraints(b, |sym_expr| {
+            expr_cont(sym_expr, Origin::Toplevel(index));
+            index += 1;
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use bumpalo::Bump;
+    use z3::{
+        ast::{Ast, Bool},
+        Context, SatResult, Solver,
+    };
+
+    use crate::internals::{
+        encoding::{Encoder, Encoding},
+        options::Limits,
+        solver::simple_solve,
+        types::ResolutionError,
+        types::{
+            AtomicExpr, Expr, Package, PackageVer, Repository, Requirement, RequirementSet, Version,
+        },
+        utils::default_config,
+    };
+
+    use super::{add_all_constraints_within, find_closure, load_closure, AsConstraints};
+
+    #[test]
+    fn test_deep_closure() {
+        // a linear chain 0 -> 1 -> ... -> n - 1, deep enough to overflow the stack
+        // if the closure was computed recursively
+        let n = 100_000;
+        let packages = (0..n)
+            .map(|pid| {
+                let requirements = if pid + 1 < n {
+                    RequirementSet::from_dep(Requirement::any_version(pid + 1))
+                } else {
+                    Default::default()
+                };
+                Package {
+                    id: pid,
+                    versions: vec![PackageVer { requirements }],
+                }
+            })
+            .collect();
+        let repo = Repository::new(packages);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let closure = find_closure(&repo, reqs.dependencies.iter()).unwrap();
+        assert_eq!(closure.len(), n as usize);
+    }
+
+    #[test]
+    fn test_limits_within() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: vec![PackageVer {
+                requirements: Default::default(),
+            }],
+        }]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let ctx = Context::new(&default_config());
+        let encoder = Encoder::new(&ctx, Encoding::Int, &repo, 0..1);
+        // the memory allocated by earlier resolutions is not counted
+        let b = Bump::new();
+        b.alloc([0u8; 1 << 16]);
+        let encode = |limits: &Limits| {
+            let mut assertions = 0;
+            let res = add_all_constraints_within(
+                &b,
+                &encoder,
+                &repo,
+                0..1,
+                &reqs,
+                limits,
+                |_| Ok(()),
+                |_, _, _| assertions += 1,
+            );
+            res.map(|()| assertions)
+        };
+        // the bounds of the package and the toplevel requirement
+        assert_eq!(encode(&Limits::new().max_arena_bytes(1 << 12)), Ok(3));
+        let e = encode(&Limits::new().max_assertions(2)).unwrap_err();
+        assert!(matches!(e, ResolutionError::LimitExceeded { value: 3, .. }));
+    }
+
+    #[test]
+    fn test_load_closure_masks() {
+        // 0 depends on 1 whose only version is masked, 2 is outside of the closure
+        let packages = (0..3)
+            .map(|pid| Package {
+                id: pid,
+                versions: vec![PackageVer {
+                    requirements: if pid == 0 {
+                        RequirementSet::from_dep(Requirement::any_version(1))
+                    } else {
+                        Default::default()
+                    },
+                }],
+            })
+            .collect();
+        let mut repo = Repository::new(packages);
+        repo.mask_version(1, 1);
+        repo.mask_version(2, 1);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let loaded = load_closure(&repo, reqs.dependencies.iter()).unwrap();
+        assert_eq!(loaded.masks, vec![Requirement::single_version(1, 1)]);
+        assert!(simple_solve(&loaded, &reqs).unwrap().is_unsat());
+    }
+
+    #[test]
+    fn test_grouped_versions() {
+        let dep = |pid| PackageVer {
+            requirements: RequirementSet::from_dep(Requirement::any_version(pid)),
+        };
+        let package = Package {
+            id: 0,
+            versions: vec![dep(1), dep(1), dep(1), dep(2), dep(2), dep(1)],
+        };
+        let repo = Repository::new(vec![
+            package.clone(),
+            Package {
+                id: 1,
+                versions: vec![dep(2)],
+            },
+            Package {
+                id: 2,
+                versions: vec![dep(1)],
+            },
+        ]);
+        let b = Bump::new();
+        let ctx = Context::new(&default_config());
+        let encoder = Encoder::new(&ctx, Encoding::Int, &repo, 0..3);
+
+        let mut grouped = Vec::new();
+        package.add_symbolic_constraints(&b, |sym_expr| grouped.push(sym_expr));
+        println!("{grouped:?}");
+        // the bounds and one implication per run of versions
+        assert_eq!(grouped.len(), 5);
+
+        // one implication per version
+        let mut naive = vec![
+            Expr::Atom(AtomicExpr::ver_ge(0, 0)),
+            Expr::Atom(AtomicExpr::ver_le(0, 6)),
+        ];
+        for (v, ver) in (1..).zip(&package.versions) {
+            ver.requirements.add_symbolic_constraints(&b, |sym_expr| {
+                naive.push(Expr::implies(
+                    &b,
+                    Expr::Atom(AtomicExpr::ver_eq(0, v as Version)),
+                    sym_expr,
+                ))
+            });
+        }
+
+        let conjunction = |exprs: &[Expr]| {
+            let exprs = exprs
+                .iter()
+                .map(|e| encoder.translate(e))
+                .collect::<Vec<_>>();
+            Bool::and(&ctx, &exprs.iter().collect::<Vec<_>>())
+        };
+        let solver = Solver::new(&ctx);
+        solver.assert(&conjunction(&grouped)._eq(&conjunction(&naive)).not());
+        assert_eq!(solver.check(), SatResult::Unsat);
     }
-    requirements.add_constraints(b, ctx, &mut expr_cont);
 }
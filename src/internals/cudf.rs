@@ -0,0 +1,591 @@
+// Import of CUDF documents, the package upgrade format of the Mancoosi project used by
+// solver competitions, and export of plans as CUDF solutions. The versions of a CUDF
+// package are mapped to consecutive versions starting from 1. Dependencies with several
+// alternatives, either written as disjunctions or satisfied by the providers of a
+// virtual package, are encoded with auxiliary packages that have one version per
+// alternative, version i depending on alternative i
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    io::{self, Write},
+};
+
+use indexmap::{map::Entry, IndexMap};
+use snafu::ResultExt;
+
+use crate::internals::types::*;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct CudfDocument {
+    pub repository: Repository,
+    // the requirements of the request stanza
+    pub requirements: RequirementSet,
+    // the versions marked as installed
+    pub installed: Plan,
+    // the CUDF versions of every package, indexed by package id. Auxiliary packages come
+    // after the packages of the document
+    packages: IndexMap<String, Vec<u64>>,
+    real: usize,
+}
+
+impl CudfDocument {
+    pub fn parse(input: &str) -> Result<Self, ResolutionError> {
+        let mut raw = Vec::new();
+        let mut request = None;
+        for stanza in stanzas(input)? {
+            match stanza.fields[0].0.as_str() {
+                "preamble" => {}
+                "package" => raw.push(RawPackage::new(&stanza)?),
+                "request" => {
+                    if request.replace(stanza).is_some() {
+                        return Err(invalid(0, "more than one request stanza"));
+                    }
+                }
+                key => return Err(invalid(stanza.line, format!("unknown stanza `{key}`"))),
+            }
+        }
+
+        let mut packages = IndexMap::<String, Vec<u64>>::new();
+        for p in &raw {
+            packages.entry(p.name.clone()).or_default().push(p.version);
+        }
+        for (name, versions) in &mut packages {
+            versions.sort_unstable();
+            if let Some(w) = versions.windows(2).find(|w| w[0] == w[1]) {
+                let message = format!("version {} of package `{name}` is defined twice", w[0]);
+                return Err(invalid(0, message));
+            }
+        }
+        let real = packages.len();
+        let id_of = |p: &RawPackage| {
+            let (pid, _, versions) = packages.get_full(&p.name).unwrap();
+            let v = versions.binary_search(&p.version).unwrap() + 1;
+            (pid as PackageId, v as Version)
+        };
+
+        let mut universe = Universe {
+            packages: &packages,
+            real,
+            provides: HashMap::new(),
+            aux: IndexMap::new(),
+        };
+        let mut installed = Vec::new();
+        for p in &raw {
+            let (pid, v) = id_of(p);
+            for vpkg in list(&p.provides, p.line)? {
+                let provided = match vpkg.constraint {
+                    None => None,
+                    Some((Op::Eq, k)) => Some(k),
+                    Some(_) => {
+                        let message =
+                            format!("only equality can be provided, found `{}`", p.provides);
+                        return Err(invalid(p.line, message));
+                    }
+                };
+                universe
+                    .provides
+                    .entry(vpkg.name.to_string())
+                    .or_default()
+                    .push((pid, v, provided));
+            }
+            if p.installed {
+                installed.push((pid, v));
+            }
+        }
+        installed.sort_unstable();
+
+        let mut versions = packages
+            .values()
+            .map(|versions| {
+                let empty = PackageVer {
+                    requirements: Default::default(),
+                };
+                vec![empty; versions.len()]
+            })
+            .collect::<Vec<_>>();
+        for p in &raw {
+            let (pid, v) = id_of(p);
+            let mut requirements = RequirementSet::default();
+            for (text, vpkgs) in formula(&p.depends, p.line)? {
+                let alternatives = universe.alternatives(&vpkgs);
+                requirements.add_dep(universe.requirement(alternatives, text));
+            }
+            for vpkg in list(&p.conflicts, p.line)? {
+                // a package never conflicts with itself
+                let mut alternatives = universe.alternatives(&[vpkg]);
+                for (other, set) in &mut alternatives {
+                    if *other == pid {
+                        *set = set.difference(&RangeSet::point(v));
+                    }
+                }
+                requirements.add_antideps(conflicts(alternatives));
+            }
+            versions[pid as usize][v as usize - 1].requirements = requirements;
+        }
+
+        let mut requirements = RequirementSet::default();
+        if let Some(request) = request {
+            for (key, value) in &request.fields[1..] {
+                match key.as_str() {
+                    "install" => {
+                        for text in value.split(',').filter(|s| !s.trim().is_empty()) {
+                            let vpkg = Vpkg::parse(text, request.line)?;
+                            let alternatives = universe.alternatives(&[vpkg]);
+                            requirements.add_dep(universe.requirement(alternatives, text.trim()));
+                        }
+                    }
+                    "remove" => {
+                        for vpkg in list(value, request.line)? {
+                            requirements.add_antideps(conflicts(universe.alternatives(&[vpkg])));
+                        }
+                    }
+                    // only the package itself can satisfy an upgrade, at a version no
+                    // older than the newest installed one
+                    "upgrade" => {
+                        for text in value.split(',').filter(|s| !s.trim().is_empty()) {
+                            let vpkg = Vpkg::parse(text, request.line)?;
+                            let pid = packages.get_index_of(vpkg.name).map(|i| i as PackageId);
+                            let oldest = installed
+                                .iter()
+                                .filter(|&&(other, _)| Some(other) == pid)
+                                .map(|&(_, v)| v)
+                                .max()
+                                .unwrap_or(1);
+                            let alternatives = universe
+                                .alternatives(&[vpkg])
+                                .into_iter()
+                                .filter(|&(other, _)| Some(other) == pid)
+                                .map(|(other, set)| {
+                                    (
+                                        other,
+                                        set.intersection(&RangeSet::interval(oldest, Version::MAX)),
+                                    )
+                                })
+                                .filter(|(_, set)| !set.is_empty())
+                                .collect();
+                            requirements.add_dep(universe.requirement(alternatives, text.trim()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let Universe { aux, .. } = universe;
+        let mut repository = Repository::new(
+            versions
+                .into_iter()
+                .enumerate()
+                .map(|(pid, versions)| Package {
+                    id: pid as PackageId,
+                    versions,
+                })
+                .collect(),
+        );
+        for (i, (alternatives, text)) in aux.into_iter().enumerate() {
+            let versions = alternatives
+                .into_iter()
+                .map(|(pid, set)| PackageVer {
+                    requirements: RequirementSet::from_dep(dependency(pid, &set)),
+                })
+                .collect::<Vec<_>>();
+            packages.insert(
+                format!("aux {i}: {text}"),
+                (1..=versions.len() as u64).collect(),
+            );
+            repository.packages_mut().push(Package {
+                id: (real + i) as PackageId,
+                versions,
+            });
+        }
+
+        Ok(Self {
+            repository,
+            requirements,
+            installed,
+            packages,
+            real,
+        })
+    }
+
+    // The id of a package of the document
+    pub fn package_id(&self, name: &str) -> Option<PackageId> {
+        self.packages
+            .get_index_of(name)
+            .filter(|&i| i < self.real)
+            .map(|i| i as PackageId)
+    }
+
+    pub fn package_name(&self, pid: PackageId) -> Option<&str> {
+        self.packages
+            .get_index(pid as usize)
+            .map(|(name, _)| name.as_str())
+    }
+
+    // Whether the package is an auxiliary package rather than one of the document
+    pub fn is_auxiliary(&self, pid: PackageId) -> bool {
+        pid as usize >= self.real
+    }
+
+    // The CUDF version of a version
+    pub fn cudf_version(&self, pid: PackageId, version: Version) -> Option<u64> {
+        let (_, versions) = self.packages.get_index(pid as usize)?;
+        versions.get(version.checked_sub(1)? as usize).copied()
+    }
+
+    // The version of a CUDF version
+    pub fn version(&self, pid: PackageId, cudf_version: u64) -> Option<Version> {
+        let (_, versions) = self.packages.get_index(pid as usize)?;
+        let i = versions.binary_search(&cudf_version).ok()?;
+        Some(i as Version + 1)
+    }
+
+    // The CUDF solution of a plan, listing the installed packages of the document
+    pub fn solution(&self, plan: &Plan) -> String {
+        let mut out = Vec::new();
+        self.write_solution(plan, &mut out)
+            .expect("Impossible: failed to write to memory");
+        String::from_utf8(out).expect("Impossible: CUDF output is not valid UTF-8")
+    }
+
+    pub fn write_solution<W: Write>(
+        &self,
+        plan: &Plan,
+        out: &mut W,
+    ) -> Result<(), ResolutionError> {
+        self.write_stanzas(plan, out).context(IoSnafu)
+    }
+
+    fn write_stanzas<W: Write>(&self, plan: &Plan, out: &mut W) -> io::Result<()> {
+        for &(pid, v) in plan {
+            if self.is_auxiliary(pid) {
+                continue;
+            }
+            if let (Some(name), Some(version)) = (self.package_name(pid), self.cudf_version(pid, v))
+            {
+                writeln!(out, "package: {name}")?;
+                writeln!(out, "version: {version}")?;
+                writeln!(out, "installed: true")?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn invalid(line: usize, message: impl Display) -> ResolutionError {
+    let message = if line == 0 {
+        format!("CUDF: {message}")
+    } else {
+        format!("CUDF line {line}: {message}")
+    };
+    InvalidInputSnafu { message }.build()
+}
+
+struct Stanza {
+    line: usize,
+    fields: Vec<(String, String)>,
+}
+
+fn stanzas(input: &str) -> Result<Vec<Stanza>, ResolutionError> {
+    let mut stanzas = Vec::new();
+    let mut current: Option<Stanza> = None;
+    for (n, line) in (1..).zip(input.lines()) {
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.trim().is_empty() {
+            stanzas.extend(current.take());
+            continue;
+        }
+        // continuation of the previous property
+        if line.starts_with(' ') {
+            match current.as_mut().and_then(|s| s.fields.last_mut()) {
+                Some((_, value)) => {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                None => return Err(invalid(n, "continuation line outside of a stanza")),
+            }
+            continue;
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| invalid(n, format!("expected a property, found `{line}`")))?;
+        current
+            .get_or_insert_with(|| Stanza {
+                line: n,
+                fields: Vec::new(),
+            })
+            .fields
+            .push((key.trim().to_string(), value.trim().to_string()));
+    }
+    stanzas.extend(current);
+    Ok(stanzas)
+}
+
+struct RawPackage {
+    line: usize,
+    name: String,
+    version: u64,
+    depends: String,
+    conflicts: String,
+    provides: String,
+    installed: bool,
+}
+
+impl RawPackage {
+    fn new(stanza: &Stanza) -> Result<Self, ResolutionError> {
+        let get = |key: &str| {
+            stanza
+                .fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        let name = get("package").unwrap_or_default();
+        let version = get("version")
+            .ok_or_else(|| invalid(stanza.line, format!("package `{name}` has no version")))?
+            .parse::<u64>()
+            .ok()
+            .filter(|&v| v > 0)
+            .ok_or_else(|| invalid(stanza.line, format!("ill-formed version of `{name}`")))?;
+        Ok(Self {
+            line: stanza.line,
+            name: name.to_string(),
+            version,
+            depends: get("depends").unwrap_or_default().to_string(),
+            conflicts: get("conflicts").unwrap_or_default().to_string(),
+            provides: get("provides").unwrap_or_default().to_string(),
+            installed: get("installed") == Some("true"),
+        })
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+enum Op {
+    Eq,
+    Neq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+}
+
+// A package name with an optional version constraint
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+struct Vpkg<'a> {
+    name: &'a str,
+    constraint: Option<(Op, u64)>,
+}
+
+impl<'a> Vpkg<'a> {
+    fn parse(s: &'a str, line: usize) -> Result<Self, ResolutionError> {
+        let s = s.trim();
+        let split = s.find(['=', '!', '<', '>']).unwrap_or(s.len());
+        let (name, rest) = (s[..split].trim(), s[split..].trim());
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(invalid(line, format!("ill-formed package `{s}`")));
+        }
+        if rest.is_empty() {
+            return Ok(Self {
+                name,
+                constraint: None,
+            });
+        }
+        let ops = [
+            ("!=", Op::Neq),
+            (">=", Op::Ge),
+            ("<=", Op::Le),
+            ("=", Op::Eq),
+            (">", Op::Gt),
+            ("<", Op::Lt),
+        ];
+        let (op, version) = ops
+            .into_iter()
+            .find_map(|(prefix, op)| rest.strip_prefix(prefix).map(|v| (op, v)))
+            .and_then(|(op, v)| Some((op, v.trim().parse::<u64>().ok()?)))
+            .ok_or_else(|| invalid(line, format!("ill-formed constraint in `{s}`")))?;
+        Ok(Self {
+            name,
+            constraint: Some((op, version)),
+        })
+    }
+
+    fn matches(&self, version: u64) -> bool {
+        match self.constraint {
+            None => true,
+            Some((Op::Eq, k)) => version == k,
+            Some((Op::Neq, k)) => version != k,
+            Some((Op::Ge, k)) => version >= k,
+            Some((Op::Gt, k)) => version > k,
+            Some((Op::Le, k)) => version <= k,
+            Some((Op::Lt, k)) => version < k,
+        }
+    }
+}
+
+fn list(s: &str, line: usize) -> Result<Vec<Vpkg<'_>>, ResolutionError> {
+    s.split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| Vpkg::parse(s, line))
+        .collect()
+}
+
+// A conjunction of disjunctions, each disjunction paired with its text
+fn formula(s: &str, line: usize) -> Result<Vec<(&str, Vec<Vpkg<'_>>)>, ResolutionError> {
+    let mut conjuncts = Vec::new();
+    for text in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match text {
+            "true!" => {}
+            "false!" => conjuncts.push((text, Vec::new())),
+            _ => conjuncts.push((
+                text,
+                text.split('|')
+                    .map(|s| Vpkg::parse(s, line))
+                    .collect::<Result<_, _>>()?,
+            )),
+        }
+    }
+    Ok(conjuncts)
+}
+
+fn dependency(pid: PackageId, set: &RangeSet) -> Requirement {
+    let versions = set
+        .to_ranges()
+        .expect("Impossible: empty alternatives are dropped");
+    Requirement::new(pid, versions)
+}
+
+fn conflicts(alternatives: Vec<(PackageId, RangeSet)>) -> Vec<Requirement> {
+    alternatives
+        .into_iter()
+        .filter(|(_, set)| !set.is_empty())
+        .map(|(pid, set)| dependency(pid, &set))
+        .collect()
+}
+
+struct Universe<'a> {
+    packages: &'a IndexMap<String, Vec<u64>>,
+    real: usize,
+    // the providers of every virtual package, with the provided version if any
+    provides: HashMap<String, Vec<(PackageId, Version, Option<u64>)>>,
+    // auxiliary packages, keyed by their alternatives
+    aux: IndexMap<Vec<(PackageId, RangeSet)>, String>,
+}
+
+impl Universe<'_> {
+    // The versions satisfying any of the packages, grouped by package
+    fn alternatives(&self, vpkgs: &[Vpkg]) -> Vec<(PackageId, RangeSet)> {
+        let mut alternatives = BTreeMap::<PackageId, RangeSet>::new();
+        let mut add = |pid: PackageId, v: Version| {
+            let set = alternatives.entry(pid).or_default();
+            *set = set.union(&RangeSet::point(v));
+        };
+        for vpkg in vpkgs {
+            if let Some((pid, _, versions)) = self.packages.get_full(vpkg.name) {
+                for (v, &k) in (1..).zip(versions) {
+                    if vpkg.matches(k) {
+                        add(pid as PackageId, v);
+                    }
+                }
+            }
+            for &(pid, v, provided) in self.provides.get(vpkg.name).into_iter().flatten() {
+                // providing a package without a version provides every version of it
+                if provided.map_or(true, |k| vpkg.matches(k)) {
+                    add(pid, v);
+                }
+            }
+        }
+        alternatives.into_iter().collect()
+    }
+
+    // A requirement satisfied by any of the alternatives, which needs an auxiliary
+    // package unless there is exactly one package to choose from. Without any
+    // alternative this is a dependency on an auxiliary package without versions
+    fn requirement(&mut self, alternatives: Vec<(PackageId, RangeSet)>, text: &str) -> Requirement {
+        if let [(pid, set)] = alternatives.as_slice() {
+            return dependency(*pid, set);
+        }
+        let i = match self.aux.entry(alternatives) {
+            Entry::Occupied(e) => e.index(),
+            Entry::Vacant(e) => {
+                let i = e.index();
+                e.insert(text.to_string());
+                i
+            }
+        };
+        Requirement::any_version((self.real + i) as PackageId)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{solver::simple_solve, types::ResolutionResult};
+
+    use super::CudfDocument;
+
+    const DOCUMENT: &str = "\
+preamble:
+property: suite: enum(stable,unstable) = \"stable\"
+
+package: libc
+version: 1
+installed: true
+
+package: libc
+version: 3
+
+package: app
+version: 2
+depends: libc >= 2, mta | sendmail
+conflicts: oldapp
+
+package: postfix
+version: 1
+provides: mta
+
+package: exim
+version: 4
+provides: mta = 2
+conflicts: postfix, mta
+
+package: oldapp
+version: 1
+installed: true
+
+request: test
+install: app
+remove: oldapp
+";
+
+    #[test]
+    fn test_cudf() {
+        let doc = CudfDocument::parse(DOCUMENT).unwrap();
+        println!("{:?}", doc.repository);
+        let libc = doc.package_id("libc").unwrap();
+        assert_eq!(doc.version(libc, 3), Some(2));
+        assert_eq!(doc.cudf_version(libc, 1), Some(1));
+        assert_eq!(
+            doc.installed,
+            vec![(libc, 1), (doc.package_id("oldapp").unwrap(), 1)]
+        );
+        // the alternatives of `mta | sendmail` are postfix and exim
+        assert_eq!(doc.repository.packages().len(), 6);
+        assert!(doc.is_auxiliary(5));
+
+        let r = simple_solve(&doc.repository, &doc.requirements).unwrap();
+        println!("{r:?}");
+        let ResolutionResult::Sat { plans, .. } = r else {
+            panic!("expected a plan")
+        };
+        let solution = doc.solution(&plans.as_vec()[0]);
+        println!("{solution}");
+        assert!(solution.contains("package: app\nversion: 2\n"));
+        assert!(solution.contains("package: libc\nversion: 3\n"));
+        assert!(!solution.contains("oldapp"));
+        assert!(!solution.contains("aux"));
+
+        assert!(CudfDocument::parse("package: a\nversion: x\n").is_err());
+    }
+}
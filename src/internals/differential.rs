@@ -0,0 +1,309 @@
+// Differential testing of resolution backends, so that users adding a backend of their own
+// can validate it against those of the library in property tests. Every backend resolves
+// the same problem, typically a generated `ResolutionProblem`; every plan they return must
+// satisfy the constraints of the problem, and they must agree on whether the requirements
+// are satisfiable and on the objective values of their plans. The values are computed from
+// the plans, whatever the backends report. Backends are expected to optimize towards
+// `Goal::Newest` like those of `encoding_backends`, the objective values of optimal plans
+// being unique while the plans are not. Enabled by the `arbitrary` feature
+use std::fmt::{self, Display, Formatter};
+
+use bumpalo::Bump;
+
+use crate::internals::{
+    constraints::{add_essential_constraints, add_toplevel_symbolic_constraints},
+    encoding::Encoding,
+    options::SolveOptions,
+    resolver::{NewestResolver, Resolver},
+    types::*,
+};
+
+pub struct Backend<'a> {
+    pub name: String,
+    pub resolver: Box<dyn Resolver + 'a>,
+}
+
+impl<'a> Backend<'a> {
+    pub fn new(name: impl Into<String>, resolver: impl Resolver + 'a) -> Self {
+        Self {
+            name: name.into(),
+            resolver: Box::new(resolver),
+        }
+    }
+}
+
+// The optimizer of the library with each of the encodings. All the backends of the library
+// solve through z3, there is no native one to compare them with: native backends are the
+// ones users bring, checked against these
+pub fn encoding_backends() -> Vec<Backend<'static>> {
+    [
+        ("int", Encoding::Int),
+        ("bool", Encoding::Bool),
+        ("bitvec", Encoding::BitVec),
+    ]
+    .into_iter()
+    .map(|(name, encoding)| {
+        let options = SolveOptions::deterministic(0).encoding(encoding);
+        Backend::new(name, NewestResolver::new(options))
+    })
+    .collect()
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Disagreement {
+    // a backend failed to resolve the problem
+    Failed {
+        backend: String,
+        error: String,
+    },
+    // a backend returned a plan violating the constraints of the problem
+    Invalid {
+        backend: String,
+        plan: Plan,
+    },
+    // one backend found a plan and the other did not
+    Satisfiability {
+        sat: String,
+        unsat: String,
+    },
+    // both found plans, with different objective values
+    Objective {
+        backends: (String, String),
+        values: (ObjectiveValues, ObjectiveValues),
+    },
+}
+
+impl Display for Disagreement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Disagreement::Failed { backend, error } => write!(f, "{backend} failed: {error}"),
+            Disagreement::Invalid { backend, plan } => {
+                write!(f, "{backend} found the invalid plan {plan:?}")
+            }
+            Disagreement::Satisfiability { sat, unsat } => {
+                write!(f, "{sat} found a plan but {unsat} found none")
+            }
+            Disagreement::Objective {
+                backends: (a, b),
+                values: (x, y),
+            } => write!(f, "{a} found a plan with {x} but {b} one with {y}"),
+        }
+    }
+}
+
+// Whether an expression holds when the packages have the versions of a plan, packages
+// outside of the plan being not installed
+struct Holds<'p>(&'p TypedPlan);
+
+impl ExprFold for Holds<'_> {
+    type Output = bool;
+    fn atom(&mut self, atom: &AtomicExpr) -> bool {
+        let version = |pid| self.0.installed_version(pid).unwrap_or(0);
+        match *atom {
+            AtomicExpr::VerEq { pid, version: v } => version(pid) == v,
+            AtomicExpr::VerLE { pid, version: v } => version(pid) <= v,
+            AtomicExpr::VerGE { pid, version: v } => version(pid) >= v,
+        }
+    }
+    fn not(&mut self, e: bool) -> bool {
+        !e
+    }
+    fn and(&mut self, l: bool, r: bool) -> bool {
+        l && r
+    }
+    fn or(&mut self, l: bool, r: bool) -> bool {
+        l || r
+    }
+    fn implies(&mut self, l: bool, r: bool) -> bool {
+        !l || r
+    }
+    fn bot(&mut self) -> bool {
+        false
+    }
+    fn top(&mut self) -> bool {
+        true
+    }
+}
+
+// Whether a plan satisfies the constraints the library encodes for a problem: those of
+// every package, the essential packages and the toplevel requirements
+fn is_valid(problem: &ResolutionProblem, plan: &Plan) -> bool {
+    let repo = &problem.repository;
+    let typed = TypedPlan::from(plan);
+    if typed
+        .installed()
+        .any(|(pid, _)| repo.fetch_package(pid).is_none())
+    {
+        return false;
+    }
+    let b = Bump::new();
+    let mut valid = true;
+    let mut check = |sym_expr: Expr<'_>| valid &= sym_expr.fold(&mut Holds(&typed));
+    for package in repo.packages() {
+        repo.package_constraints(&b, package.id, &mut check);
+    }
+    add_essential_constraints(&b, repo, |sym_expr, _| check(sym_expr));
+    add_toplevel_symbolic_constraints(&b, repo, &problem.requirements, |sym_expr, _| {
+        check(sym_expr)
+    });
+    valid
+}
+
+// The values of the standard metrics at a plan, like the optimizer computes them
+fn objective_values(repo: &Repository, plan: &Plan) -> ObjectiveValues {
+    TypedPlan::from(plan)
+        .installed()
+        .fold(ObjectiveValues::default(), |values, (pid, version)| {
+            ObjectiveValues {
+                distance_from_newest: values.distance_from_newest
+                    + (repo.newest_ver_of_unchecked(pid) - version),
+                installed: values.installed + 1,
+            }
+        })
+}
+
+// Resolve a problem with every backend, the first invalid plan or disagreement with the
+// first backend if there is one
+pub fn check_equivalence(
+    problem: &ResolutionProblem,
+    backends: &[Backend<'_>],
+) -> Result<(), Disagreement> {
+    // the objective values of the plans of each backend, None if it found no plan
+    let mut results = Vec::with_capacity(backends.len());
+    for backend in backends {
+        let result = backend
+            .resolver
+            .resolve(&problem.repository, &problem.requirements)
+            .map_err(|e| Disagreement::Failed {
+                backend: backend.name.clone(),
+                error: e.to_string(),
+            })?;
+        let values = match result {
+            ResolutionResult::Sat { plans, .. } => {
+                if let Some(plan) = plans.iter().find(|plan| !is_valid(problem, plan)) {
+                    return Err(Disagreement::Invalid {
+                        backend: backend.name.clone(),
+                        plan: plan.clone(),
+                    });
+                }
+                Some(objective_values(&problem.repository, plans.first()))
+            }
+            _ => None,
+        };
+        results.push((&backend.name, values));
+    }
+    let Some(((first, reference), rest)) = results.split_first() else {
+        return Ok(());
+    };
+    for (name, values) in rest {
+        match (reference, values) {
+            (Some(x), Some(y)) if x != y => {
+                return Err(Disagreement::Objective {
+                    backends: (first.to_string(), name.to_string()),
+                    values: (*x, *y),
+                })
+            }
+            (Some(_), None) | (None, Some(_)) => {
+                let (sat, unsat) = if reference.is_some() {
+                    (first, name)
+                } else {
+                    (name, first)
+                };
+                return Err(Disagreement::Satisfiability {
+                    sat: sat.to_string(),
+                    unsat: unsat.to_string(),
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use crate::internals::{
+        testing::repository_from_requirements,
+        types::{
+            ObjectiveValues, Plan, Repository, RequirementSet, Res, ResolutionProblem,
+            ResolutionResult, Vec1,
+        },
+    };
+
+    use super::{check_equivalence, encoding_backends, Backend, Disagreement};
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            fork: false,
+            cases: 16,
+            .. ProptestConfig::default()
+        })]
+        #[test]
+        fn test_encodings_agree(problem in any_with::<ResolutionProblem>((20, 5, 6))) {
+            let result = check_equivalence(&problem, &encoding_backends());
+            prop_assert!(result.is_ok(), "{}", result.unwrap_err());
+        }
+    }
+
+    #[test]
+    fn test_check_equivalence() {
+        let problem = ResolutionProblem {
+            repository: repository_from_requirements(&[&["", ""]]).unwrap(),
+            requirements: "pkg0".parse().unwrap(),
+        };
+        assert_eq!(check_equivalence(&problem, &encoding_backends()), Ok(()));
+
+        let check_with = |backend: Backend<'static>| {
+            let mut backends = encoding_backends();
+            backends.push(backend);
+            let disagreement = check_equivalence(&problem, &backends).unwrap_err();
+            println!("{disagreement}");
+            disagreement
+        };
+        let unsat = |_: &Repository, _: &RequirementSet| -> Res { Ok(ResolutionResult::Unsat) };
+        assert_eq!(
+            check_with(Backend::new("broken", unsat)),
+            Disagreement::Satisfiability {
+                sat: "int".to_string(),
+                unsat: "broken".to_string(),
+            }
+        );
+        let sat = |plan: Plan| {
+            move |_: &Repository, _: &RequirementSet| -> Res {
+                Ok(ResolutionResult::Sat {
+                    plans: Vec1::new(plan.clone()),
+                    objective: None,
+                    optimal: true,
+                })
+            }
+        };
+        // package 0 has no third version
+        assert_eq!(
+            check_with(Backend::new("invalid", sat(vec![(0, 3)]))),
+            Disagreement::Invalid {
+                backend: "invalid".to_string(),
+                plan: vec![(0, 3)],
+            }
+        );
+        // the values are computed even if the backend reports none
+        let newest = ObjectiveValues {
+            distance_from_newest: 0,
+            installed: 1,
+        };
+        assert_eq!(
+            check_with(Backend::new("stale", sat(vec![(0, 1)]))),
+            Disagreement::Objective {
+                backends: ("int".to_string(), "stale".to_string()),
+                values: (
+                    newest,
+                    ObjectiveValues {
+                        distance_from_newest: 1,
+                        ..newest
+                    }
+                ),
+            }
+        );
+    }
+}
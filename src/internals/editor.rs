@@ -0,0 +1,185 @@
+// Incremental edits of repositories that keep package ids and version numbers stable, so
+// that plans, masks and requirements referring to the repository stay meaningful. New
+// packages get fresh ids, new versions are appended after the existing ones, and removed
+// packages are kept as packages without versions. Every edit goes through
+// `Repository::packages_mut`, so the indices of the repository never outlive an edit
+use tinyset::SetU32;
+
+use crate::internals::types::*;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RepositoryChange {
+    AddedPackage { id: PackageId, versions: Version },
+    AppendedVersion { id: PackageId, version: Version },
+    // `versions` is the number of versions the package had
+    RemovedPackage { id: PackageId, versions: Version },
+}
+
+pub struct RepositoryEditor {
+    repo: Repository,
+    removed: SetU32,
+    changes: Vec<RepositoryChange>,
+}
+
+impl RepositoryEditor {
+    pub fn new(repo: Repository) -> Self {
+        Self {
+            repo,
+            removed: SetU32::new(),
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    pub fn changes(&self) -> &[RepositoryChange] {
+        &self.changes
+    }
+
+    // Add a package after the existing ones, returning its id
+    pub fn add_package(&mut self, versions: Vec<PackageVer>) -> PackageId {
+        let id = self.repo.packages().len() as PackageId;
+        self.changes.push(RepositoryChange::AddedPackage {
+            id,
+            versions: versions.len() as Version,
+        });
+        self.repo.packages_mut().push(Package { id, versions });
+        id
+    }
+
+    // Append a version after the existing versions of a package, returning its number
+    pub fn append_version(
+        &mut self,
+        id: PackageId,
+        version: PackageVer,
+    ) -> Result<Version, ResolutionError> {
+        self.check_present(id)?;
+        let package = &mut self.repo.packages_mut()[id as usize];
+        package.versions.push(version);
+        let version = package.newest_version_number();
+        self.changes
+            .push(RepositoryChange::AppendedVersion { id, version });
+        Ok(version)
+    }
+
+    // Remove every version of a package, the id is not reused
+    pub fn remove_package(&mut self, id: PackageId) -> Result<(), ResolutionError> {
+        self.check_present(id)?;
+        let package = &mut self.repo.packages_mut()[id as usize];
+        let versions = package.newest_version_number();
+        package.versions.clear();
+        self.removed.insert(id);
+        self.changes
+            .push(RepositoryChange::RemovedPackage { id, versions });
+        Ok(())
+    }
+
+    // The edited repository and the changes made to it, in order. Fails if a requirement
+    // refers to a package that does not exist
+    pub fn finish(self) -> Result<(Repository, Vec<RepositoryChange>), ResolutionError> {
+        let Self { repo, changes, .. } = self;
+        let len = repo.packages().len();
+        for package in repo.packages() {
+            for ver in &package.versions {
+                if let Some(req) = (&ver.requirements)
+                    .into_iter()
+                    .find(|req| req.package as usize >= len)
+                {
+                    return Err(ResolutionError::unknown_package(req.package));
+                }
+            }
+        }
+        Ok((repo, changes))
+    }
+
+    fn check_present(&self, id: PackageId) -> Result<(), ResolutionError> {
+        if id as usize >= self.repo.packages().len() {
+            Err(ResolutionError::unknown_package(id))
+        } else if self.removed.contains(id) {
+            Err(InvalidInputSnafu {
+                message: format!("package {id} has been removed"),
+            }
+            .build())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        solver::optimize_newest,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet, ResolutionResult},
+    };
+
+    use super::{RepositoryChange, RepositoryEditor};
+
+    #[test]
+    fn test_repository_editor() {
+        let empty = || PackageVer {
+            requirements: Default::default(),
+        };
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![empty()],
+            },
+            Package {
+                id: 1,
+                versions: vec![empty()],
+            },
+        ]);
+
+        let mut editor = RepositoryEditor::new(repo);
+        editor.remove_package(0).unwrap();
+        assert!(editor.append_version(0, empty()).is_err());
+        let v = editor
+            .append_version(
+                1,
+                PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(2)),
+                },
+            )
+            .unwrap();
+        assert_eq!(v, 2);
+        let id = editor.add_package(vec![empty(), empty()]);
+        assert_eq!(id, 2);
+        let (repo, changes) = editor.finish().unwrap();
+        println!("{changes:?}");
+        assert_eq!(
+            changes,
+            vec![
+                RepositoryChange::RemovedPackage { id: 0, versions: 1 },
+                RepositoryChange::AppendedVersion { id: 1, version: 2 },
+                RepositoryChange::AddedPackage { id: 2, versions: 2 },
+            ]
+        );
+
+        let reqs = RequirementSet::from_dep(Requirement::any_version(1));
+        let ResolutionResult::Sat { plans, .. } = optimize_newest(&repo, &reqs).unwrap() else {
+            panic!("expected a plan")
+        };
+        let mut plan = plans.as_vec()[0].clone();
+        plan.sort_unstable();
+        assert_eq!(plan, vec![(1, 2), (2, 2)]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        assert!(optimize_newest(&repo, &reqs).unwrap().is_unsat());
+
+        // the reverse dependencies of the repository follow the edits
+        let mut editor = RepositoryEditor::new(repo);
+        assert_eq!(editor.repository().reverse_deps(2).len(), 1);
+        let dep = PackageVer {
+            requirements: RequirementSet::from_dep(Requirement::any_version(2)),
+        };
+        editor.append_version(1, dep).unwrap();
+        assert_eq!(editor.repository().reverse_deps(2).len(), 2);
+
+        editor.add_package(vec![PackageVer {
+            requirements: RequirementSet::from_dep(Requirement::any_version(7)),
+        }]);
+        assert!(editor.finish().is_err());
+    }
+}
@@ -0,0 +1,374 @@
+// Encodings of the symbolic constraints into z3 expressions, and the reverse direction
+// of reading plans back from the models found by z3
+use intmap::IntMap;
+use z3::{
+    ast::{Ast, Bool, Int, BV},
+    Context, Model, Solver,
+};
+
+use crate::internals::types::*;
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Encoding {
+    // one integer variable per package, 0 meaning the package is not installed
+    #[default]
+    Int,
+    // one boolean variable per (package, version) pair, at most one of which may hold,
+    // none of them holding meaning the package is not installed. Usually much faster
+    // when packages only have a handful of versions
+    Bool,
+    // one bit-vector per package, just wide enough to hold its newest version
+    BitVec,
+}
+
+impl Encoding {
+    pub(crate) fn solver(self, ctx: &Context) -> Solver<'_> {
+        let logic = match self {
+            Encoding::Int => "QF_LIA",
+            Encoding::Bool => "QF_FD",
+            Encoding::BitVec => "QF_BV",
+        };
+        Solver::new_for_logic(ctx, logic).unwrap()
+    }
+}
+
+pub struct Encoder<'c> {
+    ctx: &'c Context,
+    encoding: Encoding,
+    // newest version of every package in the encoded closure, not needed by the integer encoding
+    pids: Vec<PackageId>,
+    newest: IntMap<Version>,
+}
+
+impl<'c> Encoder<'c> {
+    pub(crate) fn new<S: PackageSource + ?Sized>(
+        ctx: &'c Context,
+        encoding: Encoding,
+        repo: &S,
+        pids: impl Iterator<Item = PackageId>,
+    ) -> Self {
+        let mut newest = IntMap::new();
+        let pids = if encoding != Encoding::Int {
+            pids.collect::<Vec<_>>()
+        } else {
+            Vec::new()
+        };
+        for &pid in &pids {
+            newest.insert(pid as u64, repo.newest_ver_of_unchecked(pid));
+        }
+        Self {
+            ctx,
+            encoding,
+            pids,
+            newest,
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn ctx(&self) -> &'c Context {
+        self.ctx
+    }
+
+    fn newest(&self, pid: PackageId) -> Version {
+        *self
+            .newest
+            .get(pid as u64)
+            .unwrap_or_else(|| panic!("Impossible: package {pid} is not in the encoded closure"))
+    }
+
+    fn int_var(&self, pid: PackageId) -> Int<'c> {
+        Int::new_const(self.ctx, pid)
+    }
+
+    fn bool_var(&self, pid: PackageId, version: Version) -> Bool<'c> {
+        Bool::new_const(self.ctx, format!("{pid}@{version}"))
+    }
+
+    fn bv_width(&self, pid: PackageId) -> u32 {
+        (u64::BITS - self.newest(pid).leading_zeros()).max(1)
+    }
+
+    fn bv_var(&self, pid: PackageId) -> BV<'c> {
+        BV::new_const(self.ctx, format!("{pid}#bv"), self.bv_width(pid))
+    }
+
+    // disjunction of the variables of versions lower..=upper of a package
+    fn any_version_in(&self, pid: PackageId, lower: Version, upper: Version) -> Bool<'c> {
+        let vars = (lower.max(1)..=upper.min(self.newest(pid)))
+            .map(|v| self.bool_var(pid, v))
+            .collect::<Vec<_>>();
+        Bool::or(self.ctx, &vars.iter().collect::<Vec<_>>())
+    }
+
+    // Constraints that are part of the encoding itself rather than of the repository,
+    // these are always satisfiable and should be asserted without tracking
+    pub(crate) fn domain_constraints(&self) -> Vec<Bool<'c>> {
+        match self.encoding {
+            Encoding::Int | Encoding::BitVec => Vec::new(),
+            Encoding::Bool => self
+                .pids
+                .iter()
+                .map(|&pid| (pid, self.newest(pid)))
+                .filter(|&(_, newest)| newest > 1)
+                .map(|(pid, newest)| {
+                    let vars = (1..=newest)
+                        .map(|v| self.bool_var(pid, v))
+                        .collect::<Vec<_>>();
+                    let weighted = vars.iter().map(|var| (var, 1)).collect::<Vec<_>>();
+                    Bool::pb_le(self.ctx, &weighted, 1)
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn atom(&self, atom: &AtomicExpr) -> Bool<'c> {
+        match self.encoding {
+            Encoding::Int => match atom {
+                AtomicExpr::VerEq { pid, version } => {
+                    self.int_var(*pid)._eq(&Int::from_u64(self.ctx, *version))
+                }
+                AtomicExpr::VerLE { pid, version } => {
+                    self.int_var(*pid).le(&Int::from_u64(self.ctx, *version))
+                }
+                AtomicExpr::VerGE { pid, version } => {
+                    self.int_var(*pid).ge(&Int::from_u64(self.ctx, *version))
+                }
+            },
+            Encoding::Bool => match atom {
+                AtomicExpr::VerEq { pid, version: 0 } => {
+                    self.any_version_in(*pid, 1, Version::MAX).not()
+                }
+                AtomicExpr::VerEq { pid, version } => {
+                    if *version <= self.newest(*pid) {
+                        self.bool_var(*pid, *version)
+                    } else {
+                        Bool::from_bool(self.ctx, false)
+                    }
+                }
+                AtomicExpr::VerLE { pid, version } => self
+                    .any_version_in(*pid, version.saturating_add(1), Version::MAX)
+                    .not(),
+                AtomicExpr::VerGE { pid: _, version: 0 } => Bool::from_bool(self.ctx, true),
+                AtomicExpr::VerGE { pid, version } => {
+                    self.any_version_in(*pid, *version, Version::MAX)
+                }
+            },
+            // versions that do not fit in the bit-vector are out of range for the package
+            Encoding::BitVec => match atom {
+                AtomicExpr::VerEq { pid, version } => match self.bv_const(*pid, *version) {
+                    Some(v) => self.bv_var(*pid)._eq(&v),
+                    None => Bool::from_bool(self.ctx, false),
+                },
+                AtomicExpr::VerLE { pid, version } => match self.bv_const(*pid, *version) {
+                    Some(v) => self.bv_var(*pid).bvule(&v),
+                    None => Bool::from_bool(self.ctx, true),
+                },
+                AtomicExpr::VerGE { pid, version } => match self.bv_const(*pid, *version) {
+                    Some(v) => self.bv_var(*pid).bvuge(&v),
+                    None => Bool::from_bool(self.ctx, false),
+                },
+            },
+        }
+    }
+
+    fn bv_const(&self, pid: PackageId, version: Version) -> Option<BV<'c>> {
+        let width = self.bv_width(pid);
+        if width < u64::BITS && version >> width != 0 {
+            None
+        } else {
+            Some(BV::from_u64(self.ctx, version, width))
+        }
+    }
+
+    // The version of a package as an integer expression, for building metrics
+    pub fn version(&self, pid: PackageId) -> Int<'c> {
+        match self.encoding {
+            Encoding::Int => self.int_var(pid),
+            Encoding::Bool => {
+                let mut expr = Int::from_u64(self.ctx, 0);
+                for v in 1..=self.newest(pid) {
+                    expr += self
+                        .bool_var(pid, v)
+                        .ite(&Int::from_u64(self.ctx, v), &Int::from_u64(self.ctx, 0));
+                }
+                expr
+            }
+            Encoding::BitVec => Int::from_bv(&self.bv_var(pid), false),
+        }
+    }
+
+    // Translate a symbolic expression into a z3 expression
+    pub(crate) fn translate(&self, expr: &Expr<'_>) -> Bool<'c> {
+        match expr {
+            Expr::Atom(atom) => self.atom(atom),
+            Expr::Not(e) => self.translate(e).not(),
+            Expr::And(l, r) => self.translate(l) & self.translate(r),
+            Expr::Or(l, r) => self.translate(l) | self.translate(r),
+            Expr::Implies(l, r) => self.translate(l).implies(&self.translate(r)),
+            Expr::Bot => Bool::from_bool(self.ctx, false),
+            Expr::Top => Bool::from_bool(self.ctx, true),
+        }
+    }
+
+    pub fn not_installed(&self, pid: PackageId) -> Bool<'c> {
+        self.atom(&AtomicExpr::ver_eq(pid, 0))
+    }
+
+    pub fn installed(&self, pid: PackageId) -> Bool<'c> {
+        self.not_installed(pid).not()
+    }
+
+    // The version of a package chosen by a model, None if the model has no
+    // (valid) interpretation for it
+    pub(crate) fn version_of(&self, model: &Model<'c>, pid: PackageId) -> Option<Version> {
+        match self.encoding {
+            Encoding::Int => model
+                .get_const_interp(&self.int_var(pid))
+                .and_then(|interp| interp.as_u64()),
+            Encoding::Bool => {
+                for v in 1..=self.newest(pid) {
+                    if model.eval(&self.bool_var(pid, v), true)?.as_bool()? {
+                        return Some(v);
+                    }
+                }
+                Some(0)
+            }
+            Encoding::BitVec => model.eval(&self.bv_var(pid), true)?.as_u64(),
+        }
+    }
+
+    pub(crate) fn plan_from_model(
+        &self,
+        model: &Model<'c>,
+        pids: impl Iterator<Item = PackageId>,
+    ) -> Plan {
+        let mut plan = Vec::new();
+        let mut no_interp = Vec::new();
+
+        for pid in pids {
+            match self.version_of(model, pid) {
+                Some(v) => plan.push((pid, v)),
+                None => no_interp.push(pid),
+            }
+        }
+
+        if !no_interp.is_empty() {
+            panic!("Impossible: failed to generate a plan from a model, the following packages do not have a valid interpretation in the model:\n  {no_interp:?}")
+        }
+        plan
+    }
+
+    pub(crate) fn installation_status(
+        &self,
+        model: &Model<'c>,
+        closure: impl Iterator<Item = PackageId>,
+    ) -> (Vec<PackageId>, Vec<PackageId>) {
+        // packages without an interpretation are taken as not installed
+        let plan = closure
+            .map(|pid| (pid, self.version_of(model, pid).unwrap_or(0)))
+            .collect::<Plan>();
+        plan.installation_status()
+    }
+
+    pub(crate) fn fix_not_installed(&self, solver: &Solver<'c>, not_installed: &[PackageId]) {
+        for pid in not_installed {
+            solver.assert(&self.not_installed(*pid));
+        }
+    }
+
+    // Block the solutions assigning the same versions as the plan
+    pub(crate) fn block_plan(&self, solver: &Solver<'c>, plan: &Plan) {
+        let mut e = Bool::from_bool(self.ctx, true);
+        for &(pid, v) in plan {
+            e &= self.atom(&AtomicExpr::ver_eq(pid, v));
+        }
+        solver.assert(&e.not().simplify());
+    }
+
+    // Block every solution that is less than or equal to the model on the installed packages
+    pub(crate) fn block_le_solutions(
+        &self,
+        solver: &Solver<'c>,
+        model: &Model<'c>,
+        installed: &[PackageId],
+    ) {
+        let mut e = Bool::from_bool(self.ctx, true);
+        for pid in installed {
+            let v = self.version_of(model, *pid).unwrap_or_else(|| {
+                panic!("Impossible: package {pid} does not have an interpretation in the model")
+            });
+            e &= self.atom(&AtomicExpr::ver_eq(*pid, v));
+            solver.assert(&self.atom(&AtomicExpr::ver_ge(*pid, v)));
+        }
+        e = e.not().simplify();
+        solver.assert(&e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        options::SolveOptions,
+        solver::{optimize_with_options, simple_solve_with_options},
+        types::{Goal, Package, PackageVer, Range, Repository, Requirement, RequirementSet},
+    };
+    use crate::vec1;
+
+    use super::Encoding;
+
+    #[test]
+    fn test_encodings() {
+        // package 0 has 3 versions, package 1 requires package 0 at version 2 or 3,
+        // package 2 conflicts with version 3 of package 0
+        let empty = || PackageVer {
+            requirements: Default::default(),
+        };
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![empty(), empty(), empty()],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::new(
+                        0,
+                        vec1![Range::interval_unchecked(2, 3)],
+                    )),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_antidep(Requirement::new(
+                        0,
+                        vec1![Range::point(3)],
+                    )),
+                }],
+            },
+        ]);
+        let mut reqs = RequirementSet::from_dep(Requirement::any_version(1));
+        reqs.add_dep(Requirement::any_version(2));
+        let mut unsat_reqs = reqs.clone();
+        unsat_reqs.add_antidep(Requirement::new(0, vec1![Range::point(2)]));
+
+        let expected =
+            optimize_with_options(&repo, &reqs, Goal::Newest, &SolveOptions::deterministic(0))
+                .unwrap();
+        for encoding in [Encoding::Int, Encoding::Bool, Encoding::BitVec] {
+            let options = SolveOptions::deterministic(0).encoding(encoding);
+            let r = simple_solve_with_options(&repo, &reqs, &options).unwrap();
+            println!("{encoding:?}: {r:?}");
+            assert!(r.is_sat());
+
+            let r = optimize_with_options(&repo, &reqs, Goal::Newest, &options).unwrap();
+            assert_eq!(r, expected);
+
+            let r = simple_solve_with_options(&repo, &unsat_reqs, &options).unwrap();
+            assert!(!r.is_sat());
+        }
+    }
+}
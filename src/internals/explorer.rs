@@ -0,0 +1,300 @@
+// Drill-down into the conflicts of unsatisfiable requirements. Every constraint is
+// asserted behind a guard literal and the solver is queried under the assumption that
+// the guards hold, so that individual constraints can be disabled without rebuilding
+// the solver, which also keeps what z3 learned between queries. Answers to version
+// queries are cached
+use std::collections::HashMap;
+
+use bumpalo::Bump;
+use intmap::IntMap;
+use z3::{
+    ast::{Ast, Bool},
+    Context, SatResult, Solver,
+};
+
+use crate::internals::{
+    constraints::{add_all_constraints_within, find_closure, Origin},
+    encoding::Encoder,
+    options::SolveOptions,
+    solver::process_unsat_core,
+    tracking::AssertionRegistry,
+    types::*,
+};
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct CoreAssertion {
+    // the index to disable the assertion with
+    pub index: usize,
+    pub origin: Origin,
+    pub expr: OwnedExpr,
+}
+
+pub struct ConflictExplorer<'c> {
+    solver: Solver<'c>,
+    encoder: Encoder<'c>,
+    pids: Vec<PackageId>,
+    newest: IntMap<Version>,
+    // every assertion is guarded, see `AssertionRegistry::guard`
+    assertions: AssertionRegistry<'c, (OwnedExpr, Origin)>,
+    // the unsat core with every assertion enabled, None if the requirements are satisfiable
+    core: Option<Vec<usize>>,
+    cache: HashMap<(PackageId, Vec<usize>), Vec<Version>>,
+}
+
+impl<'c> ConflictExplorer<'c> {
+    pub fn new<S: PackageSource + ?Sized>(
+        ctx: &'c Context,
+        repo: &S,
+        requirements: &RequirementSet,
+        options: &SolveOptions,
+    ) -> Result<Self, ResolutionError> {
+        let solver = options.solver(ctx);
+        let allocator = Bump::new();
+
+        let requirements = options.effective_requirements(requirements);
+        let requirements = &*requirements;
+        let closure = find_closure(repo, requirements.into_iter())?;
+        options.limits.check_closure(closure.len())?;
+        let pids = options.ordered_pids(&closure);
+        let encoder = Encoder::new(ctx, options.encoding, repo, pids.iter().copied());
+        for domain_constraint in encoder.domain_constraints() {
+            solver.assert(&domain_constraint);
+        }
+        let mut assertions = AssertionRegistry::with_prefix(ctx, "guard");
+        for (option_constraint, restriction) in options.option_constraints(&encoder, &closure) {
+            let origin = Origin::Restriction(restriction);
+            assertions.guard(&solver, &option_constraint, (OwnedExpr::Top, origin));
+        }
+        add_all_constraints_within(
+            &allocator,
+            &encoder,
+            repo,
+            pids.iter().copied(),
+            requirements,
+            &options.limits,
+            |_| options.check_cancelled(),
+            |expr, sym_expr, origin| {
+                assertions.guard(
+                    &solver,
+                    &expr.simplify(),
+                    (sym_expr.to_owned_expr(), origin),
+                );
+            },
+        )?;
+
+        let mut newest = IntMap::new();
+        for &pid in &pids {
+            newest.insert(pid as u64, repo.newest_ver_of_unchecked(pid));
+        }
+        let mut explorer = Self {
+            solver,
+            encoder,
+            pids,
+            newest,
+            assertions,
+            core: None,
+            cache: HashMap::new(),
+        };
+        explorer.core = explorer.core_without(&[])?;
+        Ok(explorer)
+    }
+
+    pub fn is_unsat(&self) -> bool {
+        self.core.is_some()
+    }
+
+    // The assertions of the unsat core with every assertion enabled
+    pub fn core(&self) -> Vec<CoreAssertion> {
+        self.core
+            .iter()
+            .flatten()
+            .map(|&index| {
+                let (expr, origin) = &self.assertions.assertions()[index];
+                CoreAssertion {
+                    index,
+                    origin: *origin,
+                    expr: expr.clone(),
+                }
+            })
+            .collect()
+    }
+
+    // The index of the assertion of a toplevel requirement, dependencies are numbered
+    // first and conflicts after them
+    pub fn toplevel_assertion(&self, requirement: usize) -> Option<usize> {
+        self.assertions
+            .assertions()
+            .iter()
+            .position(|(_, origin)| *origin == Origin::Toplevel(requirement))
+    }
+
+    // The requirements of some assertions, e.g. of an unsat core
+    pub fn constraint_set<S: PackageSource + ?Sized>(
+        &self,
+        repo: &S,
+        assertions: &[usize],
+    ) -> ConstraintSet {
+        let allocator = Bump::new();
+        let exprs = assertions
+            .iter()
+            .map(|&i| {
+                let (expr, origin) = &self.assertions.assertions()[i];
+                (expr.to_expr(&allocator), *origin)
+            })
+            .collect::<Vec<_>>();
+        process_unsat_core(repo, exprs.iter().collect())
+    }
+
+    // The unsat core with the relaxed assertions disabled, None if that makes the
+    // requirements satisfiable
+    pub fn core_without(&self, relaxed: &[usize]) -> Result<Option<Vec<usize>>, ResolutionError> {
+        match self.check_without(relaxed, None)? {
+            SatResult::Sat => Ok(None),
+            _ => {
+                let mut core = self
+                    .solver
+                    .get_unsat_core()
+                    .iter()
+                    .filter_map(|guard| self.assertions.id_of(guard))
+                    .collect::<Vec<_>>();
+                core.sort_unstable();
+                Ok(Some(core))
+            }
+        }
+    }
+
+    // A plan with the relaxed assertions disabled, None if there is none
+    pub fn plan_without(&self, relaxed: &[usize]) -> Result<Option<Plan>, ResolutionError> {
+        match self.check_without(relaxed, None)? {
+            SatResult::Sat => {
+                let model = self
+                    .solver
+                    .get_model()
+                    .expect("Impossible: satisfiable but failed to generate a model");
+                Ok(Some(
+                    self.encoder
+                        .plan_from_model(&model, self.pids.iter().copied()),
+                ))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // The versions of a package that some plan installs once the relaxed assertions are
+    // disabled
+    pub fn acceptable_versions(
+        &mut self,
+        pid: PackageId,
+        relaxed: &[usize],
+    ) -> Result<Vec<Version>, ResolutionError> {
+        let mut relaxed = relaxed.to_vec();
+        relaxed.sort_unstable();
+        relaxed.dedup();
+        if let Some(versions) = self.cache.get(&(pid, relaxed.clone())) {
+            return Ok(versions.clone());
+        }
+
+        let newest = *self.newest.get(pid as u64).ok_or_else(|| {
+            InvalidInputSnafu {
+                message: format!("package {pid} is not in the closure of the requirements"),
+            }
+            .build()
+        })?;
+        let mut versions = Vec::new();
+        for v in 1..=newest {
+            let installed = self.encoder.atom(&AtomicExpr::ver_eq(pid, v));
+            if self.check_without(&relaxed, Some(installed))? == SatResult::Sat {
+                versions.push(v);
+            }
+        }
+        self.cache.insert((pid, relaxed), versions.clone());
+        Ok(versions)
+    }
+
+    fn check_without(
+        &self,
+        relaxed: &[usize],
+        extra: Option<Bool<'c>>,
+    ) -> Result<SatResult, ResolutionError> {
+        let mut assumptions = (0..self.assertions.len())
+            .filter(|i| !relaxed.contains(i))
+            .map(|i| self.assertions.var(i))
+            .collect::<Vec<_>>();
+        assumptions.extend(extra);
+        match self.solver.check_assumptions(&assumptions) {
+            SatResult::Unknown => Err(ResolutionError::unknown(self.solver.get_reason_unknown())),
+            res => Ok(res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use z3::Context;
+
+    use crate::internals::{
+        options::SolveOptions,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet},
+        utils::default_config,
+    };
+
+    use super::ConflictExplorer;
+
+    #[test]
+    fn test_conflict_explorer() {
+        // version v of package 0 requires version v of package 1
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (1..=2)
+                    .map(|v| PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::single_version(1, v)),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: (1..=2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        // requirement 1 forces version 1 of package 1, requirement 2 forbids version 1
+        // of package 0
+        let reqs = RequirementSet {
+            dependencies: vec![
+                Requirement::any_version(0),
+                Requirement::single_version(1, 1),
+            ],
+            conflicts: vec![Requirement::single_version(0, 1)],
+            formula: None,
+        };
+        let ctx = Context::new(&default_config());
+        let mut explorer =
+            ConflictExplorer::new(&ctx, &repo, &reqs, &SolveOptions::deterministic(0)).unwrap();
+        assert!(explorer.is_unsat());
+        let core = explorer.core();
+        println!("{core:?}");
+        let core_indices = core.iter().map(|a| a.index).collect::<Vec<_>>();
+        println!("{:?}", explorer.constraint_set(&repo, &core_indices));
+
+        let pinned = explorer.toplevel_assertion(1).unwrap();
+        let conflict = explorer.toplevel_assertion(2).unwrap();
+        assert!(core_indices.contains(&pinned) && core_indices.contains(&conflict));
+        assert_eq!(explorer.acceptable_versions(0, &[]).unwrap(), vec![]);
+        assert_eq!(explorer.acceptable_versions(0, &[pinned]).unwrap(), vec![2]);
+        assert_eq!(
+            explorer.acceptable_versions(0, &[conflict]).unwrap(),
+            vec![1]
+        );
+        // answered from the cache
+        assert_eq!(explorer.acceptable_versions(0, &[pinned]).unwrap(), vec![2]);
+
+        assert!(explorer.core_without(&[pinned]).unwrap().is_none());
+        let plan = explorer.plan_without(&[conflict]).unwrap().unwrap();
+        assert!(plan.contains(&(0, 1)) && plan.contains(&(1, 1)));
+        assert!(explorer.acceptable_versions(5, &[]).is_err());
+    }
+}
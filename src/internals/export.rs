@@ -0,0 +1,10 @@
+// Exporting resolution problems and dependency graphs to the formats of other tools
+mod dimacs;
+mod dot;
+mod mermaid;
+mod smtlib;
+
+pub use dimacs::*;
+pub use dot::*;
+pub use mermaid::*;
+pub use smtlib::*;
@@ -0,0 +1,438 @@
+// DIMACS CNF and OPB export of the boolean encoding of a resolution problem, for
+// experimenting with SAT and pseudo-boolean solvers. Like the boolean encoding, there is
+// one variable per (package, version) pair, at most one of which may hold. Expressions
+// are lowered to clauses by the Tseitin transformation, so the formulas also contain
+// auxiliary variables, which are numbered after the version variables
+use std::io::{self, Write};
+
+use bumpalo::Bump;
+use intmap::IntMap;
+use snafu::ResultExt;
+
+use crate::internals::{
+    constraints::{add_essential_constraints, find_closure, AsConstraints},
+    types::*,
+};
+
+// Literals are numbered from 1 as in DIMACS, negative literals being negations
+pub type Literal = i64;
+
+// The mapping from the version variables of an exported formula back to versions,
+// variable i being version `versions[i - 1]`
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct LiteralMap {
+    pub versions: Vec<(PackageId, Version)>,
+}
+
+impl LiteralMap {
+    pub fn get(&self, literal: Literal) -> Option<(PackageId, Version)> {
+        let i = usize::try_from(literal).ok()?.checked_sub(1)?;
+        self.versions.get(i).copied()
+    }
+
+    // The plan of an assignment given as the literals that hold, in the format of
+    // the output of SAT solvers
+    pub fn plan_from_assignment(&self, assignment: impl IntoIterator<Item = Literal>) -> Plan {
+        let mut plan = assignment
+            .into_iter()
+            .filter_map(|literal| self.get(literal))
+            .collect::<Plan>();
+        plan.sort_unstable();
+        plan
+    }
+
+    // Write the mapping with one `<variable> <package> <version>` line per variable
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<(), ResolutionError> {
+        self.write_lines(out).context(IoSnafu)
+    }
+
+    fn write_lines<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (i, (pid, version)) in self.versions.iter().enumerate() {
+            writeln!(out, "{} {pid} {version}", i + 1)?;
+        }
+        Ok(())
+    }
+}
+
+// Objectives for the OPB export, matching the metrics of the same name
+#[derive(Debug, Clone, Copy)]
+pub enum PbObjective<'a> {
+    InstalledPackages,
+    DistanceFromNewest,
+    // costs of the versions of every package, starting from version 1
+    InstallCost(&'a PackageMap<Vec<u64>>),
+}
+
+pub fn export_dimacs<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Result<(String, LiteralMap), ResolutionError> {
+    let mut out = Vec::new();
+    let map = write_dimacs(repo, requirements, &mut out)?;
+    Ok((
+        String::from_utf8(out).expect("Impossible: DIMACS output is not valid UTF-8"),
+        map,
+    ))
+}
+
+pub fn write_dimacs<S: PackageSource + ?Sized, W: Write>(
+    repo: &S,
+    requirements: &RequirementSet,
+    out: &mut W,
+) -> Result<LiteralMap, ResolutionError> {
+    let mut cnf = Cnf::new(repo, requirements)?;
+    for i in 0..cnf.domains.len() {
+        cnf.at_most_one(i);
+    }
+    write_cnf(&cnf, out).context(IoSnafu)?;
+    Ok(cnf.literal_map())
+}
+
+fn write_cnf<W: Write>(cnf: &Cnf, out: &mut W) -> io::Result<()> {
+    writeln!(out, "p cnf {} {}", cnf.vars, cnf.clauses.len())?;
+    for clause in &cnf.clauses {
+        for literal in clause {
+            write!(out, "{literal} ")?;
+        }
+        writeln!(out, "0")?;
+    }
+    Ok(())
+}
+
+pub fn export_opb<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    objective: Option<PbObjective<'_>>,
+) -> Result<(String, LiteralMap), ResolutionError> {
+    let mut out = Vec::new();
+    let map = write_opb(repo, requirements, objective, &mut out)?;
+    Ok((
+        String::from_utf8(out).expect("Impossible: OPB output is not valid UTF-8"),
+        map,
+    ))
+}
+
+pub fn write_opb<S: PackageSource + ?Sized, W: Write>(
+    repo: &S,
+    requirements: &RequirementSet,
+    objective: Option<PbObjective<'_>>,
+    out: &mut W,
+) -> Result<LiteralMap, ResolutionError> {
+    let cnf = Cnf::new(repo, requirements)?;
+    let objective = objective.map(|objective| cnf.objective(objective));
+    write_pb(&cnf, objective, out).context(IoSnafu)?;
+    Ok(cnf.literal_map())
+}
+
+fn write_pb<W: Write>(
+    cnf: &Cnf,
+    objective: Option<Vec<(u64, Literal)>>,
+    out: &mut W,
+) -> io::Result<()> {
+    // at most one constraints are native in OPB and are not lowered to clauses
+    let at_most_one = cnf.domains.iter().filter(|d| d.newest > 1).count();
+    writeln!(
+        out,
+        "* #variable= {} #constraint= {}",
+        cnf.vars,
+        cnf.clauses.len() + at_most_one
+    )?;
+    if let Some(terms) = objective {
+        write!(out, "min:")?;
+        for (weight, var) in terms {
+            write!(out, " +{weight} x{var}")?;
+        }
+        writeln!(out, " ;")?;
+    }
+    for domain in cnf.domains.iter().filter(|d| d.newest > 1) {
+        for var in domain.vars() {
+            write!(out, "-1 x{var} ")?;
+        }
+        writeln!(out, ">= -1 ;")?;
+    }
+    for clause in &cnf.clauses {
+        for &literal in clause {
+            if literal > 0 {
+                write!(out, "+1 x{literal} ")?;
+            } else {
+                write!(out, "+1 ~x{} ", -literal)?;
+            }
+        }
+        writeln!(out, ">= 1 ;")?;
+    }
+    Ok(())
+}
+
+// The version variables of a package, versions 1..=newest being the variables
+// first..first + newest
+struct Domain {
+    pid: PackageId,
+    newest: Version,
+    first: Literal,
+}
+
+impl Domain {
+    fn vars(&self) -> impl Iterator<Item = Literal> {
+        self.first..self.first + self.newest as Literal
+    }
+
+    // the variables of versions lower..=upper
+    fn vars_in(&self, lower: Version, upper: Version) -> impl Iterator<Item = Literal> {
+        let (first, lower, upper) = (self.first, lower.max(1), upper.min(self.newest));
+        (lower..=upper).map(move |v| first + v as Literal - 1)
+    }
+}
+
+struct Cnf {
+    domains: Vec<Domain>,
+    index: IntMap<usize>,
+    vars: Literal,
+    clauses: Vec<Vec<Literal>>,
+}
+
+// The result of lowering an expression, constants are kept apart so that they do not
+// need variables of their own
+#[derive(Clone, Copy)]
+enum Node {
+    Const(bool),
+    Lit(Literal),
+}
+
+impl Cnf {
+    fn new<S: PackageSource + ?Sized>(
+        repo: &S,
+        requirements: &RequirementSet,
+    ) -> Result<Self, ResolutionError> {
+        let closure = find_closure(repo, requirements.into_iter())?;
+        let mut pids = closure.iter().collect::<Vec<_>>();
+        pids.sort_unstable();
+
+        let mut cnf = Cnf {
+            domains: Vec::with_capacity(pids.len()),
+            index: IntMap::new(),
+            vars: 0,
+            clauses: Vec::new(),
+        };
+        for (i, &pid) in pids.iter().enumerate() {
+            let newest = repo.newest_ver_of_unchecked(pid);
+            cnf.domains.push(Domain {
+                pid,
+                newest,
+                first: cnf.vars + 1,
+            });
+            cnf.index.insert(pid as u64, i);
+            cnf.vars += newest as Literal;
+        }
+
+        let allocator = Bump::new();
+        let mut exprs = Vec::new();
+        for &pid in &pids {
+            repo.package_constraints(&allocator, pid, &mut |sym_expr| exprs.push(sym_expr));
+        }
+        add_essential_constraints(&allocator, repo, |sym_expr, _| exprs.push(sym_expr));
+        requirements.add_symbolic_constraints(&allocator, |sym_expr| exprs.push(sym_expr));
+        for sym_expr in &exprs {
+            match sym_expr.fold(&mut cnf) {
+                Node::Const(true) => {}
+                Node::Const(false) => cnf.clauses.push(Vec::new()),
+                Node::Lit(literal) => cnf.clauses.push(vec![literal]),
+            }
+        }
+        Ok(cnf)
+    }
+
+    fn domain(&self, pid: PackageId) -> &Domain {
+        let i = *self
+            .index
+            .get(pid as u64)
+            .unwrap_or_else(|| panic!("Impossible: package {pid} is not in the closure"));
+        &self.domains[i]
+    }
+
+    fn fresh(&mut self) -> Literal {
+        self.vars += 1;
+        self.vars
+    }
+
+    // A node equivalent to the disjunction of the literals
+    fn any(&mut self, literals: Vec<Literal>) -> Node {
+        match literals.as_slice() {
+            [] => Node::Const(false),
+            [literal] => Node::Lit(*literal),
+            _ => {
+                let a = self.fresh();
+                for &literal in &literals {
+                    self.clauses.push(vec![a, -literal]);
+                }
+                let mut clause = literals;
+                clause.push(-a);
+                self.clauses.push(clause);
+                Node::Lit(a)
+            }
+        }
+    }
+
+    // The sequential counter encoding of the at most one constraint of a package,
+    // which needs a linear number of clauses instead of a quadratic one
+    fn at_most_one(&mut self, i: usize) {
+        let vars = self.domains[i].vars().collect::<Vec<_>>();
+        let mut prev: Option<Literal> = None;
+        for (j, &x) in vars.iter().enumerate() {
+            if let Some(s) = prev {
+                self.clauses.push(vec![-x, -s]);
+            }
+            if j + 1 < vars.len() {
+                let s = self.fresh();
+                self.clauses.push(vec![-x, s]);
+                if let Some(p) = prev {
+                    self.clauses.push(vec![-p, s]);
+                }
+                prev = Some(s);
+            }
+        }
+    }
+
+    fn objective(&self, objective: PbObjective<'_>) -> Vec<(u64, Literal)> {
+        let mut terms = Vec::new();
+        for domain in &self.domains {
+            for (v, var) in (1..).zip(domain.vars()) {
+                let weight = match objective {
+                    PbObjective::InstalledPackages => 1,
+                    PbObjective::DistanceFromNewest => domain.newest - v,
+                    PbObjective::InstallCost(costs) => costs
+                        .get(domain.pid)
+                        .and_then(|costs| costs.get(v as usize - 1))
+                        .copied()
+                        .unwrap_or(0),
+                };
+                if weight != 0 {
+                    terms.push((weight, var));
+                }
+            }
+        }
+        terms
+    }
+
+    fn literal_map(&self) -> LiteralMap {
+        LiteralMap {
+            versions: self
+                .domains
+                .iter()
+                .flat_map(|d| (1..=d.newest).map(move |v| (d.pid, v)))
+                .collect(),
+        }
+    }
+}
+
+impl ExprFold for Cnf {
+    type Output = Node;
+
+    fn atom(&mut self, atom: &AtomicExpr) -> Node {
+        let (literals, negated) = match *atom {
+            AtomicExpr::VerEq { pid, version: 0 } => (self.domain(pid).vars().collect(), true),
+            AtomicExpr::VerEq { pid, version } => {
+                (self.domain(pid).vars_in(version, version).collect(), false)
+            }
+            AtomicExpr::VerLE { pid, version } => (
+                self.domain(pid)
+                    .vars_in(version.saturating_add(1), Version::MAX)
+                    .collect(),
+                true,
+            ),
+            AtomicExpr::VerGE { version: 0, .. } => return Node::Const(true),
+            AtomicExpr::VerGE { pid, version } => (
+                self.domain(pid).vars_in(version, Version::MAX).collect(),
+                false,
+            ),
+        };
+        let node = self.any(literals);
+        if negated {
+            self.not(node)
+        } else {
+            node
+        }
+    }
+
+    fn not(&mut self, e: Node) -> Node {
+        match e {
+            Node::Const(c) => Node::Const(!c),
+            Node::Lit(literal) => Node::Lit(-literal),
+        }
+    }
+
+    fn and(&mut self, l: Node, r: Node) -> Node {
+        let (l, r) = (self.not(l), self.not(r));
+        let or = self.or(l, r);
+        self.not(or)
+    }
+
+    fn or(&mut self, l: Node, r: Node) -> Node {
+        match (l, r) {
+            (Node::Const(true), _) | (_, Node::Const(true)) => Node::Const(true),
+            (Node::Const(false), e) | (e, Node::Const(false)) => e,
+            (Node::Lit(l), Node::Lit(r)) => self.any(vec![l, r]),
+        }
+    }
+
+    fn implies(&mut self, l: Node, r: Node) -> Node {
+        let l = self.not(l);
+        self.or(l, r)
+    }
+
+    fn bot(&mut self) -> Node {
+        Node::Const(false)
+    }
+
+    fn top(&mut self) -> Node {
+        Node::Const(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{
+        Package, PackageVer, Range, Repository, Requirement, RequirementSet,
+    };
+    use crate::vec1;
+
+    use super::{export_dimacs, export_opb, PbObjective};
+
+    #[test]
+    fn test_export_dimacs() {
+        // version 1 of package 0 requires package 1, version 2 conflicts with it
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet::from_antidep(Requirement::any_version(1)),
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::new(0, vec1![Range::point(2)]));
+
+        let (cnf, map) = export_dimacs(&repo, &reqs).unwrap();
+        println!("{cnf}");
+        assert_eq!(map.versions, vec![(0, 1), (0, 2), (1, 1)]);
+        assert!(cnf.starts_with("p cnf "));
+        // the toplevel requirement is a unit clause on version 2 of package 0
+        assert!(cnf.lines().any(|line| line == "2 0"));
+        assert_eq!(map.plan_from_assignment([-1, 2, -3, 4]), vec![(0, 2)]);
+
+        let (opb, _) = export_opb(&repo, &reqs, Some(PbObjective::DistanceFromNewest)).unwrap();
+        println!("{opb}");
+        assert!(opb.contains("min: +1 x1 ;"));
+        assert!(opb.contains("-1 x1 -1 x2 >= -1 ;"));
+        assert!(opb.contains("+1 x2 >= 1 ;"));
+    }
+}
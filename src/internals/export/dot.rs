@@ -0,0 +1,203 @@
+// Graphviz export of dependency graphs, with one node per package. Dependency edges are
+// labeled by the required versions, conflict edges are dashed and labeled by the
+// conflicting versions. The edges of a package gather the requirements of every version
+// drawn, all versions for a closure and the installed version for a plan
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use snafu::ResultExt;
+use tinyset::SetU32;
+
+use crate::internals::{
+    closure::{Closure, EdgeKind},
+    types::*,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum DotGraph<'a> {
+    Closure(&'a Closure),
+    Plan(&'a Plan),
+}
+
+impl<'a> From<&'a Closure> for DotGraph<'a> {
+    fn from(closure: &'a Closure) -> Self {
+        Self::Closure(closure)
+    }
+}
+
+impl<'a> From<&'a Plan> for DotGraph<'a> {
+    fn from(plan: &'a Plan) -> Self {
+        Self::Plan(plan)
+    }
+}
+
+pub fn dot<'a, S: PackageSource + ?Sized>(
+    repo: &S,
+    graph: impl Into<DotGraph<'a>>,
+) -> Result<String, ResolutionError> {
+    let mut out = Vec::new();
+    write_dot(repo, graph, &mut out)?;
+    Ok(String::from_utf8(out).expect("Impossible: DOT output is not valid UTF-8"))
+}
+
+pub fn write_dot<'a, S: PackageSource + ?Sized, W: Write>(
+    repo: &S,
+    graph: impl Into<DotGraph<'a>>,
+    out: &mut W,
+) -> Result<(), ResolutionError> {
+    let graph = graph.into();
+    let mut nodes = match graph {
+        DotGraph::Closure(closure) => closure.iter().map(|pid| (pid, None)).collect::<Vec<_>>(),
+        DotGraph::Plan(plan) => plan
+            .installed()
+            .into_iter()
+            .map(|(pid, v)| (pid, Some(v)))
+            .collect(),
+    };
+    nodes.sort_unstable();
+    let mut drawn = SetU32::new();
+    for &(pid, _) in &nodes {
+        drawn.insert(pid);
+    }
+
+    let mut edges = Vec::new();
+    for &(pid, installed) in &nodes {
+        let package = repo.try_fetch_package(pid)?;
+        let reqs = (1..)
+            .zip(&package.versions)
+            .filter(|&(v, _)| installed.map_or(true, |installed| installed == v))
+            .map(|(_, ver)| &ver.requirements);
+        for (target, kind, set) in requirement_edges(reqs) {
+            if drawn.contains(target) {
+                edges.push((pid, target, kind, set));
+            }
+        }
+    }
+
+    let name = match graph {
+        DotGraph::Closure(_) => "closure",
+        DotGraph::Plan(_) => "plan",
+    };
+    write_graph(name, &nodes, &edges, out).context(IoSnafu)
+}
+
+// The edges of requirement sets, the versions required from or conflicting with every
+// package being gathered into a single edge. Conflicts come after the dependencies on
+// the same package
+pub(super) fn requirement_edges<'a>(
+    reqs: impl Iterator<Item = &'a RequirementSet>,
+) -> Vec<(PackageId, EdgeKind, RangeSet)> {
+    let mut targets = BTreeMap::<(PackageId, bool), RangeSet>::new();
+    for reqs in reqs {
+        let reqs = reqs
+            .dependencies
+            .iter()
+            .map(|req| (req, false))
+            .chain(reqs.conflicts.iter().map(|req| (req, true)));
+        for (req, conflict) in reqs {
+            let set = targets.entry((req.package, conflict)).or_default();
+            *set = set.union(&RangeSet::from(&req.versions));
+        }
+    }
+    targets
+        .into_iter()
+        .map(|((target, conflict), set)| {
+            let kind = if conflict {
+                EdgeKind::Conflict
+            } else {
+                EdgeKind::Dependency
+            };
+            (target, kind, set)
+        })
+        .collect()
+}
+
+fn write_graph<W: Write>(
+    name: &str,
+    nodes: &[(PackageId, Option<Version>)],
+    edges: &[(PackageId, PackageId, EdgeKind, RangeSet)],
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "digraph {name} {{")?;
+    writeln!(out, "    node [shape=box];")?;
+    for (pid, installed) in nodes {
+        match installed {
+            Some(v) => writeln!(out, "    p{pid} [label=\"{pid} = {v}\"];")?,
+            None => writeln!(out, "    p{pid} [label=\"{pid}\"];")?,
+        }
+    }
+    for (from, to, kind, set) in edges {
+        match kind {
+            EdgeKind::Dependency => writeln!(out, "    p{from} -> p{to} [label=\"{set}\"];")?,
+            EdgeKind::Conflict => writeln!(
+                out,
+                "    p{from} -> p{to} [label=\"{set}\", style=dashed, color=red, arrowhead=tee];"
+            )?,
+        }
+    }
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        closure::{compute_closure, ClosureOptions},
+        types::{Package, PackageVer, Plan, Range, Repository, Requirement, RequirementSet},
+    };
+    use crate::vec1;
+
+    use super::dot;
+
+    #[test]
+    fn test_dot() {
+        // package 0 requires version 1 or 2 of package 1, its second version conflicts
+        // with version 3 of package 2
+        let empty = || PackageVer {
+            requirements: Default::default(),
+        };
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::new(
+                            1,
+                            vec1![Range::interval_unchecked(1, 2)],
+                        )),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet {
+                            dependencies: vec![Requirement::single_version(1, 2)],
+                            conflicts: vec![Requirement::single_version(2, 3)],
+                            formula: None,
+                        },
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![empty(), empty()],
+            },
+            Package {
+                id: 2,
+                versions: vec![empty(), empty(), empty()],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let closure = compute_closure(&repo, &reqs, &ClosureOptions::new()).unwrap();
+        let graph = dot(&repo, &closure).unwrap();
+        println!("{graph}");
+        assert!(graph.starts_with("digraph closure {"));
+        assert!(graph.contains("p0 -> p1 [label=\"[1, 2]\"];"));
+        assert!(graph.contains("p0 -> p2 [label=\"{3}\", style=dashed"));
+
+        let plan: Plan = vec![(0, 1), (1, 2)];
+        let graph = dot(&repo, &plan).unwrap();
+        println!("{graph}");
+        assert!(graph.contains("p1 [label=\"1 = 2\"];"));
+        assert!(graph.contains("p0 -> p1"));
+        assert!(!graph.contains("p2"));
+    }
+}
@@ -0,0 +1,192 @@
+// Mermaid flowcharts of plans and unsat cores, for embedding in issue reports and
+// generated documentation. Edges are drawn as in the Graphviz export: dependency edges
+// are labeled by the required versions and conflict edges are dotted
+use std::io::{self, Write};
+
+use snafu::ResultExt;
+use tinyset::SetU32;
+
+use crate::internals::{closure::EdgeKind, types::*};
+
+use super::dot::requirement_edges;
+
+// The dependency graph of the installed versions of a plan
+pub fn mermaid_plan<S: PackageSource + ?Sized>(
+    repo: &S,
+    plan: &Plan,
+) -> Result<String, ResolutionError> {
+    let mut out = Vec::new();
+    write_mermaid_plan(repo, plan, &mut out)?;
+    Ok(String::from_utf8(out).expect("Impossible: Mermaid output is not valid UTF-8"))
+}
+
+pub fn write_mermaid_plan<S: PackageSource + ?Sized, W: Write>(
+    repo: &S,
+    plan: &Plan,
+    out: &mut W,
+) -> Result<(), ResolutionError> {
+    let mut nodes = plan.installed();
+    nodes.sort_unstable();
+    let mut installed = SetU32::new();
+    for &(pid, _) in &nodes {
+        installed.insert(pid);
+    }
+
+    let mut edges = Vec::new();
+    for &(pid, v) in &nodes {
+        let package = repo.try_fetch_package(pid)?;
+        let ver = package.versions.get(v as usize - 1).ok_or_else(|| {
+            InvalidInputSnafu {
+                message: format!("version {v} of package {pid} does not exist"),
+            }
+            .build()
+        })?;
+        for (target, kind, set) in requirement_edges([&ver.requirements].into_iter()) {
+            if installed.contains(target) {
+                edges.push((format!("p{pid}"), target, kind, set));
+            }
+        }
+    }
+    write_plan(&nodes, &edges, out).context(IoSnafu)
+}
+
+fn write_plan<W: Write>(
+    nodes: &[(PackageId, Version)],
+    edges: &[(String, PackageId, EdgeKind, RangeSet)],
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "flowchart LR")?;
+    for (pid, v) in nodes {
+        writeln!(out, "    p{pid}[\"{pid} = {v}\"]")?;
+    }
+    write_edges(edges, out)
+}
+
+// The requirements of an unsat core. Every package with versions in the core is drawn
+// as a subgraph holding these versions, requirements point to the package they are on
+pub fn mermaid_core(core: &ConstraintSet) -> String {
+    let mut out = Vec::new();
+    write_mermaid_core(core, &mut out).expect("Impossible: failed to write to memory");
+    String::from_utf8(out).expect("Impossible: Mermaid output is not valid UTF-8")
+}
+
+pub fn write_mermaid_core<W: Write>(
+    core: &ConstraintSet,
+    out: &mut W,
+) -> Result<(), ResolutionError> {
+    write_core(core, out).context(IoSnafu)
+}
+
+fn write_core<W: Write>(core: &ConstraintSet, out: &mut W) -> io::Result<()> {
+    let mut versions = Vec::new();
+    for (pid, reqs) in core.package_reqs.iter() {
+        for (v, reqs) in reqs.iter() {
+            versions.push((pid, v, reqs));
+        }
+    }
+
+    let mut edges = Vec::new();
+    for &(pid, v, reqs) in &versions {
+        for (target, kind, set) in requirement_edges([reqs].into_iter()) {
+            edges.push((format!("v{pid}_{v}"), target, kind, set));
+        }
+    }
+    for (target, kind, set) in requirement_edges([&core.toplevel_reqs].into_iter()) {
+        edges.push(("top".to_string(), target, kind, set));
+    }
+
+    writeln!(out, "flowchart LR")?;
+    if !core.toplevel_reqs.dependencies.is_empty() || !core.toplevel_reqs.conflicts.is_empty() {
+        writeln!(out, "    top([\"requirements\"])")?;
+    }
+    let mut drawn = SetU32::new();
+    for (i, &(pid, v, _)) in versions.iter().enumerate() {
+        if drawn.insert(pid) {
+            writeln!(out, "    subgraph p{pid} [\"package {pid}\"]")?;
+        }
+        writeln!(out, "        v{pid}_{v}[\"{pid} = {v}\"]")?;
+        if versions
+            .get(i + 1)
+            .map_or(true, |&(next, _, _)| next != pid)
+        {
+            writeln!(out, "    end")?;
+        }
+    }
+    // packages without versions in the core are plain nodes
+    for (_, target, _, _) in &edges {
+        if drawn.insert(*target) {
+            writeln!(out, "    p{target}[\"package {target}\"]")?;
+        }
+    }
+    write_edges(&edges, out)
+}
+
+fn write_edges<W: Write>(
+    edges: &[(String, PackageId, EdgeKind, RangeSet)],
+    out: &mut W,
+) -> io::Result<()> {
+    for (from, to, kind, set) in edges {
+        match kind {
+            EdgeKind::Dependency => writeln!(out, "    {from} -->|\"{set}\"| p{to}")?,
+            EdgeKind::Conflict => writeln!(out, "    {from} -.->|\"conflicts {set}\"| p{to}")?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        solver::simple_solve,
+        types::{
+            Package, PackageVer, Plan, Repository, Requirement, RequirementSet, ResolutionResult,
+        },
+    };
+
+    use super::{mermaid_core, mermaid_plan};
+
+    #[test]
+    fn test_mermaid() {
+        // package 0 requires package 1, which conflicts with package 2
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_antidep(Requirement::any_version(2)),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+
+        let plan: Plan = vec![(0, 1), (1, 1), (2, 0)];
+        let chart = mermaid_plan(&repo, &plan).unwrap();
+        println!("{chart}");
+        assert!(chart.starts_with("flowchart LR\n"));
+        assert!(chart.contains("p0 -->|\"𝒰\"| p1"));
+        assert!(!chart.contains("p2"));
+
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(0),
+            Requirement::any_version(2),
+        ]);
+        let ResolutionResult::UnsatWithCore { core } = simple_solve(&repo, &reqs).unwrap() else {
+            panic!("expected an unsat core")
+        };
+        let chart = mermaid_core(&core);
+        println!("{chart}");
+        assert!(chart.contains("subgraph p1 [\"package 1\"]"));
+        assert!(chart.contains("v1_1 -.->|\"conflicts 𝒰\"| p2"));
+        assert!(chart.contains("top -->|\"𝒰\"| p0"));
+    }
+}
@@ -0,0 +1,144 @@
+// SMT-LIB2 export of the constraints generated for a resolution problem, so that they
+// can be inspected or fed to other solvers. Every assertion is named and preceded by a
+// comment with the symbolic expression it was generated from
+use std::io::{self, Write};
+
+use bumpalo::Bump;
+use snafu::ResultExt;
+
+use crate::internals::{
+    constraints::{add_essential_constraints, find_closure, AsConstraints},
+    types::*,
+};
+
+pub fn export_smtlib<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Result<String, ResolutionError> {
+    let mut out = Vec::new();
+    write_smtlib(repo, requirements, &mut out)?;
+    Ok(String::from_utf8(out).expect("Impossible: SMT-LIB output is not valid UTF-8"))
+}
+
+pub fn write_smtlib<S: PackageSource + ?Sized, W: Write>(
+    repo: &S,
+    requirements: &RequirementSet,
+    out: &mut W,
+) -> Result<(), ResolutionError> {
+    let closure = find_closure(repo, requirements.into_iter())?;
+    let mut pids = closure.iter().collect::<Vec<_>>();
+    pids.sort_unstable();
+
+    let allocator = Bump::new();
+    let mut assertions = Vec::new();
+    for &pid in &pids {
+        repo.package_constraints(&allocator, pid, &mut |sym_expr| {
+            assertions.push((format!("package {pid}"), sym_expr))
+        });
+    }
+    add_essential_constraints(&allocator, repo, |sym_expr, pid| {
+        assertions.push((format!("essential {pid}"), sym_expr))
+    });
+    let mut index = 0;
+    requirements.add_symbolic_constraints(&allocator, |sym_expr| {
+        assertions.push((format!("toplevel {index}"), sym_expr));
+        index += 1;
+    });
+
+    write_assertions(&pids, &assertions, out).context(IoSnafu)
+}
+
+fn write_assertions<W: Write>(
+    pids: &[PackageId],
+    assertions: &[(String, Expr<'_>)],
+    out: &mut W,
+) -> io::Result<()> {
+    writeln!(out, "(set-option :produce-unsat-cores true)")?;
+    writeln!(out, "(set-logic QF_LIA)")?;
+    for pid in pids {
+        writeln!(out, "(declare-const v{pid} Int)")?;
+    }
+    for (i, (origin, sym_expr)) in assertions.iter().enumerate() {
+        writeln!(out, "; {origin}: {sym_expr}")?;
+        writeln!(
+            out,
+            "(assert (! {} :named a{i}))",
+            sym_expr.fold(&mut SmtLib)
+        )?;
+    }
+    writeln!(out, "(check-sat)")?;
+    writeln!(out, "(get-model)")?;
+    writeln!(out, "(get-unsat-core)")
+}
+
+struct SmtLib;
+
+impl ExprFold for SmtLib {
+    type Output = String;
+
+    fn atom(&mut self, atom: &AtomicExpr) -> String {
+        match atom {
+            AtomicExpr::VerEq { pid, version } => format!("(= v{pid} {version})"),
+            AtomicExpr::VerLE { pid, version } => format!("(<= v{pid} {version})"),
+            AtomicExpr::VerGE { pid, version } => format!("(>= v{pid} {version})"),
+        }
+    }
+
+    fn not(&mut self, e: String) -> String {
+        format!("(not {e})")
+    }
+
+    fn and(&mut self, l: String, r: String) -> String {
+        format!("(and {l} {r})")
+    }
+
+    fn or(&mut self, l: String, r: String) -> String {
+        format!("(or {l} {r})")
+    }
+
+    fn implies(&mut self, l: String, r: String) -> String {
+        format!("(=> {l} {r})")
+    }
+
+    fn bot(&mut self) -> String {
+        "false".to_string()
+    }
+
+    fn top(&mut self) -> String {
+        "true".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{Package, PackageVer, Repository, Requirement, RequirementSet};
+
+    use super::export_smtlib;
+
+    #[test]
+    fn test_export_smtlib() {
+        // version 1 of package 0 requires package 1, which conflicts with package 0
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_antidep(Requirement::any_version(0)),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let smtlib = export_smtlib(&repo, &reqs).unwrap();
+        println!("{smtlib}");
+        assert!(smtlib.contains("(declare-const v0 Int)"));
+        assert!(smtlib.contains("(declare-const v1 Int)"));
+        assert!(smtlib.contains("; toplevel 0: "));
+        assert!(smtlib.contains("(assert (! (not (= v0 0)) :named a"));
+        assert!(smtlib.ends_with("(check-sat)\n(get-model)\n(get-unsat-core)\n"));
+    }
+}
@@ -0,0 +1,386 @@
+// C bindings, for package managers written in other languages. Enabled by the `ffi`
+// feature, the shared and static libraries are built with
+//
+//   cargo build --release --features ffi
+//
+// Repositories, requirement sets and results are opaque handles created and freed by the
+// functions here. Packages are numbered from 0 in the order they are added and versions
+// from 1, as in `Repository`. Requirements are on the versions `lower..=upper` of a
+// package, or on every version if `lower` is 0. Functions returning `c_int` return 0 on
+// success and -1 on null handles or invalid arguments. Panics never unwind into the
+// caller: a resolution that panics reports `RESOLV_ERROR`, the other functions fail as
+// they do on invalid arguments
+use std::{
+    ffi::{c_char, c_int, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use serde_json::json;
+
+use crate::internals::{
+    solver::{optimize_minimal, optimize_newest, simple_solve},
+    types::*,
+};
+
+pub struct ResolvResult {
+    result: Res,
+    // the first plan, sorted by package
+    plan: Plan,
+}
+
+#[repr(C)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct ResolvPlanEntry {
+    pub package: PackageId,
+    // 0 if the package is not installed
+    pub version: Version,
+}
+
+pub const RESOLV_SAT: c_int = 0;
+pub const RESOLV_UNSAT: c_int = 1;
+pub const RESOLV_ERROR: c_int = 2;
+
+pub const RESOLV_GOAL_ANY: c_int = 0;
+pub const RESOLV_GOAL_NEWEST: c_int = 1;
+pub const RESOLV_GOAL_MINIMAL: c_int = 2;
+
+fn requirement(package: PackageId, lower: Version, upper: Version) -> Option<Requirement> {
+    if lower == 0 {
+        Some(Requirement::any_version(package))
+    } else {
+        Requirement::range(package, lower, upper)
+    }
+}
+
+fn add_requirement(reqs: &mut RequirementSet, req: Requirement, conflict: c_int) {
+    if conflict == 0 {
+        reqs.add_dep(req)
+    } else {
+        reqs.add_antidep(req)
+    }
+}
+
+// The body of an entry point, `on_panic` being returned if it panics
+fn guarded<T>(on_panic: T, body: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or(on_panic)
+}
+
+fn into_handle<T>(value: T) -> *mut T {
+    Box::into_raw(Box::new(value))
+}
+
+/// # Safety
+///
+/// `handle` is null or was returned by this module and not freed yet
+unsafe fn free_handle<T>(handle: *mut T) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn resolv_repository_new() -> *mut Repository {
+    guarded(ptr::null_mut(), || into_handle(Repository::new(Vec::new())))
+}
+
+/// # Safety
+///
+/// `repo` is null or a repository not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_repository_free(repo: *mut Repository) {
+    guarded((), || free_handle(repo))
+}
+
+/// Add a package with the versions 1 to `versions` and no requirements. Returns the id of
+/// the package, -1 on a null handle
+///
+/// # Safety
+///
+/// `repo` is null or a repository not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_repository_add_package(
+    repo: *mut Repository,
+    versions: Version,
+) -> i64 {
+    guarded(-1, || {
+        let Some(repo) = repo.as_mut() else {
+            return -1;
+        };
+        let id = repo.packages().len() as PackageId;
+        repo.packages_mut().push(Package {
+            id,
+            versions: (0..versions)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        });
+        id as i64
+    })
+}
+
+/// Make a version of a package depend on versions of `target`, or conflict with them if
+/// `conflict` is not 0
+///
+/// # Safety
+///
+/// `repo` is null or a repository not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_repository_add_requirement(
+    repo: *mut Repository,
+    package: PackageId,
+    version: Version,
+    target: PackageId,
+    lower: Version,
+    upper: Version,
+    conflict: c_int,
+) -> c_int {
+    guarded(-1, || {
+        let Some(repo) = repo.as_mut() else {
+            return -1;
+        };
+        if target as usize >= repo.packages().len() {
+            return -1;
+        }
+        let ver = repo
+            .packages_mut()
+            .get_mut(package as usize)
+            .and_then(|package| package.versions.get_mut((version as usize).checked_sub(1)?));
+        let (Some(ver), Some(req)) = (ver, requirement(target, lower, upper)) else {
+            return -1;
+        };
+        add_requirement(&mut ver.requirements, req, conflict);
+        0
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn resolv_requirements_new() -> *mut RequirementSet {
+    guarded(ptr::null_mut(), || into_handle(RequirementSet::default()))
+}
+
+/// # Safety
+///
+/// `reqs` is null or a requirement set not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_requirements_free(reqs: *mut RequirementSet) {
+    guarded((), || free_handle(reqs))
+}
+
+/// Require versions of a package, or conflict with them if `conflict` is not 0
+///
+/// # Safety
+///
+/// `reqs` is null or a requirement set not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_requirements_add(
+    reqs: *mut RequirementSet,
+    package: PackageId,
+    lower: Version,
+    upper: Version,
+    conflict: c_int,
+) -> c_int {
+    guarded(-1, || {
+        let (Some(reqs), Some(req)) = (reqs.as_mut(), requirement(package, lower, upper)) else {
+            return -1;
+        };
+        add_requirement(reqs, req, conflict);
+        0
+    })
+}
+
+/// Resolve the requirements for one of the `RESOLV_GOAL_*` goals. Returns null on null
+/// handles or an unknown goal
+///
+/// # Safety
+///
+/// `repo` and `reqs` are null or handles not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_solve(
+    repo: *const Repository,
+    reqs: *const RequirementSet,
+    goal: c_int,
+) -> *mut ResolvResult {
+    // a resolution that panics is reported as an error, other panics return null
+    guarded(ptr::null_mut(), || {
+        let (Some(repo), Some(reqs)) = (repo.as_ref(), reqs.as_ref()) else {
+            return ptr::null_mut();
+        };
+        let solve = || match goal {
+            RESOLV_GOAL_ANY => Some(simple_solve(repo, reqs)),
+            RESOLV_GOAL_NEWEST => Some(optimize_newest(repo, reqs)),
+            RESOLV_GOAL_MINIMAL => Some(optimize_minimal(repo, reqs)),
+            _ => None,
+        };
+        let result = catch_unwind(AssertUnwindSafe(solve)).unwrap_or_else(|e| {
+            let message = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "the resolution panicked".to_string());
+            Some(Err(BackendSnafu { message }.build()))
+        });
+        let Some(result) = result else {
+            return ptr::null_mut();
+        };
+        let plan = match &result {
+            Ok(r) => r
+                .typed_plan()
+                .map(|plan| plan.to_plan())
+                .unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+        into_handle(ResolvResult { result, plan })
+    })
+}
+
+/// # Safety
+///
+/// `res` is null or a result not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_result_free(res: *mut ResolvResult) {
+    guarded((), || free_handle(res))
+}
+
+/// One of `RESOLV_SAT`, `RESOLV_UNSAT` and `RESOLV_ERROR`, -1 on a null handle
+///
+/// # Safety
+///
+/// `res` is null or a result not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_result_status(res: *const ResolvResult) -> c_int {
+    guarded(-1, || match res.as_ref().map(|res| &res.result) {
+        None => -1,
+        Some(Ok(r)) if r.is_sat() => RESOLV_SAT,
+        Some(Ok(_)) => RESOLV_UNSAT,
+        Some(Err(_)) => RESOLV_ERROR,
+    })
+}
+
+/// The number of entries of the plan, 0 unless the requirements are satisfiable
+///
+/// # Safety
+///
+/// `res` is null or a result not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_result_plan_len(res: *const ResolvResult) -> usize {
+    guarded(0, || res.as_ref().map_or(0, |res| res.plan.len()))
+}
+
+/// Write an entry of the plan, entries being sorted by package
+///
+/// # Safety
+///
+/// `res` is null or a result not freed yet, `entry` is null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn resolv_result_plan_entry(
+    res: *const ResolvResult,
+    index: usize,
+    entry: *mut ResolvPlanEntry,
+) -> c_int {
+    guarded(-1, || {
+        let (Some(res), Some(entry)) = (res.as_ref(), entry.as_mut()) else {
+            return -1;
+        };
+        let Some(&(package, version)) = res.plan.get(index) else {
+            return -1;
+        };
+        *entry = ResolvPlanEntry { package, version };
+        0
+    })
+}
+
+/// The result in the JSON schema of `ResolutionResult::to_json`, or
+/// `{ "status": "error", "message": string }`. Returns null on a null handle, the string
+/// is freed with `resolv_string_free`
+///
+/// # Safety
+///
+/// `res` is null or a result not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_result_json(res: *const ResolvResult) -> *mut c_char {
+    guarded(ptr::null_mut(), || {
+        let Some(res) = res.as_ref() else {
+            return ptr::null_mut();
+        };
+        let json = match &res.result {
+            Ok(r) => r.to_json(),
+            Err(e) => json!({ "status": "error", "message": e.to_string() }),
+        };
+        CString::new(json.to_string()).map_or(ptr::null_mut(), CString::into_raw)
+    })
+}
+
+/// # Safety
+///
+/// `s` is null or a string returned by this module and not freed yet
+#[no_mangle]
+pub unsafe extern "C" fn resolv_string_free(s: *mut c_char) {
+    guarded((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s))
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{ffi::CStr, ptr};
+
+    use super::{
+        guarded, resolv_repository_add_package, resolv_repository_add_requirement,
+        resolv_repository_free, resolv_repository_new, resolv_requirements_add,
+        resolv_requirements_free, resolv_requirements_new, resolv_result_free, resolv_result_json,
+        resolv_result_plan_entry, resolv_result_plan_len, resolv_result_status, resolv_solve,
+        resolv_string_free, ResolvPlanEntry, RESOLV_GOAL_ANY, RESOLV_GOAL_NEWEST, RESOLV_SAT,
+    };
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let repo = resolv_repository_new();
+            assert_eq!(resolv_repository_add_package(repo, 2), 0);
+            assert_eq!(resolv_repository_add_package(repo, 1), 1);
+            assert_eq!(resolv_repository_add_requirement(repo, 0, 2, 1, 1, 1, 0), 0);
+            assert_eq!(
+                resolv_repository_add_requirement(repo, 0, 3, 1, 0, 0, 0),
+                -1
+            );
+
+            let reqs = resolv_requirements_new();
+            assert_eq!(resolv_requirements_add(reqs, 0, 0, 0, 0), 0);
+            let res = resolv_solve(repo, reqs, RESOLV_GOAL_NEWEST);
+            assert_eq!(resolv_result_status(res), RESOLV_SAT);
+            assert_eq!(resolv_result_plan_len(res), 2);
+            let mut entry = ResolvPlanEntry::default();
+            assert_eq!(resolv_result_plan_entry(res, 0, &mut entry), 0);
+            assert_eq!(
+                entry,
+                ResolvPlanEntry {
+                    package: 0,
+                    version: 2
+                }
+            );
+            let json = resolv_result_json(res);
+            println!("{:?}", CStr::from_ptr(json));
+            assert!(CStr::from_ptr(json).to_str().unwrap().contains("\"sat\""));
+            resolv_string_free(json);
+            resolv_result_free(res);
+
+            assert_eq!(resolv_requirements_add(reqs, 1, 0, 0, 1), 0);
+            let res = resolv_solve(repo, reqs, RESOLV_GOAL_ANY);
+            assert_eq!(resolv_result_status(res), RESOLV_SAT);
+            assert_eq!(resolv_result_plan_entry(res, 0, &mut entry), 0);
+            assert_eq!(entry.version, 1);
+            resolv_result_free(res);
+
+            assert!(resolv_solve(repo, reqs, 7).is_null());
+            assert_eq!(resolv_result_status(ptr::null()), -1);
+            resolv_requirements_free(reqs);
+            resolv_repository_free(repo);
+        }
+        // panics stop at the boundary
+        assert_eq!(guarded(-1, || panic!("Impossible: boom")), -1);
+    }
+}
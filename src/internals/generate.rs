@@ -0,0 +1,171 @@
+// Deterministic synthetic repositories for benchmarking, independent of proptest so that
+// benchmark profiles can be shared between crates and produce the same instances
+// everywhere. Dependencies always point to packages with larger ids, which keeps the
+// dependency graph acyclic, while conflicts may target any other package
+use std::cmp::{max, min};
+
+use crate::internals::types::*;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Profile {
+    pub packages: usize,
+    // the maximum number of versions of a package, every package has at least one
+    pub versions: Version,
+    // the number of dependencies of every version, fewer near the end of the repository
+    pub fanout: usize,
+    // the probability of a version conflicting with some other package
+    pub conflict_density: f64,
+    // the number of packages required by the toplevel requirements
+    pub roots: usize,
+    pub seed: u64,
+}
+
+impl Profile {
+    pub fn small() -> Self {
+        Self {
+            packages: 50,
+            versions: 5,
+            fanout: 2,
+            conflict_density: 0.05,
+            roots: 3,
+            seed: 0,
+        }
+    }
+
+    pub fn medium() -> Self {
+        Self {
+            packages: 500,
+            versions: 10,
+            fanout: 3,
+            conflict_density: 0.05,
+            roots: 10,
+            seed: 0,
+        }
+    }
+
+    pub fn large() -> Self {
+        Self {
+            packages: 5000,
+            versions: 20,
+            fanout: 4,
+            conflict_density: 0.02,
+            roots: 30,
+            seed: 0,
+        }
+    }
+
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { seed, ..self }
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::small()
+    }
+}
+
+// SplitMix64, small and stable across platforms
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, n)
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % max(n, 1)
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+
+    // a random point or interval within the versions of a package
+    fn range(&mut self, versions: Version) -> Range {
+        let lower = 1 + self.below(versions);
+        let upper = lower + self.below(versions - lower + 1);
+        if lower == upper {
+            Range::point(lower)
+        } else {
+            Range::interval_unchecked(lower, upper)
+        }
+    }
+}
+
+// A repository generated from a profile and requirements on its first packages. The same
+// profile always yields the same instance
+pub fn synthetic(profile: &Profile) -> (Repository, RequirementSet) {
+    let mut rng = Rng(profile.seed);
+    let count = profile.packages;
+    let version_counts = (0..count)
+        .map(|_| 1 + rng.below(profile.versions))
+        .collect::<Vec<_>>();
+    let packages = (0..count)
+        .map(|i| {
+            let versions = (0..version_counts[i])
+                .map(|_| {
+                    let mut requirements = RequirementSet::default();
+                    let later = (count - i - 1) as u64;
+                    for _ in 0..min(profile.fanout as u64, later) {
+                        let target = i + 1 + rng.below(later) as usize;
+                        let range = rng.range(version_counts[target]);
+                        requirements.add_dep(Requirement::new(target as PackageId, vec1![range]));
+                    }
+                    if count > 1 && rng.chance(profile.conflict_density) {
+                        let target = (i + 1 + rng.below(count as u64 - 1) as usize) % count;
+                        let range = rng.range(version_counts[target]);
+                        requirements
+                            .add_antidep(Requirement::new(target as PackageId, vec1![range]));
+                    }
+                    PackageVer { requirements }
+                })
+                .collect();
+            Package {
+                id: i as PackageId,
+                versions,
+            }
+        })
+        .collect();
+    let requirements = RequirementSet::from_deps(
+        (0..min(profile.roots, count))
+            .map(|pid| Requirement::any_version(pid as PackageId))
+            .collect(),
+    );
+    (Repository::new(packages), requirements)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::solver::{optimize_newest, simple_solve};
+
+    use super::{synthetic, Profile};
+
+    #[test]
+    fn test_synthetic() {
+        let profile = Profile::small();
+        let (repo, reqs) = synthetic(&profile);
+        println!("{:x}", repo.fingerprint());
+        assert_eq!(repo.packages().len(), profile.packages);
+        assert_eq!(reqs.dependencies.len(), profile.roots);
+        assert_eq!(synthetic(&profile).0, repo);
+        assert_ne!(synthetic(&profile.with_seed(1)).0, repo);
+        for package in repo.packages() {
+            assert!(!package.versions.is_empty());
+            for ver in &package.versions {
+                for req in &ver.requirements.dependencies {
+                    assert!(req.package > package.id);
+                }
+            }
+        }
+
+        let solved = simple_solve(&repo, &reqs).unwrap();
+        let optimized = optimize_newest(&repo, &reqs).unwrap();
+        assert_eq!(solved.is_sat(), optimized.is_sat());
+    }
+}
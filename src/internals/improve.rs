@@ -0,0 +1,136 @@
+// Local search towards newer versions, for instances on which the optimizer is too slow
+// but arbitrary plans are not good enough. Starting from a plan meeting the requirements,
+// each installed package is bumped in turn to the newest version that keeps the plan
+// satisfiable, no installed package going below its version in the plan. Each bump is
+// validated by checking the constraints under assumptions, so that one solver serves all
+// of them. The result is locally optimal for single bumps, not optimal
+use bumpalo::Bump;
+use z3::{ast::Bool, Context, Model, SatResult, Solver};
+
+use crate::internals::{
+    constraints::{add_all_constraints_within, find_closure},
+    encoding::Encoder,
+    options::SolveOptions,
+    types::*,
+    utils::default_config,
+};
+
+pub fn improve_plan<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    plan: &Plan,
+) -> Result<Plan, ResolutionError> {
+    improve_plan_with_options(repo, requirements, plan, &SolveOptions::default())
+}
+
+// Packages of the closure the plan leaves out are taken as not installed, the improved
+// plan lists every package of the closure in increasing order
+pub fn improve_plan_with_options<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    plan: &Plan,
+    options: &SolveOptions,
+) -> Result<Plan, ResolutionError> {
+    let ctx = Context::new(&default_config());
+    let solver = options.solver(&ctx);
+    let allocator = Bump::new();
+
+    let requirements = options.effective_requirements(requirements);
+    let requirements = &*requirements;
+    let closure = find_closure(repo, requirements.into_iter())?;
+    options.limits.check_closure(closure.len())?;
+    // packages are bumped in increasing order
+    let mut pids = options.ordered_pids(&closure);
+    pids.sort_unstable();
+    let encoder = Encoder::new(&ctx, options.encoding, repo, pids.iter().copied());
+    for domain_constraint in encoder.domain_constraints() {
+        solver.assert(&domain_constraint);
+    }
+    for (option_constraint, _) in options.option_constraints(&encoder, &closure) {
+        solver.assert(&option_constraint);
+    }
+    add_all_constraints_within(
+        &allocator,
+        &encoder,
+        repo,
+        pids.iter().copied(),
+        requirements,
+        &options.limits,
+        |_| options.check_cancelled(),
+        |expr, _, _| solver.assert(&expr.simplify()),
+    )?;
+
+    let version_in =
+        |plan: &Plan, pid| plan.iter().find(|&&(p, _)| p == pid).map_or(0, |&(_, v)| v);
+    let exact = pids
+        .iter()
+        .map(|&pid| encoder.atom(&AtomicExpr::ver_eq(pid, version_in(plan, pid))))
+        .collect::<Vec<_>>();
+    if !check(&solver, &exact)? {
+        return InvalidInputSnafu {
+            message: "the plan does not meet the requirements".to_string(),
+        }
+        .fail();
+    }
+    let mut current = encoder.plan_from_model(&model(&solver), pids.iter().copied());
+
+    for &pid in &pids {
+        let installed = version_in(&current, pid);
+        if installed == 0 {
+            continue;
+        }
+        let newest = repo.newest_ver_of(pid).unwrap_or(installed);
+        // the installed packages keep at least their versions
+        let floors = current
+            .iter()
+            .filter(|&&(p, v)| p != pid && v != 0)
+            .map(|&(p, v)| encoder.atom(&AtomicExpr::ver_ge(p, v)))
+            .collect::<Vec<_>>();
+        for v in (installed + 1..=newest).rev() {
+            let mut assumptions = floors.clone();
+            assumptions.push(encoder.atom(&AtomicExpr::ver_eq(pid, v)));
+            if check(&solver, &assumptions)? {
+                current = encoder.plan_from_model(&model(&solver), pids.iter().copied());
+                break;
+            }
+        }
+    }
+    Ok(current)
+}
+
+fn check<'c>(solver: &Solver<'c>, assumptions: &[Bool<'c>]) -> Result<bool, ResolutionError> {
+    match solver.check_assumptions(assumptions) {
+        SatResult::Sat => Ok(true),
+        SatResult::Unsat => Ok(false),
+        SatResult::Unknown => Err(ResolutionError::unknown(solver.get_reason_unknown())),
+    }
+}
+
+fn model<'c>(solver: &Solver<'c>) -> Model<'c> {
+    solver
+        .get_model()
+        .expect("Impossible: satisfiable but failed to generate a model")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{testing::repository_from_requirements, types::RequirementSet};
+
+    use super::improve_plan;
+
+    #[test]
+    fn test_improve_plan() {
+        // every version of package 0 requires package 1, the newest one requires its
+        // oldest version
+        let repo =
+            repository_from_requirements(&[&["pkg1", "pkg1", "pkg1 in {1}"], &["", ""]]).unwrap();
+        let reqs: RequirementSet = "pkg0".parse().unwrap();
+        let improved = improve_plan(&repo, &reqs, &vec![(0, 1), (1, 1)]).unwrap();
+        println!("{improved:?}");
+        assert_eq!(improved, vec![(0, 3), (1, 1)]);
+        // already as new as single bumps get
+        assert_eq!(improve_plan(&repo, &reqs, &improved).unwrap(), improved);
+        assert!(improve_plan(&repo, &reqs, &vec![(0, 3), (1, 2)]).is_err());
+        assert!(improve_plan(&repo, &reqs, &vec![]).is_err());
+    }
+}
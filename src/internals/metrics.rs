@@ -0,0 +1,230 @@
+// Optimization metrics. A metric builds an integer expression over the versions of the
+// packages in the closure, which the optimizer then minimizes
+use tinyset::SetU32;
+use z3::ast::Int;
+
+use crate::internals::{
+    encoding::Encoder,
+    types::*,
+    utils::{changes, distance_from_newest, downgrades, installed_packages, total_cost},
+};
+
+pub trait Metric<S: PackageSource + ?Sized> {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], repo: &S) -> Int<'c>;
+}
+
+impl<S, F> Metric<S> for F
+where
+    S: PackageSource + ?Sized,
+    F: for<'c> Fn(&Encoder<'c>, &[PackageId], &S) -> Int<'c>,
+{
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], repo: &S) -> Int<'c> {
+        self(encoder, closure, repo)
+    }
+}
+
+// The taxicab distance of all installed packages from their newest versions
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct DistanceFromNewest;
+
+impl<S: PackageSource + ?Sized> Metric<S> for DistanceFromNewest {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], repo: &S) -> Int<'c> {
+        distance_from_newest(
+            encoder,
+            closure
+                .iter()
+                .map(|&pid| (pid, repo.newest_ver_of_unchecked(pid))),
+        )
+    }
+}
+
+// The number of installed packages
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct InstalledPackages;
+
+impl<S: PackageSource + ?Sized> Metric<S> for InstalledPackages {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], _repo: &S) -> Int<'c> {
+        installed_packages(encoder, closure.iter().copied())
+    }
+}
+
+// The number of installed packages that are not meta-packages, i.e. of the artifacts
+// actually installed
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct InstalledArtifacts;
+
+impl<S: PackageSource + ?Sized> Metric<S> for InstalledArtifacts {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], repo: &S) -> Int<'c> {
+        let artifacts = closure.iter().copied();
+        installed_packages(encoder, artifacts.filter(|&pid| !repo.is_meta_package(pid)))
+    }
+}
+
+// The distance of all installed packages from their preferred versions, counted in steps
+// of the preference order of the package source. Without preferences this is the same as
+// `DistanceFromNewest`
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct DistanceFromPreferred;
+
+// The position of every version in the preference order, indexed from version 1
+fn preference_ranks(newest: Version, preferred: &[Version]) -> Vec<u64> {
+    let mut ranks = vec![None; newest as usize];
+    let listed = preferred.iter().filter(|&&v| 1 <= v && v <= newest);
+    let rest = (1..=newest).rev();
+    let mut rank = 0;
+    for v in listed.copied().chain(rest) {
+        let slot = &mut ranks[v as usize - 1];
+        if slot.is_none() {
+            *slot = Some(rank);
+            rank += 1;
+        }
+    }
+    ranks.into_iter().flatten().collect()
+}
+
+impl<S: PackageSource + ?Sized> Metric<S> for DistanceFromPreferred {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], repo: &S) -> Int<'c> {
+        let ranks = closure
+            .iter()
+            .map(|&pid| {
+                let preferred = repo.preferred_versions(pid).unwrap_or_default();
+                (
+                    pid,
+                    preference_ranks(repo.newest_ver_of_unchecked(pid), &preferred),
+                )
+            })
+            .collect();
+        total_cost(encoder, closure.iter().copied(), &ranks)
+    }
+}
+
+// The total cost of the installed versions, e.g. their download sizes. The costs of
+// a package are indexed by version, starting from version 1
+#[derive(Debug, Clone, Copy)]
+pub struct InstallCost<'a>(pub &'a PackageMap<Vec<u64>>);
+
+impl<S: PackageSource + ?Sized> Metric<S> for InstallCost<'_> {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], _repo: &S) -> Int<'c> {
+        total_cost(encoder, closure.iter().copied(), self.0)
+    }
+}
+
+// The number of packages of a plan that are downgraded or removed
+#[derive(Debug, Clone, Copy)]
+pub struct DowngradeCount<'a>(pub &'a Plan);
+
+impl<S: PackageSource + ?Sized> Metric<S> for DowngradeCount<'_> {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], _repo: &S) -> Int<'c> {
+        let mut pids = SetU32::new();
+        for &pid in closure {
+            pids.insert(pid);
+        }
+        downgrades(
+            encoder,
+            self.0
+                .iter()
+                .filter(|(pid, _)| pids.contains(*pid))
+                .copied(),
+        )
+    }
+}
+
+// The number of packages in the closure whose version differs from the one in a plan,
+// packages missing from the plan count as not installed
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeCount<'a>(pub &'a Plan);
+
+impl<S: PackageSource + ?Sized> Metric<S> for ChangeCount<'_> {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], _repo: &S) -> Int<'c> {
+        let current = closure.iter().map(|&pid| {
+            let version = self
+                .0
+                .iter()
+                .find(|(p, _)| *p == pid)
+                .map_or(0, |(_, v)| *v);
+            (pid, version)
+        });
+        changes(encoder, current)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use z3::ast::Int;
+
+    use crate::internals::{
+        encoding::Encoder,
+        objective::Objective,
+        options::SolveOptions,
+        solver::{optimize_with_metrics, optimize_with_objective, optimize_with_options},
+        types::{
+            Goal, ObjectiveValues, Package, PackageId, PackageVer, Repository, Requirement,
+            RequirementSet, ResolutionResult,
+        },
+    };
+    use crate::vec1;
+
+    use super::{preference_ranks, InstalledPackages, Metric};
+
+    #[test]
+    fn test_custom_metric() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..3)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        // prefer the oldest versions
+        fn oldest<'c>(encoder: &Encoder<'c>, closure: &[PackageId], _repo: &Repository) -> Int<'c> {
+            let mut expr = Int::from_u64(encoder.ctx(), 0);
+            for &pid in closure {
+                expr += encoder.version(pid);
+            }
+            expr
+        }
+        let metrics: [&dyn Metric<Repository>; 2] = [&InstalledPackages, &oldest];
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let r = optimize_with_metrics(&repo, &reqs, &metrics, &SolveOptions::default()).unwrap();
+        println!("{r:?}");
+        assert_eq!(
+            r,
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 1)]],
+                objective: Some(ObjectiveValues {
+                    distance_from_newest: 2,
+                    installed: 1
+                }),
+                optimal: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_distance_from_preferred() {
+        assert_eq!(preference_ranks(4, &[2, 9, 2]), vec![3, 0, 2, 1]);
+        assert_eq!(preference_ranks(3, &[]), vec![2, 1, 0]);
+
+        let mut repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..3)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let options = SolveOptions::default();
+        repo.prefer(0, vec![2]);
+        let r = optimize_with_objective(&repo, &reqs, &Objective::preferred(), &options).unwrap();
+        println!("{r:?}");
+        assert_eq!(r.typed_plan().unwrap().installed_version(0), Some(2));
+        let by_goal = optimize_with_options(&repo, &reqs, Goal::Preferred, &options).unwrap();
+        assert_eq!(by_goal.typed_plan().unwrap().installed_version(0), Some(2));
+
+        repo.clear_preference(0);
+        let r = optimize_with_objective(&repo, &reqs, &Objective::preferred(), &options).unwrap();
+        assert_eq!(r.typed_plan().unwrap().installed_version(0), Some(3));
+    }
+}
@@ -1,4 +1,49 @@
+pub mod analysis;
+pub mod audit;
+pub mod batch;
+#[cfg(feature = "bench-internals")]
+pub mod bench;
+#[cfg(feature = "cache")]
+pub mod cache;
+pub mod closure;
+pub mod config;
 pub(crate) mod constraints;
+pub mod cudf;
+#[cfg(feature = "arbitrary")]
+pub mod differential;
+pub mod editor;
+pub(crate) mod encoding;
+pub mod explorer;
+pub mod export;
+#[cfg(feature = "ffi")]
+#[allow(unsafe_code)]
+pub mod ffi;
+pub mod generate;
+pub mod improve;
+pub mod metrics;
+pub mod objective;
+pub mod options;
+pub mod policy;
+pub mod pool;
+pub mod prepared;
+pub mod preprocess;
+#[cfg(feature = "python")]
+#[allow(unsafe_code)]
+pub mod python;
+pub mod render;
+pub mod repair;
+pub mod resolver;
+pub mod screen;
+#[cfg(feature = "service")]
+pub mod service;
+pub mod session;
+#[cfg(feature = "shards")]
+#[allow(unsafe_code)]
+pub mod shard;
+pub mod solutions;
 pub mod solver;
+pub mod task;
+pub mod testing;
+pub mod tracking;
 pub mod types;
 pub(crate) mod utils;
@@ -0,0 +1,229 @@
+// Multi-objective optimization. An objective is a list of levels, each level being a
+// weighted sum of metrics; how the levels are traded off against each other is
+// controlled by the priority mode
+use z3::{ast::Int, Params};
+
+use crate::internals::{
+    encoding::Encoder,
+    metrics::{DistanceFromNewest, DistanceFromPreferred, InstalledPackages, Metric},
+    types::*,
+    utils::zero,
+};
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum Priority {
+    // earlier levels strictly take precedence over later ones
+    #[default]
+    Lexicographic,
+    // no level takes precedence, the result is a pareto optimal plan
+    Pareto,
+    // every level is optimized independently of the others
+    Independent,
+}
+
+impl Priority {
+    fn as_z3_symbol(self) -> &'static str {
+        match self {
+            Priority::Lexicographic => "lex",
+            Priority::Pareto => "pareto",
+            Priority::Independent => "box",
+        }
+    }
+}
+
+type Level<'m, S> = Vec<(u64, Box<dyn Metric<S> + 'm>)>;
+
+pub struct Objective<'m, S: PackageSource + ?Sized> {
+    levels: Vec<Level<'m, S>>,
+    priority: Priority,
+    // the goal the objective was made for, until it is changed
+    goal: Option<Goal>,
+}
+
+// adapter for metrics only available as trait objects
+struct ByRef<'m, S: PackageSource + ?Sized>(&'m dyn Metric<S>);
+
+impl<S: PackageSource + ?Sized> Metric<S> for ByRef<'_, S> {
+    fn build<'c>(&self, encoder: &Encoder<'c>, closure: &[PackageId], repo: &S) -> Int<'c> {
+        self.0.build(encoder, closure, repo)
+    }
+}
+
+impl<'m, S: PackageSource + ?Sized> Objective<'m, S> {
+    pub fn new() -> Self {
+        Self {
+            levels: Vec::new(),
+            priority: Priority::default(),
+            goal: None,
+        }
+    }
+
+    // Minimize the metrics lexicographically, in the order given
+    pub fn lexicographic(metrics: &[&'m dyn Metric<S>]) -> Self {
+        metrics.iter().fold(Self::new(), |objective, &metric| {
+            objective.minimize(ByRef(metric))
+        })
+    }
+
+    pub fn for_goal(goal: Goal) -> Self {
+        let objective = match goal {
+            Goal::Newest => Self::new()
+                .minimize(DistanceFromNewest)
+                .minimize(InstalledPackages),
+            Goal::Minimal => Self::new()
+                .minimize(InstalledPackages)
+                .minimize(DistanceFromNewest),
+            Goal::Preferred => Self::new()
+                .minimize(DistanceFromPreferred)
+                .minimize(InstalledPackages),
+        };
+        Self {
+            goal: Some(goal),
+            ..objective
+        }
+    }
+
+    // Like `Goal::Newest`, with the preference order of the package source in place of
+    // the numeric order of the versions
+    pub fn preferred() -> Self {
+        Self::for_goal(Goal::Preferred)
+    }
+
+    // Add a level minimizing a single metric, after all the existing levels
+    pub fn minimize(self, metric: impl Metric<S> + 'm) -> Self {
+        self.minimize_weighted(vec![(1, Box::new(metric))])
+    }
+
+    // Add a level minimizing the weighted sum of the metrics, after all the existing levels
+    pub fn minimize_weighted(mut self, metrics: Level<'m, S>) -> Self {
+        self.levels.push(metrics);
+        self.goal = None;
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self.goal = None;
+        self
+    }
+
+    pub fn get_priority(&self) -> Priority {
+        self.priority
+    }
+
+    // The goal of objectives made by `for_goal` and left as they are
+    pub fn goal(&self) -> Option<Goal> {
+        self.goal
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.is_empty()
+    }
+
+    pub(crate) fn apply_params(&self, p: &mut Params) {
+        p.set_symbol("priority", self.priority.as_z3_symbol());
+    }
+
+    // One expression per level
+    pub(crate) fn build<'c>(
+        &self,
+        encoder: &Encoder<'c>,
+        closure: &[PackageId],
+        repo: &S,
+    ) -> Vec<Int<'c>> {
+        let ctx = encoder.ctx();
+        self.levels
+            .iter()
+            .map(|level| match level.as_slice() {
+                [(1, metric)] => metric.build(encoder, closure, repo),
+                _ => {
+                    let mut expr = zero(ctx);
+                    for (weight, metric) in level {
+                        expr += Int::from_u64(ctx, *weight) * metric.build(encoder, closure, repo);
+                    }
+                    expr
+                }
+            })
+            .collect()
+    }
+}
+
+impl<S: PackageSource + ?Sized> Default for Objective<'_, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        metrics::{DistanceFromNewest, InstalledPackages, Metric},
+        options::SolveOptions,
+        solver::optimize_with_objective,
+        types::{
+            ObjectiveValues, Package, PackageVer, Repository, Requirement, RequirementSet,
+            ResolutionResult,
+        },
+    };
+    use crate::vec1;
+
+    use super::{Objective, Priority};
+
+    #[test]
+    fn test_weighted_objective() {
+        // the newest version of package 0 requires package 1, the older one does not
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: Default::default(),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let options = SolveOptions::deterministic(0);
+
+        // an extra package weighs more than being one version behind
+        let weights: Vec<(u64, Box<dyn Metric<Repository>>)> = vec![
+            (1, Box::new(DistanceFromNewest)),
+            (2, Box::new(InstalledPackages)),
+        ];
+        let objective = Objective::new().minimize_weighted(weights);
+        let r = optimize_with_objective(&repo, &reqs, &objective, &options).unwrap();
+        println!("{r:?}");
+        assert_eq!(
+            r,
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 1), (1, 0)]],
+                objective: Some(ObjectiveValues {
+                    distance_from_newest: 1,
+                    installed: 1
+                }),
+                optimal: true
+            }
+        );
+
+        let objective = Objective::new()
+            .minimize(DistanceFromNewest)
+            .minimize(InstalledPackages)
+            .priority(Priority::Pareto);
+        let r = optimize_with_objective(&repo, &reqs, &objective, &options).unwrap();
+        println!("{r:?}");
+        assert!(r.is_sat());
+    }
+}
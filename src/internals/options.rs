@@ -0,0 +1,848 @@
+// Options controlling how a resolution is carried out
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use tinyset::SetU32;
+use z3::{
+    ast::{Bool, Int},
+    Context, Params, Solver,
+};
+
+use crate::internals::{
+    audit::{Audit, AuditHook},
+    config::SolverConfig,
+    encoding::{Encoder, Encoding},
+    metrics::{ChangeCount, DistanceFromNewest, DowngradeCount, Metric},
+    policy::Policy,
+    types::*,
+    utils::{installed_packages, zero},
+};
+
+// What to do with packages of the current plan that would be downgraded
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum DowngradePolicy {
+    #[default]
+    Allow,
+    // installed packages stay installed and are never downgraded
+    Forbid,
+    // downgrades are allowed but minimized before any other metric, only affects optimization
+    Penalize,
+}
+
+// How to choose among plans the optimizer finds equally good, e.g. so that the plans of
+// a build are stable across runs. Only affects lexicographic objectives, the tie-breaker
+// being minimized after all of their levels. Pareto and box objectives, the plain solver
+// and the enumerating optimizers ignore it
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub enum TieBreaker {
+    // the first plan z3 finds
+    #[default]
+    Arbitrary,
+    // the newest versions, compared package by package in increasing order of ids. A
+    // single objective weighting each package above all the packages after it
+    Lexicographic,
+    // the fewest packages whose version differs from a reference plan, see `ChangeCount`
+    FewestChanges(Plan),
+    // the least total distance of the installed versions from the newest ones
+    LeastDistance,
+}
+
+impl TieBreaker {
+    // The objectives breaking ties, in order of precedence
+    pub(crate) fn objectives<'c, S: PackageSource + ?Sized>(
+        &self,
+        encoder: &Encoder<'c>,
+        pids: &[PackageId],
+        repo: &S,
+    ) -> Vec<Int<'c>> {
+        match self {
+            TieBreaker::Arbitrary => Vec::new(),
+            TieBreaker::Lexicographic => {
+                // the distances from the newest versions as the digits of a number, in
+                // mixed radix so that no package outweighs the ones before it
+                let ctx = encoder.ctx();
+                let mut pids = pids.to_vec();
+                pids.sort_unstable();
+                let mut expr = zero(ctx);
+                for pid in pids {
+                    let newest = repo.newest_ver_of_unchecked(pid);
+                    let distance = Int::from_u64(ctx, newest) - encoder.version(pid);
+                    expr = expr * Int::from_u64(ctx, newest + 1) + distance;
+                }
+                vec![expr]
+            }
+            TieBreaker::FewestChanges(plan) => vec![ChangeCount(plan).build(encoder, pids, repo)],
+            TieBreaker::LeastDistance => vec![DistanceFromNewest.build(encoder, pids, repo)],
+        }
+    }
+}
+
+// Thresholds trading optimality for latency, e.g. in interactive tools. They only affect
+// the resolutions optimizing through z3's optimizer
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct OptimizeOptions {
+    // accept the first plan whose metrics are at or below these values, one per level of
+    // the objective in order. Levels without a threshold are not constrained
+    pub good_enough: Option<Vec<u64>>,
+    // minimize the levels of a lexicographic objective one at a time, moving on to the
+    // next level with the best plan found so far once a level has taken this much time.
+    // Box and pareto objectives get this much time per level for all of them at once.
+    // Plans cut short are not `optimal`
+    pub max_time_per_objective: Option<Duration>,
+    // find some plan first, then spend what is left of this much time improving it, the
+    // best plan found being returned when the time is up. `SolveStats::optimal` tells
+    // whether the plan was proven optimal
+    pub deadline: Option<Duration>,
+}
+
+impl OptimizeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn good_enough(mut self, thresholds: Vec<u64>) -> Self {
+        self.good_enough = Some(thresholds);
+        self
+    }
+
+    pub fn max_time_per_objective(mut self, time: Duration) -> Self {
+        self.max_time_per_objective = Some(time);
+        self
+    }
+
+    pub fn deadline(mut self, time: Duration) -> Self {
+        self.deadline = Some(time);
+        self
+    }
+
+    // The time left of the deadline after `spent`, None without a deadline
+    pub(crate) fn remaining(&self, spent: Duration) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_sub(spent))
+    }
+
+    // Whether the optimizer stops with the best plan found so far when out of time
+    pub(crate) fn is_bounded(&self) -> bool {
+        self.max_time_per_objective.is_some() || self.deadline.is_some()
+    }
+
+    // The timeout of a check minimizing `levels` levels at once
+    pub(crate) fn apply_params(&self, p: &mut Params, levels: usize, spent: Duration) {
+        let per_objective = self
+            .max_time_per_objective
+            .map(|time| time.as_millis().saturating_mul(levels.max(1) as u128));
+        // a timeout of 0 would disable it
+        let remaining = self.remaining(spent).map(|time| time.as_millis().max(1));
+        let timeout = match (per_objective, remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if let Some(millis) = timeout {
+            p.set_u32("timeout", millis.try_into().unwrap_or(u32::MAX));
+        }
+    }
+}
+
+// Bounds on the work done before z3 runs, so that pathological closures fail with
+// `ResolutionError::LimitExceeded` instead of exhausting the memory of the host. Every
+// assertion is checked against the limits as it is generated
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub struct Limits {
+    // packages in the closure of the requirements
+    pub max_closure: Option<usize>,
+    // constraints generated for the solver
+    pub max_assertions: Option<usize>,
+    // bytes held by the allocator of the symbolic constraints
+    pub max_arena_bytes: Option<usize>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_closure(mut self, packages: usize) -> Self {
+        self.max_closure = Some(packages);
+        self
+    }
+
+    pub fn max_assertions(mut self, assertions: usize) -> Self {
+        self.max_assertions = Some(assertions);
+        self
+    }
+
+    pub fn max_arena_bytes(mut self, bytes: usize) -> Self {
+        self.max_arena_bytes = Some(bytes);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn check(limit: &'static str, value: usize, max: Option<usize>) -> Result<(), ResolutionError> {
+        match max {
+            Some(max) if value > max => LimitExceededSnafu { limit, value, max }.fail(),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_closure(&self, packages: usize) -> Result<(), ResolutionError> {
+        Self::check("closure size", packages, self.max_closure)
+    }
+
+    pub(crate) fn check_constraints(
+        &self,
+        assertions: usize,
+        arena_bytes: usize,
+    ) -> Result<(), ResolutionError> {
+        Self::check("assertions", assertions, self.max_assertions)?;
+        Self::check("arena bytes", arena_bytes, self.max_arena_bytes)
+    }
+}
+
+// How often a resolution looks whether it was cancelled while z3 solves
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Cancels the resolutions whose options carry a clone of the token, between two chunks of
+// packages being encoded or by interrupting z3 while it solves. Tokens compare equal if
+// they are clones of each other
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancelToken {}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct SolveOptions {
+    // threads, random seed and other parameters of z3
+    pub config: SolverConfig,
+    // fix the order in which packages are encoded and models are enumerated and disable
+    // parallel solving, so that the same inputs always yield the same plan
+    pub deterministic: bool,
+    // how the constraints are encoded for z3
+    pub encoding: Encoding,
+    // the currently installed plan, which downgrades are relative to
+    pub current: Option<Plan>,
+    pub downgrades: DowngradePolicy,
+    // pinned, held and forbidden packages
+    pub policy: Policy,
+    // prune unusable versions before encoding, see `preprocess`
+    pub preprocess: bool,
+    // only encode the versions the requirements leave possible, see `restricted_closure`
+    pub restrict_versions: bool,
+    pub optimize: OptimizeOptions,
+    // a previous plan the optimizer should stay close to, e.g. when re-resolving after a
+    // small change of the requirements. Its versions only break ties between optimal plans
+    pub warm_start: Option<Plan>,
+    // at most this many packages of the closure are installed
+    pub max_installed: Option<u64>,
+    // no package outside of the allowlist is installed
+    pub allowed: Option<BTreeSet<PackageId>>,
+    // receives a record of every resolution, see `audit`
+    pub audit: Option<Audit>,
+    pub limits: Limits,
+    pub cancel: Option<CancelToken>,
+    // only applies to lexicographic objectives, see `TieBreaker`
+    pub tie_breaker: TieBreaker,
+    // solve without tracking the constraints first, tracking them only to extract the core
+    // of unsatisfiable requirements. Speeds up workloads that are mostly satisfiable at the
+    // cost of encoding unsatisfiable ones twice, only affects the plain solver
+    pub untracked_first: bool,
+}
+
+impl SolveOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Reproducible resolution: the same repository and requirements always
+    // yield the same plan(s)
+    pub fn deterministic(seed: u32) -> Self {
+        Self {
+            config: SolverConfig::new().seed(seed),
+            deterministic: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.config.seed = Some(seed);
+        self
+    }
+
+    // Number of threads z3 may use for this resolution
+    pub fn threads(mut self, threads: u32) -> Self {
+        self.config.threads = Some(threads);
+        self
+    }
+
+    pub fn config(mut self, config: SolverConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn never_downgrade(mut self, current: Plan) -> Self {
+        self.current = Some(current);
+        self.downgrades = DowngradePolicy::Forbid;
+        self
+    }
+
+    pub fn penalize_downgrades(mut self, current: Plan) -> Self {
+        self.current = Some(current);
+        self.downgrades = DowngradePolicy::Penalize;
+        self
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn preprocess(mut self) -> Self {
+        self.preprocess = true;
+        self
+    }
+
+    pub fn restrict_versions(mut self) -> Self {
+        self.restrict_versions = true;
+        self
+    }
+
+    pub fn optimize(mut self, optimize: OptimizeOptions) -> Self {
+        self.optimize = optimize;
+        self
+    }
+
+    pub fn warm_start(mut self, previous: Plan) -> Self {
+        self.warm_start = Some(previous);
+        self
+    }
+
+    // e.g. for size-constrained container images
+    pub fn limit_total_installs(mut self, n: u64) -> Self {
+        self.max_installed = Some(n);
+        self
+    }
+
+    // e.g. for checking that requirements can be met from a curated set of packages
+    pub fn closed_world(mut self, allowed: SetU32) -> Self {
+        self.allowed = Some(allowed.iter().collect());
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn cancel_with(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn untracked_first(mut self) -> Self {
+        self.untracked_first = true;
+        self
+    }
+
+    pub fn tie_breaker(mut self, tie_breaker: TieBreaker) -> Self {
+        self.tie_breaker = tie_breaker;
+        self
+    }
+
+    pub fn audit(mut self, hook: impl AuditHook + 'static) -> Self {
+        self.audit = Some(Audit::new(hook));
+        self
+    }
+
+    // The requirements extended with the conflicts enforcing the policy
+    pub(crate) fn effective_requirements<'a>(
+        &self,
+        requirements: &'a RequirementSet,
+    ) -> Cow<'a, RequirementSet> {
+        if self.policy.is_empty() {
+            Cow::Borrowed(requirements)
+        } else {
+            let mut requirements = requirements.clone();
+            requirements.add_antideps(self.policy.conflicts(self.current.as_ref()));
+            Cow::Owned(requirements)
+        }
+    }
+
+    // Constraints of the options on the packages in the closure: no downgrades if the policy
+    // says so, the limit on the number of installed packages and the allowlist. Each comes
+    // with the restriction it enforces, for tracking it in unsat cores
+    pub(crate) fn option_constraints<'c>(
+        &self,
+        encoder: &Encoder<'c>,
+        closure: &SetU32,
+    ) -> Vec<(Bool<'c>, Restriction)> {
+        let mut constraints = self.downgrade_constraints(encoder, closure);
+        if let Some(n) = self.max_installed {
+            let installed = installed_packages(encoder, closure.iter());
+            constraints.push((
+                installed.le(&Int::from_u64(encoder.ctx(), n)),
+                Restriction::MaxInstalled(n),
+            ));
+        }
+        if let Some(allowed) = &self.allowed {
+            let forbidden = closure.iter().filter(|pid| !allowed.contains(pid));
+            constraints.extend(
+                forbidden.map(|pid| (encoder.not_installed(pid), Restriction::NotAllowed(pid))),
+            );
+        }
+        constraints
+    }
+
+    // Constraints forbidding the downgrade of the packages in the closure
+    fn downgrade_constraints<'c>(
+        &self,
+        encoder: &Encoder<'c>,
+        closure: &SetU32,
+    ) -> Vec<(Bool<'c>, Restriction)> {
+        match (&self.current, self.downgrades) {
+            (Some(current), DowngradePolicy::Forbid) => current
+                .iter()
+                .filter(|&&(pid, version)| version != 0 && closure.contains(pid))
+                .map(|&(package, version)| {
+                    (
+                        encoder.atom(&AtomicExpr::ver_ge(package, version)),
+                        Restriction::NoDowngrade { package, version },
+                    )
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    // The metric to be minimized ahead of everything else, if the policy says so
+    pub(crate) fn downgrade_penalty(&self) -> Option<DowngradeCount<'_>> {
+        match (&self.current, self.downgrades) {
+            (Some(current), DowngradePolicy::Penalize) => Some(DowngradeCount(current)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn solver<'ctx>(&self, ctx: &'ctx Context) -> Solver<'ctx> {
+        let solver = self.config.solver(ctx, self.encoding);
+        solver.set_params(&self.solver_params(ctx));
+        solver
+    }
+
+    pub(crate) fn solver_params<'ctx>(&self, ctx: &'ctx Context) -> Params<'ctx> {
+        let mut p = self.config.solver_params(ctx);
+        self.apply_params(&mut p);
+        p
+    }
+
+    pub(crate) fn optimizer_params<'ctx>(&self, ctx: &'ctx Context) -> Params<'ctx> {
+        let mut p = self.config.optimizer_params(ctx);
+        self.apply_params(&mut p);
+        p
+    }
+
+    fn apply_params(&self, p: &mut Params) {
+        if self.deterministic {
+            p.set_u32("threads", 1);
+        }
+    }
+
+    // Called after each chunk of packages encoded, fails if the resolution was cancelled
+    pub(crate) fn check_cancelled(&self) -> Result<(), ResolutionError> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            return CancelledSnafu.fail();
+        }
+        Ok(())
+    }
+
+    // Run a resolution in a context, interrupting z3 from a watcher thread if the token is
+    // cancelled meanwhile. The failure of an interrupted resolution is reported as a
+    // cancellation
+    pub(crate) fn interruptible<T>(
+        &self,
+        ctx: &Context,
+        resolve: impl FnOnce() -> Result<T, ResolutionError>,
+    ) -> Result<T, ResolutionError> {
+        let Some(token) = &self.cancel else {
+            return resolve();
+        };
+        let done = AtomicBool::new(false);
+        let handle = ctx.handle();
+        let res = thread::scope(|s| {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    if token.is_cancelled() {
+                        handle.interrupt();
+                        return;
+                    }
+                    thread::sleep(CANCEL_POLL_INTERVAL);
+                }
+            });
+            let res = resolve();
+            done.store(true, Ordering::Relaxed);
+            res
+        });
+        match res {
+            Err(_) if token.is_cancelled() => CancelledSnafu.fail(),
+            res => res,
+        }
+    }
+
+    // The packages in the closure in the order they should be encoded
+    pub(crate) fn ordered_pids(&self, closure: &SetU32) -> Vec<PackageId> {
+        let mut pids = closure.iter().collect::<Vec<_>>();
+        if self.deterministic {
+            pids.sort_unstable();
+        }
+        pids
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tinyset::SetU32;
+    use z3::Context;
+
+    use crate::internals::{
+        config::SolverConfig,
+        metrics::{InstalledPackages, Metric},
+        solver::{
+            optimize_with_metrics, optimize_with_options, simple_solve_with_options,
+            simple_solve_with_progress,
+        },
+        testing::repository_from_requirements,
+        types::{
+            Goal, Package, PackageVer, Progress, Range, Repository, Requirement, RequirementSet,
+            ResolutionError, ResolutionResult, Restriction,
+        },
+        utils::default_config,
+    };
+    use crate::vec1;
+
+    use super::{CancelToken, Limits, SolveOptions, TieBreaker};
+
+    #[test]
+    fn test_downgrade_policy() {
+        // package 1 requires an older version of package 0
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (0..3)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::new(
+                        0,
+                        vec1![Range::interval_unchecked(1, 2)],
+                    )),
+                }],
+            },
+        ]);
+        let current = vec![(0, 3)];
+        let reqs = RequirementSet::from_dep(Requirement::any_version(1));
+
+        let options = SolveOptions::deterministic(0).never_downgrade(current.clone());
+        let r = simple_solve_with_options(&repo, &reqs, &options).unwrap();
+        println!("{r:?}");
+        assert!(r.is_unsat());
+        let r = optimize_with_options(&repo, &reqs, Goal::Minimal, &options).unwrap();
+        assert!(r.is_unsat());
+
+        let options = SolveOptions::deterministic(0).penalize_downgrades(current);
+        let r = optimize_with_options(&repo, &reqs, Goal::Minimal, &options).unwrap();
+        println!("{r:?}");
+        assert!(r.is_sat());
+    }
+
+    #[test]
+    fn test_tie_breaker() {
+        // the newest versions of the packages conflict, so either of them is newest in
+        // the optimal plans
+        let repo =
+            repository_from_requirements(&[&["", "!pkg1 in {2}"], &["", "!pkg0 in {2}"]]).unwrap();
+        let reqs: RequirementSet = "pkg0; pkg1".parse().unwrap();
+        let optimize = |tie_breaker| {
+            let options = SolveOptions::deterministic(0).tie_breaker(tie_breaker);
+            let r = optimize_with_options(&repo, &reqs, Goal::Newest, &options).unwrap();
+            println!("{r:?}");
+            r.typed_plan().unwrap().installed().collect::<Vec<_>>()
+        };
+        assert_eq!(optimize(TieBreaker::Lexicographic), vec![(0, 2), (1, 1)]);
+        let reference = vec![(0, 1), (1, 2)];
+        assert_eq!(
+            optimize(TieBreaker::FewestChanges(reference.clone())),
+            reference
+        );
+
+        // the newest version of package 0 conflicts with the two newest ones of package 1,
+        // the least distance trades a version of package 0 for two of package 1
+        let repo =
+            repository_from_requirements(&[&["", "!pkg1 in [2, 3]"], &["", "", ""]]).unwrap();
+        let optimize = |tie_breaker| {
+            let options = SolveOptions::deterministic(0).tie_breaker(tie_breaker);
+            let r = optimize_with_metrics(&repo, &reqs, &[&InstalledPackages], &options).unwrap();
+            println!("{r:?}");
+            r.typed_plan().unwrap().installed().collect::<Vec<_>>()
+        };
+        assert_eq!(optimize(TieBreaker::Lexicographic), vec![(0, 2), (1, 1)]);
+        assert_eq!(optimize(TieBreaker::LeastDistance), vec![(0, 1), (1, 3)]);
+    }
+
+    #[test]
+    fn test_thread_count() {
+        let ctx = Context::new(&default_config());
+        let p = SolveOptions::new()
+            .threads(3)
+            .solver_params(&ctx)
+            .to_string();
+        println!("{p}");
+        assert!(p.contains("threads 3"));
+
+        // unless configured, the thread count of z3 is left alone
+        let p = SolveOptions::new().solver_params(&ctx).to_string();
+        println!("{p}");
+        assert!(!p.contains("threads"));
+
+        let p = SolveOptions::deterministic(0)
+            .threads(3)
+            .solver_params(&ctx)
+            .to_string();
+        assert!(p.contains("threads 1"));
+
+        // deterministic resolutions are not affected by the thread count configured
+        let repo = Repository::new(
+            (0..6)
+                .map(|id| Package {
+                    id,
+                    versions: (0..4)
+                        .map(|_| PackageVer {
+                            requirements: RequirementSet::from_dep(Requirement::any_version(
+                                (id + 1) % 6,
+                            )),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        );
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let expected = simple_solve_with_options(&repo, &reqs, &SolveOptions::deterministic(7));
+        let config = SolverConfig::new().seed(7).autodetect_threads();
+        let options = SolveOptions::deterministic(7).config(config);
+        assert_eq!(simple_solve_with_options(&repo, &reqs, &options), expected);
+    }
+
+    #[test]
+    fn test_warm_start() {
+        // the versions of package 0 require different packages, both plans install two
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(2)),
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let metrics: [&dyn Metric<Repository>; 1] = [&InstalledPackages];
+        for previous in [vec![(0, 1), (1, 1)], vec![(0, 2), (2, 1)]] {
+            let options = SolveOptions::deterministic(0).warm_start(previous.clone());
+            let r = optimize_with_metrics(&repo, &reqs, &metrics, &options).unwrap();
+            println!("{r:?}");
+            let plan = r.typed_plan().unwrap();
+            assert_eq!(plan.installed().collect::<Vec<_>>(), previous);
+        }
+    }
+
+    #[test]
+    fn test_install_limits() {
+        // the newer version of package 0 needs two more packages, the older one only one
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet::from_deps(vec![
+                            Requirement::any_version(1),
+                            Requirement::any_version(2),
+                        ]),
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let solve = |options: SolveOptions| {
+            optimize_with_options(&repo, &reqs, Goal::Newest, &options).unwrap()
+        };
+
+        let r = solve(SolveOptions::deterministic(0).limit_total_installs(2));
+        println!("{r:?}");
+        assert_eq!(r.typed_plan().unwrap().installed_version(0), Some(1));
+        assert!(solve(SolveOptions::deterministic(0).limit_total_installs(1)).is_unsat());
+
+        let allowlist = |pids: [u32; 2]| {
+            let mut allowed = SetU32::new();
+            for pid in pids {
+                allowed.insert(pid);
+            }
+            SolveOptions::deterministic(0).closed_world(allowed)
+        };
+        let r = solve(allowlist([0, 1]));
+        assert_eq!(r.typed_plan().unwrap().installed_version(2), None);
+        // the package outside of the allowlist shows up in the core
+        match solve(allowlist([0, 2])) {
+            ResolutionResult::UnsatWithCore { core } => {
+                assert_eq!(core.restrictions(), &[Restriction::NotAllowed(1)])
+            }
+            r => panic!("unexpected result {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        // a chain of 5 packages, each requiring the next one
+        let repo = Repository::new(
+            (0..5)
+                .map(|id| Package {
+                    id,
+                    versions: vec![PackageVer {
+                        requirements: if id < 4 {
+                            RequirementSet::from_dep(Requirement::any_version(id + 1))
+                        } else {
+                            Default::default()
+                        },
+                    }],
+                })
+                .collect(),
+        );
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+
+        let options = SolveOptions::new().limits(Limits::new().max_closure(3));
+        let e = simple_solve_with_options(&repo, &reqs, &options).unwrap_err();
+        println!("{e}");
+        assert!(matches!(
+            e,
+            ResolutionError::LimitExceeded {
+                value: 5,
+                max: 3,
+                ..
+            }
+        ));
+        let options = SolveOptions::new().limits(Limits::new().max_assertions(2));
+        let e = optimize_with_options(&repo, &reqs, Goal::Newest, &options).unwrap_err();
+        assert!(matches!(e, ResolutionError::LimitExceeded { max: 2, .. }));
+
+        let limits = Limits::new()
+            .max_closure(5)
+            .max_assertions(10)
+            .max_arena_bytes(1 << 20);
+        let options = SolveOptions::new().limits(limits);
+        assert!(simple_solve_with_options(&repo, &reqs, &options)
+            .unwrap()
+            .is_sat());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let repo = Repository::new(
+            (0..3)
+                .map(|id| Package {
+                    id,
+                    versions: vec![PackageVer {
+                        requirements: Default::default(),
+                    }],
+                })
+                .collect(),
+        );
+        let reqs = RequirementSet::from_deps((0..3).map(Requirement::any_version).collect());
+        let mut reports = Vec::new();
+        simple_solve_with_progress(&repo, &reqs, |progress| reports.push(progress)).unwrap();
+        println!("{reports:?}");
+        assert!(reports.contains(&Progress::PackagesEncoded {
+            encoded: 3,
+            total: 3
+        }));
+
+        let token = CancelToken::new();
+        let options = SolveOptions::new().cancel_with(token.clone());
+        assert!(simple_solve_with_options(&repo, &reqs, &options).is_ok());
+        token.cancel();
+        let e = optimize_with_options(&repo, &reqs, Goal::Newest, &options).unwrap_err();
+        assert!(matches!(e, ResolutionError::Cancelled { .. }));
+
+        // z3 giving up once interrupted is a cancellation
+        let ctx = Context::new(&default_config());
+        let e = options
+            .interruptible(&ctx, || Err::<(), _>(ResolutionError::unknown(None)))
+            .unwrap_err();
+        assert!(matches!(e, ResolutionError::Cancelled { .. }));
+    }
+}
@@ -0,0 +1,137 @@
+// Package policies restricting which versions may be chosen. Policies are translated
+// into toplevel conflicts, so they show up in unsat cores like any other requirement
+use crate::internals::types::*;
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Policy {
+    // packages that may only be installed at the given version
+    pub pins: Vec<(PackageId, Version)>,
+    // packages that may only be installed at the version in the current plan
+    pub holds: Vec<PackageId>,
+    // packages that may not be installed at all
+    pub forbidden: Vec<PackageId>,
+}
+
+impl Policy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pin(mut self, package: PackageId, version: Version) -> Self {
+        self.pins.push((package, version));
+        self
+    }
+
+    pub fn hold(mut self, package: PackageId) -> Self {
+        self.holds.push(package);
+        self
+    }
+
+    pub fn forbid(mut self, package: PackageId) -> Self {
+        self.forbidden.push(package);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty() && self.holds.is_empty() && self.forbidden.is_empty()
+    }
+
+    // The conflicts enforcing the policy, held packages that are not in the current plan
+    // are held at not being installed
+    pub(crate) fn conflicts(&self, current: Option<&Plan>) -> Vec<Requirement> {
+        let current_version = |pid: PackageId| {
+            current
+                .and_then(|plan| plan.iter().find(|(p, _)| *p == pid))
+                .map_or(0, |(_, v)| *v)
+        };
+        let pins = self
+            .pins
+            .iter()
+            .copied()
+            .chain(self.holds.iter().map(|&pid| (pid, current_version(pid))));
+
+        let mut conflicts = Vec::new();
+        for (pid, version) in pins {
+            if version == 0 {
+                conflicts.push(Requirement::any_version(pid));
+                continue;
+            }
+            // every version other than the pinned one
+            let mut others = Vec::new();
+            if let Some(below) = Range::interval(1, version - 1) {
+                others.push(below);
+            }
+            if version < Version::MAX {
+                others.push(Range::interval_unchecked(version + 1, Version::MAX));
+            }
+            if let Ok(others) = Vec1::try_from(others) {
+                conflicts.push(Requirement::new(pid, others));
+            }
+        }
+        for &pid in &self.forbidden {
+            conflicts.push(Requirement::any_version(pid));
+        }
+        conflicts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        options::SolveOptions,
+        solver::simple_solve_with_options,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet, ResolutionResult},
+    };
+    use crate::vec1;
+
+    use super::Policy;
+
+    #[test]
+    fn test_policy() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..3)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let solve =
+            |options: SolveOptions| simple_solve_with_options(&repo, &reqs, &options).unwrap();
+
+        let r = solve(SolveOptions::new().policy(Policy::new().pin(0, 2)));
+        assert_eq!(
+            r,
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 2)]],
+                objective: None,
+                optimal: false
+            }
+        );
+
+        let mut options = SolveOptions::new().policy(Policy::new().hold(0));
+        options.current = Some(vec![(0, 1)]);
+        let r = solve(options);
+        assert_eq!(
+            r,
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 1)]],
+                objective: None,
+                optimal: false
+            }
+        );
+
+        let r = solve(SolveOptions::new().policy(Policy::new().forbid(0)));
+        println!("{r:?}");
+        match r {
+            ResolutionResult::UnsatWithCore { core } => {
+                assert!(core
+                    .toplevel_reqs
+                    .conflicts
+                    .contains(&Requirement::any_version(0)))
+            }
+            _ => panic!("expected an unsat core"),
+        }
+    }
+}
@@ -0,0 +1,331 @@
+// Pools of solvers with the constraints of a repository already asserted, so that services
+// resolving many requirement sets against one repository pay for its encoding once per
+// worker rather than once per resolution. z3 contexts cannot move between threads, each
+// worker is a thread owning its context and solver, and resolves the requirement sets sent
+// to it in solver scopes as `solve_batch` does, with the limits, cancellation and audit of
+// the options of the pool. Solvers are checked out of the pool for exclusive use and
+// checked back in when dropped, unless their worker stopped. Updating the repository of
+// the pool retires the workers encoding the old one once its fingerprint differs, those
+// still checked out count against the capacity until they are checked in
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Sender, SyncSender},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+};
+
+use bumpalo::Bump;
+use tinyset::SetU32;
+use z3::Context;
+
+use crate::internals::{
+    audit::{audited, ResolutionKind},
+    batch::ScopedSolver,
+    options::SolveOptions,
+    solver::{simple_solve_in, Hooks},
+    types::*,
+    utils::default_config,
+};
+
+type Job = (RequirementSet, SyncSender<Res>);
+
+#[derive(Debug)]
+struct Worker {
+    jobs: Sender<Job>,
+    // the repository version the worker encodes, see `PoolState::generation`
+    generation: u64,
+    thread: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    repo: Arc<Repository>,
+    fingerprint: u64,
+    // bumped whenever the repository changes
+    generation: u64,
+    idle: Vec<Worker>,
+    // the workers of every generation, idle or checked out
+    started: usize,
+}
+
+#[derive(Debug)]
+pub struct ContextPool {
+    options: SolveOptions,
+    capacity: usize,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+// A solver checked out of a pool, checked back in when dropped
+#[derive(Debug)]
+pub struct PooledSolver<'p> {
+    pool: &'p ContextPool,
+    worker: Option<Worker>,
+    // whether the worker failed to answer, it is not checked back in then
+    stopped: AtomicBool,
+}
+
+fn spawn_worker(repo: Arc<Repository>, options: SolveOptions, generation: u64) -> Worker {
+    let (jobs, receiver) = channel::<Job>();
+    let thread = thread::spawn(move || {
+        let ctx = Context::new(&default_config());
+        let allocator = Bump::new();
+        let closure = repo.packages().iter().map(|p| p.id).collect::<SetU32>();
+        // a repository outgrowing the limits of the options is encoded for each
+        // requirement set instead, within the limits
+        let scoped = ScopedSolver::new(&ctx, &allocator, &*repo, &closure, &options).ok();
+        let mut stats = SolveStats::default();
+        // the thread ends once the pool drops the worker
+        for (requirements, reply) in receiver {
+            let mut hooks = Hooks {
+                progress: &mut |_| {},
+                stats: &mut stats,
+            };
+            let res = match &scoped {
+                Some(scoped) => audited(
+                    &*repo,
+                    &requirements,
+                    ResolutionKind::Solve,
+                    &options,
+                    || {
+                        options.interruptible(&ctx, || {
+                            let requirements = options.effective_requirements(&requirements);
+                            scoped.solve(&*repo, &requirements, &options, &mut hooks)
+                        })
+                    },
+                ),
+                None => simple_solve_in(&ctx, &Bump::new(), &*repo, &requirements, &options, hooks),
+            };
+            // the solver may have been dropped while waiting
+            let _ = reply.send(res);
+        }
+    });
+    Worker {
+        jobs,
+        generation,
+        thread,
+    }
+}
+
+impl ContextPool {
+    // A pool of at most `capacity` solvers, started as they are first checked out
+    pub fn new(repo: Arc<Repository>, capacity: usize) -> Self {
+        Self::with_options(repo, capacity, SolveOptions::default())
+    }
+
+    pub fn with_options(repo: Arc<Repository>, capacity: usize, options: SolveOptions) -> Self {
+        Self {
+            options,
+            capacity: capacity.max(1),
+            state: Mutex::new(PoolState {
+                fingerprint: repo.fingerprint(),
+                repo,
+                generation: 0,
+                idle: Vec::new(),
+                started: 0,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state
+            .lock()
+            .expect("Impossible: pool panicked while holding the lock")
+    }
+
+    // The fingerprint of the repository of the pool
+    pub fn fingerprint(&self) -> u64 {
+        self.lock().fingerprint
+    }
+
+    // Replace the repository of the pool. Unless its fingerprint is unchanged, the idle
+    // workers are dropped and the checked out ones are dropped when checked in
+    pub fn update(&self, repo: Arc<Repository>) {
+        let fingerprint = repo.fingerprint();
+        let mut state = self.lock();
+        if fingerprint == state.fingerprint {
+            return;
+        }
+        state.repo = repo;
+        state.fingerprint = fingerprint;
+        state.generation += 1;
+        state.started -= state.idle.len();
+        state.idle.clear();
+        self.available.notify_all();
+    }
+
+    // Check out an idle solver, starting one if the pool is below capacity, waiting for one
+    // to be checked in otherwise
+    pub fn checkout(&self) -> PooledSolver<'_> {
+        let mut state = self.lock();
+        loop {
+            if let Some(worker) = state.idle.pop() {
+                return PooledSolver {
+                    pool: self,
+                    worker: Some(worker),
+                    stopped: AtomicBool::new(false),
+                };
+            }
+            if state.started < self.capacity {
+                state.started += 1;
+                let worker =
+                    spawn_worker(state.repo.clone(), self.options.clone(), state.generation);
+                return PooledSolver {
+                    pool: self,
+                    worker: Some(worker),
+                    stopped: AtomicBool::new(false),
+                };
+            }
+            state = self
+                .available
+                .wait(state)
+                .expect("Impossible: pool panicked while holding the lock");
+        }
+    }
+
+    // Start idle solvers until `count` are started or the pool is at capacity, so that the
+    // first resolutions do not wait for the encoding
+    pub fn prestart(&self, count: usize) {
+        let mut state = self.lock();
+        while state.started < count.min(self.capacity) {
+            state.started += 1;
+            let worker = spawn_worker(state.repo.clone(), self.options.clone(), state.generation);
+            state.idle.push(worker);
+        }
+        self.available.notify_all();
+    }
+
+    // The number of solvers waiting to be checked out
+    pub fn idle(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    // Workers of an older repository and stopped ones are dropped, making room for a new
+    // worker
+    fn checkin(&self, worker: Worker, stopped: bool) {
+        let mut state = self.lock();
+        if worker.generation == state.generation && !stopped && !worker.thread.is_finished() {
+            state.idle.push(worker);
+        } else {
+            state.started -= 1;
+        }
+        self.available.notify_one();
+    }
+}
+
+impl<'p> PooledSolver<'p> {
+    // Resolve requirements against the repository the solver was checked out for
+    pub fn solve(&self, requirements: &RequirementSet) -> Res {
+        let worker = self
+            .worker
+            .as_ref()
+            .expect("Impossible: solver used after being checked in");
+        let stopped = || {
+            self.stopped.store(true, Ordering::Relaxed);
+            BackendSnafu {
+                message: "the worker of the pool stopped".to_string(),
+            }
+            .build()
+        };
+        let (reply, result) = sync_channel(1);
+        worker
+            .jobs
+            .send((requirements.clone(), reply))
+            .map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for PooledSolver<'_> {
+    fn drop(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.pool
+                .checkin(worker, self.stopped.load(Ordering::Relaxed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, thread};
+
+    use crate::internals::{
+        options::{CancelToken, Limits, SolveOptions},
+        solver::simple_solve,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet, ResolutionError},
+    };
+
+    use super::ContextPool;
+
+    #[test]
+    fn test_context_pool() {
+        // package i depends on package i + 1
+        let mut repo = Repository::new(
+            (0..4)
+                .map(|id| Package {
+                    id,
+                    versions: vec![PackageVer {
+                        requirements: if id < 3 {
+                            RequirementSet::from_dep(Requirement::any_version(id + 1))
+                        } else {
+                            RequirementSet::default()
+                        },
+                    }],
+                })
+                .collect(),
+        );
+        let pool = ContextPool::new(Arc::new(repo.clone()), 2);
+        pool.prestart(1);
+        assert_eq!(pool.idle(), 1);
+        let sets = (0..4)
+            .map(|id| RequirementSet::from_dep(Requirement::any_version(id)))
+            .collect::<Vec<_>>();
+        thread::scope(|scope| {
+            for reqs in &sets {
+                let (pool, repo) = (&pool, &repo);
+                scope.spawn(move || {
+                    let solver = pool.checkout();
+                    let result = solver.solve(reqs).unwrap();
+                    println!("{result:?}");
+                    assert_eq!(result.is_sat(), simple_solve(repo, reqs).unwrap().is_sat());
+                });
+            }
+        });
+        assert!(pool.idle() >= 1 && pool.idle() <= 2);
+
+        let checked_out = pool.checkout();
+        repo.mask_version(3, 1);
+        pool.update(Arc::new(repo.clone()));
+        assert_eq!(pool.fingerprint(), repo.fingerprint());
+        assert_eq!(pool.idle(), 0);
+        // the solver of the old repository counts against the capacity until checked in
+        pool.prestart(2);
+        assert_eq!(pool.idle(), 1);
+        drop(checked_out);
+        assert_eq!(pool.idle(), 1);
+        pool.prestart(2);
+        assert_eq!(pool.idle(), 2);
+        let solver = pool.checkout();
+        assert!(solver.solve(&sets[0]).unwrap().is_unsat());
+        assert!(solver.solve(&sets[3]).unwrap().is_unsat());
+        drop(solver);
+        assert_eq!(pool.idle(), 2);
+
+        // the limits and the cancellation of the options apply to every resolution
+        let token = CancelToken::new();
+        let options = SolveOptions::default()
+            .limits(Limits::new().max_closure(3))
+            .cancel_with(token.clone());
+        let limited = ContextPool::with_options(Arc::new(repo.clone()), 1, options);
+        let solver = limited.checkout();
+        let e = solver.solve(&sets[0]).unwrap_err();
+        assert!(matches!(e, ResolutionError::LimitExceeded { max: 3, .. }));
+        assert!(solver.solve(&sets[1]).is_ok());
+        token.cancel();
+        let e = solver.solve(&sets[1]).unwrap_err();
+        assert!(matches!(e, ResolutionError::Cancelled { .. }));
+    }
+}
@@ -0,0 +1,197 @@
+// A repository whose symbolic constraints are generated ahead of time. Repeated
+// resolutions against it with different toplevel requirements only need to translate
+// the cached constraints to z3 instead of regenerating them from scratch. The cached
+// constraints own their expressions, so replacing them on a refresh frees the old ones.
+use std::borrow::Cow;
+
+use bumpalo::Bump;
+
+use crate::internals::{
+    constraints::AsConstraints,
+    types::{fingerprint::combine_fingerprints, *},
+};
+
+struct CachedPackage {
+    // the fingerprint of the package as given, the one the repository fingerprint is
+    // made of
+    hash: u64,
+    // the fingerprint of the normalized package, which also changes when the packages it
+    // refers to gain or lose versions
+    normalized_hash: u64,
+    constraints: Vec<OwnedExpr>,
+}
+
+pub struct PreparedRepository<'r> {
+    repo: &'r Repository,
+    packages: Vec<CachedPackage>,
+}
+
+// the constraints are generated from the package normalized against the repository,
+// like `Repository` itself does
+fn prepare_package(repo: &Repository, package: &Package) -> CachedPackage {
+    let normalized = repo
+        .normalized_package(package.id)
+        .expect("Impossible: package is in the repository");
+    let b = Bump::new();
+    let mut constraints = Vec::new();
+    normalized.add_symbolic_constraints(&b, |sym_expr| constraints.push(sym_expr.to_owned_expr()));
+    CachedPackage {
+        hash: package.fingerprint(),
+        normalized_hash: normalized.fingerprint(),
+        constraints,
+    }
+}
+
+impl<'r> PreparedRepository<'r> {
+    pub fn new(repo: &'r Repository) -> Self {
+        let packages = repo
+            .packages()
+            .iter()
+            .map(|package| prepare_package(repo, package))
+            .collect();
+        Self { repo, packages }
+    }
+
+    pub fn repository(&self) -> &'r Repository {
+        self.repo
+    }
+
+    // Switch to a new version of the repository, only regenerating the constraints
+    // of packages whose content changed. Returns the number of regenerated packages
+    pub fn refresh(&mut self, repo: &'r Repository) -> usize {
+        let mut regenerated = 0;
+        self.packages.truncate(repo.packages().len());
+        for (i, package) in repo.packages().iter().enumerate() {
+            let normalized_hash = repo
+                .normalized_package(package.id)
+                .map(|normalized| normalized.fingerprint());
+            match self.packages.get_mut(i) {
+                // the package may have changed as written all the same, e.g. a range widened
+                // past the last version
+                Some(cached) if normalized_hash == Some(cached.normalized_hash) => {
+                    cached.hash = package.fingerprint();
+                }
+                _ => {
+                    let cached = prepare_package(repo, package);
+                    if i < self.packages.len() {
+                        self.packages[i] = cached;
+                    } else {
+                        self.packages.push(cached);
+                    }
+                    regenerated += 1;
+                }
+            }
+        }
+        self.repo = repo;
+        regenerated
+    }
+
+    // The fingerprint of the repository, from the fingerprints of the cached packages
+    pub fn fingerprint(&self) -> u64 {
+        combine_fingerprints(
+            self.packages.iter().map(|package| package.hash),
+            self.repo.masks(),
+            &self.repo.essential_packages(),
+            self.repo.preferences(),
+            self.repo.meta_packages(),
+        )
+    }
+}
+
+impl PackageSource for PreparedRepository<'_> {
+    fn fetch_package(&self, id: PackageId) -> Option<Cow<'_, Package>> {
+        self.repo.fetch_package(id)
+    }
+
+    fn preferred_versions(&self, id: PackageId) -> Option<Cow<'_, [Version]>> {
+        self.repo.preferred_versions(id)
+    }
+
+    fn is_meta_package(&self, id: PackageId) -> bool {
+        self.repo.is_meta_package(id)
+    }
+
+    fn essential_packages(&self) -> Cow<'_, [PackageId]> {
+        self.repo.essential_packages()
+    }
+
+    fn masks_of(&self, id: PackageId) -> Cow<'_, [Requirement]> {
+        self.repo.masks_of(id)
+    }
+
+    fn package_constraints<'s>(
+        &'s self,
+        b: &'s Bump,
+        id: PackageId,
+        expr_cont: &mut dyn FnMut(Expr<'s>),
+    ) {
+        let cached = self
+            .packages
+            .get(id as usize)
+            .unwrap_or_else(|| panic!("Illegal index: index {id} is out of bound"));
+        for sym_expr in &cached.constraints {
+            expr_cont(sym_expr.to_expr(b))
+        }
+        mask_constraints(self, b, id, expr_cont);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        solver::simple_solve,
+        types::{Package, PackageVer, Range, Repository, Requirement, RequirementSet},
+    };
+    use crate::vec1;
+
+    use super::PreparedRepository;
+
+    #[test]
+    fn test_prepared_repository() {
+        let mut repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let expected = simple_solve(&repo, &reqs).unwrap();
+
+        let prepared = PreparedRepository::new(&repo);
+        assert_eq!(simple_solve(&prepared, &reqs).unwrap(), expected);
+        let fingerprint = prepared.fingerprint();
+        drop(prepared);
+
+        let old_repo = repo.clone();
+        repo.packages_mut()[1].versions.push(PackageVer {
+            requirements: Default::default(),
+        });
+        let mut prepared = PreparedRepository::new(&old_repo);
+        assert_eq!(prepared.refresh(&repo), 1);
+        assert_ne!(prepared.fingerprint(), fingerprint);
+        assert_eq!(prepared.fingerprint(), repo.fingerprint());
+
+        // widening the range of a dependency past the last version keeps the constraints
+        // but not the fingerprint
+        let dependency = |upper| {
+            RequirementSet::from_dep(Requirement::new(
+                1,
+                vec1![Range::interval_unchecked(1, upper)],
+            ))
+        };
+        repo.packages_mut()[0].versions[0].requirements = dependency(2);
+        let old_repo = repo.clone();
+        repo.packages_mut()[0].versions[0].requirements = dependency(5);
+        let mut prepared = PreparedRepository::new(&old_repo);
+        assert_eq!(prepared.refresh(&repo), 0);
+        assert_eq!(prepared.fingerprint(), repo.fingerprint());
+    }
+}
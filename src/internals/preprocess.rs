@@ -0,0 +1,208 @@
+// Preprocessing of the closure before it is encoded. Versions that can never be part of
+// a plan are pruned by unit propagation: a version is unusable if it is excluded by the
+// toplevel requirements, directly or through the restrictions they accumulate along
+// forced packages (see `version_restrictions`), if one of its dependencies has no usable
+// version left or if it conflicts with every usable version of a package that is forced
+// to be installed. Pruned versions keep their number but their requirements are replaced
+// by a conflict with the package itself, so the solutions stay the same while the
+// closure and the encoding shrink. Masked versions are unusable as well, the masks,
+// preferred versions and meta-packages of the source are kept in the result
+use intmap::IntMap;
+
+use crate::internals::{closure::version_restrictions, constraints::find_closure, types::*};
+
+pub struct Preprocessed {
+    pub repository: PartialRepository,
+    pub pruned: Vec<(PackageId, Version)>,
+}
+
+pub fn preprocess<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Result<Preprocessed, ResolutionError> {
+    let closure = find_closure(repo, requirements.into_iter())?;
+    let mut pids = closure.iter().collect::<Vec<_>>();
+    pids.sort_unstable();
+    let packages = pids
+        .iter()
+        .map(|&pid| repo.try_fetch_package(pid).map(|p| p.into_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut index = IntMap::new();
+    for (i, &pid) in pids.iter().enumerate() {
+        index.insert(pid as u64, i);
+    }
+    let index_of = |pid: PackageId| {
+        *index
+            .get(pid as u64)
+            .unwrap_or_else(|| panic!("Impossible: package {pid} is not in the closure"))
+    };
+
+    let mut usable = packages
+        .iter()
+        .map(|p| {
+            let masks = repo.masks_of(p.id);
+            let masked = RangeSet::from_ranges(masks.iter().flat_map(|mask| mask.versions.iter()));
+            RangeSet::interval(1, p.newest_version_number()).difference(&masked)
+        })
+        .collect::<Vec<_>>();
+    let mut forced = vec![false; packages.len()];
+    for (pid, restriction) in version_restrictions(repo, requirements)?.iter() {
+        let i = index_of(pid);
+        usable[i] = usable[i].intersection(&restriction.versions);
+        forced[i] = restriction.forced;
+    }
+
+    let is_unusable = |usable: &[RangeSet], ver: &PackageVer| {
+        let dead_dep = ver.deps().any(|dep| {
+            usable[index_of(dep.package)]
+                .intersection(&RangeSet::from(&dep.versions))
+                .is_empty()
+        });
+        let dead_antidep = ver.antideps().any(|antidep| {
+            let i = index_of(antidep.package);
+            forced[i] && usable[i].is_subset(&RangeSet::from(&antidep.versions))
+        });
+        dead_dep || dead_antidep
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (i, package) in packages.iter().enumerate() {
+            let mut pruned = RangeSet::empty();
+            for (v, ver) in (1..).zip(&package.versions) {
+                if usable[i].contains(v) && is_unusable(&usable, ver) {
+                    pruned = pruned.union(&RangeSet::point(v));
+                }
+            }
+            if !pruned.is_empty() {
+                usable[i] = usable[i].difference(&pruned);
+                changed = true;
+            }
+        }
+    }
+
+    let mut repository = PartialRepository::new();
+    repository.essential_packages = repo.essential_packages().into_owned();
+    let mut pruned = Vec::new();
+    for (i, mut package) in packages.into_iter().enumerate() {
+        let pid = package.id;
+        for (v, ver) in (1..).zip(&mut package.versions) {
+            if !usable[i].contains(v) {
+                ver.requirements = RequirementSet::from_antidep(Requirement::any_version(pid));
+                pruned.push((pid, v));
+            }
+        }
+        repository.insert_from(repo, package);
+    }
+    Ok(Preprocessed { repository, pruned })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        options::SolveOptions,
+        solver::simple_solve_with_options,
+        types::{
+            Package, PackageSource, PackageVer, Range, Repository, Requirement, RequirementSet,
+        },
+    };
+    use crate::vec1;
+
+    use super::preprocess;
+
+    #[test]
+    fn test_preprocess() {
+        // version 1 of package 0 requires a version of package 1 that does not exist,
+        // version 1 of package 2 requires version 1 of package 0
+        let empty = || PackageVer {
+            requirements: Default::default(),
+        };
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::new(
+                            1,
+                            vec1![Range::point(5)],
+                        )),
+                    },
+                    empty(),
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![empty()],
+            },
+            Package {
+                id: 2,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::new(
+                            0,
+                            vec1![Range::point(1)],
+                        )),
+                    },
+                    empty(),
+                ],
+            },
+        ]);
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(0),
+            Requirement::any_version(2),
+        ]);
+        let preprocessed = preprocess(&repo, &reqs).unwrap();
+        println!("{:?}", preprocessed.pruned);
+        assert_eq!(preprocessed.pruned, vec![(0, 1), (2, 1)]);
+
+        let r = simple_solve_with_options(&repo, &reqs, &SolveOptions::new().preprocess()).unwrap();
+        println!("{r:?}");
+        assert!(r.is_sat());
+
+        // forcing a pruned version makes the requirements unsatisfiable
+        let reqs = RequirementSet::from_dep(Requirement::new(2, vec1![Range::point(1)]));
+        let r = simple_solve_with_options(&repo, &reqs, &SolveOptions::new().preprocess()).unwrap();
+        assert!(r.is_unsat());
+    }
+
+    #[test]
+    fn test_preprocess_masks() {
+        // both versions of package 0 depend on package 1, the newest version of each
+        // package is masked
+        let mut repo = Repository::new(
+            (0..2)
+                .map(|id| Package {
+                    id,
+                    versions: (0..2)
+                        .map(|_| PackageVer {
+                            requirements: if id == 0 {
+                                RequirementSet::from_dep(Requirement::any_version(1))
+                            } else {
+                                Default::default()
+                            },
+                        })
+                        .collect(),
+                })
+                .collect(),
+        );
+        repo.mask_version(0, 2);
+        repo.mask_version(1, 2);
+        repo.prefer(1, vec![1, 2]);
+        repo.mark_meta_package(0);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let preprocessed = preprocess(&repo, &reqs).unwrap();
+        assert_eq!(preprocessed.pruned, vec![(0, 2), (1, 2)]);
+        assert_eq!(preprocessed.repository.masks, repo.masks());
+        assert_eq!(
+            preprocessed.repository.preferred_versions(1).as_deref(),
+            Some(&[1, 2][..])
+        );
+        assert!(preprocessed.repository.is_meta_package(0));
+
+        let plain = simple_solve_with_options(&repo, &reqs, &SolveOptions::new()).unwrap();
+        let r = simple_solve_with_options(&repo, &reqs, &SolveOptions::new().preprocess()).unwrap();
+        assert_eq!(r, plain);
+        assert_eq!(r.typed_plan().unwrap().installed_version(1), Some(1));
+    }
+}
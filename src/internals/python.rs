@@ -0,0 +1,297 @@
+// Python bindings, for driving the resolver from notebooks and scripts. Enabled by the
+// `python` feature, the extension module is built with maturin:
+//
+//   maturin develop --features python,pyo3/extension-module
+//
+// which loads the cdylib the crate is built as. The doc comments of the items below are
+// their Python docstrings
+//
+// Packages are numbered from 0 in the order they are added and versions from 1, as in
+// `Repository`. Requirements take an optional pair `(lower, upper)` of versions, every
+// version being required when it is left out
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+};
+
+use crate::internals::{
+    options::SolveOptions,
+    render::{render, RenderOptions},
+    solver::{optimize_with_options, simple_solve},
+    types::*,
+};
+
+fn requirement(package: PackageId, versions: Option<(Version, Version)>) -> PyResult<Requirement> {
+    match versions {
+        None => Ok(Requirement::any_version(package)),
+        Some((lower, upper)) => Requirement::range(package, lower, upper)
+            .ok_or_else(|| PyValueError::new_err(format!("invalid versions {lower}..={upper}"))),
+    }
+}
+
+fn render_options(color: bool, unicode: bool, width: usize) -> RenderOptions {
+    RenderOptions::new()
+        .color(color)
+        .unicode(unicode)
+        .width(width)
+}
+
+/// Packages with numbered versions and the requirements of each version
+#[pyclass(name = "Repository")]
+#[derive(Clone, Debug)]
+pub struct PyRepository {
+    pub inner: Repository,
+}
+
+#[pymethods]
+impl PyRepository {
+    /// An empty repository
+    #[new]
+    pub fn new() -> Self {
+        Self {
+            inner: Repository::new(Vec::new()),
+        }
+    }
+
+    /// Add a package with the versions 1 to `versions` and no requirements, returns its id
+    pub fn add_package(&mut self, versions: Version) -> PackageId {
+        let id = self.inner.packages().len() as PackageId;
+        self.inner.packages_mut().push(Package {
+            id,
+            versions: (0..versions)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        });
+        id
+    }
+
+    /// Make a version of a package depend on versions of `target`, or conflict with them.
+    /// `versions` is a pair `(lower, upper)`, every version of `target` by default
+    #[pyo3(signature = (package, version, target, versions = None, conflict = false))]
+    pub fn add_requirement(
+        &mut self,
+        package: PackageId,
+        version: Version,
+        target: PackageId,
+        versions: Option<(Version, Version)>,
+        conflict: bool,
+    ) -> PyResult<()> {
+        if target as usize >= self.inner.packages().len() {
+            return Err(PyValueError::new_err(format!("unknown package {target}")));
+        }
+        let req = requirement(target, versions)?;
+        let ver = self
+            .inner
+            .packages_mut()
+            .get_mut(package as usize)
+            .and_then(|package| package.versions.get_mut((version as usize).checked_sub(1)?))
+            .ok_or_else(|| {
+                PyValueError::new_err(format!("unknown version {version} of {package}"))
+            })?;
+        if conflict {
+            ver.requirements.add_antidep(req)
+        } else {
+            ver.requirements.add_dep(req)
+        }
+        Ok(())
+    }
+
+    /// Exclude a version of a package from resolution
+    pub fn mask_version(&mut self, package: PackageId, version: Version) {
+        self.inner.mask_version(package, version)
+    }
+
+    /// The number of packages
+    pub fn __len__(&self) -> usize {
+        self.inner.packages().len()
+    }
+
+    /// The packages and their requirements as text
+    #[pyo3(signature = (color = false, unicode = true, width = 80))]
+    pub fn render(&self, color: bool, unicode: bool, width: usize) -> String {
+        render(self.inner.clone(), &render_options(color, unicode, width))
+    }
+
+    pub fn __str__(&self) -> String {
+        render(self.inner.clone(), &RenderOptions::default())
+    }
+}
+
+impl Default for PyRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Toplevel requirements, dependencies and conflicts on versions of packages
+#[pyclass(name = "RequirementSet")]
+#[derive(Clone, Debug, Default)]
+pub struct PyRequirementSet {
+    pub inner: RequirementSet,
+}
+
+#[pymethods]
+impl PyRequirementSet {
+    /// An empty set of requirements
+    #[new]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require versions of a package, given as a pair `(lower, upper)`, any version by
+    /// default
+    #[pyo3(signature = (package, versions = None))]
+    pub fn add_dependency(
+        &mut self,
+        package: PackageId,
+        versions: Option<(Version, Version)>,
+    ) -> PyResult<()> {
+        self.inner.add_dep(requirement(package, versions)?);
+        Ok(())
+    }
+
+    /// Forbid versions of a package, given like those of `add_dependency`
+    #[pyo3(signature = (package, versions = None))]
+    pub fn add_conflict(
+        &mut self,
+        package: PackageId,
+        versions: Option<(Version, Version)>,
+    ) -> PyResult<()> {
+        self.inner.add_antidep(requirement(package, versions)?);
+        Ok(())
+    }
+
+    /// The requirements as text
+    #[pyo3(signature = (color = false, unicode = true, width = 80))]
+    pub fn render(&self, color: bool, unicode: bool, width: usize) -> String {
+        render(self.inner.clone(), &render_options(color, unicode, width))
+    }
+
+    pub fn __str__(&self) -> String {
+        render(self.inner.clone(), &RenderOptions::default())
+    }
+}
+
+/// The outcome of a resolution, a plan or the reason the requirements cannot be met
+#[pyclass(name = "ResolutionResult")]
+#[derive(Clone, Debug)]
+pub struct PyResolutionResult {
+    pub inner: ResolutionResult,
+}
+
+#[pymethods]
+impl PyResolutionResult {
+    /// Whether the requirements can be met
+    pub fn is_sat(&self) -> bool {
+        self.inner.is_sat()
+    }
+
+    /// The first plan as (package, version) pairs sorted by package, version 0 standing
+    /// for not installed. None if the requirements are unsatisfiable
+    pub fn plan(&self) -> Option<Vec<(PackageId, Version)>> {
+        Some(self.inner.typed_plan()?.to_plan())
+    }
+
+    /// The installed packages of the first plan with their versions
+    pub fn installed(&self) -> Option<Vec<(PackageId, Version)>> {
+        Some(self.inner.typed_plan()?.installed().collect())
+    }
+
+    /// The plan or the unsat core as text
+    #[pyo3(signature = (color = false, unicode = true, width = 80))]
+    pub fn render(&self, color: bool, unicode: bool, width: usize) -> String {
+        render(self.inner.clone(), &render_options(color, unicode, width))
+    }
+
+    pub fn __str__(&self) -> String {
+        render(self.inner.clone(), &RenderOptions::default())
+    }
+}
+
+fn into_py_result(result: Res) -> PyResult<PyResolutionResult> {
+    result
+        .map(|inner| PyResolutionResult { inner })
+        .map_err(|e| PyRuntimeError::new_err(e.to_string()))
+}
+
+/// Any plan satisfying the requirements
+#[pyfunction]
+pub fn solve(
+    py: Python<'_>,
+    repo: &PyRepository,
+    reqs: &PyRequirementSet,
+) -> PyResult<PyResolutionResult> {
+    // resolved without the GIL, so that other Python threads keep running
+    let (repo, reqs) = (repo.inner.clone(), reqs.inner.clone());
+    into_py_result(py.allow_threads(move || simple_solve(&repo, &reqs)))
+}
+
+/// An optimal plan, the goal being "newest", "minimal" or "preferred"
+#[pyfunction]
+#[pyo3(signature = (repo, reqs, goal = "newest"))]
+pub fn optimize(
+    py: Python<'_>,
+    repo: &PyRepository,
+    reqs: &PyRequirementSet,
+    goal: &str,
+) -> PyResult<PyResolutionResult> {
+    let goal = match goal {
+        "newest" => Goal::Newest,
+        "minimal" => Goal::Minimal,
+        "preferred" => Goal::Preferred,
+        _ => return Err(PyValueError::new_err(format!("unknown goal {goal}"))),
+    };
+    let (repo, reqs) = (repo.inner.clone(), reqs.inner.clone());
+    into_py_result(
+        py.allow_threads(move || {
+            optimize_with_options(&repo, &reqs, goal, &SolveOptions::default())
+        }),
+    )
+}
+
+#[pymodule]
+fn libresolv(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyRepository>()?;
+    m.add_class::<PyRequirementSet>()?;
+    m.add_class::<PyResolutionResult>()?;
+    m.add_function(wrap_pyfunction!(solve, m)?)?;
+    m.add_function(wrap_pyfunction!(optimize, m)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use pyo3::Python;
+
+    use super::{optimize, solve, PyRepository, PyRequirementSet};
+
+    #[test]
+    fn test_python_wrappers() {
+        let mut repo = PyRepository::new();
+        let app = repo.add_package(2);
+        let lib = repo.add_package(3);
+        repo.add_requirement(app, 2, lib, Some((2, 3)), false)
+            .unwrap();
+        assert!(repo.add_requirement(app, 3, lib, None, false).is_err());
+        assert!(repo
+            .add_requirement(app, 1, lib, Some((3, 2)), false)
+            .is_err());
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut reqs = PyRequirementSet::new();
+            reqs.add_dependency(app, None).unwrap();
+            let r = optimize(py, &repo, &reqs, "newest").unwrap();
+            println!("{}", r.__str__());
+            assert_eq!(r.installed(), Some(vec![(app, 2), (lib, 3)]));
+            assert!(optimize(py, &repo, &reqs, "oldest").is_err());
+
+            reqs.add_conflict(lib, None).unwrap();
+            let r = solve(py, &repo, &reqs).unwrap();
+            assert_eq!(r.installed(), Some(vec![(app, 1)]));
+        });
+        assert!(repo.render(false, false, 80).starts_with("Package(0):"));
+    }
+}
@@ -0,0 +1,130 @@
+// Rendering of the pretty printed documents into strings, with or without colors and
+// with or without mathematical symbols, for logs and terminals without UTF-8 support
+use pretty::{Arena, Pretty};
+use termcolor::{Buffer, ColorSpec};
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub struct RenderOptions {
+    // emit ANSI color codes
+    pub color: bool,
+    // the line width documents are laid out for
+    pub width: usize,
+    // use mathematical symbols instead of their ASCII replacements
+    pub unicode: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            color: false,
+            width: 80,
+            unicode: true,
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // No colors and no symbols outside of ASCII
+    pub fn plain() -> Self {
+        Self::default().unicode(false)
+    }
+
+    pub fn color(mut self, enable: bool) -> Self {
+        self.color = enable;
+        self
+    }
+
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn unicode(mut self, enable: bool) -> Self {
+        self.unicode = enable;
+        self
+    }
+}
+
+pub fn render<T>(value: T, options: &RenderOptions) -> String
+where
+    T: for<'a> Pretty<'a, Arena<'a, ColorSpec>, ColorSpec>,
+{
+    let arena = Arena::new();
+    let mut buffer = if options.color {
+        Buffer::ansi()
+    } else {
+        Buffer::no_color()
+    };
+    value
+        .pretty(&arena)
+        .render_colored(options.width, &mut buffer)
+        .expect("Impossible: failed to write to memory");
+    let rendered = String::from_utf8(buffer.into_inner())
+        .expect("Impossible: rendered document is not valid UTF-8");
+    if options.unicode {
+        rendered
+    } else {
+        to_ascii(&rendered)
+    }
+}
+
+// ASCII replacements of the symbols used by the documents and the Display impls
+const ASCII: [(&str, &str); 13] = [
+    ("∈", "in"),
+    ("∉", "not in"),
+    ("∪", "|"),
+    ("𝒰", "*"),
+    ("∅", "{}"),
+    ("⇒", "=>"),
+    ("≤", "<="),
+    ("≥", ">="),
+    ("∧", "&&"),
+    ("∨", "||"),
+    ("→", "->"),
+    ("⊤", "true"),
+    ("⊥", "false"),
+];
+
+// Replace the mathematical symbols by ASCII. The replacements are wider than the symbols,
+// so lines laid out for a width may exceed it by a few columns
+pub fn to_ascii(s: &str) -> String {
+    ASCII
+        .iter()
+        .fold(s.to_string(), |s, (symbol, ascii)| s.replace(symbol, ascii))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        solver::simple_solve,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet},
+    };
+
+    use super::{render, RenderOptions};
+
+    #[test]
+    fn test_render() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: vec![PackageVer {
+                requirements: RequirementSet::from_antidep(Requirement::any_version(0)),
+            }],
+        }]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let r = simple_solve(&repo, &reqs).unwrap();
+
+        let plain = render(r.clone(), &RenderOptions::plain());
+        println!("{plain}");
+        assert!(plain.is_ascii());
+        assert!(plain.contains("not in"));
+
+        let fancy = render(r, &RenderOptions::new().color(true).width(40));
+        println!("{fancy}");
+        assert!(fancy.contains('∉'));
+        assert!(fancy.contains("\x1b["));
+    }
+}
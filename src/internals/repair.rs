@@ -0,0 +1,276 @@
+// Repair of broken installations. The installed versions whose requirements or conflicts
+// are violated are reported, and the closest consistent plan is found by minimizing the
+// number of changed packages. Among equally small repairs, the ones keeping the installed
+// packages are preferred, then the ones with newer versions
+use bumpalo::Bump;
+use z3::{
+    ast::{Ast, Int},
+    Context, Optimize, SatResult,
+};
+
+use crate::internals::{
+    closure::EdgeKind,
+    constraints::{add_all_constraints, find_closure},
+    encoding::Encoder,
+    metrics::{ChangeCount, DistanceFromNewest, Metric},
+    options::SolveOptions,
+    types::*,
+    utils::{default_config, zero},
+};
+
+// A requirement of an installed version that the installation violates
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Violation {
+    pub package: PackageId,
+    pub version: Version,
+    pub requirement: Requirement,
+    pub kind: EdgeKind,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Change {
+    Install {
+        package: PackageId,
+        version: Version,
+    },
+    Remove {
+        package: PackageId,
+        version: Version,
+    },
+    Upgrade {
+        package: PackageId,
+        from: Version,
+        to: Version,
+    },
+    Downgrade {
+        package: PackageId,
+        from: Version,
+        to: Version,
+    },
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct Repair {
+    pub violations: Vec<Violation>,
+    // the changes turning the installation into the plan, sorted by package
+    pub changes: Vec<Change>,
+    pub plan: Plan,
+}
+
+impl Repair {
+    pub fn is_consistent(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+fn installed_version(installed: &Plan, pid: PackageId) -> Version {
+    installed
+        .iter()
+        .find(|(p, _)| *p == pid)
+        .map_or(0, |(_, v)| *v)
+}
+
+// The requirements of the installed versions that the installation violates
+pub fn find_violations<S: PackageSource + ?Sized>(
+    repo: &S,
+    installed: &Plan,
+) -> Result<Vec<Violation>, ResolutionError> {
+    let mut violations = Vec::new();
+    for (pid, v) in installed.installed() {
+        let package = repo.try_fetch_package(pid)?;
+        let ver = package.versions.get(v as usize - 1).ok_or_else(|| {
+            InvalidInputSnafu {
+                message: format!("version {v} of package {pid} does not exist"),
+            }
+            .build()
+        })?;
+        let reqs = ver
+            .requirements
+            .dependencies
+            .iter()
+            .map(|req| (req, EdgeKind::Dependency))
+            .chain(
+                ver.requirements
+                    .conflicts
+                    .iter()
+                    .map(|req| (req, EdgeKind::Conflict)),
+            );
+        for (req, kind) in reqs {
+            let target = installed_version(installed, req.package);
+            let within = target != 0 && RangeSet::from(&req.versions).contains(target);
+            let violated = match kind {
+                EdgeKind::Dependency => !within,
+                EdgeKind::Conflict => within,
+            };
+            if violated {
+                violations.push(Violation {
+                    package: pid,
+                    version: v,
+                    requirement: req.clone(),
+                    kind,
+                });
+            }
+        }
+    }
+    Ok(violations)
+}
+
+// Find the violated requirements of an installation and the smallest set of changes
+// restoring consistency. Packages the installed versions may depend on are candidates
+// for being added
+pub fn repair<S: PackageSource + ?Sized>(
+    repo: &S,
+    installed: &Plan,
+) -> Result<Repair, ResolutionError> {
+    let violations = find_violations(repo, installed)?;
+    if violations.is_empty() {
+        return Ok(Repair {
+            violations,
+            changes: Vec::new(),
+            plan: installed.clone(),
+        });
+    }
+
+    let ctx = Context::new(&default_config());
+    let allocator = Bump::new();
+    let options = SolveOptions::default();
+    let solver = Optimize::new(&ctx);
+    solver.set_params(&options.optimizer_params(&ctx));
+
+    let roots = installed
+        .installed()
+        .into_iter()
+        .map(|(pid, _)| Requirement::any_version(pid))
+        .collect::<Vec<_>>();
+    let closure = find_closure(repo, roots.iter())?;
+    let pids = options.ordered_pids(&closure);
+    let encoder = Encoder::new(&ctx, options.encoding, repo, pids.iter().copied());
+    for domain_constraint in encoder.domain_constraints() {
+        solver.assert(&domain_constraint);
+    }
+    // repairs take no options, so there are no limits to honour
+    add_all_constraints(
+        &allocator,
+        &encoder,
+        repo,
+        pids.iter().copied(),
+        &RequirementSet::default(),
+        |expr, _, _| solver.assert(&expr.simplify()),
+    );
+    let mut removals = zero(&ctx);
+    for (pid, _) in installed.installed() {
+        removals += encoder
+            .not_installed(pid)
+            .ite(&Int::from_u64(&ctx, 1), &zero(&ctx));
+    }
+    solver.minimize(&ChangeCount(installed).build(&encoder, &pids, repo));
+    solver.minimize(&removals);
+    solver.minimize(&DistanceFromNewest.build(&encoder, &pids, repo));
+
+    // removing every package is always consistent
+    match solver.check(&[]) {
+        SatResult::Sat => {}
+        SatResult::Unknown => return Err(ResolutionError::unknown(solver.get_reason_unknown())),
+        SatResult::Unsat => unreachable!("Impossible: removing every package is inconsistent"),
+    }
+    let model = solver
+        .get_model()
+        .expect("Impossible: satisfiable but failed to generate a model");
+    let mut plan = encoder.plan_from_model(&model, pids.iter().copied());
+    plan.sort_unstable();
+
+    let changes = plan
+        .iter()
+        .filter_map(|&(package, to)| {
+            let from = installed_version(installed, package);
+            match (from, to) {
+                _ if from == to => None,
+                (0, version) => Some(Change::Install { package, version }),
+                (version, 0) => Some(Change::Remove { package, version }),
+                _ if from < to => Some(Change::Upgrade { package, from, to }),
+                _ => Some(Change::Downgrade { package, from, to }),
+            }
+        })
+        .collect();
+    Ok(Repair {
+        violations,
+        changes,
+        plan,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        closure::EdgeKind,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet},
+    };
+
+    use super::{repair, Change};
+
+    #[test]
+    fn test_repair() {
+        let empty = || PackageVer {
+            requirements: Default::default(),
+        };
+        // version 1 of package 0 requires version 1 of package 1, version 2 requires
+        // version 2 of package 1 and conflicts with package 2
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::single_version(1, 1)),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet {
+                            dependencies: vec![Requirement::single_version(1, 2)],
+                            conflicts: vec![Requirement::any_version(2)],
+                            formula: None,
+                        },
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![empty(), empty()],
+            },
+            Package {
+                id: 2,
+                versions: vec![empty()],
+            },
+        ]);
+
+        let consistent = repair(&repo, &vec![(0, 1), (1, 1)]).unwrap();
+        assert!(consistent.is_consistent());
+        assert!(consistent.changes.is_empty());
+
+        // package 1 got upgraded without package 0 following
+        let r = repair(&repo, &vec![(0, 1), (1, 2), (2, 1)]).unwrap();
+        println!("{r:?}");
+        assert_eq!(r.violations.len(), 1);
+        assert_eq!(r.violations[0].kind, EdgeKind::Dependency);
+        assert_eq!(
+            r.changes,
+            vec![Change::Downgrade {
+                package: 1,
+                from: 2,
+                to: 1
+            }]
+        );
+
+        // package 1 is missing and package 0 conflicts with package 2, removing package 0
+        // is the only single change
+        let r = repair(&repo, &vec![(0, 2), (2, 1)]).unwrap();
+        println!("{r:?}");
+        assert_eq!(r.violations.len(), 2);
+        assert_eq!(
+            r.changes,
+            vec![Change::Remove {
+                package: 0,
+                version: 2
+            }]
+        );
+        assert!(r.plan.contains(&(2, 1)));
+    }
+}
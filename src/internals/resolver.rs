@@ -0,0 +1,153 @@
+// An object-safe facade over the resolution functions, so that applications can select
+// the strategy at runtime, e.g. from a configuration file, and substitute their own
+// resolver in tests. Closures with the signature of `resolve` are resolvers as well
+use crate::internals::{
+    options::SolveOptions,
+    solver::{optimize_with_options, simple_solve_with_options},
+    types::*,
+};
+
+pub trait Resolver {
+    fn resolve(&self, repo: &Repository, requirements: &RequirementSet) -> Res;
+}
+
+impl<F> Resolver for F
+where
+    F: Fn(&Repository, &RequirementSet) -> Res,
+{
+    fn resolve(&self, repo: &Repository, requirements: &RequirementSet) -> Res {
+        self(repo, requirements)
+    }
+}
+
+// Any plan satisfying the requirements
+#[derive(Debug, Clone, Default)]
+pub struct SimpleResolver {
+    pub options: SolveOptions,
+}
+
+// A plan with the newest versions, see `Goal::Newest`
+#[derive(Debug, Clone, Default)]
+pub struct NewestResolver {
+    pub options: SolveOptions,
+}
+
+// A plan installing as few packages as possible, see `Goal::Minimal`
+#[derive(Debug, Clone, Default)]
+pub struct MinimalResolver {
+    pub options: SolveOptions,
+}
+
+// A plan with the preferred versions, see `Goal::Preferred`
+#[derive(Debug, Clone, Default)]
+pub struct PreferredResolver {
+    pub options: SolveOptions,
+}
+
+impl SimpleResolver {
+    pub fn new(options: SolveOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl NewestResolver {
+    pub fn new(options: SolveOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl MinimalResolver {
+    pub fn new(options: SolveOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl PreferredResolver {
+    pub fn new(options: SolveOptions) -> Self {
+        Self { options }
+    }
+}
+
+impl Resolver for SimpleResolver {
+    fn resolve(&self, repo: &Repository, requirements: &RequirementSet) -> Res {
+        simple_solve_with_options(repo, requirements, &self.options)
+    }
+}
+
+impl Resolver for NewestResolver {
+    fn resolve(&self, repo: &Repository, requirements: &RequirementSet) -> Res {
+        optimize_with_options(repo, requirements, Goal::Newest, &self.options)
+    }
+}
+
+impl Resolver for MinimalResolver {
+    fn resolve(&self, repo: &Repository, requirements: &RequirementSet) -> Res {
+        optimize_with_options(repo, requirements, Goal::Minimal, &self.options)
+    }
+}
+
+impl Resolver for PreferredResolver {
+    fn resolve(&self, repo: &Repository, requirements: &RequirementSet) -> Res {
+        optimize_with_options(repo, requirements, Goal::Preferred, &self.options)
+    }
+}
+
+// The resolver optimizing for a goal
+impl From<Goal> for Box<dyn Resolver> {
+    fn from(goal: Goal) -> Self {
+        match goal {
+            Goal::Newest => Box::new(NewestResolver::default()),
+            Goal::Minimal => Box::new(MinimalResolver::default()),
+            Goal::Preferred => Box::new(PreferredResolver::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{
+        Goal, Package, PackageVer, Repository, Requirement, RequirementSet, Res, ResolutionResult,
+    };
+
+    use super::{Resolver, SimpleResolver};
+
+    #[test]
+    fn test_resolvers() {
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (0..2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let mock = |_: &Repository, _: &RequirementSet| -> Res { Ok(ResolutionResult::Unsat) };
+        let resolvers: Vec<Box<dyn Resolver>> = vec![
+            Box::new(SimpleResolver::default()),
+            Goal::Newest.into(),
+            Goal::Minimal.into(),
+            Box::new(mock),
+        ];
+        let results = resolvers
+            .iter()
+            .map(|resolver| resolver.resolve(&repo, &reqs).unwrap())
+            .collect::<Vec<_>>();
+        println!("{results:?}");
+        assert!(results[0].is_sat());
+        assert_eq!(
+            results[1].typed_plan().unwrap().installed_version(0),
+            Some(2)
+        );
+        assert!(!results[2].typed_plan().unwrap().is_installed(1));
+        assert!(results[3].is_unsat());
+    }
+}
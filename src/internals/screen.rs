@@ -0,0 +1,186 @@
+// Screening of a repository for versions that can never be installed, whatever else is
+// installed alongside them, e.g. so that indexes can flag permanently broken releases.
+// A version is installable if requiring exactly that version is satisfiable. Most broken
+// versions are caught by the unit propagation of `preprocess`, run once per package on
+// the requirement of any of its versions, the others take one resolution each. Verdicts are kept with the fingerprint of the closure they were decided
+// on, the packages of the closure and their masks, so that screening the repository again
+// after a change only resolves for the versions whose closure changed
+use crate::internals::{
+    constraints::find_closure,
+    preprocess::preprocess,
+    session::SolverSession,
+    types::{fingerprint::combine_fingerprints, *},
+};
+
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct Screening {
+    // whether each version is installable, with the fingerprint of its closure
+    verdicts: PackageMap<VersionMap<(u64, bool)>>,
+}
+
+// Screen every version of the repository
+pub fn screen_repo(repo: &Repository) -> Result<Screening, ResolutionError> {
+    let mut screening = Screening::new();
+    screening.screen(repo)?;
+    Ok(screening)
+}
+
+// The closure only depends on the package, it is the same for all of its versions
+fn closure_fingerprint(repo: &Repository, pid: PackageId) -> Result<u64, ResolutionError> {
+    let closure = find_closure(repo, std::iter::once(&Requirement::any_version(pid)))?;
+    let mut pids = closure.iter().collect::<Vec<_>>();
+    pids.sort_unstable();
+    let packages = pids
+        .iter()
+        .filter_map(|&pid| repo.get_package(pid))
+        .map(Package::fingerprint);
+    let masks = repo
+        .masks()
+        .iter()
+        .filter(|mask| closure.contains(mask.package))
+        .cloned()
+        .collect::<Vec<_>>();
+    // the preferences and the meta-packages do not decide whether a version installs
+    Ok(combine_fingerprints(
+        packages,
+        &masks,
+        &repo.essential_packages(),
+        &[],
+        &[],
+    ))
+}
+
+impl Screening {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Decide whether each version of the repository is installable, reusing the verdicts
+    // of earlier screenings whose closure is unchanged. Verdicts on packages and versions
+    // the repository no longer has are dropped
+    pub fn screen(&mut self, repo: &Repository) -> Result<(), ResolutionError> {
+        let mut session = SolverSession::new(repo);
+        let mut verdicts = PackageMap::new();
+        for package in repo.packages() {
+            let fingerprint = closure_fingerprint(repo, package.id)?;
+            // the versions no plan installing the package can hold, if some version has
+            // to be decided
+            let mut pruned = None;
+            let mut versions = VersionMap::new();
+            for v in 1..=package.newest_version_number() {
+                let cached = self
+                    .verdicts
+                    .get(package.id)
+                    .and_then(|versions| versions.get(v))
+                    .filter(|&&(cached, _)| cached == fingerprint);
+                let installable = match cached {
+                    Some(&(_, installable)) => installable,
+                    None => {
+                        if pruned.is_none() {
+                            let reqs =
+                                RequirementSet::from_dep(Requirement::any_version(package.id));
+                            pruned = Some(preprocess(repo, &reqs)?.pruned);
+                        }
+                        let pruned = pruned.as_ref().expect("Impossible: preprocessed above");
+                        let reqs =
+                            RequirementSet::from_dep(Requirement::single_version(package.id, v));
+                        !pruned.contains(&(package.id, v)) && session.solve(&reqs)?.is_sat()
+                    }
+                };
+                versions.insert(v, (fingerprint, installable));
+            }
+            verdicts.insert(package.id, versions);
+        }
+        self.verdicts = verdicts;
+        Ok(())
+    }
+
+    // None if the version was not screened
+    pub fn is_installable(&self, pid: PackageId, version: Version) -> Option<bool> {
+        let &(_, installable) = self.verdicts.get(pid)?.get(version)?;
+        Some(installable)
+    }
+
+    // The versions that cannot be installed, in increasing order
+    pub fn broken(&self) -> Vec<(PackageId, Version)> {
+        self.verdicts
+            .iter()
+            .flat_map(|(pid, versions)| {
+                versions
+                    .iter()
+                    .filter(|(_, &(_, installable))| !installable)
+                    .map(move |(v, _)| (pid, v))
+            })
+            .collect()
+    }
+
+    // Mask the broken versions, so that resolutions never consider them. The fingerprints of
+    // the closures change, the next screening of the repository resolves again for the
+    // versions whose closure contains a broken version
+    pub fn mask_broken(&self, repo: &mut Repository) {
+        for (pid, v) in self.broken() {
+            repo.mask_version(pid, v);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{
+        vec1, Package, PackageVer, Range, Repository, Requirement, RequirementSet,
+    };
+
+    use super::screen_repo;
+
+    #[test]
+    fn test_screen_repo() {
+        // version 1 of package 0 requires a missing version of package 1, version 2 of
+        // package 0 requires package 2 and conflicts with it
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::new(
+                            1,
+                            vec1![Range::point(3)],
+                        )),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet {
+                            dependencies: vec![Requirement::any_version(2)],
+                            conflicts: vec![Requirement::any_version(2)],
+                            formula: None,
+                        },
+                    },
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let mut screening = screen_repo(&repo).unwrap();
+        println!("{screening:?}");
+        assert_eq!(screening.broken(), vec![(0, 1), (0, 2)]);
+        assert_eq!(screening.is_installable(0, 3), Some(true));
+        assert_eq!(screening.is_installable(0, 4), None);
+
+        let mut masked = repo.clone();
+        screening.mask_broken(&mut masked);
+        screening.screen(&masked).unwrap();
+        assert_eq!(screening.broken(), vec![(0, 1), (0, 2)]);
+        assert_eq!(screening.is_installable(1, 1), Some(true));
+    }
+}
@@ -0,0 +1,423 @@
+// A resolution daemon, so that an organization can run one resolver shared by its tools
+// and load its repositories once. Enabled by the `service` feature, the server speaks
+// JSON-RPC 2.0 over TCP, one request or response per line:
+//
+//   load    { "name": string, "repository": repository }     -> { "fingerprint": string }
+//   unload  { "name": string }                                -> { "unloaded": bool }
+//   solve   { "repository": string | repository, "requirements": string,
+//             "goal": "any" | "newest" | "minimal"
+//                     | "preferred" }                         -> result
+//
+// where repositories are in the JSON schema of `Repository::from_json`, requirements are
+// written in the syntax of `RequirementSet`, the goal defaults to "any" and the result is
+// in the JSON schema of `ResolutionResult::to_json`. Batches of requests are answered by
+// batches of responses. A repository given by name must have been loaded before,
+// repositories sent inline are kept by their JSON text so that clients resolving
+// repeatedly against the same one neither rebuild it nor its caches, evicting the least
+// recently used beyond the capacity. Connections beyond `max_connections` and lines
+// longer than `max_line_bytes` are answered with an error and closed
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread,
+    time::Duration,
+};
+
+use indexmap::IndexMap;
+use serde_json::{json, Value};
+use snafu::ResultExt;
+
+use crate::internals::{
+    options::SolveOptions,
+    solver::{optimize_with_options, simple_solve_with_options},
+    types::*,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const RESOLUTION_ERROR: i64 = -32000;
+const SERVER_BUSY: i64 = -32001;
+
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+type RpcError = (i64, String);
+
+fn invalid_params(message: impl Into<String>) -> RpcError {
+    (INVALID_PARAMS, message.into())
+}
+
+fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, RpcError> {
+    params[name]
+        .as_str()
+        .ok_or_else(|| invalid_params(format!("expected a string `{name}`")))
+}
+
+fn requirements_of(value: &Value) -> Result<RequirementSet, RpcError> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid_params("expected requirements as a string"))?
+        .parse()
+        .map_err(|e: ResolutionError| invalid_params(e.to_string()))
+}
+
+fn repository_of(value: &Value) -> Result<Repository, RpcError> {
+    Repository::from_json(value).map_err(|e| invalid_params(e.to_string()))
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+#[derive(Debug)]
+pub struct ResolverService {
+    pub options: SolveOptions,
+    // connections served at once, the others being refused
+    pub max_connections: usize,
+    // the longest request line accepted, in bytes
+    pub max_line_bytes: usize,
+    loaded: RwLock<HashMap<String, Arc<Repository>>>,
+    // inline repositories by their JSON text, the least recently used first
+    cached: Mutex<IndexMap<String, Arc<Repository>>>,
+    capacity: usize,
+    connections: AtomicUsize,
+}
+
+// A connection being served, counted until it is dropped
+struct Connection<'s>(&'s AtomicUsize);
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ResolverService {
+    // A service keeping up to `capacity` inline repositories
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            options: SolveOptions::default(),
+            max_connections: 64,
+            max_line_bytes: 64 << 20,
+            loaded: RwLock::new(HashMap::new()),
+            cached: Mutex::new(IndexMap::new()),
+            capacity,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    // Make a repository available to requests under a name, replacing the one loaded under
+    // the same name if any
+    pub fn load(&self, name: impl Into<String>, repo: Repository) {
+        self.loaded
+            .write()
+            .expect("poisoned service")
+            .insert(name.into(), Arc::new(repo));
+    }
+
+    pub fn unload(&self, name: &str) -> bool {
+        self.loaded
+            .write()
+            .expect("poisoned service")
+            .remove(name)
+            .is_some()
+    }
+
+    pub fn repository(&self, name: &str) -> Option<Arc<Repository>> {
+        self.loaded
+            .read()
+            .expect("poisoned service")
+            .get(name)
+            .cloned()
+    }
+
+    // The number of inline repositories currently kept
+    pub fn cached(&self) -> usize {
+        self.cached.lock().expect("poisoned service").len()
+    }
+
+    // The repository sent inline, only built if it is not kept already
+    fn cache(&self, value: &Value) -> Result<Arc<Repository>, RpcError> {
+        let key = value.to_string();
+        let hit = self
+            .cached
+            .lock()
+            .expect("poisoned service")
+            .shift_remove(&key);
+        // built without holding the lock, so that other requests are not held up
+        let repo = match hit {
+            Some(repo) => repo,
+            None => Arc::new(repository_of(value)?),
+        };
+        let mut cached = self.cached.lock().expect("poisoned service");
+        cached.insert(key, repo.clone());
+        while cached.len() > self.capacity {
+            cached.shift_remove_index(0);
+        }
+        Ok(repo)
+    }
+
+    fn call(&self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "load" => {
+                let name = string_param(params, "name")?;
+                let repo = repository_of(&params["repository"])?;
+                let fingerprint = format!("{:016x}", repo.fingerprint());
+                self.load(name, repo);
+                Ok(json!({ "fingerprint": fingerprint }))
+            }
+            "unload" => Ok(json!({ "unloaded": self.unload(string_param(params, "name")?) })),
+            "solve" => {
+                let repo = match &params["repository"] {
+                    Value::String(name) => self
+                        .repository(name)
+                        .ok_or_else(|| invalid_params(format!("no repository named `{name}`")))?,
+                    repo => self.cache(repo)?,
+                };
+                let reqs = requirements_of(&params["requirements"])?;
+                let result = match params["goal"].as_str().unwrap_or("any") {
+                    "any" => simple_solve_with_options(&repo, &reqs, &self.options),
+                    "newest" => optimize_with_options(&repo, &reqs, Goal::Newest, &self.options),
+                    "minimal" => optimize_with_options(&repo, &reqs, Goal::Minimal, &self.options),
+                    "preferred" => {
+                        optimize_with_options(&repo, &reqs, Goal::Preferred, &self.options)
+                    }
+                    goal => return Err(invalid_params(format!("unknown goal `{goal}`"))),
+                };
+                result
+                    .map(|r| r.to_json())
+                    .map_err(|e| (RESOLUTION_ERROR, e.to_string()))
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method `{method}`"))),
+        }
+    }
+
+    // The response to a request or a batch of requests, None for notifications and
+    // batches of notifications
+    pub fn handle(&self, request: &Value) -> Option<Value> {
+        match request {
+            Value::Array(requests) if requests.is_empty() => Some(error_response(
+                Value::Null,
+                INVALID_REQUEST,
+                "empty batch".to_string(),
+            )),
+            Value::Array(requests) => {
+                let responses = requests
+                    .iter()
+                    .filter_map(|request| self.handle_single(request))
+                    .collect::<Vec<_>>();
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            request => self.handle_single(request),
+        }
+    }
+
+    fn handle_single(&self, request: &Value) -> Option<Value> {
+        let (Some("2.0"), Some(method)) = (request["jsonrpc"].as_str(), request["method"].as_str())
+        else {
+            // answered even without an id, only well-formed notifications go unanswered
+            let id = request.get("id").cloned().unwrap_or(Value::Null);
+            let message = "not a JSON-RPC 2.0 request".to_string();
+            return Some(error_response(id, INVALID_REQUEST, message));
+        };
+        let result = self.call(method, &request["params"]);
+        let id = request.get("id").cloned()?;
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => error_response(id, code, message),
+        })
+    }
+
+    // The same for a request as a line of text
+    pub fn handle_line(&self, line: &str) -> Option<String> {
+        match serde_json::from_str::<Value>(line) {
+            Ok(request) => self.handle(&request).map(|response| response.to_string()),
+            Err(e) => Some(error_response(Value::Null, PARSE_ERROR, e.to_string()).to_string()),
+        }
+    }
+
+    // Answer the requests of a connection line by line, until its end or a line that is
+    // too long
+    fn serve_lines(
+        &self,
+        reader: impl BufRead,
+        mut writer: impl Write,
+    ) -> Result<(), ResolutionError> {
+        let limit = self.max_line_bytes as u64 + 1;
+        let mut reader = reader.take(limit);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            reader.set_limit(limit);
+            if reader.read_until(b'\n', &mut line).context(IoSnafu)? == 0 {
+                return Ok(());
+            }
+            if line.len() as u64 == limit && line.last() != Some(&b'\n') {
+                let message = format!("request longer than {} bytes", self.max_line_bytes);
+                let response = error_response(Value::Null, INVALID_REQUEST, message);
+                return writeln!(writer, "{response}").context(IoSnafu);
+            }
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle_line(&line) {
+                writeln!(writer, "{response}").context(IoSnafu)?;
+            }
+        }
+    }
+
+    fn serve_connection(&self, stream: TcpStream) -> Result<(), ResolutionError> {
+        let writer = stream.try_clone().context(IoSnafu)?;
+        self.serve_lines(BufReader::new(stream), writer)
+    }
+
+    // Answer the connections of a listener, each on its own thread. Failing to accept a
+    // connection, e.g. when out of file descriptors under load, only drops that connection
+    pub fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), ResolutionError> {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                // give the connections being served the time to release their resources
+                thread::sleep(ACCEPT_BACKOFF);
+                continue;
+            };
+            if self.connections.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+                self.connections.fetch_sub(1, Ordering::SeqCst);
+                let message = "too many connections".to_string();
+                let _ = writeln!(
+                    stream,
+                    "{}",
+                    error_response(Value::Null, SERVER_BUSY, message)
+                );
+                continue;
+            }
+            let service = self.clone();
+            // a broken connection only ends itself
+            thread::spawn(move || {
+                let _connection = Connection(&service.connections);
+                service.serve_connection(stream)
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for ResolverService {
+    fn default() -> Self {
+        Self::new(16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use serde_json::{json, Value};
+
+    use super::ResolverService;
+
+    fn request_line() -> String {
+        format!(
+            "{}\n",
+            json!({ "jsonrpc": "2.0", "id": 7, "method": "install" })
+        )
+    }
+
+    #[test]
+    fn test_service() {
+        let service = ResolverService::new(1);
+        let call = |method: &str, params: Value| {
+            let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+            let response = service.handle_line(&request.to_string()).unwrap();
+            println!("{response}");
+            serde_json::from_str::<Value>(&response).unwrap()
+        };
+        let packages = json!([["pkg1 in {2}", "pkg1"], ["", ""]]);
+        let repo = json!({ "packages": packages, "masks": ["pkg0 in {2}"] });
+
+        let r = call("load", json!({ "name": "main", "repository": repo }));
+        assert!(r["result"]["fingerprint"].is_string());
+        let r = call(
+            "solve",
+            json!({ "repository": "main", "requirements": "pkg0", "goal": "newest" }),
+        );
+        assert_eq!(
+            r["result"]["plans"][0],
+            json!([{ "package": 0, "version": 1 }, { "package": 1, "version": 2 }])
+        );
+
+        let r = call(
+            "solve",
+            json!({ "repository": repo, "requirements": "pkg0; !pkg1" }),
+        );
+        assert_eq!(r["result"]["status"], "unsat");
+        call(
+            "solve",
+            json!({ "repository": repo, "requirements": "pkg1" }),
+        );
+        assert_eq!(service.cached(), 1);
+        // the repository kept is the one built first
+        let first = service.cache(&repo).unwrap();
+        assert!(Arc::ptr_eq(&first, &service.cache(&repo).unwrap()));
+
+        // batches are answered in order, leaving out notifications
+        let batch = json!([
+            { "jsonrpc": "2.0", "id": 1, "method": "solve",
+              "params": { "repository": "main", "requirements": "pkg1" } },
+            { "jsonrpc": "2.0", "method": "unload", "params": { "name": "x" } },
+            { "jsonrpc": "2.0", "id": 2, "method": "install" },
+        ]);
+        let r = service.handle(&batch).unwrap();
+        assert_eq!(r.as_array().unwrap().len(), 2);
+        assert_eq!(r[0]["result"]["status"], "sat");
+        assert_eq!(r[1]["error"]["code"], -32601);
+        assert_eq!(service.handle(&json!([])).unwrap()["error"]["code"], -32600);
+
+        let r = call(
+            "solve",
+            json!({ "repository": "other", "requirements": "pkg0" }),
+        );
+        assert_eq!(r["error"]["code"], -32602);
+        let r = call("unload", json!({ "name": "main" }));
+        assert_eq!(r["result"]["unloaded"], true);
+        assert_eq!(call("install", json!({}))["error"]["code"], -32601);
+        assert!(service.handle_line("{").unwrap().contains("-32700"));
+        assert!(service
+            .handle_line(r#"{ "jsonrpc": "2.0", "method": "unload", "params": {} }"#)
+            .is_none());
+        // malformed requests are answered with a null id, one error per element of a batch
+        let r = service.handle(&json!({ "method": "x" })).unwrap();
+        assert_eq!(r["error"]["code"], -32600);
+        assert_eq!(r["id"], Value::Null);
+        let r = service.handle(&json!([1])).unwrap();
+        assert_eq!(r.as_array().unwrap().len(), 1);
+        assert_eq!(r[0]["error"]["code"], -32600);
+        assert_eq!(r[0]["id"], Value::Null);
+
+        // an overlong line ends the connection
+        let mut service = ResolverService::new(1);
+        service.max_line_bytes = 64;
+        let long = format!("{}\n", " ".repeat(100));
+        let input = format!("{}{long}{}", request_line(), request_line());
+        let mut output = Vec::new();
+        service.serve_lines(input.as_bytes(), &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        println!("{output}");
+        assert_eq!(output.lines().count(), 2);
+        assert!(output
+            .lines()
+            .last()
+            .unwrap()
+            .contains("longer than 64 bytes"));
+    }
+}
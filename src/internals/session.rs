@@ -0,0 +1,177 @@
+// A resolution session owning a z3 context and an allocator that are reused
+// across resolutions, for tools resolving many requirement sets in a loop. Sessions only
+// read their repository: any number of sessions, each on its own thread, can resolve
+// against one repository at once, and clones of a repository share its packages
+use bumpalo::Bump;
+use z3::Context;
+
+use crate::internals::{
+    options::SolveOptions,
+    solutions::SolutionIter,
+    solver::{optimize_in, simple_solve_in, Hooks},
+    types::*,
+    utils::default_config,
+};
+
+pub struct SolverSession<'r, S: PackageSource + ?Sized> {
+    repo: &'r S,
+    options: SolveOptions,
+    ctx: Context,
+    allocator: Bump,
+}
+
+impl<'r, S: PackageSource + ?Sized> SolverSession<'r, S> {
+    pub fn new(repo: &'r S) -> Self {
+        Self::with_options(repo, SolveOptions::default())
+    }
+
+    pub fn with_options(repo: &'r S, options: SolveOptions) -> Self {
+        Self {
+            repo,
+            options,
+            ctx: Context::new(&default_config()),
+            allocator: Bump::new(),
+        }
+    }
+
+    pub fn options(&self) -> &SolveOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: SolveOptions) {
+        self.options = options
+    }
+
+    // Memory currently held by the allocator, this is retained between resolutions
+    pub fn allocated_bytes(&self) -> usize {
+        self.allocator.allocated_bytes()
+    }
+
+    pub fn solve(&mut self, requirements: &RequirementSet) -> Res {
+        self.allocator.reset();
+        let hooks = Hooks {
+            progress: &mut |_| {},
+            stats: &mut SolveStats::default(),
+        };
+        simple_solve_in(
+            &self.ctx,
+            &self.allocator,
+            self.repo,
+            requirements,
+            &self.options,
+            hooks,
+        )
+    }
+
+    // Lazily enumerate the solutions using the context of the session
+    pub fn solutions(
+        &self,
+        requirements: &RequirementSet,
+    ) -> Result<SolutionIter<'_>, ResolutionError> {
+        SolutionIter::new(&self.ctx, self.repo, requirements, &self.options)
+    }
+
+    pub fn optimize(&mut self, requirements: &RequirementSet, goal: Goal) -> Res {
+        self.allocator.reset();
+        let hooks = Hooks {
+            progress: &mut |_| {},
+            stats: &mut SolveStats::default(),
+        };
+        optimize_in(
+            &self.ctx,
+            &self.allocator,
+            self.repo,
+            requirements,
+            goal,
+            &self.options,
+            hooks,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use crate::internals::{
+        solver::simple_solve,
+        types::{Goal, Package, PackageVer, Repository, Requirement, RequirementSet},
+    };
+
+    use super::SolverSession;
+
+    #[test]
+    fn test_session_reuse() {
+        let repo = Repository::new(
+            (0..5)
+                .map(|id| Package {
+                    id,
+                    versions: vec![
+                        PackageVer {
+                            requirements: Default::default(),
+                        },
+                        PackageVer {
+                            requirements: Default::default(),
+                        },
+                    ],
+                })
+                .collect(),
+        );
+        let mut session = SolverSession::new(&repo);
+        for pid in 0..5 {
+            let reqs = RequirementSet::from_dep(Requirement::any_version(pid));
+            assert_eq!(
+                session.solve(&reqs).unwrap(),
+                simple_solve(&repo, &reqs).unwrap()
+            );
+            assert!(session.optimize(&reqs, Goal::Newest).unwrap().is_sat());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_sessions() {
+        // package i depends on package i + 1
+        let repo = Repository::new(
+            (0..8)
+                .map(|id| Package {
+                    id,
+                    versions: (0..3)
+                        .map(|_| PackageVer {
+                            requirements: match id {
+                                7 => Default::default(),
+                                _ => RequirementSet::from_dep(Requirement::any_version(id + 1)),
+                            },
+                        })
+                        .collect(),
+                })
+                .collect(),
+        );
+        let shared = repo.clone();
+        assert!(repo.shares_packages_with(&shared));
+        let expected = (0..8)
+            .map(|pid| {
+                let reqs = RequirementSet::from_dep(Requirement::any_version(pid));
+                simple_solve(&repo, &reqs).unwrap()
+            })
+            .collect::<Vec<_>>();
+        thread::scope(|scope| {
+            for pid in 0..8 {
+                let (repo, expected) = (&shared, &expected);
+                scope.spawn(move || {
+                    let mut session = SolverSession::new(repo);
+                    let reqs = RequirementSet::from_dep(Requirement::any_version(pid));
+                    for _ in 0..4 {
+                        assert_eq!(session.solve(&reqs).unwrap(), expected[pid as usize]);
+                        assert!(session.optimize(&reqs, Goal::Newest).unwrap().is_sat());
+                    }
+                });
+            }
+        });
+
+        // editing a clone leaves the packages of the others alone
+        let mut edited = shared.clone();
+        edited.packages_mut()[7].versions.clear();
+        assert!(!repo.shares_packages_with(&edited));
+        assert_eq!(repo.packages()[7].versions.len(), 3);
+    }
+}
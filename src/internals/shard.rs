@@ -0,0 +1,288 @@
+// Repositories split into shards of consecutive packages, for indexes too large to be
+// loaded whole. Enabled by the `shards` feature, `write_shards` writes a directory of rkyv
+// archives:
+//
+//   index      the packages of each shard and the metadata of the whole repository:
+//              masks, preferences, meta and essential packages
+//   shard-N    the packages of the N-th shard
+//
+// `ShardedRepository` reads the index when it is opened and memory-maps a shard the first
+// time one of its packages is fetched, so that resolutions, or `load_closure`, only map
+// the shards the closure of their requirements touches. Archives are validated when they
+// are read, a shard when it is mapped, and must not be modified while they are mapped.
+// Packages are decoded the first time they are fetched and kept afterwards
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use memmap2::Mmap;
+use rkyv::{AlignedVec, Archive, Deserialize, Infallible, Serialize};
+use snafu::ResultExt;
+
+use crate::internals::types::*;
+
+const INDEX: &str = "index";
+
+fn shard_file(shard: usize) -> String {
+    format!("shard-{shard}")
+}
+
+// The packages `first` to `last` of a shard
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct ShardEntry {
+    pub first: PackageId,
+    pub last: PackageId,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Default, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
+pub struct ShardIndex {
+    // in increasing order of packages
+    pub shards: Vec<ShardEntry>,
+    pub masks: Vec<Requirement>,
+    pub preferences: Vec<(PackageId, Vec<Version>)>,
+    pub meta_packages: Vec<PackageId>,
+    pub essential_packages: Vec<PackageId>,
+}
+
+// Write a repository as shards of up to `packages_per_shard` packages into a directory,
+// creating it if needed
+pub fn write_shards(
+    repo: &Repository,
+    dir: impl AsRef<Path>,
+    packages_per_shard: usize,
+) -> Result<ShardIndex, ResolutionError> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir).context(IoSnafu)?;
+    let mut index = ShardIndex {
+        shards: Vec::new(),
+        masks: repo.masks().to_vec(),
+        preferences: repo
+            .packages()
+            .iter()
+            .filter_map(|p| Some((p.id, repo.preferred_versions(p.id)?.into_owned())))
+            .collect(),
+        meta_packages: repo
+            .packages()
+            .iter()
+            .map(|p| p.id)
+            .filter(|&pid| repo.is_meta_package(pid))
+            .collect(),
+        essential_packages: repo.essential_packages().into_owned(),
+    };
+    for (i, shard) in repo
+        .packages()
+        .chunks(packages_per_shard.max(1))
+        .enumerate()
+    {
+        let bytes = rkyv::to_bytes::<_, 4096>(&shard.to_vec())
+            .expect("Impossible: failed to serialize into memory");
+        fs::write(dir.join(shard_file(i)), &bytes).context(IoSnafu)?;
+        index.shards.push(ShardEntry {
+            first: shard[0].id,
+            last: shard[shard.len() - 1].id,
+        });
+    }
+    let bytes =
+        rkyv::to_bytes::<_, 1024>(&index).expect("Impossible: failed to serialize into memory");
+    fs::write(dir.join(INDEX), &bytes).context(IoSnafu)?;
+    Ok(index)
+}
+
+fn invalid(message: String) -> ResolutionError {
+    InvalidInputSnafu { message }.build()
+}
+
+// A mapped shard and the packages decoded from it so far
+#[derive(Debug)]
+struct Shard {
+    mmap: Mmap,
+    packages: Vec<OnceLock<Package>>,
+}
+
+#[derive(Debug)]
+pub struct ShardedRepository {
+    dir: PathBuf,
+    index: ShardIndex,
+    shards: Vec<OnceLock<Shard>>,
+}
+
+impl ShardedRepository {
+    // Open the shards written by `write_shards` into a directory, only reading the index
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, ResolutionError> {
+        let dir = dir.as_ref().to_path_buf();
+        // archives have to be aligned
+        let mut bytes = AlignedVec::new();
+        bytes.extend_from_slice(&fs::read(dir.join(INDEX)).context(IoSnafu)?);
+        let archived = rkyv::check_archived_root::<ShardIndex>(&bytes)
+            .map_err(|e| invalid(format!("invalid shard index: {e}")))?;
+        let index: ShardIndex = archived
+            .deserialize(&mut Infallible)
+            .expect("Impossible: infallible deserialization failed");
+        // `shard_of` searches the shards by their packages
+        let ordered = index.shards.iter().all(|entry| entry.first <= entry.last)
+            && index.shards.windows(2).all(|w| w[0].last < w[1].first);
+        if !ordered {
+            return Err(invalid(
+                "the shards of the index are not in order".to_string(),
+            ));
+        }
+        let shards = index.shards.iter().map(|_| OnceLock::new()).collect();
+        Ok(Self { dir, index, shards })
+    }
+
+    pub fn index(&self) -> &ShardIndex {
+        &self.index
+    }
+
+    // The shard holding a package, None if there is none
+    pub fn shard_of(&self, id: PackageId) -> Option<usize> {
+        let shard = self.index.shards.partition_point(|entry| entry.last < id);
+        let entry = self.index.shards.get(shard)?;
+        (entry.first <= id).then_some(shard)
+    }
+
+    // The number of shards mapped so far
+    pub fn mapped_shards(&self) -> usize {
+        self.shards
+            .iter()
+            .filter(|shard| shard.get().is_some())
+            .count()
+    }
+
+    // Map and validate a shard unless it is mapped already
+    pub fn map_shard(&self, shard: usize) -> Result<&Mmap, ResolutionError> {
+        self.shard(shard).map(|shard| &shard.mmap)
+    }
+
+    fn shard(&self, shard: usize) -> Result<&Shard, ResolutionError> {
+        let cell = self
+            .shards
+            .get(shard)
+            .ok_or_else(|| invalid(format!("there is no shard {shard}")))?;
+        if let Some(mapped) = cell.get() {
+            return Ok(mapped);
+        }
+        let file = File::open(self.dir.join(shard_file(shard))).context(IoSnafu)?;
+        // the shards are not modified while they are mapped, see the header
+        let mmap = unsafe { Mmap::map(&file) }.context(IoSnafu)?;
+        let len = rkyv::check_archived_root::<Vec<Package>>(&mmap)
+            .map_err(|e| invalid(format!("invalid shard {shard}: {e}")))?
+            .len();
+        let entry = self.index.shards[shard];
+        let expected = (entry.last - entry.first) as usize + 1;
+        if len != expected {
+            return Err(invalid(format!(
+                "shard {shard} holds {len} packages instead of {expected}"
+            )));
+        }
+        let packages = (0..len).map(|_| OnceLock::new()).collect();
+        // a shard mapped concurrently by another thread wins, ours is unmapped
+        Ok(cell.get_or_init(|| Shard { mmap, packages }))
+    }
+}
+
+// Packages of shards that cannot be mapped are missing for `fetch_package`,
+// `try_fetch_package` reports why
+impl PackageSource for ShardedRepository {
+    fn fetch_package(&self, id: PackageId) -> Option<Cow<'_, Package>> {
+        self.try_fetch_package(id).ok()
+    }
+
+    fn try_fetch_package(&self, id: PackageId) -> Result<Cow<'_, Package>, ResolutionError> {
+        let i = self
+            .shard_of(id)
+            .ok_or_else(|| ResolutionError::unknown_package(id))?;
+        let shard = self.shard(i)?;
+        let offset = (id - self.index.shards[i].first) as usize;
+        let cell = &shard.packages[offset];
+        if let Some(package) = cell.get() {
+            return Ok(Cow::Borrowed(package));
+        }
+        // the shard was validated when it was mapped
+        let packages = unsafe { rkyv::archived_root::<Vec<Package>>(&shard.mmap) };
+        let package: Package = packages[offset]
+            .deserialize(&mut Infallible)
+            .expect("Impossible: infallible deserialization failed");
+        if package.id != id {
+            return Err(invalid(format!(
+                "shard {i} holds package {} in place of package {id}",
+                package.id
+            )));
+        }
+        Ok(Cow::Borrowed(cell.get_or_init(|| package)))
+    }
+
+    fn preferred_versions(&self, id: PackageId) -> Option<Cow<'_, [Version]>> {
+        self.index
+            .preferences
+            .iter()
+            .find(|(pid, _)| *pid == id)
+            .map(|(_, versions)| Cow::Borrowed(&versions[..]))
+    }
+
+    fn is_meta_package(&self, id: PackageId) -> bool {
+        self.index.meta_packages.contains(&id)
+    }
+
+    fn essential_packages(&self) -> Cow<'_, [PackageId]> {
+        Cow::Borrowed(&self.index.essential_packages)
+    }
+
+    fn masks_of(&self, id: PackageId) -> Cow<'_, [Requirement]> {
+        masks_of(&self.index.masks, id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use crate::internals::{
+        solver::simple_solve,
+        testing::repository_from_requirements,
+        types::{PackageSource, RequirementSet, ResolutionError},
+    };
+
+    use super::{write_shards, ShardedRepository};
+
+    #[test]
+    fn test_shards() {
+        // 0 depends on 1, 3 depends on 4 whose only version is masked
+        let mut repo =
+            repository_from_requirements(&[&["pkg1"], &[""], &[""], &["pkg4"], &[""]]).unwrap();
+        repo.mask_version(4, 1);
+        let dir = env::temp_dir().join(format!("libresolv-shards-{}", std::process::id()));
+        let index = write_shards(&repo, &dir, 2).unwrap();
+        println!("{index:?}");
+        assert_eq!(index.shards.len(), 3);
+
+        let sharded = ShardedRepository::open(&dir).unwrap();
+        assert_eq!(sharded.index(), &index);
+        assert_eq!(sharded.mapped_shards(), 0);
+        let reqs: RequirementSet = "pkg0".parse().unwrap();
+        assert!(simple_solve(&sharded, &reqs).unwrap().is_sat());
+        assert_eq!(sharded.mapped_shards(), 1);
+        let reqs: RequirementSet = "pkg3".parse().unwrap();
+        assert!(simple_solve(&sharded, &reqs).unwrap().is_unsat());
+        assert_eq!(sharded.mapped_shards(), 3);
+        assert_eq!(sharded.shard_of(4), Some(2));
+        assert_eq!(sharded.shard_of(5), None);
+        assert_eq!(sharded.try_fetch_package(1).unwrap().id, 1);
+
+        // corrupt shards are reported when their packages are fetched, not as missing
+        drop(sharded);
+        fs::write(dir.join("shard-1"), [0xff; 64]).unwrap();
+        let sharded = ShardedRepository::open(&dir).unwrap();
+        let error = sharded.try_fetch_package(2).unwrap_err();
+        assert_ne!(error, ResolutionError::unknown_package(2));
+        assert!(simple_solve(&sharded, &"pkg2".parse().unwrap()).is_err());
+        fs::write(dir.join("index"), [0xff; 16]).unwrap();
+        assert!(ShardedRepository::open(&dir).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
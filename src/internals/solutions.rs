@@ -0,0 +1,266 @@
+// Enumeration of the solutions of a resolution problem. Solutions are either yielded
+// lazily, every plan being blocked once it has been yielded so that only a single model
+// is held in memory at a time, or counted and sampled by enumerating the models
+use std::ops::ControlFlow;
+
+use bumpalo::Bump;
+use z3::{ast::Ast, Context, SatResult, Solver};
+
+use crate::internals::{
+    constraints::{add_all_constraints_within, find_closure},
+    encoding::Encoder,
+    options::SolveOptions,
+    types::*,
+    utils::{default_config, enumerate_models},
+};
+
+pub struct SolutionIter<'c> {
+    solver: Solver<'c>,
+    encoder: Encoder<'c>,
+    pids: Vec<PackageId>,
+    done: bool,
+}
+
+impl<'c> SolutionIter<'c> {
+    pub fn new<S: PackageSource + ?Sized>(
+        ctx: &'c Context,
+        repo: &S,
+        requirements: &RequirementSet,
+        options: &SolveOptions,
+    ) -> Result<Self, ResolutionError> {
+        let solver = options.solver(ctx);
+        let allocator = Bump::new();
+
+        let requirements = options.effective_requirements(requirements);
+        let requirements = &*requirements;
+        let closure = find_closure(repo, requirements.into_iter())?;
+        options.limits.check_closure(closure.len())?;
+        let pids = options.ordered_pids(&closure);
+        let encoder = Encoder::new(ctx, options.encoding, repo, pids.iter().copied());
+        for domain_constraint in encoder.domain_constraints() {
+            solver.assert(&domain_constraint);
+        }
+        for (option_constraint, _) in options.option_constraints(&encoder, &closure) {
+            solver.assert(&option_constraint);
+        }
+        add_all_constraints_within(
+            &allocator,
+            &encoder,
+            repo,
+            pids.iter().copied(),
+            requirements,
+            &options.limits,
+            |_| options.check_cancelled(),
+            |expr, _sym_expr, _origin| solver.assert(&expr.simplify()),
+        )?;
+
+        Ok(Self {
+            solver,
+            encoder,
+            pids,
+            done: false,
+        })
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum SolutionCount {
+    Exact(usize),
+    // the enumeration stopped at the limit or z3 could not decide whether there are more
+    // solutions, there may be more of them
+    AtLeast(usize),
+}
+
+// Count the solutions, enumerating at most `limit` of them
+pub fn count_solutions<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    limit: usize,
+) -> Result<SolutionCount, ResolutionError> {
+    let ctx = Context::new(&default_config());
+    let iter = SolutionIter::new(&ctx, repo, requirements, &SolveOptions::default())?;
+    let vars = iter.pids.iter().map(|&pid| iter.encoder.version(pid));
+
+    let mut count = 0;
+    let complete = enumerate_models(&iter.solver, vars, Some(limit), None, |_| {
+        count += 1;
+        ControlFlow::Continue(())
+    });
+    Ok(if complete {
+        SolutionCount::Exact(count)
+    } else {
+        SolutionCount::AtLeast(count)
+    })
+}
+
+// Draw `n` distinct solutions uniformly at random, or all of them if there are fewer.
+// The same seed always yields the same sample, but note that this enumerates the
+// whole solution space. Fails if z3 cannot enumerate all of it, the sample would not be
+// uniform
+pub fn sample_solutions<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    n: usize,
+    seed: u64,
+) -> Result<Vec<Plan>, ResolutionError> {
+    let ctx = Context::new(&default_config());
+    let options = SolveOptions::deterministic(seed as u32);
+    let iter = SolutionIter::new(&ctx, repo, requirements, &options)?;
+    let vars = iter.pids.iter().map(|&pid| iter.encoder.version(pid));
+
+    // reservoir sampling over the models
+    let mut rng = SplitMix64(seed);
+    let mut sample = Vec::with_capacity(n);
+    let mut seen: u64 = 0;
+    let complete = enumerate_models(&iter.solver, vars, None, None, |model| {
+        let slot = if sample.len() < n {
+            Some(sample.len())
+        } else {
+            Some((rng.next() % (seen + 1)) as usize).filter(|&j| j < n)
+        };
+        if let Some(slot) = slot {
+            let plan = iter
+                .encoder
+                .plan_from_model(&model, iter.pids.iter().copied());
+            if slot == sample.len() {
+                sample.push(plan);
+            } else {
+                sample[slot] = plan;
+            }
+        }
+        seen += 1;
+        ControlFlow::Continue(())
+    });
+    if !complete {
+        return Err(ResolutionError::unknown(iter.solver.get_reason_unknown()));
+    }
+    Ok(sample)
+}
+
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Iterator for SolutionIter<'_> {
+    type Item = Plan;
+
+    fn next(&mut self) -> Option<Plan> {
+        if self.done {
+            return None;
+        }
+        match self.solver.check() {
+            SatResult::Sat => {
+                let model = self
+                    .solver
+                    .get_model()
+                    .expect("Impossible: satisfiable but failed to generate a model");
+                let plan = self
+                    .encoder
+                    .plan_from_model(&model, self.pids.iter().copied());
+                self.encoder.block_plan(&self.solver, &plan);
+                Some(plan)
+            }
+            // an unknown result ends the enumeration as well
+            SatResult::Unsat | SatResult::Unknown => {
+                self.done = true;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use z3::Context;
+
+    use crate::internals::{
+        options::SolveOptions,
+        types::{Package, PackageVer, Repository, Requirement, RequirementSet},
+        utils::default_config,
+    };
+
+    use super::{count_solutions, sample_solutions, SolutionCount, SolutionIter};
+
+    #[test]
+    fn test_solution_iter() {
+        // package 0 has 3 versions, package 1 has 2
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (0..3)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: (0..2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        let ctx = Context::new(&default_config());
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let options = SolveOptions::deterministic(0);
+
+        let mut plans = SolutionIter::new(&ctx, &repo, &reqs, &options)
+            .unwrap()
+            .collect::<Vec<_>>();
+        plans.sort();
+        println!("{plans:?}");
+        assert_eq!(plans, vec![vec![(0, 1)], vec![(0, 2)], vec![(0, 3)]]);
+
+        let reqs = RequirementSet::from_dep(Requirement::any_version(1));
+        let iter = SolutionIter::new(&ctx, &repo, &reqs, &options).unwrap();
+        assert_eq!(iter.take(1).count(), 1);
+    }
+
+    #[test]
+    fn test_count_and_sample() {
+        // package 0 has 4 versions and requires any version of package 1, which has 2
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (0..4)
+                    .map(|_| PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: (0..2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        assert_eq!(
+            count_solutions(&repo, &reqs, 100).unwrap(),
+            SolutionCount::Exact(8)
+        );
+        assert_eq!(
+            count_solutions(&repo, &reqs, 5).unwrap(),
+            SolutionCount::AtLeast(5)
+        );
+
+        let sample = sample_solutions(&repo, &reqs, 3, 7).unwrap();
+        println!("{sample:?}");
+        assert_eq!(sample.len(), 3);
+        assert_eq!(sample, sample_solutions(&repo, &reqs, 3, 7).unwrap());
+        assert_eq!(sample_solutions(&repo, &reqs, 20, 7).unwrap().len(), 8);
+    }
+}
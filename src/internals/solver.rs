@@ -1,71 +1,61 @@
 use crate::internals::{
-    constraints::{add_all_constraints, find_closure},
-    types::*,
+    audit::{audited, ResolutionKind},
+    closure::restricted_closure,
+    constraints::{add_all_constraints, add_all_constraints_within, find_closure, Origin},
+    encoding::{Encoder, Encoding},
+    metrics::{DistanceFromNewest, InstallCost, InstalledPackages, Metric},
+    objective::{Objective, Priority},
+    options::SolveOptions,
+    preprocess::preprocess,
+    tracking::AssertionRegistry,
+    types::{
+        formula::{conflict_of, requirement_of},
+        *,
+    },
     utils::{iter_max_map, z3::*},
 };
 
 use bumpalo::Bump;
-use intmap::IntMap;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::{
+    ops::ControlFlow,
+    time::{Duration, Instant},
+};
 use tinyset::SetU32;
 use z3::{
     ast::{Ast, Bool, Int},
-    Config, Context, Model, Optimize, SatResult, Solver,
+    Config, Context, Model, Optimize, Params, SatResult, Solver,
 };
 
-fn plan_from_model(ctx: &Context, model: Model, pids: impl Iterator<Item = PackageId>) -> Plan {
-    let mut plan = Vec::new();
-    let mut no_interp = Vec::new();
-    let mut interp_not_u64 = Vec::new();
-
-    for package_id in pids {
-        let p = Int::new_const(ctx, package_id);
-        if let Some(interp) = model.get_const_interp(&p) {
-            if let Some(v) = interp.as_u64() {
-                plan.push((package_id, v))
-            } else {
-                interp_not_u64.push(package_id);
+pub(crate) fn process_unsat_core<S: PackageSource + ?Sized>(
+    repo: &S,
+    core_assertions: Vec<&(Expr<'_>, Origin)>,
+) -> ConstraintSet {
+    let mut package_reqs = PackageMap::<VersionMap<RequirementSet>>::new();
+    let mut toplevel_reqs = RequirementSet::default();
+    let mut toplevel_labels = Vec::new();
+    let mut restrictions = Vec::new();
+    for (assertion, origin) in core_assertions {
+        match origin {
+            Origin::Toplevel(index) => {
+                let mut packages = assertion.atoms().iter().map(AtomicExpr::pid).collect_vec();
+                packages.sort_unstable();
+                packages.dedup();
+                toplevel_labels.push((*index, packages));
             }
-        } else {
-            no_interp.push(package_id);
-        }
-    }
-
-    if !(no_interp.is_empty() && interp_not_u64.is_empty()) {
-        let mut panic_msg = String::new();
-
-        panic_msg.push_str("Impossible: failed to generate a plan from a model, reasons:\n");
-
-        if !no_interp.is_empty() {
-            panic_msg
-                .push_str("The following packages do not have an interpretation in the model:\n");
-            panic_msg.push_str(&format!("  {no_interp:?}"))
-        }
-
-        if !interp_not_u64.is_empty() {
-            panic_msg.push_str(
-                "The following packages have an interpretation but the value cannot fit in a u64:\n",
-            );
-            panic_msg.push_str(&format!("  {interp_not_u64:?}"))
+            Origin::Restriction(restriction) => {
+                restrictions.push(*restriction);
+                continue;
+            }
+            Origin::Package(_) | Origin::Essential(_) => {}
         }
-        panic!("{panic_msg}");
-    }
-    plan
-}
-
-fn process_unsat_core(repo: &Repository, core_assertions: Vec<&Expr<'_>>) -> ConstraintSet {
-    let mut package_reqs: IntMap<IntMap<RequirementSet>> = IntMap::new();
-    let mut dependencies = Vec::new();
-    let mut conflicts = Vec::new();
-    for assertion in core_assertions {
         match assertion {
             Expr::Atom(e) => match e {
                 AtomicExpr::VerEq { pid, version } => {
                     if *version == 0 {
-                        conflicts.push(Requirement::new(*pid, vec1![Range::all()]))
+                        toplevel_reqs.add_antidep(Requirement::new(*pid, vec1![Range::all()]))
                     } else {
-                        dependencies.push(Requirement::new(*pid, vec1![Range::point(*version)]))
+                        toplevel_reqs.add_dep(Requirement::new(*pid, vec1![Range::point(*version)]))
                     }
                 }
                 AtomicExpr::VerLE { pid, version } => {
@@ -79,294 +69,1044 @@ fn process_unsat_core(repo: &Repository, core_assertions: Vec<&Expr<'_>>) -> Con
                     }
                 }
             },
-            Expr::Not(e) => {
-                let req = process_version_range(e);
-                conflicts.push(req);
-            }
-            Expr::Implies(Expr::Atom(AtomicExpr::VerEq { pid, version }), rhs) => {
-                let req;
-                let mut reverse = false;
-                match rhs {
-                    Expr::Atom(AtomicExpr::VerEq {
-                        pid: pid2,
-                        version: 0,
-                    }) => {
-                        req = Some(Requirement::new(*pid2, vec1![Range::all()]));
-                        reverse = true;
-                    }
-                    Expr::Not(e) => {
-                        req = Some(process_version_range(e));
-                        reverse = true;
-                    }
-                    _ => {
-                        req = Some(process_version_range(rhs));
-                    }
+            Expr::Implies(guard, rhs) => {
+                let (pid, lower, upper) = version_guard(guard);
+                // a guard over an interval stands for every version in it
+                for version in lower..=upper {
+                    let req_set = package_reqs
+                        .get_or_insert_with(pid, VersionMap::new)
+                        .get_or_insert_with(version, RequirementSet::default);
+                    add_requirement(req_set, rhs);
                 }
-                let req_ = req.unwrap();
-
-                if let Some(ver_req_map) = package_reqs.get_mut(*pid as u64) {
-                    if let Some(req_set) = ver_req_map.get_mut(*version) {
-                        if reverse {
-                            req_set.add_antidep(req_)
-                        } else {
-                            req_set.add_dep(req_)
-                        }
-                    } else {
-                        let req_set = if reverse {
-                            RequirementSet::from_antidep(req_)
-                        } else {
-                            RequirementSet::from_dep(req_)
-                        };
-                        ver_req_map.insert(*version, req_set);
-                    }
-                } else {
-                    let mut ver_req_map = IntMap::new();
-                    let req_set = if reverse {
-                        RequirementSet::from_antidep(req_)
-                    } else {
-                        RequirementSet::from_dep(req_)
-                    };
-                    ver_req_map.insert(*version, req_set);
-                    package_reqs.insert(*pid as u64, ver_req_map);
-                }
-            }
-            _ => {
-                let req = process_version_range(assertion);
-                dependencies.push(req);
             }
+            _ => add_requirement(&mut toplevel_reqs, assertion),
         }
     }
 
+    toplevel_labels.sort_unstable();
+    let (toplevel_indices, toplevel_packages) = toplevel_labels.into_iter().unzip();
     ConstraintSet {
         package_reqs,
-        toplevel_reqs: RequirementSet {
-            dependencies,
-            conflicts,
-        },
+        toplevel_reqs,
+        toplevel_indices,
+        toplevel_packages,
+        restrictions,
     }
 }
 
-fn process_version_range(expr: &Expr<'_>) -> Requirement {
-    fn go(expr: &Expr<'_>) -> (PackageId, Vec1<Range>) {
-        match expr {
-            Expr::Atom(AtomicExpr::VerEq { pid, version }) => (*pid, vec1![Range::point(*version)]),
-            Expr::And(lhs, rhs) => {
-                let mut lb = 0;
-                let mut ub = 0;
-                let package_id;
-                match lhs {
-                    Expr::Atom(AtomicExpr::VerGE { pid, version }) => {
-                        lb = *version;
-                        package_id = *pid;
-                    }
-                    Expr::Atom(AtomicExpr::VerLE { pid, version }) => {
-                        ub = *version;
-                        package_id = *pid;
-                    }
-                    _ => panic!("Impossible: unknown lhs {lhs} of the expression {expr}"),
-                }
-                match rhs {
-                    Expr::Atom(AtomicExpr::VerGE { pid, version }) => {
-                        lb = *version;
-                        assert_eq!(package_id, *pid);
-                    }
-                    Expr::Atom(AtomicExpr::VerLE { pid, version }) => {
-                        ub = *version;
-                        assert_eq!(package_id, *pid);
-                    }
-                    _ => panic!("Impossible: unknown rhs {rhs} of the expression {expr}"),
-                }
-                let rs = vec1![Range::interval(lb, ub).unwrap_or_else(|| {
-                    panic!("Impossible: lower bound is bigger than upper bound in expr {expr}")
-                })];
-                (package_id, rs)
-            }
-            Expr::Or(lhs, rhs) => {
-                let (pid1, mut rs1) = go(lhs);
-                let (pid2, rs2) = go(rhs);
-                assert_eq!(pid1, pid2);
-                rs1.append(&mut rs2.into_vec());
-                (pid1, rs1)
-            }
-            Expr::Not(Expr::Atom(AtomicExpr::VerEq { pid, version: 0 })) => {
-                (*pid, vec1![Range::all()])
-            }
-            _ => panic!("Impossible: unknown expression {expr} for version range(s)"),
-        }
+// The versions lower..=upper of a package guarding the requirements of a package
+fn version_guard(guard: &Expr<'_>) -> (PackageId, Version, Version) {
+    match guard {
+        Expr::Atom(AtomicExpr::VerEq { pid, version }) => (*pid, *version, *version),
+        Expr::And(
+            Expr::Atom(AtomicExpr::VerGE {
+                pid,
+                version: lower,
+            }),
+            Expr::Atom(AtomicExpr::VerLE { version: upper, .. }),
+        ) => (*pid, *lower, *upper),
+        _ => panic!("Impossible: unknown guard {guard} of a package requirement"),
+    }
+}
+
+// Add the requirement encoded by an expression to a requirement set, as a dependency,
+// as a conflict or as part of the formula of the set
+fn add_requirement(reqs: &mut RequirementSet, expr: &Expr<'_>) {
+    if let Some(req) = requirement_of(expr) {
+        reqs.add_dep(req)
+    } else if let Some(req) = conflict_of(expr) {
+        reqs.add_antidep(req)
+    } else {
+        let formula = ReqExpr::from_expr(expr)
+            .unwrap_or_else(|| panic!("Impossible: unknown expression {expr} for a requirement"));
+        reqs.add_formula(formula)
     }
+}
+
+// Callbacks and statistics threaded through a resolution
+pub struct Hooks<'h> {
+    pub progress: &'h mut dyn FnMut(Progress),
+    pub stats: &'h mut SolveStats,
+}
+
+pub fn simple_solve<S: PackageSource + ?Sized>(repo: &S, requirements: &RequirementSet) -> Res {
+    simple_solve_with_progress(repo, requirements, |_| {})
+}
+
+pub fn simple_solve_with_progress<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    mut progress: impl FnMut(Progress),
+) -> Res {
+    let hooks = Hooks {
+        progress: &mut progress,
+        stats: &mut SolveStats::default(),
+    };
+    simple_solve_impl(repo, requirements, &SolveOptions::default(), hooks)
+}
+
+pub fn simple_solve_with_options<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &SolveOptions,
+) -> Res {
+    let hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut SolveStats::default(),
+    };
+    simple_solve_impl(repo, requirements, options, hooks)
+}
 
-    let (pid, ranges) = go(expr);
-    Requirement::new(pid, ranges)
+pub fn simple_solve_with_stats<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &SolveOptions,
+) -> Result<(ResolutionResult, SolveStats), ResolutionError> {
+    let mut stats = SolveStats::default();
+    let hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut stats,
+    };
+    let res = simple_solve_impl(repo, requirements, options, hooks)?;
+    Ok((res, stats))
 }
 
-pub fn simple_solve(repo: &Repository, requirements: &RequirementSet) -> Res {
+fn simple_solve_impl<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &SolveOptions,
+    hooks: Hooks,
+) -> Res {
     let cfg = default_config();
     let ctx = Context::new(&cfg);
-    let solver = Solver::new_for_logic(&ctx, "QF_LIA").unwrap();
-    solver.set_params(&default_params(&ctx));
-
     let allocator = Bump::new();
+    simple_solve_in(&ctx, &allocator, repo, requirements, options, hooks)
+}
 
-    let closure = find_closure(repo, requirements.into_iter());
+// Solve using an existing context and allocator
+pub fn simple_solve_in<S: PackageSource + ?Sized>(
+    ctx: &Context,
+    allocator: &Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &SolveOptions,
+    hooks: Hooks,
+) -> Res {
+    audited(repo, requirements, ResolutionKind::Solve, options, || {
+        options.interruptible(ctx, || {
+            solve_unaudited(ctx, allocator, repo, requirements, options, hooks)
+        })
+    })
+}
 
+fn solve_unaudited<S: PackageSource + ?Sized>(
+    ctx: &Context,
+    allocator: &Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &SolveOptions,
+    mut hooks: Hooks,
+) -> Res {
+    // preprocessing prunes the versions the restrictions leave impossible as well
+    if options.preprocess || options.restrict_versions {
+        let pruned = if options.preprocess {
+            preprocess(repo, requirements)?.repository
+        } else {
+            restricted_closure(repo, &options.effective_requirements(requirements))?
+        };
+        let options = SolveOptions {
+            preprocess: false,
+            restrict_versions: false,
+            ..options.clone()
+        };
+        return solve_unaudited(ctx, allocator, &pruned, requirements, &options, hooks);
+    }
+    let start = Instant::now();
+    let solver = options.solver(ctx);
+
+    let original = requirements;
+    let requirements = options.effective_requirements(requirements);
+    let requirements = &*requirements;
+    let closure = find_closure(repo, requirements.into_iter())?;
+    options.limits.check_closure(closure.len())?;
+    (hooks.progress)(Progress::ClosureComputed {
+        packages: closure.len(),
+    });
+    if let Some(core) = requirements.contradiction() {
+        hooks.stats.closure_size = closure.len();
+        hooks.stats.elapsed = start.elapsed();
+        return Ok(ResolutionResult::UnsatWithCore { core });
+    }
+    let pids = options.ordered_pids(&closure);
+    let encoder = Encoder::new(ctx, options.encoding, repo, pids.iter().copied());
+    for domain_constraint in encoder.domain_constraints() {
+        solver.assert(&domain_constraint);
+    }
     let mut assert_id = 0;
-    let mut assertion_map = HashMap::new();
-    let expr_cont = |expr: Bool, sym_expr| {
-        let assert_var = Bool::new_const(&ctx, assert_id);
-        solver.assert_and_track(&expr.simplify(), &assert_var);
-        assertion_map.insert(assert_var, sym_expr);
+    let mut registry = AssertionRegistry::new(ctx);
+    for (option_constraint, restriction) in options.option_constraints(&encoder, &closure) {
+        if options.untracked_first {
+            solver.assert(&option_constraint);
+        } else {
+            let origin = Origin::Restriction(restriction);
+            registry.track(&solver, &option_constraint, (Expr::Top, origin));
+        }
+    }
+    let expr_cont = |expr: Bool, sym_expr, origin| {
+        if options.untracked_first {
+            solver.assert(&expr.simplify());
+        } else {
+            registry.track(&solver, &expr.simplify(), (sym_expr, origin));
+        }
         assert_id += 1;
     };
-    add_all_constraints(
-        &allocator,
-        &ctx,
+    let after_chunk = |encoded| {
+        (hooks.progress)(Progress::PackagesEncoded {
+            encoded,
+            total: pids.len(),
+        });
+        options.check_cancelled()
+    };
+    add_all_constraints_within(
+        allocator,
+        &encoder,
         repo,
-        closure.iter(),
+        pids.iter().copied(),
         requirements,
+        &options.limits,
+        after_chunk,
         expr_cont,
+    )?;
+    (hooks.progress)(Progress::AssertionsAdded {
+        count: assert_id as usize,
+    });
+    hooks.stats.closure_size = closure.len();
+    hooks.stats.assertions = assert_id as usize;
+
+    // without tracking there is no core, the constraints are encoded again with tracking.
+    // The arena of the first attempt is not counted against the limits of the second
+    let res = if options.untracked_first {
+        match solver.check() {
+            SatResult::Sat => Ok(extract_plan(&encoder, &solver, &pids, &[], &mut hooks)),
+            SatResult::Unknown => Err(ResolutionError::unknown(solver.get_reason_unknown())),
+            SatResult::Unsat => {
+                collect_statistics(&solver.get_statistics(), hooks.stats);
+                hooks.stats.elapsed = start.elapsed();
+                let options = SolveOptions {
+                    untracked_first: false,
+                    ..options.clone()
+                };
+                return retry_unaudited(ctx, &Bump::new(), repo, original, &options, hooks);
+            }
+        }
+    } else {
+        check_and_extract(&encoder, &solver, repo, &pids, &registry, &mut hooks)
+    };
+
+    collect_statistics(&solver.get_statistics(), hooks.stats);
+    hooks.stats.elapsed = start.elapsed();
+    res
+}
+
+// Solve again after a failed attempt, whose statistics are already in the hooks
+fn retry_unaudited<S: PackageSource + ?Sized>(
+    ctx: &Context,
+    allocator: &Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    options: &SolveOptions,
+    hooks: Hooks,
+) -> Res {
+    let mut retry = SolveStats::default();
+    let res = solve_unaudited(
+        ctx,
+        allocator,
+        repo,
+        requirements,
+        options,
+        Hooks {
+            progress: hooks.progress,
+            stats: &mut retry,
+        },
     );
+    hooks.stats.accumulate(retry);
+    res
+}
 
-    match solver.check() {
+// The requirements of the unsat core of the solver, from the registries tracking its
+// assertions
+fn unsat_core<'c, S: PackageSource + ?Sized>(
+    repo: &S,
+    solver: &Solver<'c>,
+    registries: &[&AssertionRegistry<'c, (Expr<'_>, Origin)>],
+) -> ConstraintSet {
+    let mut core_assertions = Vec::new();
+    for var in solver.get_unsat_core() {
+        let assertion = registries.iter().find_map(|registry| registry.lookup(&var));
+        let assertion = assertion.unwrap_or_else(|| {
+            panic!(
+                "Impossible: unable to find the assertion tracked by the boolean variable {var} in the registries"
+            )
+        });
+        core_assertions.push(assertion);
+    }
+    process_unsat_core(repo, core_assertions)
+}
+
+// Check the assertions in the solver, then either extract a (locally optimal) plan
+// or an unsat core
+pub fn check_and_extract<'c, S: PackageSource + ?Sized>(
+    encoder: &Encoder<'c>,
+    solver: &Solver<'c>,
+    repo: &S,
+    pids: &[PackageId],
+    registry: &AssertionRegistry<'c, (Expr<'_>, Origin)>,
+    hooks: &mut Hooks,
+) -> Res {
+    check_and_extract_in(encoder, solver, repo, pids, &[registry], &[], hooks)
+}
+
+// The same with the tracked assertions split over several registries, e.g. the
+// assertions of the packages kept across resolutions and the toplevel ones of a single
+// resolution, and checked under assumptions, e.g. the guards of the enabled assertions
+pub(crate) fn check_and_extract_in<'c, S: PackageSource + ?Sized>(
+    encoder: &Encoder<'c>,
+    solver: &Solver<'c>,
+    repo: &S,
+    pids: &[PackageId],
+    registries: &[&AssertionRegistry<'c, (Expr<'_>, Origin)>],
+    assumptions: &[Bool<'c>],
+    hooks: &mut Hooks,
+) -> Res {
+    match solver.check_assumptions(assumptions) {
         SatResult::Unsat => {
-            let core_vars = solver.get_unsat_core();
-            let mut core_assertions = Vec::new();
-            for var in core_vars {
-                let assertion = assertion_map.get(&var).unwrap_or_else(|| {
-                    panic!(
-                        "Impossible: unable to find the assertion tracked by the boolean variable {var} in the assertion map"
-                    )
-                });
-                core_assertions.push(assertion);
-            }
-            let core = process_unsat_core(repo, core_assertions);
+            let core = unsat_core(repo, solver, registries);
             Ok(ResolutionResult::UnsatWithCore { core })
         }
-        SatResult::Unknown => Err(ResolutionError::ResolutionFailure {
-            reason: solver
-                .get_reason_unknown()
-                .expect("Impossible: failed to obtain a reason"),
-        }),
-        SatResult::Sat => {
-            let mut model = solver
-                .get_model()
-                .expect("Impossible: satisfiable but failed to generate a model");
-            let (installed_pkgs, not_installed_pkgs) =
-                installation_status(&ctx, &model, closure.iter());
-            fix_installed_pkgs(&ctx, &solver, &not_installed_pkgs);
-
-            while matches!(solver.check(), SatResult::Sat) {
-                model = solver
-                    .get_model()
-                    .expect("Impossible: satisfiable but failed to generate a model");
-                block_le_solutions(&ctx, &solver, &model, &installed_pkgs);
-            }
+        SatResult::Unknown => Err(ResolutionError::unknown(solver.get_reason_unknown())),
+        SatResult::Sat => Ok(extract_plan(encoder, solver, pids, assumptions, hooks)),
+    }
+}
 
-            let plan = plan_from_model(&ctx, model, closure.iter());
+// The plan of the model of a satisfiable check, improved until no plan with newer
+// versions of the packages it installs is left
+fn extract_plan<'c>(
+    encoder: &Encoder<'c>,
+    solver: &Solver<'c>,
+    pids: &[PackageId],
+    assumptions: &[Bool<'c>],
+    hooks: &mut Hooks,
+) -> ResolutionResult {
+    let mut model = solver
+        .get_model()
+        .expect("Impossible: satisfiable but failed to generate a model");
+    let (installed_pkgs, not_installed_pkgs) =
+        encoder.installation_status(&model, pids.iter().copied());
+    encoder.fix_not_installed(solver, &not_installed_pkgs);
 
-            Ok(ResolutionResult::Sat {
-                plans: Vec1::new(plan),
-            })
-        }
+    let mut rounds = 0;
+    while matches!(solver.check_assumptions(assumptions), SatResult::Sat) {
+        model = solver
+            .get_model()
+            .expect("Impossible: satisfiable but failed to generate a model");
+        encoder.block_le_solutions(solver, &model, &installed_pkgs);
+        rounds += 1;
+        (hooks.progress)(Progress::BoundTightened { rounds });
     }
+    hooks.stats.rounds = rounds;
+
+    let plan = encoder.plan_from_model(&model, pids.iter().copied());
+
+    ResolutionResult::Sat {
+        plans: Vec1::new(plan),
+        objective: None,
+        optimal: false,
+    }
+}
+
+// An optimizer with all the constraints of the requirements asserted and the levels of
+// the objective to be minimized
+struct PreparedOptimizer<'c> {
+    solver: Optimize<'c>,
+    encoder: Encoder<'c>,
+    pids: Vec<PackageId>,
+    metrics: Vec<Int<'c>>,
+    // every level in order: the downgrade penalty, the metrics, the tie-breakers and the
+    // distance from the warm start
+    levels: Vec<Int<'c>>,
+    // whether the levels are left to be minimized one at a time, each with a timeout of
+    // its own, instead of being handed to z3 at once
+    per_level: bool,
+    // a plan meeting the thresholds of the options, found before optimizing
+    good_enough: Option<Model<'c>>,
 }
 
-fn optimize_with(
-    repo: &Repository,
+fn prepare_optimizer<'c, S: PackageSource + ?Sized>(
+    ctx: &'c Context,
+    allocator: &Bump,
+    repo: &S,
     requirements: &RequirementSet,
-    gen_metric: impl FnOnce(&Context, Vec<(u32, u64)>, SetU32) -> Vec<Int>,
-) -> Res {
-    let cfg = Config::new();
-    let ctx = Context::new(&cfg);
-    let solver = Optimize::new(&ctx);
+    objective: &Objective<S>,
+    options: &SolveOptions,
+    // the time already spent on the resolution, counted against the deadline
+    spent: Duration,
+    hooks: &mut Hooks,
+) -> Result<PreparedOptimizer<'c>, ResolutionError> {
+    let solver = Optimize::new(ctx);
 
-    let allocator = Bump::new();
+    let requirements = options.effective_requirements(requirements);
+    let requirements = &*requirements;
+    // the metrics are built over the original repository, the pruned versions keep
+    // their numbers
+    let pruned = if options.preprocess {
+        Some(preprocess(repo, requirements)?.repository)
+    } else if options.restrict_versions {
+        Some(restricted_closure(repo, requirements)?)
+    } else {
+        None
+    };
+    let closure = match &pruned {
+        Some(p) => find_closure(p, requirements.into_iter())?,
+        None => find_closure(repo, requirements.into_iter())?,
+    };
+    options.limits.check_closure(closure.len())?;
+    (hooks.progress)(Progress::ClosureComputed {
+        packages: closure.len(),
+    });
+    let pids = options.ordered_pids(&closure);
 
-    let closure = find_closure(repo, requirements.into_iter());
+    let encoder = Encoder::new(ctx, options.encoding, repo, pids.iter().copied());
+    for domain_constraint in encoder.domain_constraints() {
+        solver.assert(&domain_constraint);
+    }
+    for (option_constraint, _) in options.option_constraints(&encoder, &closure) {
+        solver.assert(&option_constraint);
+    }
+    let metrics = objective.build(&encoder, &pids, repo);
+    let penalty = options
+        .downgrade_penalty()
+        .map(|penalty| penalty.build(&encoder, &pids, repo));
 
-    let package_pairs = closure
-        .iter()
-        .map(|pid| (pid, repo.newest_ver_of_unchecked(pid)))
+    let mut levels = penalty
+        .into_iter()
+        .chain(metrics.iter().cloned())
         .collect_vec();
+    // ties are only left once the levels are minimized in order
+    if objective.get_priority() == Priority::Lexicographic {
+        levels.extend(options.tie_breaker.objectives(&encoder, &pids, repo));
+    }
+    // last, so that keeping the versions of the previous plan only breaks ties
+    if let Some(previous) = &options.warm_start {
+        let (zero, one) = (Int::from_u64(ctx, 0), Int::from_u64(ctx, 1));
+        let changed = previous
+            .iter()
+            .filter(|&&(pid, _)| closure.contains(pid))
+            .map(|&(pid, v)| encoder.atom(&AtomicExpr::ver_eq(pid, v)).ite(&zero, &one))
+            .collect_vec();
+        if !changed.is_empty() {
+            levels.push(Int::add(ctx, &changed.iter().collect_vec()));
+        }
+    }
+    // box and pareto objectives are not minimized in order, they share the time of all
+    // the levels
+    let per_level = objective.get_priority() == Priority::Lexicographic
+        && options.optimize.max_time_per_objective.is_some();
 
-    let metrics = gen_metric(&ctx, package_pairs, closure.clone());
+    let mut params = options.optimizer_params(ctx);
+    objective.apply_params(&mut params);
+    let timed_levels = if per_level { 1 } else { levels.len() };
+    options
+        .optimize
+        .apply_params(&mut params, timed_levels, spent);
+    solver.set_params(&params);
 
-    let mut assert_id = 0;
-    let expr_cont = |expr: Bool, _sym_expr| {
+    let mut assert_id: usize = 0;
+    let expr_cont = |expr: Bool, _sym_expr, _origin| {
         solver.assert(&expr.simplify());
         assert_id += 1;
     };
-    add_all_constraints(
-        &allocator,
-        &ctx,
+    let mut after_chunk = |encoded| {
+        (hooks.progress)(Progress::PackagesEncoded {
+            encoded,
+            total: pids.len(),
+        });
+        options.check_cancelled()
+    };
+    match &pruned {
+        Some(p) => add_all_constraints_within(
+            allocator,
+            &encoder,
+            p,
+            pids.iter().copied(),
+            requirements,
+            &options.limits,
+            &mut after_chunk,
+            expr_cont,
+        )?,
+        None => add_all_constraints_within(
+            allocator,
+            &encoder,
+            repo,
+            pids.iter().copied(),
+            requirements,
+            &options.limits,
+            &mut after_chunk,
+            expr_cont,
+        )?,
+    }
+    (hooks.progress)(Progress::AssertionsAdded { count: assert_id });
+    hooks.stats.closure_size = closure.len();
+    hooks.stats.assertions = assert_id;
+
+    // checked before the objectives are added, so that z3 only looks for some plan. A
+    // pareto front is made of optimal plans only, there is nothing to accept early
+    let good_enough = options
+        .optimize
+        .good_enough
+        .as_ref()
+        .filter(|_| objective.get_priority() != Priority::Pareto)
+        .and_then(|thresholds| {
+            solver.push();
+            for (metric, &threshold) in metrics.iter().zip(thresholds) {
+                solver.assert(&metric.le(&Int::from_u64(ctx, threshold)));
+            }
+            let model = match solver.check(&[]) {
+                SatResult::Sat => solver.get_model(),
+                _ => None,
+            };
+            solver.pop();
+            model
+        });
+
+    if !per_level {
+        for level in &levels {
+            solver.minimize(level);
+        }
+    }
+
+    Ok(PreparedOptimizer {
+        solver,
+        encoder,
+        pids,
+        metrics,
+        levels,
+        per_level,
+        good_enough,
+    })
+}
+
+// Minimize the levels one after the other, each check getting the time per objective of
+// the options. A level is then bounded by its value in the best model, so a level out of
+// time still leaves the next ones their share. Returns the best model with whether every
+// level reached its optimum, `Ok(None)` if the constraints are unsatisfiable and an
+// error if not even the first level found a plan
+fn minimize_levels<'c>(
+    ctx: &'c Context,
+    solver: &Optimize<'c>,
+    levels: &[Int<'c>],
+    options: &SolveOptions,
+    start: Instant,
+) -> Result<Option<(Model<'c>, bool)>, ResolutionError> {
+    let mut best: Option<Model<'c>> = None;
+    let mut optimal = true;
+    for level in levels {
+        let mut params = Params::new(ctx);
+        options
+            .optimize
+            .apply_params(&mut params, 1, start.elapsed());
+        solver.set_params(&params);
+
+        solver.push();
+        solver.minimize(level);
+        let res = solver.check(&[]);
+        // the model is gone once the scope of the objective is popped
+        let model = solver.get_model();
+        solver.pop();
+        let model = match (res, model) {
+            (SatResult::Unsat, _) if best.is_none() => return Ok(None),
+            (SatResult::Sat, Some(model)) => model,
+            (_, model) => {
+                optimal = false;
+                match model.or(best.take()) {
+                    Some(model) => model,
+                    None => return Err(ResolutionError::unknown(solver.get_reason_unknown())),
+                }
+            }
+        };
+        let value = model
+            .eval(level, true)
+            .unwrap_or_else(|| panic!("Impossible: failed to evaluate level {level} in model"));
+        solver.assert(&level.le(&value));
+        best = Some(model);
+    }
+    match best {
+        Some(model) => Ok(Some((model, optimal))),
+        // no level at all, some plan is enough
+        None => match solver.check(&[]) {
+            SatResult::Sat => Ok(solver.get_model().map(|model| (model, true))),
+            SatResult::Unsat => Ok(None),
+            SatResult::Unknown => Err(ResolutionError::unknown(solver.get_reason_unknown())),
+        },
+    }
+}
+
+// A model of the optimizer pinned to a plan, for the values of the levels at the plan.
+// Packages of the closure the plan leaves out are not installed, None if the constraints
+// of the optimizer rule the plan out
+fn model_of_plan<'c>(
+    solver: &Optimize<'c>,
+    encoder: &Encoder<'c>,
+    pids: &[PackageId],
+    plan: &Plan,
+) -> Option<Model<'c>> {
+    let versions = plan.iter().copied().collect::<PackageMap<_>>();
+    solver.push();
+    for &pid in pids {
+        let version = versions.get(pid).copied().unwrap_or(0);
+        solver.assert(&encoder.atom(&AtomicExpr::ver_eq(pid, version)));
+    }
+    let model = match solver.check(&[]) {
+        SatResult::Sat => solver.get_model(),
+        _ => None,
+    };
+    solver.pop();
+    model
+}
+
+// The values of the levels in a model, which compare in the order of the levels
+fn level_values(model: &Model, levels: &[Int]) -> Vec<i64> {
+    levels
+        .iter()
+        .map(|level| {
+            model
+                .eval(level, true)
+                .and_then(|v| v.as_i64())
+                .unwrap_or_else(|| panic!("Impossible: failed to evaluate level {level} in model"))
+        })
+        .collect()
+}
+
+// The values of the standard metrics in the model of an optimal plan, whatever the
+// objective was
+fn objective_values<S: PackageSource + ?Sized>(
+    model: &Model,
+    encoder: &Encoder,
+    pids: &[PackageId],
+    repo: &S,
+) -> ObjectiveValues {
+    let eval = |metric: &dyn Metric<S>| {
+        let expr = metric.build(encoder, pids, repo);
+        model
+            .eval(&expr, true)
+            .and_then(|v| v.as_u64())
+            .unwrap_or_else(|| panic!("Impossible: failed to evaluate metric {expr} in model"))
+    };
+    ObjectiveValues {
+        distance_from_newest: eval(&DistanceFromNewest),
+        installed: eval(&InstalledPackages),
+    }
+}
+
+// Optimize towards an objective, using an existing context and allocator
+pub fn optimize_with<S: PackageSource + ?Sized>(
+    ctx: &Context,
+    allocator: &Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    objective: &Objective<S>,
+    options: &SolveOptions,
+    hooks: Hooks,
+) -> Res {
+    let kind = objective
+        .goal()
+        .map_or(ResolutionKind::Objective, ResolutionKind::Goal);
+    audited(repo, requirements, kind, options, || {
+        options.interruptible(ctx, || {
+            optimize_unaudited(
+                ctx,
+                allocator,
+                repo,
+                requirements,
+                objective,
+                options,
+                hooks,
+            )
+        })
+    })
+}
+
+fn optimize_unaudited<S: PackageSource + ?Sized>(
+    ctx: &Context,
+    allocator: &Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    objective: &Objective<S>,
+    options: &SolveOptions,
+    mut hooks: Hooks,
+) -> Res {
+    // nothing to optimize, the plain solver reports the contradiction
+    if options
+        .effective_requirements(requirements)
+        .contradiction()
+        .is_some()
+    {
+        return solve_unaudited(ctx, allocator, repo, requirements, options, hooks);
+    }
+    let start = Instant::now();
+    // with a deadline, some plan is found first so that there is one to return whenever
+    // the time is up. It gets an allocator of its own, which the limits do not count
+    let feasible = match options.optimize.deadline {
+        None => None,
+        Some(_) => {
+            let hooks = Hooks {
+                progress: &mut *hooks.progress,
+                stats: &mut *hooks.stats,
+            };
+            match solve_unaudited(ctx, &Bump::new(), repo, requirements, options, hooks)? {
+                ResolutionResult::Sat { plans, .. } => Some(plans.first().clone()),
+                unsat => return Ok(unsat),
+            }
+        }
+    };
+    if let (Some(plan), Some(Duration::ZERO)) =
+        (&feasible, options.optimize.remaining(start.elapsed()))
+    {
+        hooks.stats.optimal = Some(false);
+        hooks.stats.elapsed = start.elapsed();
+        return Ok(ResolutionResult::Sat {
+            plans: Vec1::new(plan.clone()),
+            objective: None,
+            optimal: false,
+        });
+    }
+
+    let PreparedOptimizer {
+        solver,
+        encoder,
+        pids,
+        levels,
+        per_level,
+        good_enough,
+        ..
+    } = prepare_optimizer(
+        ctx,
+        allocator,
         repo,
-        closure.iter(),
         requirements,
-        expr_cont,
-    );
-
-    for metric in metrics {
-        solver.minimize(&metric);
+        objective,
+        options,
+        start.elapsed(),
+        &mut hooks,
+    )?;
+    // the plan found first bounds the first level, and is kept if the optimizer runs out of
+    // time with a worse plan
+    let feasible = feasible.map(|plan| (model_of_plan(&solver, &encoder, &pids, &plan), plan));
+    if let (Some((Some(model), _)), Some(first)) = (&feasible, levels.first()) {
+        let bound = model
+            .eval(first, true)
+            .unwrap_or_else(|| panic!("Impossible: failed to evaluate level {first} in model"));
+        solver.assert(&first.le(&bound));
     }
 
-    match solver.check(&[]) {
-        SatResult::Unsat => simple_solve(repo, requirements),
-        SatResult::Unknown => Err(ResolutionError::ResolutionFailure {
-            reason: solver
-                .get_reason_unknown()
-                .expect("Impossible: failed to obtain a reason"),
+    let (model, optimal) = match good_enough {
+        Some(model) => (Ok(model), false),
+        None if per_level => match minimize_levels(ctx, &solver, &levels, options, start) {
+            Ok(None) => {
+                collect_statistics(&solver.get_statistics(), hooks.stats);
+                hooks.stats.elapsed = start.elapsed();
+                return retry_unaudited(ctx, allocator, repo, requirements, options, hooks);
+            }
+            Ok(Some((model, optimal))) => (Ok(model), optimal),
+            Err(e) => (Err(e), false),
+        },
+        None => match solver.check(&[]) {
+            SatResult::Unsat => {
+                collect_statistics(&solver.get_statistics(), hooks.stats);
+                hooks.stats.elapsed = start.elapsed();
+                return retry_unaudited(ctx, allocator, repo, requirements, options, hooks);
+            }
+            // out of time, the best plan so far if there is one
+            SatResult::Unknown => (
+                options
+                    .optimize
+                    .is_bounded()
+                    .then(|| solver.get_model())
+                    .flatten()
+                    .ok_or_else(|| ResolutionError::unknown(solver.get_reason_unknown())),
+                false,
+            ),
+            SatResult::Sat => (
+                Ok(solver
+                    .get_model()
+                    .expect("Impossible: satisfiable but failed to generate a model")),
+                true,
+            ),
+        },
+    };
+    let sat = |model: Model| {
+        let plan = encoder.plan_from_model(&model, pids.iter().copied());
+        ResolutionResult::Sat {
+            plans: Vec1::new(plan),
+            objective: Some(objective_values(&model, &encoder, &pids, repo)),
+            optimal,
+        }
+    };
+    let res = match (model, feasible) {
+        (Ok(model), Some((Some(first), _))) if !optimal => {
+            if level_values(&first, &levels) < level_values(&model, &levels) {
+                Ok(sat(first))
+            } else {
+                Ok(sat(model))
+            }
+        }
+        (Err(_), Some((Some(first), _))) => Ok(sat(first)),
+        // the optimizer found nothing in time and ruled out the plan found first, whose
+        // metrics are then unknown
+        (Err(_), Some((None, plan))) => Ok(ResolutionResult::Sat {
+            plans: Vec1::new(plan),
+            objective: None,
+            optimal: false,
         }),
-        SatResult::Sat => {
-            let model = solver
-                .get_model()
-                .expect("Impossible: satisfiable but failed to generate a model");
+        (model, _) => model.map(sat),
+    };
+    hooks.stats.optimal = Some(optimal);
 
-            let plan = plan_from_model(&ctx, model, closure.iter());
+    collect_statistics(&solver.get_statistics(), hooks.stats);
+    hooks.stats.elapsed = start.elapsed();
+    res
+}
 
-            Ok(ResolutionResult::Sat {
-                plans: Vec1::new(plan),
-            })
+// Enumerate the pareto front of the metrics, each point of the front is returned with
+// the values of the metrics in the same order as the metrics
+pub fn optimize_pareto<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    metrics: &[&dyn Metric<S>],
+) -> Result<ParetoResult, ResolutionError> {
+    let start = Instant::now();
+    let cfg = Config::new();
+    let ctx = Context::new(&cfg);
+    let allocator = Bump::new();
+    let options = SolveOptions::default();
+    let mut stats = SolveStats::default();
+    let mut hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut stats,
+    };
+    let objective = Objective::lexicographic(metrics).priority(Priority::Pareto);
+    let PreparedOptimizer {
+        solver,
+        encoder,
+        pids,
+        metrics,
+        ..
+    } = prepare_optimizer(
+        &ctx,
+        &allocator,
+        repo,
+        requirements,
+        &objective,
+        &options,
+        start.elapsed(),
+        &mut hooks,
+    )?;
+
+    // every check yields a new point of the front until it is exhausted
+    let mut points = Vec::new();
+    loop {
+        match solver.check(&[]) {
+            SatResult::Sat => {
+                let model = solver
+                    .get_model()
+                    .expect("Impossible: satisfiable but failed to generate a model");
+                let values = metrics
+                    .iter()
+                    .map(|metric| {
+                        model
+                            .eval(metric, true)
+                            .and_then(|v| v.as_i64())
+                            .unwrap_or_else(|| {
+                                panic!("Impossible: failed to evaluate metric {metric} in model")
+                            })
+                    })
+                    .collect();
+                let plan = encoder.plan_from_model(&model, pids.iter().copied());
+                points.push(ParetoPoint { plan, values });
+            }
+            SatResult::Unknown if points.is_empty() => {
+                return Err(ResolutionError::unknown(solver.get_reason_unknown()))
+            }
+            SatResult::Unsat | SatResult::Unknown => break,
+        }
+    }
+
+    match Vec1::try_from(points) {
+        Ok(points) => Ok(ParetoResult::Frontier(points)),
+        Err(()) => {
+            let res = simple_solve_in(&ctx, &allocator, repo, requirements, &options, hooks)?;
+            Ok(ParetoResult::Unsat(res))
         }
     }
 }
 
-pub fn optimize_newest(repo: &Repository, requirements: &RequirementSet) -> Res {
-    optimize_with(repo, requirements, |ctx, package_pairs, closure| {
-        let metric = distance_from_newest(ctx, package_pairs.into_iter());
-        let metric2 = installed_packages(ctx, closure.iter());
-        vec![metric, metric2]
-    })
+pub fn optimize_newest<S: PackageSource + ?Sized>(repo: &S, requirements: &RequirementSet) -> Res {
+    optimize_with_progress(repo, requirements, Goal::Newest, |_| {})
 }
 
-pub fn optimize_minimal(repo: &Repository, requirements: &RequirementSet) -> Res {
-    optimize_with(repo, requirements, |ctx, package_pairs, closure| {
-        let metric = installed_packages(ctx, closure.iter());
-        let metric2 = distance_from_newest(ctx, package_pairs.into_iter());
-        vec![metric, metric2]
-    })
+pub fn optimize_minimal<S: PackageSource + ?Sized>(repo: &S, requirements: &RequirementSet) -> Res {
+    optimize_with_progress(repo, requirements, Goal::Minimal, |_| {})
+}
+
+pub fn optimize_with_progress<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    goal: Goal,
+    mut progress: impl FnMut(Progress),
+) -> Res {
+    let hooks = Hooks {
+        progress: &mut progress,
+        stats: &mut SolveStats::default(),
+    };
+    optimize_impl(repo, requirements, goal, &SolveOptions::default(), hooks)
+}
+
+pub fn optimize_with_options<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    goal: Goal,
+    options: &SolveOptions,
+) -> Res {
+    let hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut SolveStats::default(),
+    };
+    optimize_impl(repo, requirements, goal, options, hooks)
 }
 
-fn parallel_optimize_with<T: Ord>(
-    repo: &Repository,
+pub fn optimize_with_metrics<S: PackageSource + ?Sized>(
+    repo: &S,
     requirements: &RequirementSet,
+    metrics: &[&dyn Metric<S>],
+    options: &SolveOptions,
+) -> Res {
+    optimize_with_objective(
+        repo,
+        requirements,
+        &Objective::lexicographic(metrics),
+        options,
+    )
+}
+
+pub fn optimize_with_objective<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    objective: &Objective<S>,
+    options: &SolveOptions,
+) -> Res {
+    let hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut SolveStats::default(),
+    };
+    let cfg = Config::new();
+    let ctx = Context::new(&cfg);
+    let allocator = Bump::new();
+    optimize_with(
+        &ctx,
+        &allocator,
+        repo,
+        requirements,
+        objective,
+        options,
+        hooks,
+    )
+}
+
+// Minimize the total cost of the installed versions, preferring newer versions among
+// equally cheap plans
+pub fn optimize_cheapest<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    costs: &PackageMap<Vec<u64>>,
+) -> Res {
+    optimize_with_metrics(
+        repo,
+        requirements,
+        &[&InstallCost(costs), &DistanceFromNewest],
+        &SolveOptions::default(),
+    )
+}
+
+pub fn optimize_with_stats<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    goal: Goal,
+    options: &SolveOptions,
+) -> Result<(ResolutionResult, SolveStats), ResolutionError> {
+    let mut stats = SolveStats::default();
+    let hooks = Hooks {
+        progress: &mut |_| {},
+        stats: &mut stats,
+    };
+    let res = optimize_impl(repo, requirements, goal, options, hooks)?;
+    Ok((res, stats))
+}
+
+fn optimize_impl<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+    goal: Goal,
+    options: &SolveOptions,
+    hooks: Hooks,
+) -> Res {
+    let cfg = Config::new();
+    let ctx = Context::new(&cfg);
+    let allocator = Bump::new();
+    optimize_in(&ctx, &allocator, repo, requirements, goal, options, hooks)
+}
+
+// Optimize using an existing context and allocator
+pub fn optimize_in<S: PackageSource + ?Sized>(
     ctx: &Context,
+    allocator: &Bump,
+    repo: &S,
+    requirements: &RequirementSet,
+    goal: Goal,
+    options: &SolveOptions,
+    hooks: Hooks,
+) -> Res {
+    optimize_with(
+        ctx,
+        allocator,
+        repo,
+        requirements,
+        &Objective::for_goal(goal),
+        options,
+        hooks,
+    )
+}
+
+const MAX_ENUMERATED_MODELS: usize = 10_000;
+
+fn parallel_optimize_with<S: PackageSource + ?Sized, T: Ord>(
+    repo: &S,
+    requirements: &RequirementSet,
+    encoder: &Encoder,
     closure: SetU32,
     eval: impl Fn(&Model) -> T,
 ) -> Res {
+    let ctx = encoder.ctx();
     let solver = Solver::new_for_logic(ctx, "QF_LIA").unwrap();
 
     let allocator = Bump::new();
 
-    let mut assert_id = 0;
-    let mut assertion_map = HashMap::new();
-    let expr_cont = |expr: Bool, sym_expr| {
-        let assert_var = Bool::new_const(ctx, assert_id);
-        solver.assert_and_track(&expr.simplify(), &assert_var);
-        assertion_map.insert(assert_var, sym_expr);
-        assert_id += 1;
+    let mut registry = AssertionRegistry::new(ctx);
+    let expr_cont = |expr: Bool, sym_expr, origin| {
+        registry.track(&solver, &expr.simplify(), (sym_expr, origin));
     };
+    // these resolutions take no options, so there are no limits to honour
     add_all_constraints(
         &allocator,
-        ctx,
+        encoder,
         repo,
         closure.iter(),
         requirements,
@@ -380,45 +1120,49 @@ fn parallel_optimize_with<T: Ord>(
 
     match solver.check() {
         SatResult::Unsat => {
-            let core_vars = solver.get_unsat_core();
-            let mut core_assertions = Vec::new();
-            for var in core_vars {
-                let assertion = assertion_map.get(&var).unwrap_or_else(|| {
-                    panic!(
-                        "Impossible: unable to find the assertion tracked by the boolean variable {var} in the assertion map"
-                    )
-                });
-                core_assertions.push(assertion);
-            }
-            let core = process_unsat_core(repo, core_assertions);
+            let core = unsat_core(repo, &solver, &[&registry]);
             Ok(ResolutionResult::UnsatWithCore { core })
         }
-        SatResult::Unknown => Err(ResolutionError::ResolutionFailure {
-            reason: solver
-                .get_reason_unknown()
-                .expect("Impossible: failed to obtain a reason"),
-        }),
+        SatResult::Unknown => Err(ResolutionError::unknown(solver.get_reason_unknown())),
         SatResult::Sat => {
+            // the best plans among the first models, enumerating all of them is infeasible
+            // for all but the smallest closures. The plans are only known to be optimal
+            // when every model was enumerated
             let mut models = Vec::new();
-            let cont = |model| models.push(model);
-
-            enumerate_models(&solver, vars.clone().into_iter(), cont);
+            let cont = |model| {
+                models.push(model);
+                ControlFlow::Continue(())
+            };
+            let complete = enumerate_models(
+                &solver,
+                vars.clone().into_iter(),
+                Some(MAX_ENUMERATED_MODELS),
+                None,
+                cont,
+            );
 
             let plans_v = iter_max_map(
                 models.into_iter(),
                 |model| eval(model),
-                |model| plan_from_model(ctx, model, closure.iter()),
+                |model| encoder.plan_from_model(&model, closure.iter()),
             );
 
             let plans = Vec1::try_from(plans_v).expect("Impossible: no plans despite satisfiable");
-            Ok(ResolutionResult::Sat { plans })
+            Ok(ResolutionResult::Sat {
+                plans,
+                objective: None,
+                optimal: complete,
+            })
         }
     }
 }
 
 #[deprecated(note = "This function does not actually parallelize and is very slow")]
-pub fn parallel_optimize_newest(repo: &Repository, requirements: &RequirementSet) -> Res {
-    let closure = find_closure(repo, requirements.into_iter());
+pub fn parallel_optimize_newest<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Res {
+    let closure = find_closure(repo, requirements.into_iter())?;
     let package_pairs = closure
         .iter()
         .map(|pid| (pid, repo.newest_ver_of_unchecked(pid)));
@@ -426,9 +1170,11 @@ pub fn parallel_optimize_newest(repo: &Repository, requirements: &RequirementSet
     let cfg = default_config();
     let ctx = Context::new(&cfg);
 
-    let distance_from_newest_expr = distance_from_newest(&ctx, package_pairs);
-    let installed_packages_expr = installed_packages(&ctx, closure.iter());
-    parallel_optimize_with(repo, requirements, &ctx, closure, |model| {
+    let encoder = Encoder::new(&ctx, Encoding::Int, repo, closure.iter());
+
+    let distance_from_newest_expr = distance_from_newest(&encoder, package_pairs);
+    let installed_packages_expr = installed_packages(&encoder, closure.iter());
+    parallel_optimize_with(repo, requirements, &encoder, closure, |model| {
         let distance_from_newest = eval_int_expr_in_model(model, &distance_from_newest_expr);
         let installed_packages = eval_int_expr_in_model(model, &installed_packages_expr);
         (distance_from_newest, installed_packages)
@@ -436,8 +1182,11 @@ pub fn parallel_optimize_newest(repo: &Repository, requirements: &RequirementSet
 }
 
 #[deprecated(note = "This function does not actually parallelize and is very slow")]
-pub fn parallel_optimize_minimal(repo: &Repository, requirements: &RequirementSet) -> Res {
-    let closure = find_closure(repo, requirements.into_iter());
+pub fn parallel_optimize_minimal<S: PackageSource + ?Sized>(
+    repo: &S,
+    requirements: &RequirementSet,
+) -> Res {
+    let closure = find_closure(repo, requirements.into_iter())?;
     let package_pairs = closure
         .iter()
         .map(|pid| (pid, repo.newest_ver_of_unchecked(pid)));
@@ -445,9 +1194,11 @@ pub fn parallel_optimize_minimal(repo: &Repository, requirements: &RequirementSe
     let cfg = default_config();
     let ctx = Context::new(&cfg);
 
-    let distance_from_newest_expr = distance_from_newest(&ctx, package_pairs);
-    let installed_packages_expr = installed_packages(&ctx, closure.iter());
-    parallel_optimize_with(repo, requirements, &ctx, closure, |model| {
+    let encoder = Encoder::new(&ctx, Encoding::Int, repo, closure.iter());
+
+    let distance_from_newest_expr = distance_from_newest(&encoder, package_pairs);
+    let installed_packages_expr = installed_packages(&encoder, closure.iter());
+    parallel_optimize_with(repo, requirements, &encoder, closure, |model| {
         let distance_from_newest = eval_int_expr_in_model(model, &distance_from_newest_expr);
         let installed_packages = eval_int_expr_in_model(model, &installed_packages_expr);
         (installed_packages, distance_from_newest)
@@ -457,11 +1208,22 @@ pub fn parallel_optimize_minimal(repo: &Repository, requirements: &RequirementSe
 #[cfg(test)]
 mod test {
     use crate::internals::{
-        solver::{optimize_minimal, optimize_newest},
-        types::{Package, PackageVer, Range, Repository, Requirement, RequirementSet},
-        utils::set_global_params,
+        generate::{synthetic, Profile},
+        metrics::{DistanceFromNewest, InstalledPackages},
+        options::{OptimizeOptions, SolveOptions},
+        solver::{
+            optimize_cheapest, optimize_minimal, optimize_newest, optimize_pareto,
+            optimize_with_options, optimize_with_stats, parallel_optimize_newest,
+            simple_solve_with_options, simple_solve_with_stats,
+        },
+        testing::repository_from_requirements,
+        types::{
+            Goal, ObjectiveValues, Package, PackageMap, PackageVer, ParetoResult, Range,
+            Repository, Requirement, RequirementSet, ResolutionError, ResolutionResult,
+        },
     };
     use crate::vec1;
+    use std::time::Duration;
 
     use super::simple_solve;
 
@@ -515,10 +1277,7 @@ mod test {
             1,
             vec1![Range::interval_unchecked(1, 1)],
         )]);
-        let repo = Repository {
-            packages: vec![p0, p1, p2],
-        };
-        set_global_params();
+        let repo = Repository::new(vec![p0, p1, p2]);
         let mut r = simple_solve(&repo, &req_set).unwrap();
         println!("{r:?}");
         r = optimize_newest(&repo, &req_set).unwrap();
@@ -526,4 +1285,272 @@ mod test {
         r = optimize_minimal(&repo, &req_set).unwrap();
         println!("{r:?}");
     }
+
+    #[test]
+    fn test_untracked_first() {
+        // package 0 requires package 1, which conflicts with package 2
+        let repo = repository_from_requirements(&[&["pkg1"], &["!pkg2"], &[""]]).unwrap();
+        let options = SolveOptions::deterministic(0).untracked_first();
+        for reqs in ["pkg0", "pkg0; pkg2", "pkg0; !pkg1"] {
+            let reqs: RequirementSet = reqs.parse().unwrap();
+            let (r, stats) = simple_solve_with_stats(&repo, &reqs, &options).unwrap();
+            println!("{r:?}");
+            let tracked = SolveOptions::deterministic(0);
+            let (tracked_r, tracked_stats) =
+                simple_solve_with_stats(&repo, &reqs, &tracked).unwrap();
+            assert_eq!(r, tracked_r);
+            // the attempt without tracking is counted as well
+            let attempts = if r.is_sat() { 1 } else { 2 };
+            assert_eq!(stats.assertions, attempts * tracked_stats.assertions);
+            // satisfiable plans are improved from the model of the untracked check
+            if r.is_sat() {
+                assert_eq!(stats.rounds, tracked_stats.rounds);
+            }
+        }
+    }
+
+    #[test]
+    fn test_deterministic_solve() {
+        let repo = Repository::new(
+            (0..10)
+                .map(|id| Package {
+                    id,
+                    versions: (0..5)
+                        .map(|_| PackageVer {
+                            requirements: RequirementSet::from_dep(Requirement::any_version(
+                                (id + 1) % 10,
+                            )),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        );
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        let options = SolveOptions::deterministic(42);
+        let r1 = simple_solve_with_options(&repo, &req_set, &options).unwrap();
+        let r2 = simple_solve_with_options(&repo, &req_set, &options).unwrap();
+        assert_eq!(r1, r2);
+        let r1 = optimize_with_options(&repo, &req_set, Goal::Minimal, &options).unwrap();
+        let r2 = optimize_with_options(&repo, &req_set, Goal::Minimal, &options).unwrap();
+        assert_eq!(r1, r2);
+    }
+
+    #[test]
+    fn test_optimize_cheapest() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..3)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let mut costs = PackageMap::new();
+        costs.insert(0, vec![5, 1, 10]);
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        let r = optimize_cheapest(&repo, &req_set, &costs).unwrap();
+        println!("{r:?}");
+        assert_eq!(
+            r,
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 2)]],
+                objective: Some(ObjectiveValues {
+                    distance_from_newest: 1,
+                    installed: 1
+                }),
+                optimal: true
+            }
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_parallel_optimize() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..3)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        // every model was enumerated, the plan is known to be optimal
+        assert_eq!(
+            parallel_optimize_newest(&repo, &req_set).unwrap(),
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 3)]],
+                objective: None,
+                optimal: true
+            }
+        );
+    }
+
+    #[test]
+    fn test_good_enough() {
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (0..3)
+                    .map(|_| PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: (0..3)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        let optimize = |optimize: OptimizeOptions| {
+            let options = SolveOptions::deterministic(0).optimize(optimize);
+            optimize_with_options(&repo, &req_set, Goal::Newest, &options).unwrap()
+        };
+
+        for threshold in [0, 2, 4] {
+            let r = optimize(OptimizeOptions::new().good_enough(vec![threshold]));
+            println!("{r:?}");
+            let ResolutionResult::Sat {
+                objective: Some(objective),
+                ..
+            } = r
+            else {
+                panic!("expected a plan with objective values")
+            };
+            assert!(objective.distance_from_newest <= threshold);
+        }
+
+        let r = optimize(OptimizeOptions::new().max_time_per_objective(Duration::from_secs(10)));
+        assert_eq!(r, optimize_newest(&repo, &req_set).unwrap());
+
+        // far too little time for every level, whatever comes back is not optimal
+        let (repo, req_set) = synthetic(&Profile::medium());
+        let options = SolveOptions::deterministic(0)
+            .optimize(OptimizeOptions::new().max_time_per_objective(Duration::from_millis(1)));
+        match optimize_with_options(&repo, &req_set, Goal::Newest, &options) {
+            Ok(ResolutionResult::Sat { optimal, .. }) => assert!(!optimal),
+            Err(ResolutionError::Unknown { .. }) => {}
+            r => panic!("expected a resolution out of time, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (0..3)
+                    .map(|_| PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        let optimize = |deadline| {
+            let options =
+                SolveOptions::deterministic(0).optimize(OptimizeOptions::new().deadline(deadline));
+            optimize_with_stats(&repo, &req_set, Goal::Newest, &options).unwrap()
+        };
+
+        let (r, stats) = optimize(Duration::from_secs(10));
+        println!("{r:?}");
+        assert_eq!(r, optimize_newest(&repo, &req_set).unwrap());
+        assert_eq!(stats.optimal, Some(true));
+
+        // out of time after the first phase, the plan found first is returned as is
+        let (r, stats) = optimize(Duration::ZERO);
+        println!("{r:?}");
+        let ResolutionResult::Sat {
+            plans,
+            objective,
+            optimal,
+        } = r
+        else {
+            panic!("expected a plan")
+        };
+        assert_eq!(objective, None);
+        assert!(!optimal);
+        assert!(plans.first().contains(&(1, 1)));
+        assert_eq!(stats.optimal, Some(false));
+
+        let conflicting = RequirementSet {
+            conflicts: vec![Requirement::any_version(1)],
+            ..req_set.clone()
+        };
+        let options =
+            SolveOptions::new().optimize(OptimizeOptions::new().deadline(Duration::from_secs(10)));
+        assert!(
+            optimize_with_options(&repo, &conflicting, Goal::Newest, &options)
+                .unwrap()
+                .is_unsat()
+        );
+    }
+
+    #[test]
+    fn test_optimize_pareto() {
+        // the newest version of package 0 requires package 1, the older one does not,
+        // so freshness and the number of installed packages are at odds
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![
+                    PackageVer {
+                        requirements: Default::default(),
+                    },
+                    PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                    },
+                ],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        let r =
+            optimize_pareto(&repo, &req_set, &[&DistanceFromNewest, &InstalledPackages]).unwrap();
+        println!("{r:?}");
+        match r {
+            ParetoResult::Frontier(points) => {
+                let mut values = points
+                    .into_iter()
+                    .map(|point| point.values)
+                    .collect::<Vec<_>>();
+                values.sort();
+                assert_eq!(values, vec![vec![0, 2], vec![1, 1]]);
+            }
+            ParetoResult::Unsat(_) => panic!("expected a pareto front"),
+        }
+    }
+
+    #[test]
+    fn test_unknown_package() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: vec![PackageVer {
+                requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+            }],
+        }]);
+        let req_set = RequirementSet::from_dep(Requirement::any_version(0));
+        let r = simple_solve_with_options(&repo, &req_set, &SolveOptions::default());
+        println!("{r:?}");
+        assert!(matches!(r, Err(ResolutionError::InvalidInput { .. })));
+        let r = optimize_newest(&repo, &req_set);
+        assert!(matches!(r, Err(ResolutionError::InvalidInput { .. })));
+    }
 }
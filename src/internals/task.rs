@@ -0,0 +1,203 @@
+// Asynchronous resolution. The Z3 work is carried out on a fixed pool of blocking
+// worker threads, one per core, so that it never stalls an executor and concurrent calls
+// queue up instead of spawning a thread each. The caller receives a future resolving to
+// the result together with a channel delivering progress reports.
+use crate::internals::{
+    solver::{optimize_with_progress, simple_solve_with_progress},
+    types::*,
+};
+
+use std::{
+    future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex, OnceLock,
+    },
+    task::{self, Poll, Waker},
+    thread,
+};
+
+struct Shared {
+    result: Option<Res>,
+    waker: Option<Waker>,
+}
+
+// A future resolving to the result of a resolution running on a worker thread
+pub struct SolveFuture {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl Future for SolveFuture {
+    type Output = Res;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self
+            .shared
+            .lock()
+            .expect("Impossible: worker thread panicked while holding the lock");
+        match shared.result.take() {
+            Some(res) => Poll::Ready(res),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+// Queue a job to the worker pool, started on first use. Jobs never panic, the workers
+// live as long as the process
+fn submit(job: Job) {
+    static QUEUE: OnceLock<Mutex<Sender<Job>>> = OnceLock::new();
+    let queue = QUEUE.get_or_init(|| {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        for _ in 0..workers {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                // the lock is released before the job runs
+                let job = receiver
+                    .lock()
+                    .expect("Impossible: worker panicked while holding the lock")
+                    .recv();
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break,
+                }
+            });
+        }
+        Mutex::new(sender)
+    });
+    queue
+        .lock()
+        .expect("Impossible: worker panicked while holding the lock")
+        .send(job)
+        .expect("Impossible: the workers stopped");
+}
+
+fn spawn_blocking(
+    job: impl FnOnce(&mut dyn FnMut(Progress)) -> Res + Send + 'static,
+) -> (SolveFuture, Receiver<Progress>) {
+    let (sender, receiver) = channel();
+    let shared = Arc::new(Mutex::new(Shared {
+        result: None,
+        waker: None,
+    }));
+    let worker_shared = shared.clone();
+
+    submit(Box::new(move || {
+        // the receiving end may have been dropped, in which case the reports are simply discarded
+        let mut report = |p: Progress| {
+            let _ = sender.send(p);
+        };
+        let res = catch_unwind(AssertUnwindSafe(|| job(&mut report))).unwrap_or_else(|e| {
+            let reason = e
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "worker thread panicked".to_string());
+            Err(BackendSnafu { message: reason }.build())
+        });
+
+        let mut shared = worker_shared
+            .lock()
+            .expect("Impossible: future panicked while holding the lock");
+        shared.result = Some(res);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake()
+        }
+    }));
+
+    (SolveFuture { shared }, receiver)
+}
+
+// Asynchronous version of `simple_solve`
+pub fn solve_async<S: PackageSource + Send + Sync + 'static>(
+    repo: Arc<S>,
+    requirements: RequirementSet,
+) -> (SolveFuture, Receiver<Progress>) {
+    spawn_blocking(move |progress| simple_solve_with_progress(&*repo, &requirements, progress))
+}
+
+// Asynchronous version of `optimize_newest` and `optimize_minimal`
+pub fn optimize_async<S: PackageSource + Send + Sync + 'static>(
+    repo: Arc<S>,
+    requirements: RequirementSet,
+    goal: Goal,
+) -> (SolveFuture, Receiver<Progress>) {
+    spawn_blocking(move |progress| optimize_with_progress(&*repo, &requirements, goal, progress))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::Future,
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    use crate::internals::types::{
+        Goal, Package, PackageVer, Progress, Repository, Requirement, RequirementSet,
+    };
+
+    use super::{optimize_async, solve_async};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark()
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let waker: Waker = Arc::new(ThreadWaker(thread::current())).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(fut);
+        loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(r) => return r,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimize_async() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: vec![
+                PackageVer {
+                    requirements: Default::default(),
+                },
+                PackageVer {
+                    requirements: Default::default(),
+                },
+            ],
+        }]);
+        let repo = Arc::new(repo);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let (fut, progress) = optimize_async(repo.clone(), reqs.clone(), Goal::Newest);
+        let r = block_on(fut).unwrap();
+        assert!(r.is_sat());
+        let reports = progress.into_iter().collect::<Vec<_>>();
+        assert_eq!(reports[0], Progress::ClosureComputed { packages: 1 });
+
+        // more calls than workers queue up
+        let calls = (0..64)
+            .map(|_| solve_async(repo.clone(), reqs.clone()))
+            .collect::<Vec<_>>();
+        for (fut, progress) in calls {
+            assert!(block_on(fut).unwrap().is_sat());
+            let reports = progress.into_iter().collect::<Vec<_>>();
+            assert!(reports.contains(&Progress::BoundTightened { rounds: 1 }));
+        }
+    }
+}
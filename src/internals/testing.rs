@@ -0,0 +1,543 @@
+// Reproducible resolution problems for bug reports and regression tests. A test case is a
+// repository, toplevel requirements, the kind of resolution and its expected outcome,
+// stored as text:
+//
+//   goal newest                 solve, newest or minimal
+//   package 0                   the following versions are those of package 0
+//   version                     the following requirements are those of the next version
+//   dep 1 2,4-5,*               a dependency on the points, intervals or all versions
+//   conflict 1 2                a conflict, in the same format
+//   formula pkg1 ∨ ¬pkg2        a formula, in the syntax of `ReqExpr`
+//   mask 1 3                    masked versions of a package
+//   require                     the following requirements are toplevel ones
+//   expect plan 0=1 1=2         a sat result with exactly this plan, or `expect sat`
+//                               and `expect unsat`
+//
+// Empty lines and lines starting with `#` are ignored. Failing cases are reduced with
+// delta debugging, first removing packages, then requirements
+use std::{
+    cmp::{max, min},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use snafu::ResultExt;
+
+use crate::internals::{
+    options::SolveOptions,
+    solver::{optimize_with_options, simple_solve},
+    types::*,
+    utils::{format_ranges, parse_requirement},
+};
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Expected {
+    Sat,
+    Unsat,
+    // the installed versions of the optimal plan, sorted by package
+    Plan(Plan),
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct TestCase {
+    pub repository: Repository,
+    pub requirements: RequirementSet,
+    // None for a plain satisfiability check
+    pub goal: Option<Goal>,
+    pub expected: Expected,
+}
+
+// A requirement of a version, None for toplevel ones, whether it is a conflict and its
+// index
+type RequirementLocation = (Option<(PackageId, usize)>, bool, usize);
+
+fn installed(plan: &Plan) -> Plan {
+    let mut plan = plan.installed();
+    plan.sort_unstable();
+    plan
+}
+
+fn write_requirements(text: &mut String, reqs: &RequirementSet) {
+    let requirements = reqs
+        .dependencies
+        .iter()
+        .map(|req| ("dep", req))
+        .chain(reqs.conflicts.iter().map(|req| ("conflict", req)));
+    for (kind, req) in requirements {
+        text.push_str(&format!(
+            "{kind} {} {}\n",
+            req.package,
+            format_ranges(&req.versions)
+        ));
+    }
+    if let Some(formula) = &reqs.formula {
+        text.push_str(&format!("formula {formula}\n"));
+    }
+}
+
+impl TestCase {
+    // Resolve a problem and record the result as the expected one
+    pub fn record(
+        repository: Repository,
+        requirements: RequirementSet,
+        goal: Option<Goal>,
+    ) -> Result<Self, ResolutionError> {
+        let mut case = Self {
+            repository,
+            requirements,
+            goal,
+            expected: Expected::Sat,
+        };
+        case.expected = match (goal, case.replay()?) {
+            (Some(_), ResolutionResult::Sat { plans, .. }) => {
+                Expected::Plan(installed(&plans.as_vec()[0]))
+            }
+            (None, ResolutionResult::Sat { .. }) => Expected::Sat,
+            _ => Expected::Unsat,
+        };
+        Ok(case)
+    }
+
+    pub fn replay(&self) -> Result<ResolutionResult, ResolutionError> {
+        match self.goal {
+            None => simple_solve(&self.repository, &self.requirements),
+            Some(goal) => optimize_with_options(
+                &self.repository,
+                &self.requirements,
+                goal,
+                &SolveOptions::default(),
+            ),
+        }
+    }
+
+    pub fn matches(&self, result: &ResolutionResult) -> bool {
+        match (&self.expected, result) {
+            (Expected::Sat, ResolutionResult::Sat { .. }) => true,
+            (Expected::Unsat, result) => result.is_unsat(),
+            (Expected::Plan(plan), ResolutionResult::Sat { plans, .. }) => plans
+                .as_vec()
+                .iter()
+                .any(|candidate| installed(candidate) == installed(plan)),
+            _ => false,
+        }
+    }
+
+    // Replay the case, returning whether the result is the expected one
+    pub fn check(&self) -> Result<bool, ResolutionError> {
+        Ok(self.matches(&self.replay()?))
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let goal = match self.goal {
+            None => "solve",
+            Some(Goal::Newest) => "newest",
+            Some(Goal::Minimal) => "minimal",
+            Some(Goal::Preferred) => "preferred",
+        };
+        text.push_str(&format!("goal {goal}\n"));
+        for package in self.repository.packages() {
+            text.push_str(&format!("package {}\n", package.id));
+            for ver in &package.versions {
+                text.push_str("version\n");
+                write_requirements(&mut text, &ver.requirements);
+            }
+        }
+        for mask in self.repository.masks() {
+            text.push_str(&format!(
+                "mask {} {}\n",
+                mask.package,
+                format_ranges(&mask.versions)
+            ));
+        }
+        text.push_str("require\n");
+        write_requirements(&mut text, &self.requirements);
+        match &self.expected {
+            Expected::Sat => text.push_str("expect sat\n"),
+            Expected::Unsat => text.push_str("expect unsat\n"),
+            Expected::Plan(plan) => {
+                text.push_str("expect plan");
+                for (pid, v) in plan {
+                    text.push_str(&format!(" {pid}={v}"));
+                }
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ResolutionError> {
+        let mut packages = Vec::<Package>::new();
+        let mut masks = Vec::new();
+        let mut requirements = RequirementSet::default();
+        let mut goal = None;
+        let mut expected = None;
+        // whether requirements are toplevel ones, as opposed to those of the last version
+        let mut toplevel = false;
+        for (number, line) in (1..).zip(text.lines()) {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| {
+                InvalidInputSnafu {
+                    message: format!("line {number}: {message}"),
+                }
+                .build()
+            };
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words[..] {
+                ["goal", mode] => {
+                    goal = match mode {
+                        "solve" => None,
+                        "newest" => Some(Goal::Newest),
+                        "minimal" => Some(Goal::Minimal),
+                        "preferred" => Some(Goal::Preferred),
+                        _ => return Err(invalid("unknown goal")),
+                    }
+                }
+                ["package", id] => {
+                    let id = id
+                        .parse::<PackageId>()
+                        .map_err(|_| invalid("malformed package id"))?;
+                    if id as usize != packages.len() {
+                        return Err(invalid("packages are not numbered consecutively"));
+                    }
+                    packages.push(Package {
+                        id,
+                        versions: Vec::new(),
+                    });
+                    toplevel = false;
+                }
+                ["version"] => {
+                    let package = packages
+                        .last_mut()
+                        .filter(|_| !toplevel)
+                        .ok_or_else(|| invalid("version outside of a package"))?;
+                    package.versions.push(PackageVer {
+                        requirements: RequirementSet::default(),
+                    });
+                }
+                ["mask", pid, ranges] => {
+                    masks.push(
+                        parse_requirement(pid, ranges).ok_or_else(|| invalid("malformed mask"))?,
+                    );
+                }
+                [kind @ ("dep" | "conflict"), pid, ranges] => {
+                    let req = parse_requirement(pid, ranges)
+                        .ok_or_else(|| invalid("malformed requirement"))?;
+                    let reqs = if toplevel {
+                        &mut requirements
+                    } else {
+                        &mut packages
+                            .last_mut()
+                            .and_then(|package| package.versions.last_mut())
+                            .ok_or_else(|| invalid("requirement outside of a version"))?
+                            .requirements
+                    };
+                    if kind == "dep" {
+                        reqs.add_dep(req);
+                    } else {
+                        reqs.add_antidep(req);
+                    }
+                }
+                ["formula", ..] => {
+                    let formula = line["formula".len()..]
+                        .parse::<ReqExpr>()
+                        .map_err(|e| invalid(&e.to_string()))?;
+                    let reqs = if toplevel {
+                        &mut requirements
+                    } else {
+                        &mut packages
+                            .last_mut()
+                            .and_then(|package| package.versions.last_mut())
+                            .ok_or_else(|| invalid("formula outside of a version"))?
+                            .requirements
+                    };
+                    reqs.add_formula(formula);
+                }
+                ["require"] => toplevel = true,
+                ["expect", "sat"] => expected = Some(Expected::Sat),
+                ["expect", "unsat"] => expected = Some(Expected::Unsat),
+                ["expect", "plan", ..] => {
+                    let plan = words[2..]
+                        .iter()
+                        .map(|word| {
+                            let (pid, v) = word.split_once('=')?;
+                            Some((pid.parse().ok()?, v.parse().ok()?))
+                        })
+                        .collect::<Option<Plan>>()
+                        .ok_or_else(|| invalid("malformed plan"))?;
+                    expected = Some(Expected::Plan(installed(&plan)));
+                }
+                _ => return Err(invalid("unknown directive")),
+            }
+        }
+
+        let mut repository = Repository::new(packages);
+        for mask in masks {
+            for range in mask.versions.into_vec() {
+                repository.mask_range(mask.package, range);
+            }
+        }
+        let expected = expected.ok_or_else(|| {
+            InvalidInputSnafu {
+                message: "missing expected result",
+            }
+            .build()
+        })?;
+        Ok(Self {
+            repository,
+            requirements,
+            goal,
+            expected,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ResolutionError> {
+        fs::write(path, self.to_text()).context(IoSnafu)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ResolutionError> {
+        Self::parse(&fs::read_to_string(path).context(IoSnafu)?)
+    }
+
+    // Reduce a case while `failing` holds, e.g. while the result differs from the
+    // expected one. The expected result is carried over, with the packages of an
+    // expected plan renumbered along with the repository
+    pub fn minimize(&self, mut failing: impl FnMut(&TestCase) -> bool) -> TestCase {
+        let pids = (0..self.repository.packages().len() as PackageId).collect::<Vec<_>>();
+        let pids = ddmin(pids, |kept| failing(&self.keep_packages(kept)));
+        let case = self.keep_packages(&pids);
+        let locations = ddmin(case.requirement_locations(), |kept| {
+            failing(&case.keep_requirements(kept))
+        });
+        case.keep_requirements(&locations)
+    }
+
+    // The case restricted to some of its packages, renumbered in order. Requirements on
+    // the other packages are dropped
+    fn keep_packages(&self, kept: &[PackageId]) -> Self {
+        let renumber = |pid: PackageId| {
+            kept.iter()
+                .position(|&p| p == pid)
+                .map(|id| id as PackageId)
+        };
+        let filter = |reqs: &RequirementSet| {
+            let filter = |reqs: &Vec<Requirement>| {
+                reqs.iter()
+                    .filter_map(|req| {
+                        Some(Requirement::new(
+                            renumber(req.package)?,
+                            req.versions.clone(),
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            };
+            // a formula is kept only if all of its packages are
+            let formula = reqs.formula.as_ref().and_then(|formula| {
+                formula.map_requirements(&mut |req| {
+                    Some(Requirement::new(
+                        renumber(req.package)?,
+                        req.versions.clone(),
+                    ))
+                })
+            });
+            RequirementSet {
+                dependencies: filter(&reqs.dependencies),
+                conflicts: filter(&reqs.conflicts),
+                formula,
+            }
+        };
+        let packages = (0..)
+            .zip(kept)
+            .map(|(id, &pid)| Package {
+                id,
+                versions: self.repository.packages()[pid as usize]
+                    .versions
+                    .iter()
+                    .map(|ver| PackageVer {
+                        requirements: filter(&ver.requirements),
+                    })
+                    .collect(),
+            })
+            .collect();
+        let mut repository = Repository::new(packages);
+        for mask in self.repository.masks() {
+            if let Some(id) = renumber(mask.package) {
+                for range in mask.versions.as_vec() {
+                    repository.mask_range(id, range.clone());
+                }
+            }
+        }
+        let expected = match &self.expected {
+            Expected::Plan(plan) => Expected::Plan(
+                plan.iter()
+                    .filter_map(|&(pid, v)| Some((renumber(pid)?, v)))
+                    .collect(),
+            ),
+            expected => expected.clone(),
+        };
+        Self {
+            repository,
+            requirements: filter(&self.requirements),
+            goal: self.goal,
+            expected,
+        }
+    }
+
+    fn keep_requirements(&self, kept: &[RequirementLocation]) -> Self {
+        let filter = |version: Option<(PackageId, usize)>, reqs: &RequirementSet| {
+            let filter = |conflict: bool, reqs: &Vec<Requirement>| {
+                (0..)
+                    .zip(reqs)
+                    .filter(|&(i, _)| kept.contains(&(version, conflict, i)))
+                    .map(|(_, req)| req.clone())
+                    .collect::<Vec<_>>()
+            };
+            RequirementSet {
+                dependencies: filter(false, &reqs.dependencies),
+                conflicts: filter(true, &reqs.conflicts),
+                formula: reqs.formula.clone(),
+            }
+        };
+        let mut case = self.clone();
+        case.requirements = filter(None, &self.requirements);
+        for package in case.repository.packages_mut() {
+            for (v, ver) in package.versions.iter_mut().enumerate() {
+                ver.requirements = filter(Some((package.id, v)), &ver.requirements);
+            }
+        }
+        case
+    }
+
+    fn requirement_locations(&self) -> Vec<RequirementLocation> {
+        let locations = |version, reqs: &RequirementSet| {
+            let deps = (0..reqs.dependencies.len()).map(move |i| (version, false, i));
+            let conflicts = (0..reqs.conflicts.len()).map(move |i| (version, true, i));
+            deps.chain(conflicts).collect::<Vec<_>>()
+        };
+        let mut all = locations(None, &self.requirements);
+        for package in self.repository.packages() {
+            for (v, ver) in package.versions.iter().enumerate() {
+                all.extend(locations(Some((package.id, v)), &ver.requirements));
+            }
+        }
+        all
+    }
+}
+
+// Delta debugging: remove ever smaller chunks of the items as long as the test keeps
+// failing without them
+fn ddmin<T: Clone>(mut items: Vec<T>, mut failing: impl FnMut(&[T]) -> bool) -> Vec<T> {
+    let mut chunks = 2;
+    while items.len() >= 2 {
+        let size = items.len().div_ceil(chunks);
+        let reduced = (0..items.len()).step_by(size).find_map(|start| {
+            let mut complement = items[..start].to_vec();
+            complement.extend_from_slice(&items[min(start + size, items.len())..]);
+            failing(&complement).then_some(complement)
+        });
+        match reduced {
+            Some(complement) => {
+                items = complement;
+                chunks = max(chunks - 1, 2);
+            }
+            None if chunks >= items.len() => break,
+            None => chunks = min(chunks * 2, items.len()),
+        }
+    }
+    if items.len() == 1 && failing(&[]) {
+        items.clear();
+    }
+    items
+}
+
+// A repository from the requirements of the versions of each package, in the syntax of
+// `RequirementSet`, the package ids being the positions of the packages
+pub fn repository_from_requirements(versions: &[&[&str]]) -> Result<Repository, ResolutionError> {
+    let packages = (0..)
+        .zip(versions)
+        .map(|(id, versions)| {
+            let versions = versions
+                .iter()
+                .map(|reqs| {
+                    Ok(PackageVer {
+                        requirements: reqs.parse()?,
+                    })
+                })
+                .collect::<Result<Vec<_>, ResolutionError>>()?;
+            Ok(Package { id, versions })
+        })
+        .collect::<Result<Vec<_>, ResolutionError>>()?;
+    Ok(Repository::new(packages))
+}
+
+// The test cases stored in a directory, sorted by path
+pub fn load_corpus(dir: impl AsRef<Path>) -> Result<Vec<(PathBuf, TestCase)>, ResolutionError> {
+    let mut paths = fs::read_dir(dir)
+        .context(IoSnafu)?
+        .map(|entry| Ok(entry.context(IoSnafu)?.path()))
+        .collect::<Result<Vec<_>, ResolutionError>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort_unstable();
+    paths
+        .into_iter()
+        .map(|path| {
+            let case = TestCase::load(&path)?;
+            Ok((path, case))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{Goal, ReqExpr, Requirement, RequirementSet};
+
+    use super::{repository_from_requirements, Expected, TestCase};
+
+    #[test]
+    fn test_case_roundtrip_and_minimize() {
+        // package 0 requires packages 1 and 2, package 1 conflicts with package 3
+        let mut repo =
+            repository_from_requirements(&[&["pkg1; pkg2"], &["!pkg3"], &["", ""], &[""]]).unwrap();
+        repo.mask_version(2, 2);
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(0),
+            Requirement::any_version(2),
+        ]);
+        let case = TestCase::record(repo, reqs, Some(Goal::Newest)).unwrap();
+        assert_eq!(case.expected, Expected::Plan(vec![(0, 1), (1, 1), (2, 1)]));
+        let text = case.to_text();
+        println!("{text}");
+        assert_eq!(TestCase::parse(&text).unwrap(), case);
+        assert!(case.check().unwrap());
+        // formulas are kept too
+        let mut with_formula = case.clone();
+        with_formula.requirements.add_formula(ReqExpr::or([
+            ReqExpr::not(ReqExpr::req(Requirement::any_version(3))),
+            ReqExpr::req(Requirement::any_version(2)),
+        ]));
+        let text = with_formula.to_text();
+        assert!(text.contains("formula ¬pkg3 ∨ pkg2"));
+        assert_eq!(TestCase::parse(&text).unwrap(), with_formula);
+        assert!(TestCase::parse("goal newest\nversion\nexpect sat\n").is_err());
+
+        // pretend requiring package 3 as well should have been possible
+        let mut failing = case.clone();
+        failing.requirements.add_dep(Requirement::any_version(3));
+        failing.expected = Expected::Sat;
+        assert!(!failing.check().unwrap());
+        let minimal = failing.minimize(|case| !case.check().unwrap());
+        println!("{}", minimal.to_text());
+        // packages 0, 1 and 3 and every requirement between them are needed
+        assert_eq!(minimal.repository.packages().len(), 3);
+        assert_eq!(minimal.requirements.dependencies.len(), 2);
+        assert_eq!(
+            minimal.repository.packages()[1].versions[0]
+                .requirements
+                .conflicts,
+            vec![Requirement::any_version(2)]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+// Tracking of the assertions that unsat cores are made of. The assertion with id `i` of a
+// registry is tracked by the boolean variable named `{prefix}{i}`, ids being allocated in
+// sequence, so that the variables of a core map back to their assertions by their name
+// rather than through a map hashing z3 terms. A registry lives as long as its solver:
+// resolutions in scopes of the solver truncate the assertions of a popped scope, and
+// registries with distinct prefixes can track assertions of the same solver, e.g. those
+// kept across resolutions and the toplevel ones of a single resolution
+use z3::{
+    ast::{Ast, Bool},
+    Context, Solver,
+};
+
+const DEFAULT_PREFIX: &str = "track";
+
+pub struct AssertionRegistry<'c, T> {
+    ctx: &'c Context,
+    prefix: String,
+    // indexed by id
+    assertions: Vec<T>,
+}
+
+impl<'c, T> AssertionRegistry<'c, T> {
+    pub fn new(ctx: &'c Context) -> Self {
+        Self::with_prefix(ctx, DEFAULT_PREFIX)
+    }
+
+    // Registries sharing a solver must have prefixes that are not prefixes of each other
+    pub fn with_prefix(ctx: &'c Context, prefix: &str) -> Self {
+        Self {
+            ctx,
+            prefix: prefix.to_string(),
+            assertions: Vec::new(),
+        }
+    }
+
+    // Assert an expression tracked under the next id, which is returned
+    pub fn track(&mut self, solver: &Solver<'c>, expr: &Bool<'c>, assertion: T) -> usize {
+        let id = self.assertions.len();
+        solver.assert_and_track(expr, &self.var(id));
+        self.assertions.push(assertion);
+        id
+    }
+
+    // Assert an expression that only holds under the assumption of the variable of the
+    // next id, which is returned, so that it can be disabled by leaving the variable out
+    // of the assumptions
+    pub fn guard(&mut self, solver: &Solver<'c>, expr: &Bool<'c>, assertion: T) -> usize {
+        let id = self.assertions.len();
+        solver.assert(&self.var(id).implies(expr));
+        self.assertions.push(assertion);
+        id
+    }
+
+    // The variable tracking an id
+    pub fn var(&self, id: usize) -> Bool<'c> {
+        Bool::new_const(self.ctx, format!("{}{id}", self.prefix))
+    }
+
+    // The id tracked by a variable, None if the variable is not one of the registry
+    pub fn id_of(&self, var: &Bool<'c>) -> Option<usize> {
+        let name = var.decl().name();
+        let id = name.strip_prefix(&self.prefix)?.parse().ok()?;
+        (id < self.assertions.len()).then_some(id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&T> {
+        self.assertions.get(id)
+    }
+
+    // The assertion tracked by a variable, e.g. of an unsat core
+    pub fn lookup(&self, var: &Bool<'c>) -> Option<&T> {
+        self.get(self.id_of(var)?)
+    }
+
+    // The assertions in the order of their ids
+    pub fn assertions(&self) -> &[T] {
+        &self.assertions
+    }
+
+    pub fn len(&self) -> usize {
+        self.assertions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assertions.is_empty()
+    }
+
+    // Forget the assertions from id `len` on, e.g. those of a popped scope, their ids are
+    // allocated again
+    pub fn truncate(&mut self, len: usize) {
+        self.assertions.truncate(len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use z3::{ast::Bool, Context, SatResult, Solver};
+
+    use crate::internals::utils::default_config;
+
+    use super::AssertionRegistry;
+
+    #[test]
+    fn test_assertion_registry() {
+        let ctx = Context::new(&default_config());
+        let solver = Solver::new(&ctx);
+        let x = Bool::new_const(&ctx, "x");
+        let mut registry = AssertionRegistry::new(&ctx);
+        let mut toplevel = AssertionRegistry::with_prefix(&ctx, "toplevel");
+        registry.track(&solver, &x, "x");
+        solver.push();
+        assert_eq!(toplevel.track(&solver, &x.not(), "not x"), 0);
+        assert_eq!(solver.check(), SatResult::Unsat);
+        let mut core = solver
+            .get_unsat_core()
+            .iter()
+            .map(|var| registry.lookup(var).or_else(|| toplevel.lookup(var)))
+            .collect::<Vec<_>>();
+        core.sort_unstable();
+        println!("{core:?}");
+        assert_eq!(core, vec![Some(&"not x"), Some(&"x")]);
+        solver.pop(1);
+        toplevel.truncate(0);
+        assert_eq!(solver.check(), SatResult::Sat);
+        assert_eq!(registry.id_of(&toplevel.var(0)), None);
+
+        // guarded assertions hold under the assumption of their variable only
+        let mut guards = AssertionRegistry::with_prefix(&ctx, "guard");
+        let id = guards.guard(&solver, &x.not(), ());
+        assert_eq!(solver.check(), SatResult::Sat);
+        assert_eq!(
+            solver.check_assumptions(&[guards.var(id)]),
+            SatResult::Unsat
+        );
+        assert_eq!(guards.len(), 1);
+    }
+}
@@ -1,21 +1,55 @@
 #[cfg(feature = "arbitrary")]
 pub(crate) mod arbitrary;
+pub(crate) mod derived;
+pub(crate) mod describe;
 pub(crate) mod expr;
 pub(crate) mod extended;
+pub(crate) mod fingerprint;
+pub(crate) mod formula;
+#[cfg(feature = "serde")]
+pub(crate) mod json;
+pub(crate) mod maps;
+pub(crate) mod named;
+pub(crate) mod plan;
+pub(crate) mod range_set;
+pub(crate) mod reverse;
+pub(crate) mod syntax;
 pub(crate) mod vec1;
 
-use intmap::IntMap;
+use bumpalo::Bump;
 use itertools::Itertools;
 use pretty::{DocAllocator, DocBuilder, Pretty};
-use rkyv::{Archive, Deserialize, Serialize};
-use std::{cmp::Ordering, fmt::Display, iter::Chain, slice, vec};
+use rkyv::{
+    de::deserializers::SharedDeserializeMap, with::Skip, AlignedVec, Archive, Deserialize,
+    Serialize,
+};
+use snafu::{Backtrace, Snafu};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    fmt::Display,
+    iter::Chain,
+    slice,
+    sync::{Arc, OnceLock},
+    time::Duration,
+    vec,
+};
 use termcolor::ColorSpec;
 
-use crate::internals::utils::{blue_text, green_text, red_text};
+use crate::internals::{
+    constraints::AsConstraints,
+    types::derived::Derived,
+    utils::{blue_text, green_text, red_text},
+};
 
 #[cfg(feature = "arbitrary")]
 pub use arbitrary::*;
 pub use expr::*;
+pub use formula::ReqExpr;
+pub use maps::{PackageMap, VersionMap};
+pub use plan::{PlanEntry, PlanExt, TypedPlan};
+pub use range_set::*;
+pub use reverse::{ReverseDep, ReverseIndex};
 pub use vec1::*;
 
 // We use (initial segments of) positive integers to represent versions since the
@@ -34,7 +68,8 @@ pub type Index = u32;
 pub type Plan = Vec<(PackageId, Version)>;
 
 // Version range
-#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub enum Range {
     Interval { lower: Version, upper: Version },
     Point(Version),
@@ -83,7 +118,8 @@ impl Range {
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Requirement {
     pub package: PackageId,
     pub versions: Vec1<Range>,
@@ -158,12 +194,29 @@ impl Requirement {
             versions: vec1![r],
         })
     }
+
+    // The requirement with its ranges clamped to [1, newest] and merged. Requirements
+    // matching no version and requirements on unknown packages are left as they are
+    pub fn normalized<S: PackageSource + ?Sized>(&self, repo: &S) -> Requirement {
+        let Some(newest) = repo.newest_ver_of(self.package) else {
+            return self.clone();
+        };
+        let set = RangeSet::clamped(&self.versions, newest);
+        if set.is_empty() {
+            self.clone()
+        } else {
+            to_requirement(self.package, newest, set)
+        }
+    }
 }
 
-#[derive(Eq, PartialEq, Debug, Default, Clone, Archive, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Default, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct RequirementSet {
     pub dependencies: Vec<Requirement>,
     pub conflicts: Vec<Requirement>,
+    // has to hold on top of the dependencies and conflicts
+    pub formula: Option<ReqExpr>,
 }
 
 impl<'a, D> Pretty<'a, D, ColorSpec> for RequirementSet
@@ -179,26 +232,53 @@ where
                     .into_iter()
                     .map(|req| RequirementPretty { req, invert: true }),
                 allocator.hardline(),
-            ))
+            )
+            + match self.formula {
+                Some(formula) => allocator.hardline() + allocator.text(formula.to_string()),
+                None => allocator.nil(),
+            })
         .align()
     }
 }
 
+// The dependencies, then the conflicts, then the requirements of the formula. Since sets
+// have formulas this is every requirement the set mentions, whatever its polarity: a
+// requirement of the formula may well be negated, so code that treats the items as
+// dependencies has to look at `dependencies` instead
 impl IntoIterator for RequirementSet {
     type Item = Requirement;
-    type IntoIter = Chain<vec::IntoIter<Self::Item>, vec::IntoIter<Self::Item>>;
+    type IntoIter = Chain<
+        Chain<vec::IntoIter<Self::Item>, vec::IntoIter<Self::Item>>,
+        vec::IntoIter<Self::Item>,
+    >;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.dependencies.into_iter().chain(self.conflicts)
+        let formula = self
+            .formula
+            .map_or_else(Vec::new, ReqExpr::into_requirements);
+        self.dependencies
+            .into_iter()
+            .chain(self.conflicts)
+            .chain(formula)
     }
 }
 
 impl<'a> IntoIterator for &'a RequirementSet {
     type Item = &'a Requirement;
-    type IntoIter = Chain<slice::Iter<'a, Requirement>, slice::Iter<'a, Requirement>>;
+    type IntoIter = Chain<
+        Chain<slice::Iter<'a, Requirement>, slice::Iter<'a, Requirement>>,
+        vec::IntoIter<&'a Requirement>,
+    >;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.dependencies.iter().chain(&self.conflicts)
+        let formula = self
+            .formula
+            .as_ref()
+            .map_or_else(Vec::new, ReqExpr::requirements);
+        self.dependencies
+            .iter()
+            .chain(&self.conflicts)
+            .chain(formula)
     }
 }
 
@@ -207,6 +287,7 @@ impl RequirementSet {
         Self {
             dependencies: vec![dep],
             conflicts: Vec::new(),
+            formula: None,
         }
     }
 
@@ -214,6 +295,7 @@ impl RequirementSet {
         Self {
             dependencies: deps,
             conflicts: Vec::new(),
+            formula: None,
         }
     }
 
@@ -221,6 +303,7 @@ impl RequirementSet {
         Self {
             dependencies: Vec::new(),
             conflicts: vec![antidep],
+            formula: None,
         }
     }
 
@@ -228,6 +311,7 @@ impl RequirementSet {
         Self {
             dependencies: Vec::new(),
             conflicts: antideps,
+            formula: None,
         }
     }
 
@@ -246,10 +330,159 @@ impl RequirementSet {
     pub fn add_antideps(&mut self, mut antideps: Vec<Requirement>) {
         self.conflicts.append(&mut antideps);
     }
+
+    pub fn from_formula(formula: ReqExpr) -> Self {
+        Self {
+            dependencies: Vec::new(),
+            conflicts: Vec::new(),
+            formula: Some(formula),
+        }
+    }
+
+    // Conjoined with the formula already in the set, if any
+    pub fn add_formula(&mut self, formula: ReqExpr) {
+        self.formula = Some(match self.formula.take() {
+            Some(existing) => ReqExpr::and([existing, formula]),
+            None => formula,
+        });
+    }
+
+    // A direct contradiction among the requirements on a single package, i.e. the
+    // dependencies on it have no version in common or every such version is excluded by
+    // the conflicts. This is cheap and catches obvious mistakes without invoking z3
+    pub fn contradiction(&self) -> Option<ConstraintSet> {
+        let offset = self.dependencies.len();
+        for package in self.dependencies.iter().map(|req| req.package).unique() {
+            let deps = self
+                .dependencies
+                .iter()
+                .enumerate()
+                .filter(|(_, req)| req.package == package)
+                .collect_vec();
+            let allowed = deps
+                .iter()
+                .map(|(_, req)| RangeSet::from(&req.versions))
+                .reduce(|a, b| a.intersection(&b))
+                .expect("Impossible: there is a dependency on every package iterated over");
+            let antideps = if allowed.is_empty() {
+                Vec::new()
+            } else {
+                self.conflicts
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, req)| req.package == package)
+                    .collect_vec()
+            };
+            let excluded = antideps.iter().fold(RangeSet::empty(), |set, (_, req)| {
+                set.union(&RangeSet::from(&req.versions))
+            });
+            if allowed.is_subset(&excluded) {
+                let toplevel_indices = deps
+                    .iter()
+                    .map(|(i, _)| *i)
+                    .chain(antideps.iter().map(|(i, _)| offset + i))
+                    .collect_vec();
+                let toplevel_packages = vec![vec![package]; toplevel_indices.len()];
+                return Some(ConstraintSet {
+                    package_reqs: PackageMap::new(),
+                    toplevel_reqs: RequirementSet {
+                        dependencies: deps.into_iter().map(|(_, req)| req.clone()).collect(),
+                        conflicts: antideps.into_iter().map(|(_, req)| req.clone()).collect(),
+                        formula: None,
+                    },
+                    toplevel_indices,
+                    toplevel_packages,
+                    restrictions: Vec::new(),
+                });
+            }
+        }
+        None
+    }
+
+    // The requirements normalized one at a time, see `Requirement::normalized`. Unlike
+    // `normalize`, every requirement keeps its position, which unsat cores refer to
+    pub fn normalized_each<S: PackageSource + ?Sized>(&self, repo: &S) -> RequirementSet {
+        RequirementSet {
+            dependencies: self
+                .dependencies
+                .iter()
+                .map(|r| r.normalized(repo))
+                .collect(),
+            conflicts: self.conflicts.iter().map(|r| r.normalized(repo)).collect(),
+            formula: self.formula.clone(),
+        }
+    }
+
+    // Simplify the requirements against the versions available in the repository:
+    // ranges are clamped to [1, newest] and merged, dependencies on the same package are
+    // intersected, conflicts with the same package are united and conflicts that exclude
+    // no version are dropped. Dependencies that cannot be satisfied are kept as they are
+    // and returned, requirements on unknown packages are kept as well
+    pub fn normalize<S: PackageSource + ?Sized>(&mut self, repo: &S) -> Vec<Requirement> {
+        fn group<S: PackageSource + ?Sized>(
+            repo: &S,
+            reqs: Vec<Requirement>,
+            combine: impl Fn(&RangeSet, &RangeSet) -> RangeSet,
+        ) -> (Vec<(PackageId, Version, RangeSet)>, Vec<Requirement>) {
+            let mut groups: Vec<(PackageId, Version, RangeSet)> = Vec::new();
+            let mut unknown = Vec::new();
+            for req in reqs {
+                let Some(newest) = repo.newest_ver_of(req.package) else {
+                    unknown.push(req);
+                    continue;
+                };
+                let versions = RangeSet::clamped(&req.versions, newest);
+                match groups.iter_mut().find(|(pid, _, _)| *pid == req.package) {
+                    Some((_, _, set)) => *set = combine(set, &versions),
+                    None => groups.push((req.package, newest, versions)),
+                }
+            }
+            (groups, unknown)
+        }
+
+        let original = self.dependencies.clone();
+        let (deps, mut dependencies) = group(
+            repo,
+            std::mem::take(&mut self.dependencies),
+            RangeSet::intersection,
+        );
+        let mut unsatisfiable = Vec::new();
+        for (package, newest, set) in deps {
+            if set.is_empty() {
+                unsatisfiable.extend(original.iter().filter(|r| r.package == package).cloned());
+            } else {
+                dependencies.push(to_requirement(package, newest, set));
+            }
+        }
+        dependencies.extend(unsatisfiable.iter().cloned());
+
+        let (antideps, mut conflicts) =
+            group(repo, std::mem::take(&mut self.conflicts), RangeSet::union);
+        for (package, newest, set) in antideps {
+            if !set.is_empty() {
+                conflicts.push(to_requirement(package, newest, set));
+            }
+        }
+
+        self.dependencies = dependencies;
+        self.conflicts = conflicts;
+        unsatisfiable
+    }
+}
+
+fn to_requirement(package: PackageId, newest: Version, set: RangeSet) -> Requirement {
+    let versions = if set == RangeSet::interval(1, newest) {
+        vec1![Range::All]
+    } else {
+        set.to_ranges()
+            .expect("Impossible: empty ranges are filtered out before")
+    };
+    Requirement::new(package, versions)
 }
 
 #[repr(transparent)]
-#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct PackageVer {
     pub requirements: RequirementSet,
 }
@@ -294,7 +527,8 @@ where
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Package {
     pub id: PackageId,
     pub versions: Vec<PackageVer>,
@@ -333,11 +567,63 @@ impl Package {
     pub fn newest_version(&self) -> &PackageVer {
         &self.versions[self.newest_version_number() as usize - 1]
     }
+
+    // The package with the requirements of every version normalized. `Repository` only
+    // normalizes each package once, see `Repository::normalized_package`
+    pub fn normalized<S: PackageSource + ?Sized>(&self, repo: &S) -> Package {
+        let mut package = self.clone();
+        for ver in &mut package.versions {
+            ver.requirements.normalize(repo);
+        }
+        package
+    }
 }
 
-#[derive(Eq, PartialEq, Clone, Debug, Archive, Serialize, Deserialize)]
+#[derive(Clone, Debug, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 pub struct Repository {
-    pub packages: Vec<Package>,
+    // shared with the clones of the repository until one of them is edited, see
+    // `packages_mut`
+    packages: Arc<Vec<Package>>,
+    // versions excluded from resolution, e.g. yanked ones, kept as conflicts
+    masks: Vec<Requirement>,
+    // versions of packages from the most to the least preferred, see `prefer`
+    preferences: Vec<(PackageId, Vec<Version>)>,
+    // packages that only carry requirements, see `mark_meta_package`
+    meta_packages: Vec<PackageId>,
+    // packages every plan installs, see `mark_essential`
+    essential_packages: Vec<PackageId>,
+    #[with(Skip)]
+    reverse_index: Derived<ReverseIndex>,
+    // the packages with their requirements normalized, each normalized on first use
+    #[with(Skip)]
+    normalized: Derived<Vec<OnceLock<Package>>>,
+    // also dropped by the edits of the masks, preferences, meta-packages and essential
+    // packages, see `fingerprint`
+    #[with(Skip)]
+    fingerprint: Derived<u64>,
+}
+
+// the caches are derived from the other fields, so they never make repositories differ
+impl PartialEq for Repository {
+    fn eq(&self, other: &Self) -> bool {
+        self.packages == other.packages
+            && self.masks == other.masks
+            && self.preferences == other.preferences
+            && self.meta_packages == other.meta_packages
+            && self.essential_packages == other.essential_packages
+    }
+}
+
+impl Eq for Repository {}
+
+// Repositories are shared read-only between threads, e.g. by sessions resolving against
+// one repository concurrently, nothing they hold may lose `Send` or `Sync`
+#[allow(dead_code)]
+fn assert_shareable<K: Send + Sync, V: Send + Sync, R: Send + Sync>() {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<Repository>();
+    send_sync::<extended::ERepository<K, V, R>>();
 }
 
 impl<'a, D> Pretty<'a, D, ColorSpec> for Repository
@@ -347,12 +633,159 @@ where
 {
     fn pretty(self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec> {
         allocator
-            .intersperse(self.packages, allocator.hardline())
+            .intersperse(Arc::unwrap_or_clone(self.packages), allocator.hardline())
             .align()
     }
 }
 
 impl Repository {
+    pub fn new(packages: Vec<Package>) -> Self {
+        Self {
+            packages: Arc::new(packages),
+            masks: Vec::new(),
+            preferences: Vec::new(),
+            meta_packages: Vec::new(),
+            essential_packages: Vec::new(),
+            reverse_index: Default::default(),
+            normalized: Default::default(),
+            fingerprint: Default::default(),
+        }
+    }
+
+    pub fn packages(&self) -> &[Package] {
+        &self.packages
+    }
+
+    // Whether the packages are still shared with another repository, e.g. a clone that
+    // neither of them edited since
+    pub fn shares_packages_with(&self, other: &Repository) -> bool {
+        Arc::ptr_eq(&self.packages, &other.packages)
+    }
+
+    // The packages for editing, copied first if they are shared with clones of the
+    // repository. This is the only way to edit them, so the caches derived from the
+    // packages are dropped here
+    pub fn packages_mut(&mut self) -> &mut Vec<Package> {
+        self.invalidate_caches();
+        Arc::make_mut(&mut self.packages)
+    }
+
+    // Exclude a version from resolution without renumbering the versions. Resolutions
+    // treat masked versions as conflicting with the requirements, so they show up in
+    // unsat cores as toplevel conflicts
+    pub fn mask_version(&mut self, id: PackageId, version: Version) {
+        self.fingerprint = Default::default();
+        self.masks.push(Requirement::single_version(id, version));
+    }
+
+    pub fn mask_range(&mut self, id: PackageId, range: Range) {
+        self.fingerprint = Default::default();
+        self.masks.push(Requirement::new(id, Vec1::new(range)));
+    }
+
+    // Lift every mask of a package
+    pub fn unmask(&mut self, id: PackageId) {
+        self.fingerprint = Default::default();
+        self.masks.retain(|mask| mask.package != id);
+    }
+
+    pub fn masks(&self) -> &[Requirement] {
+        &self.masks
+    }
+
+    // Rank the versions of a package differently from their numbers, e.g. to prefer LTS
+    // versions. The versions are given from the most preferred on, the versions left out
+    // come after them from the newest to the oldest. Only `DistanceFromPreferred` takes
+    // the ranking into account
+    pub fn prefer(&mut self, id: PackageId, versions: Vec<Version>) {
+        // resets the fingerprint
+        self.clear_preference(id);
+        self.preferences.push((id, versions));
+    }
+
+    pub fn clear_preference(&mut self, id: PackageId) {
+        self.fingerprint = Default::default();
+        self.preferences.retain(|(pid, _)| *pid != id);
+    }
+
+    pub fn preferences(&self) -> &[(PackageId, Vec<Version>)] {
+        &self.preferences
+    }
+
+    // Mark a package as a meta-package, e.g. a group of other packages. Meta-packages are
+    // resolved like any other package, `InstalledArtifacts` leaves them out of the count
+    pub fn mark_meta_package(&mut self, id: PackageId) {
+        if !self.meta_packages.contains(&id) {
+            self.fingerprint = Default::default();
+            self.meta_packages.push(id);
+        }
+    }
+
+    pub fn meta_packages(&self) -> &[PackageId] {
+        &self.meta_packages
+    }
+
+    // Mark a package as essential, like `Essential: yes` in apt. Every resolution installs
+    // some version of it as if it were required, and the requirement shows up in unsat cores
+    pub fn mark_essential(&mut self, id: PackageId) {
+        if !self.essential_packages.contains(&id) {
+            self.fingerprint = Default::default();
+            self.essential_packages.push(id);
+        }
+    }
+
+    // The repository as an rkyv archive, without its caches
+    pub fn to_archive(&self) -> Vec<u8> {
+        rkyv::to_bytes::<_, 4096>(self)
+            .expect("Impossible: failed to serialize into memory")
+            .into_vec()
+    }
+
+    // Read back an archive of `to_archive`. The archive is validated first, so that
+    // arbitrary bytes only fail
+    pub fn from_archive(bytes: &[u8]) -> Result<Self, ResolutionError> {
+        let invalid = |message: String| InvalidInputSnafu { message }.build();
+        // the archived repository has to be aligned, which bytes read from a file may not be
+        let mut aligned = AlignedVec::with_capacity(bytes.len());
+        aligned.extend_from_slice(bytes);
+        let archived = rkyv::check_archived_root::<Self>(&aligned)
+            .map_err(|e| invalid(format!("invalid archive: {e}")))?;
+        archived
+            .deserialize(&mut SharedDeserializeMap::new())
+            .map_err(|e| invalid(format!("invalid archive: {e}")))
+    }
+
+    pub fn is_masked(&self, id: PackageId, version: Version) -> bool {
+        self.masks
+            .iter()
+            .any(|mask| mask.package == id && RangeSet::from(&mask.versions).contains(version))
+    }
+
+    // The versions of a package within some ranges that resolutions may pick: the ranges
+    // clamped to the existing versions, without the masked ones. Empty if the package does
+    // not exist
+    pub fn satisfying(&self, id: PackageId, versions: &Vec1<Range>) -> RangeSet {
+        let Some(newest) = self.newest_ver_of(id) else {
+            return RangeSet::empty();
+        };
+        let masked = RangeSet::from_ranges(
+            self.masks
+                .iter()
+                .filter(|mask| mask.package == id)
+                .flat_map(|mask| mask.versions.iter()),
+        );
+        RangeSet::clamped(versions, newest).difference(&masked)
+    }
+
+    // The newest version of a package within some ranges, answered without invoking z3
+    pub fn max_satisfying(&self, id: PackageId, versions: &Vec1<Range>) -> Option<Version> {
+        self.satisfying(id, versions).highest()
+    }
+
+    pub fn min_satisfying(&self, id: PackageId, versions: &Vec1<Range>) -> Option<Version> {
+        self.satisfying(id, versions).lowest()
+    }
+
     pub fn get_package(&self, id: PackageId) -> Option<&Package> {
         self.packages.get(id as usize)
     }
@@ -361,6 +794,16 @@ impl Repository {
         &self.packages[id as usize]
     }
 
+    // The package with its requirements normalized, see `Package::normalized`. Each
+    // package is only normalized once, until the packages are edited
+    pub fn normalized_package(&self, id: PackageId) -> Option<&Package> {
+        let package = self.get_package(id)?;
+        let normalized = self
+            .normalized
+            .get_or_init(|| (0..self.packages.len()).map(|_| OnceLock::new()).collect());
+        Some(normalized[id as usize].get_or_init(|| package.normalized(self)))
+    }
+
     pub fn newest_ver_of(&self, id: PackageId) -> Option<Version> {
         self.get_package(id).map(|p| p.newest_version_number())
     }
@@ -370,15 +813,517 @@ impl Repository {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+// A source of package metadata. Resolution only ever queries the packages in the
+// dependency closure of the requirements, so sources backed by a disk or the network
+// can load packages on demand instead of materializing the whole repository.
+pub trait PackageSource {
+    fn fetch_package(&self, id: PackageId) -> Option<Cow<'_, Package>>;
+
+    // Like `fetch_package`, but with the reason a package could not be fetched. Sources
+    // that can fail to read a package they have, e.g. from files, report the failure here
+    // instead of an unknown package
+    fn try_fetch_package(&self, id: PackageId) -> Result<Cow<'_, Package>, ResolutionError> {
+        self.fetch_package(id)
+            .ok_or_else(|| ResolutionError::unknown_package(id))
+    }
+
+    fn newest_ver_of(&self, id: PackageId) -> Option<Version> {
+        self.fetch_package(id).map(|p| p.newest_version_number())
+    }
+
+    fn newest_ver_of_unchecked(&self, id: PackageId) -> Version {
+        self.newest_ver_of(id)
+            .unwrap_or_else(|| panic!("Illegal index: package {id} does not exist"))
+    }
+
+    // The versions of a package from the most preferred on, None if the numeric order
+    // applies
+    fn preferred_versions(&self, _id: PackageId) -> Option<Cow<'_, [Version]>> {
+        None
+    }
+
+    // Whether the package only carries requirements, without an artifact to install
+    fn is_meta_package(&self, _id: PackageId) -> bool {
+        false
+    }
+
+    // The packages every plan has to install
+    fn essential_packages(&self) -> Cow<'_, [PackageId]> {
+        Cow::Borrowed(&[])
+    }
+
+    // The masks of a package, see `Repository::mask_version`. Sources wrapping another
+    // one have to forward them, the constraints of packages block the masked versions
+    fn masks_of(&self, _id: PackageId) -> Cow<'_, [Requirement]> {
+        Cow::Borrowed(&[])
+    }
+
+    // A fingerprint of the whole source, None for sources that cannot compute one cheaply,
+    // e.g. those loading packages on demand
+    fn source_fingerprint(&self) -> Option<u64> {
+        None
+    }
+
+    // Generate the symbolic constraints of a package. Sources that cache
+    // constraints can override this to replay them instead
+    fn package_constraints<'b>(
+        &'b self,
+        b: &'b Bump,
+        id: PackageId,
+        expr_cont: &mut dyn FnMut(Expr<'b>),
+    ) {
+        let package = self
+            .fetch_package(id)
+            .unwrap_or_else(|| panic!("Illegal index: index {id} is out of bound"));
+        package
+            .normalized(self)
+            .add_symbolic_constraints(b, &mut *expr_cont);
+        mask_constraints(self, b, id, expr_cont);
+    }
+}
+
+// The constraints blocking the masked versions of a package
+pub(crate) fn mask_constraints<'b, S: PackageSource + ?Sized>(
+    source: &S,
+    b: &'b Bump,
+    id: PackageId,
+    expr_cont: &mut dyn FnMut(Expr<'b>),
+) {
+    for mask in source.masks_of(id).iter() {
+        mask.add_symbolic_constraints(b, |sym_expr| expr_cont(Expr::not(b, sym_expr)));
+    }
+}
+
+impl PackageSource for Repository {
+    fn fetch_package(&self, id: PackageId) -> Option<Cow<'_, Package>> {
+        self.get_package(id).map(Cow::Borrowed)
+    }
+
+    fn preferred_versions(&self, id: PackageId) -> Option<Cow<'_, [Version]>> {
+        self.preferences
+            .iter()
+            .find(|(pid, _)| *pid == id)
+            .map(|(_, versions)| Cow::Borrowed(&versions[..]))
+    }
+
+    fn is_meta_package(&self, id: PackageId) -> bool {
+        self.meta_packages.contains(&id)
+    }
+
+    fn essential_packages(&self) -> Cow<'_, [PackageId]> {
+        Cow::Borrowed(&self.essential_packages)
+    }
+
+    fn masks_of(&self, id: PackageId) -> Cow<'_, [Requirement]> {
+        masks_of(&self.masks, id)
+    }
+
+    fn source_fingerprint(&self) -> Option<u64> {
+        Some(self.fingerprint())
+    }
+
+    fn package_constraints<'b>(
+        &'b self,
+        b: &'b Bump,
+        id: PackageId,
+        expr_cont: &mut dyn FnMut(Expr<'b>),
+    ) {
+        self.normalized_package(id)
+            .unwrap_or_else(|| panic!("Illegal index: index {id} is out of bound"))
+            .add_symbolic_constraints(b, &mut *expr_cont);
+        mask_constraints(self, b, id, expr_cont);
+    }
+}
+
+// The masks of a package among the masks of a whole source
+pub(crate) fn masks_of(masks: &[Requirement], id: PackageId) -> Cow<'_, [Requirement]> {
+    let masks = masks.iter().filter(|mask| mask.package == id);
+    Cow::Owned(masks.cloned().collect())
+}
+
+// The subset of a package source that has been loaded into memory, usually the
+// dependency closure of some requirements
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct PartialRepository {
+    pub packages: PackageMap<Package>,
+    // the essential packages of the source the packages were loaded from
+    pub essential_packages: Vec<PackageId>,
+    // the masks of the packages loaded, see `PackageSource::masks_of`
+    pub masks: Vec<Requirement>,
+    // the preferred versions of the packages loaded, see `Repository::prefer`
+    pub preferences: Vec<(PackageId, Vec<Version>)>,
+    // the meta-packages among the packages loaded
+    pub meta_packages: Vec<PackageId>,
+}
+
+impl PackageSource for PartialRepository {
+    fn fetch_package(&self, id: PackageId) -> Option<Cow<'_, Package>> {
+        self.packages.get(id).map(Cow::Borrowed)
+    }
+
+    fn preferred_versions(&self, id: PackageId) -> Option<Cow<'_, [Version]>> {
+        self.preferences
+            .iter()
+            .find(|(pid, _)| *pid == id)
+            .map(|(_, versions)| Cow::Borrowed(&versions[..]))
+    }
+
+    fn is_meta_package(&self, id: PackageId) -> bool {
+        self.meta_packages.contains(&id)
+    }
+
+    fn essential_packages(&self) -> Cow<'_, [PackageId]> {
+        Cow::Borrowed(&self.essential_packages)
+    }
+
+    fn masks_of(&self, id: PackageId) -> Cow<'_, [Requirement]> {
+        masks_of(&self.masks, id)
+    }
+}
+
+impl PartialRepository {
+    pub fn new() -> Self {
+        Self {
+            packages: PackageMap::new(),
+            essential_packages: Vec::new(),
+            masks: Vec::new(),
+            preferences: Vec::new(),
+            meta_packages: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, package: Package) {
+        self.packages.insert(package.id, package);
+    }
+
+    // Insert a package of a source along with its masks, preferred versions and whether it
+    // is a meta-package
+    pub fn insert_from<S: PackageSource + ?Sized>(&mut self, source: &S, package: Package) {
+        let id = package.id;
+        self.masks.extend(source.masks_of(id).iter().cloned());
+        if let Some(versions) = source.preferred_versions(id) {
+            self.preferences.push((id, versions.into_owned()));
+        }
+        if source.is_meta_package(id) {
+            self.meta_packages.push(id);
+        }
+        self.insert(package);
+    }
+
+    pub fn contains(&self, id: PackageId) -> bool {
+        self.packages.contains_key(id)
+    }
+}
+
+impl Default for PartialRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
 pub enum ResolutionError {
-    ResolutionFailure { reason: String },
+    // z3 could not decide whether the requirements are satisfiable, e.g. on a timeout
+    #[snafu(display("Unable to decide the requirements: {reason}"))]
+    Unknown {
+        reason: String,
+        backtrace: Backtrace,
+    },
+    // the repository or the requirements are malformed
+    #[snafu(display("Invalid input: {message}"))]
+    InvalidInput {
+        message: String,
+        backtrace: Backtrace,
+    },
+    // the solver failed in an unexpected way
+    #[snafu(display("Solver backend error: {message}"))]
+    Backend {
+        message: String,
+        backtrace: Backtrace,
+    },
+    // the closure or the constraints outgrew the limits of the options, see `Limits`
+    #[snafu(display("Limit exceeded: {limit} reached {value}, more than {max}"))]
+    LimitExceeded {
+        limit: &'static str,
+        value: usize,
+        max: usize,
+        backtrace: Backtrace,
+    },
+    // the resolution was cancelled through its `CancelToken`
+    #[snafu(display("Resolution cancelled"))]
+    Cancelled { backtrace: Backtrace },
+    // reading or writing an external representation failed
+    #[snafu(display("I/O error: {source}"))]
+    Io {
+        source: std::io::Error,
+        backtrace: Backtrace,
+    },
+}
+
+// Backtraces are ignored, I/O errors are compared by their kind
+impl PartialEq for ResolutionError {
+    fn eq(&self, other: &Self) -> bool {
+        use ResolutionError::*;
+        match (self, other) {
+            (Unknown { reason: l, .. }, Unknown { reason: r, .. }) => l == r,
+            (InvalidInput { message: l, .. }, InvalidInput { message: r, .. })
+            | (Backend { message: l, .. }, Backend { message: r, .. }) => l == r,
+            (
+                LimitExceeded {
+                    limit: l,
+                    value: lv,
+                    max: lm,
+                    ..
+                },
+                LimitExceeded {
+                    limit: r,
+                    value: rv,
+                    max: rm,
+                    ..
+                },
+            ) => (l, lv, lm) == (r, rv, rm),
+            (Cancelled { .. }, Cancelled { .. }) => true,
+            (Io { source: l, .. }, Io { source: r, .. }) => l.kind() == r.kind(),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ResolutionError {}
+
+impl ResolutionError {
+    pub(crate) fn unknown(reason: Option<String>) -> Self {
+        UnknownSnafu {
+            reason: reason.unwrap_or_else(|| "no reason given".to_string()),
+        }
+        .build()
+    }
+
+    pub(crate) fn unknown_package(id: PackageId) -> Self {
+        InvalidInputSnafu {
+            message: format!("package {id} does not exist"),
+        }
+        .build()
+    }
+}
+
+// A requirement set whose requirements carry labels (e.g. the manifest line or command line
+// flag they come from), so that the requirements in an unsat core can be traced back
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct LabeledRequirementSet<M> {
+    requirements: RequirementSet,
+    dependency_labels: Vec<M>,
+    conflict_labels: Vec<M>,
+}
+
+impl<M> LabeledRequirementSet<M> {
+    pub fn new() -> Self {
+        Self {
+            requirements: RequirementSet::default(),
+            dependency_labels: Vec::new(),
+            conflict_labels: Vec::new(),
+        }
+    }
+
+    pub fn add_dep(&mut self, dep: Requirement, label: M) {
+        self.requirements.add_dep(dep);
+        self.dependency_labels.push(label);
+    }
+
+    pub fn add_antidep(&mut self, antidep: Requirement, label: M) {
+        self.requirements.add_antidep(antidep);
+        self.conflict_labels.push(label);
+    }
+
+    pub fn requirements(&self) -> &RequirementSet {
+        &self.requirements
+    }
+
+    // The label of a toplevel requirement by its index in an unsat core
+    pub fn label(&self, index: usize) -> Option<&M> {
+        self.dependency_labels
+            .iter()
+            .chain(&self.conflict_labels)
+            .nth(index)
+    }
+
+    // The labels of the requirements involved in an unsat core
+    pub fn core_labels(&self, core: &ConstraintSet) -> Vec<&M> {
+        core.toplevel_indices()
+            .iter()
+            .filter_map(|&index| self.label(index))
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Requirement, &M)> {
+        (&self.requirements)
+            .into_iter()
+            .zip(self.dependency_labels.iter().chain(&self.conflict_labels))
+    }
+}
+
+impl<M> Default for LabeledRequirementSet<M> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub struct ConstraintSet {
-    pub package_reqs: IntMap<IntMap<RequirementSet>>,
+    pub package_reqs: PackageMap<VersionMap<RequirementSet>>,
     pub toplevel_reqs: RequirementSet,
+    // indices of the toplevel requirements involved, dependencies are numbered first
+    // and conflicts after them
+    pub(crate) toplevel_indices: Vec<usize>,
+    // the packages each of these requirements is on, in the same order
+    pub(crate) toplevel_packages: Vec<Vec<PackageId>>,
+    // the restrictions of the solve options involved
+    pub(crate) restrictions: Vec<Restriction>,
+}
+
+// A constraint put on plans by the solve options rather than by the requirements, as it
+// shows up in unsat cores
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash)]
+pub enum Restriction {
+    // the package is not downgraded from its version in the current plan
+    NoDowngrade {
+        package: PackageId,
+        version: Version,
+    },
+    // at most this many packages are installed
+    MaxInstalled(u64),
+    // the package is outside of the allowlist
+    NotAllowed(PackageId),
+}
+
+impl Display for Restriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Restriction::NoDowngrade { package, version } => {
+                write!(
+                    f,
+                    "package {package} is not downgraded from version {version}"
+                )
+            }
+            Restriction::MaxInstalled(n) => write!(f, "at most {n} packages are installed"),
+            Restriction::NotAllowed(package) => write!(f, "package {package} is not allowed"),
+        }
+    }
+}
+
+// Slices of a core by package, for error messages going through a large core one package
+// at a time
+impl ConstraintSet {
+    // A set of constraints not traced back to any toplevel requirement
+    pub fn new(
+        package_reqs: PackageMap<VersionMap<RequirementSet>>,
+        toplevel_reqs: RequirementSet,
+    ) -> Self {
+        Self {
+            package_reqs,
+            toplevel_reqs,
+            toplevel_indices: Vec::new(),
+            toplevel_packages: Vec::new(),
+            restrictions: Vec::new(),
+        }
+    }
+
+    // The indices of the toplevel requirements involved, sorted
+    pub fn toplevel_indices(&self) -> &[usize] {
+        &self.toplevel_indices
+    }
+
+    // The restrictions of the solve options involved, e.g. a package outside of the
+    // allowlist
+    pub fn restrictions(&self) -> &[Restriction] {
+        &self.restrictions
+    }
+
+    // The packages whose versions or requirements appear in the core, sorted
+    pub fn involved_packages(&self) -> Vec<PackageId> {
+        let mut pids = (&self.toplevel_reqs)
+            .into_iter()
+            .map(|req| req.package)
+            .collect::<Vec<_>>();
+        for (pid, versions) in self.package_reqs.iter() {
+            pids.push(pid);
+            for reqs in versions.values() {
+                pids.extend(reqs.into_iter().map(|req| req.package));
+            }
+        }
+        pids.sort_unstable();
+        pids.dedup();
+        pids
+    }
+
+    // The versions of a package in the core with their requirements, sorted by version
+    pub fn versions_of(&self, pid: PackageId) -> Vec<(Version, &RequirementSet)> {
+        match self.package_reqs.get(pid) {
+            Some(versions) => versions.iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn toplevel_deps_on(&self, pid: PackageId) -> Vec<&Requirement> {
+        let deps = self.toplevel_reqs.dependencies.iter();
+        deps.filter(|req| req.package == pid).collect()
+    }
+
+    pub fn toplevel_conflicts_with(&self, pid: PackageId) -> Vec<&Requirement> {
+        let conflicts = self.toplevel_reqs.conflicts.iter();
+        conflicts.filter(|req| req.package == pid).collect()
+    }
+
+    // The part of the core about some packages: their versions and the toplevel
+    // requirements on them. The indices of the toplevel requirements left still refer to
+    // the requirements of the resolution
+    pub fn restrict_to(&self, pids: &[PackageId]) -> ConstraintSet {
+        let (toplevel_indices, toplevel_packages) = self
+            .toplevel_indices
+            .iter()
+            .zip(&self.toplevel_packages)
+            .filter(|(_, packages)| packages.iter().any(|pid| pids.contains(pid)))
+            .map(|(&index, packages)| (index, packages.clone()))
+            .unzip();
+        let package_reqs = self
+            .package_reqs
+            .iter()
+            .filter(|(pid, _)| pids.contains(pid))
+            .map(|(pid, versions)| (pid, versions.clone()))
+            .collect();
+        let on_pids = |reqs: &Vec<Requirement>| {
+            reqs.iter()
+                .filter(|req| pids.contains(&req.package))
+                .cloned()
+                .collect()
+        };
+        ConstraintSet {
+            package_reqs,
+            toplevel_reqs: RequirementSet {
+                dependencies: on_pids(&self.toplevel_reqs.dependencies),
+                conflicts: on_pids(&self.toplevel_reqs.conflicts),
+                formula: self.toplevel_reqs.formula.as_ref().and_then(|formula| {
+                    formula.map_requirements(&mut |req| {
+                        pids.contains(&req.package).then(|| req.clone())
+                    })
+                }),
+            },
+            toplevel_indices,
+            toplevel_packages,
+            restrictions: self
+                .restrictions
+                .iter()
+                .copied()
+                .filter(|restriction| match restriction {
+                    Restriction::NoDowngrade { package, .. } | Restriction::NotAllowed(package) => {
+                        pids.contains(package)
+                    }
+                    Restriction::MaxInstalled(_) => true,
+                })
+                .collect(),
+        }
+    }
 }
 
 impl<'a, D> Pretty<'a, D, ColorSpec> for ConstraintSet
@@ -389,11 +1334,8 @@ where
     fn pretty(self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec> {
         let pkg_constraint_doc = {
             let mut doc = allocator.nil();
-            let mut pkg_reqs = self.package_reqs.into_iter().collect_vec();
-            pkg_reqs.sort_by_key(|(pid, _)| *pid);
-            for (pid, reqs) in pkg_reqs {
-                let mut reqs = reqs.into_iter().collect_vec();
-                reqs.sort_by_key(|(version, _)| *version);
+            // both maps iterate in increasing order
+            for (pid, reqs) in self.package_reqs {
                 doc += allocator.text(format!("Package {pid}:"))
                     + allocator.hardline()
                     + allocator
@@ -411,20 +1353,66 @@ where
             doc = doc.align();
             doc
         };
+        let restrictions_doc = if self.restrictions.is_empty() {
+            allocator.nil()
+        } else {
+            allocator.hardline()
+                + allocator.text("Option restrictions:")
+                + allocator.hardline()
+                + allocator
+                    .intersperse(
+                        self.restrictions
+                            .iter()
+                            .map(|restriction| allocator.text(restriction.to_string())),
+                        allocator.hardline(),
+                    )
+                    .align()
+                    .indent(2)
+        };
         allocator.text("Top-level constraints:")
             + self.toplevel_reqs.pretty(allocator).indent(2)
             + allocator.hardline()
             + allocator.text("Package constraints:")
             + allocator.hardline()
             + pkg_constraint_doc.indent(2)
+            + restrictions_doc
     }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum ResolutionResult {
     Unsat,
-    UnsatWithCore { core: ConstraintSet },
-    Sat { plans: Vec1<Plan> },
+    UnsatWithCore {
+        core: ConstraintSet,
+    },
+    // the optimizers also report the objective values achieved by the plans. The plans
+    // are `optimal` only when an optimizer proved them so, plain resolutions look for
+    // locally optimal plans and optimizations may be cut short
+    Sat {
+        plans: Vec1<Plan>,
+        objective: Option<ObjectiveValues>,
+        optimal: bool,
+    },
+}
+
+// The values of the standard metrics at an optimal plan, for displaying and comparing the
+// quality of solutions
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default, Hash)]
+pub struct ObjectiveValues {
+    // the total distance of the installed versions from the newest versions
+    pub distance_from_newest: u64,
+    // the number of installed packages
+    pub installed: u64,
+}
+
+impl Display for ObjectiveValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "distance from newest {}, {} installed",
+            self.distance_from_newest, self.installed
+        )
+    }
 }
 
 impl<'a, D> Pretty<'a, D, ColorSpec> for ResolutionResult
@@ -440,10 +1428,19 @@ where
                     + allocator.hardline()
                     + core.pretty(allocator)
             }
-            Self::Sat { plans } => {
-                let mut doc = allocator
-                    .text("Satisifiable with the following (optimal) installation plan(s):")
-                    + allocator.hardline();
+            Self::Sat {
+                plans,
+                objective,
+                optimal,
+            } => {
+                let mut doc = allocator.text(if optimal {
+                    "Satisifiable with the following optimal installation plan(s):"
+                } else {
+                    "Satisifiable with the following installation plan(s):"
+                }) + allocator.hardline();
+                if let Some(objective) = objective {
+                    doc += allocator.text(format!("Objective: {objective}")) + allocator.hardline();
+                }
                 for (mut plan, index) in plans.into_iter().zip(1..) {
                     plan.sort_by_key(|(pid, _)| *pid);
                     doc += allocator.text(format!("{index}.")) + allocator.hardline();
@@ -471,15 +1468,152 @@ impl ResolutionResult {
     pub fn is_unsat(&self) -> bool {
         !self.is_sat()
     }
+
+    // The first plan, with not installed packages told apart from installed ones
+    pub fn typed_plan(&self) -> Option<TypedPlan> {
+        match self {
+            Self::Sat { plans, .. } => Some(plans.first().into()),
+            _ => None,
+        }
+    }
 }
 
 pub type Res = Result<ResolutionResult, ResolutionError>;
 
+// A point of a pareto front, with the values of the metrics at that point
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ParetoPoint {
+    pub plan: Plan,
+    pub values: Vec<i64>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum ParetoResult {
+    // the result of a plain resolution of the unsatisfiable requirements
+    Unsat(ResolutionResult),
+    Frontier(Vec1<ParetoPoint>),
+}
+
+// A single statistic reported by z3
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum StatValue {
+    UInt(u32),
+    Double(f64),
+}
+
+impl StatValue {
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Self::UInt(v) => *v as f64,
+            Self::Double(v) => *v,
+        }
+    }
+
+    // the value over two attempts, peaks are kept and counters added up
+    fn combine(self, key: &str, other: StatValue) -> StatValue {
+        match (self, other) {
+            _ if key.contains("memory") => StatValue::Double(self.as_f64().max(other.as_f64())),
+            (Self::UInt(l), Self::UInt(r)) => Self::UInt(l.saturating_add(r)),
+            (l, r) => Self::Double(l.as_f64() + r.as_f64()),
+        }
+    }
+}
+
+// Statistics collected during a resolution
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SolveStats {
+    // number of packages in the dependency closure
+    pub closure_size: usize,
+    // number of assertions generated
+    pub assertions: usize,
+    // number of times the solution space was narrowed down after finding a model
+    pub rounds: usize,
+    // wall-clock time spent in the whole resolution, including constraint generation
+    pub elapsed: Duration,
+    // whether the plan of an optimization was proven optimal, None for other resolutions
+    pub optimal: Option<bool>,
+    // raw statistics reported by z3 for the last solver used
+    pub z3: Vec<(String, StatValue)>,
+}
+
+impl SolveStats {
+    pub fn get(&self, key: &str) -> Option<StatValue> {
+        self.z3.iter().find(|(k, _)| k == key).map(|(_, v)| *v)
+    }
+
+    // fold in the statistics of another attempt at the same resolution, e.g. the retry
+    // that extracts an unsat core
+    pub fn accumulate(&mut self, other: SolveStats) {
+        self.closure_size = self.closure_size.max(other.closure_size);
+        self.assertions += other.assertions;
+        self.rounds += other.rounds;
+        self.elapsed += other.elapsed;
+        self.optimal = other.optimal.or(self.optimal);
+        for (key, value) in other.z3 {
+            match self.z3.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => *v = v.combine(&key, value),
+                None => self.z3.push((key, value)),
+            }
+        }
+    }
+
+    // the smt and sat cores report conflicts and decisions under different keys
+    fn get_any(&self, keys: &[&str]) -> Option<StatValue> {
+        keys.iter().find_map(|k| self.get(k))
+    }
+
+    pub fn conflicts(&self) -> Option<StatValue> {
+        self.get_any(&["conflicts", "sat conflicts"])
+    }
+
+    pub fn decisions(&self) -> Option<StatValue> {
+        self.get_any(&["decisions", "sat decisions"])
+    }
+
+    // memory in megabytes
+    pub fn memory(&self) -> Option<StatValue> {
+        self.get_any(&["max memory", "memory"])
+    }
+}
+
+// Progress reports emitted while a resolution is running
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum Progress {
+    // the dependency closure has been computed and contains `packages` packages
+    ClosureComputed { packages: usize },
+    // the constraints of `encoded` of the `total` packages of the closure have been
+    // generated, reported after each chunk of packages
+    PackagesEncoded { encoded: usize, total: usize },
+    // all constraints have been generated and `count` assertions were added to the solver
+    AssertionsAdded { count: usize },
+    // a better model has been found and the solution space has been narrowed down
+    // for the `rounds`-th time. Reported by the plain (locally optimal) resolution, the
+    // optimizing one leaves the search to Z3
+    BoundTightened { rounds: usize },
+}
+
+// The optimization goal of an optimizing resolution
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Goal {
+    // prefer newest versions first, then fewer installed packages
+    Newest,
+    // prefer fewer installed packages first, then newest versions
+    Minimal,
+    // like `Newest`, with the versions ranked by `Repository::prefer`
+    Preferred,
+}
+
 #[cfg(test)]
 mod test {
-    use crate::internals::types::{vec1, Requirement};
+    use crate::internals::{
+        solver::{optimize_newest, simple_solve},
+        types::{
+            vec1, ObjectiveValues, Package, PackageVer, Repository, ReqExpr, Requirement,
+            ResolutionResult,
+        },
+    };
 
-    use super::{Range, RequirementSet};
+    use super::{LabeledRequirementSet, Range, RequirementSet};
     use pretty::{Arena, Pretty};
     use termcolor::{ColorChoice, StandardStream};
 
@@ -499,4 +1633,293 @@ mod test {
         let stdout = StandardStream::stdout(ColorChoice::Auto);
         doc.render_colored(20, stdout).unwrap()
     }
+
+    #[test]
+    fn test_archive() {
+        let mut reqs = RequirementSet::from_dep(Requirement::any_version(1));
+        reqs.add_formula(ReqExpr::not(ReqExpr::req(Requirement::single_version(
+            1, 2,
+        ))));
+        let mut repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer { requirements: reqs }],
+            },
+            Package {
+                id: 1,
+                versions: (0..2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        repo.mask_version(1, 1);
+        repo.prefer(1, vec![2, 1]);
+        let bytes = repo.to_archive();
+        assert_eq!(Repository::from_archive(&bytes).unwrap(), repo);
+        // unaligned and corrupted archives
+        let unaligned = [&[0][..], &bytes].concat();
+        assert_eq!(Repository::from_archive(&unaligned[1..]).unwrap(), repo);
+        assert!(Repository::from_archive(&bytes[..bytes.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn test_core_labels() {
+        let repo = Repository::new(vec![Package {
+            id: 0,
+            versions: vec![PackageVer {
+                requirements: Default::default(),
+            }],
+        }]);
+        let mut reqs = LabeledRequirementSet::new();
+        reqs.add_dep(Requirement::any_version(0), "manifest:1");
+        reqs.add_antidep(Requirement::single_version(0, 1), "--exclude");
+        match simple_solve(&repo, reqs.requirements()).unwrap() {
+            ResolutionResult::UnsatWithCore { core } => {
+                assert_eq!(reqs.core_labels(&core), vec![&"manifest:1", &"--exclude"])
+            }
+            r => panic!("expected an unsat core, got {r:?}"),
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let repo = Repository::new(
+            (0..3)
+                .map(|id| Package {
+                    id,
+                    versions: (0..4)
+                        .map(|_| PackageVer {
+                            requirements: Default::default(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        );
+        let mut reqs = RequirementSet::from_deps(vec![
+            Requirement::new(0, vec1![Range::interval_unchecked(1, 3)]),
+            Requirement::new(0, vec1![Range::interval_unchecked(2, 10)]),
+            Requirement::new(
+                1,
+                vec1![
+                    Range::interval_unchecked(1, 2),
+                    Range::point(3),
+                    Range::point(4)
+                ],
+            ),
+            Requirement::new(2, vec1![Range::interval_unchecked(5, 6)]),
+        ]);
+        reqs.add_antideps(vec![
+            Requirement::new(0, vec1![Range::interval_unchecked(7, 9)]),
+            Requirement::new(1, vec1![Range::point(1)]),
+            Requirement::new(1, vec1![Range::point(2)]),
+        ]);
+        let unsatisfiable = reqs.normalize(&repo);
+        println!("{reqs:?}");
+        assert_eq!(
+            unsatisfiable,
+            vec![Requirement::new(2, vec1![Range::interval_unchecked(5, 6)])]
+        );
+        assert_eq!(
+            reqs.dependencies,
+            vec![
+                Requirement::new(0, vec1![Range::interval_unchecked(2, 3)]),
+                Requirement::any_version(1),
+                Requirement::new(2, vec1![Range::interval_unchecked(5, 6)]),
+            ]
+        );
+        assert_eq!(
+            reqs.conflicts,
+            vec![Requirement::new(1, vec1![Range::interval_unchecked(1, 2)])]
+        );
+
+        // requirements normalized one at a time keep their positions
+        let each = reqs.normalized_each(&repo);
+        assert_eq!(each.dependencies[1], Requirement::any_version(1));
+        assert_eq!(each.dependencies.len(), reqs.dependencies.len());
+
+        // normalized packages are cached and dropped along with the packages
+        let mut repo = repo;
+        repo.packages_mut()[0].versions[0].requirements = reqs.clone();
+        let normalized = repo.get_package(0).unwrap().normalized(&repo);
+        assert_eq!(repo.normalized_package(0), Some(&normalized));
+        repo.packages_mut()[1].versions.truncate(2);
+        assert_ne!(repo.normalized_package(0), Some(&normalized));
+    }
+
+    #[test]
+    fn test_contradiction() {
+        let repo = Repository::new(
+            (0..4)
+                .map(|id| Package {
+                    id,
+                    versions: (0..3)
+                        .map(|_| PackageVer {
+                            requirements: Default::default(),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        );
+        let mut reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(0),
+            Requirement::new(3, vec1![Range::point(2)]),
+        ]);
+        reqs.add_antideps(vec![
+            Requirement::new(0, vec1![Range::point(1)]),
+            Requirement::any_version(3),
+        ]);
+        let core = reqs.contradiction().unwrap();
+        assert_eq!(core.toplevel_indices(), vec![1, 3]);
+        assert_eq!(
+            simple_solve(&repo, &reqs).unwrap(),
+            ResolutionResult::UnsatWithCore { core }
+        );
+
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::new(1, vec1![Range::point(1)]),
+            Requirement::new(1, vec1![Range::interval_unchecked(2, 3)]),
+        ]);
+        assert_eq!(reqs.contradiction().unwrap().toplevel_indices(), vec![0, 1]);
+
+        let mut reqs = RequirementSet::from_dep(Requirement::any_version(2));
+        reqs.add_antidep(Requirement::new(2, vec1![Range::point(1)]));
+        assert_eq!(reqs.contradiction(), None);
+    }
+
+    #[test]
+    fn test_mask_version() {
+        let mut repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..3)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        repo.mask_version(0, 3);
+        assert!(repo.is_masked(0, 3) && !repo.is_masked(0, 2));
+        assert_eq!(
+            optimize_newest(&repo, &reqs).unwrap(),
+            ResolutionResult::Sat {
+                plans: vec1![vec![(0, 2)]],
+                objective: Some(ObjectiveValues {
+                    distance_from_newest: 1,
+                    installed: 1
+                }),
+                optimal: true
+            }
+        );
+
+        // the mask shows up in the core as a toplevel conflict
+        repo.mask_range(0, Range::interval_unchecked(1, 2));
+        let ResolutionResult::UnsatWithCore { core } = simple_solve(&repo, &reqs).unwrap() else {
+            panic!("expected an unsat core")
+        };
+        println!("{core:?}");
+        assert_eq!(core.toplevel_indices(), vec![0]);
+        assert_eq!(core.toplevel_reqs.conflicts.len(), 2);
+
+        repo.unmask(0);
+        assert!(simple_solve(&repo, &reqs).unwrap().is_sat());
+    }
+
+    #[test]
+    fn test_max_satisfying() {
+        let mut repo = Repository::new(vec![Package {
+            id: 0,
+            versions: (0..4)
+                .map(|_| PackageVer {
+                    requirements: Default::default(),
+                })
+                .collect(),
+        }]);
+        let ranges = vec1![Range::point(1), Range::interval_unchecked(3, 9)];
+        assert_eq!(repo.max_satisfying(0, &ranges), Some(4));
+        assert_eq!(repo.min_satisfying(0, &ranges), Some(1));
+        assert_eq!(repo.max_satisfying(0, &vec1![Range::all()]), Some(4));
+        assert_eq!(repo.max_satisfying(0, &vec1![Range::point(5)]), None);
+        assert_eq!(repo.max_satisfying(1, &ranges), None);
+
+        repo.mask_range(0, Range::interval_unchecked(4, 4));
+        repo.mask_version(0, 1);
+        assert_eq!(repo.max_satisfying(0, &ranges), Some(3));
+        assert_eq!(repo.min_satisfying(0, &ranges), Some(3));
+    }
+
+    #[test]
+    fn test_core_slicing() {
+        // package 0 requires package 1, which the toplevel requirements conflict with
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet {
+            dependencies: vec![Requirement::any_version(0)],
+            conflicts: vec![Requirement::any_version(1)],
+            formula: None,
+        };
+        let ResolutionResult::UnsatWithCore { core } = simple_solve(&repo, &reqs).unwrap() else {
+            panic!("expected an unsat core")
+        };
+        println!("{core:?}");
+        assert_eq!(core.involved_packages(), vec![0, 1]);
+        assert_eq!(core.versions_of(0).len(), 1);
+        assert!(core.versions_of(1).is_empty());
+        assert_eq!(core.toplevel_deps_on(0), vec![&Requirement::any_version(0)]);
+        assert_eq!(
+            core.toplevel_conflicts_with(1),
+            vec![&Requirement::any_version(1)]
+        );
+
+        assert_eq!(core.toplevel_indices(), &[0, 1]);
+
+        let sliced = core.restrict_to(&[1]);
+        assert!(sliced.package_reqs.is_empty());
+        assert!(sliced.toplevel_reqs.dependencies.is_empty());
+        assert_eq!(sliced.toplevel_reqs.conflicts.len(), 1);
+        assert_eq!(sliced.toplevel_indices(), &[1]);
+    }
+
+    #[test]
+    fn test_essential_packages() {
+        let mut repo = Repository::new(
+            (0..2)
+                .map(|id| Package {
+                    id,
+                    versions: vec![PackageVer {
+                        requirements: Default::default(),
+                    }],
+                })
+                .collect(),
+        );
+        repo.mark_essential(1);
+        let r = simple_solve(
+            &repo,
+            &RequirementSet::from_dep(Requirement::any_version(0)),
+        );
+        println!("{r:?}");
+        assert!(r.unwrap().typed_plan().unwrap().is_installed(1));
+
+        let reqs = RequirementSet::from_antidep(Requirement::any_version(1));
+        let ResolutionResult::UnsatWithCore { core } = simple_solve(&repo, &reqs).unwrap() else {
+            panic!("expected an unsat core")
+        };
+        println!("{core:?}");
+        assert_eq!(core.toplevel_deps_on(1), vec![&Requirement::any_version(1)]);
+        // only the user's conflict is a toplevel requirement
+        assert_eq!(core.toplevel_indices(), vec![0]);
+    }
 }
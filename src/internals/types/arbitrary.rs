@@ -10,6 +10,8 @@ use itertools::Itertools;
 use proptest::{
     collection::{btree_set, vec},
     prelude::*,
+    strategy::{NewTree, ValueTree},
+    test_runner::TestRunner,
 };
 
 impl Range {
@@ -248,6 +250,7 @@ impl RequirementSet {
                     |(dependencies, conflicts)| RequirementSet {
                         dependencies,
                         conflicts,
+                        formula: None,
                     },
                 )
             })
@@ -285,6 +288,7 @@ impl RequirementSet {
             dependency_strategies.prop_map(|dependencies| RequirementSet {
                 dependencies,
                 conflicts: vec![],
+                formula: None,
             })
         })
     }
@@ -393,6 +397,98 @@ impl Package {
     }
 }
 
+/// The shape of the dependency graph of generated repositories. Edges always point from
+/// a package to packages with larger ids, so the graphs are acyclic.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum Topology {
+    /// Every package depends on the next one.
+    Chain,
+    /// The packages are split into `layers` layers of consecutive ids, every package
+    /// depends on up to `fanout` random packages of the next layer.
+    Layered { layers: usize, fanout: usize },
+    /// Preferential attachment: every package depends on up to `edges` packages with
+    /// larger ids, chosen with probability proportional to their number of dependents
+    /// plus one, which yields a few hubs most packages depend on.
+    ScaleFree { edges: usize },
+    /// Diamonds of four packages, the top depending on the two sides and the sides on
+    /// the bottom, which depends on the top of the next diamond.
+    Diamonds,
+}
+
+impl Topology {
+    /// A strategy that generates the dependencies of `pkg_count` packages.
+    pub fn random_graph(self, pkg_count: usize) -> BoxedStrategy<Vec<Vec<PackageId>>> {
+        let n = pkg_count as PackageId;
+        match self {
+            Topology::Chain => {
+                Just((0..n).map(|pid| (pid + 1..n).take(1).collect()).collect()).boxed()
+            }
+            Topology::Diamonds => Just(
+                (0..n)
+                    .map(|pid| {
+                        let targets = match pid % 4 {
+                            0 => vec![pid + 1, pid + 2],
+                            1 | 2 => vec![pid - pid % 4 + 3],
+                            _ => vec![pid + 1],
+                        };
+                        targets.into_iter().filter(|&t| t < n).collect()
+                    })
+                    .collect(),
+            )
+            .boxed(),
+            Topology::Layered { layers, fanout } => {
+                let width = max(pkg_count.div_ceil(max(layers, 1)), 1);
+                (0..pkg_count)
+                    .map(|i| {
+                        let next = (i / width + 1) * width;
+                        if next >= pkg_count {
+                            Just(Vec::new()).boxed()
+                        } else {
+                            let last = min(next + width, pkg_count) - 1;
+                            btree_set(next..=last, 0..=min(fanout, last - next + 1))
+                                .prop_map(|set| set.into_iter().map(|t| t as PackageId).collect())
+                                .boxed()
+                        }
+                    })
+                    .collect_vec()
+                    .boxed()
+            }
+            Topology::ScaleFree { edges } => Just(())
+                .prop_perturb(move |_, mut g| {
+                    let mut graph = vec![Vec::new(); pkg_count];
+                    let mut dependents = vec![0usize; pkg_count];
+                    // packages are attached from the largest id down, so that hubs have
+                    // large ids and edges point upwards
+                    for i in (0..pkg_count).rev() {
+                        let candidates = i + 1..pkg_count;
+                        let mut targets = Vec::new();
+                        for _ in 0..min(edges, candidates.len()) {
+                            let available = candidates
+                                .clone()
+                                .filter(|t| !targets.contains(t))
+                                .collect_vec();
+                            let total: usize = available.iter().map(|&t| dependents[t] + 1).sum();
+                            let mut pick = g.gen_range(0..total);
+                            for t in available {
+                                if pick < dependents[t] + 1 {
+                                    targets.push(t);
+                                    break;
+                                }
+                                pick -= dependents[t] + 1;
+                            }
+                        }
+                        for &t in &targets {
+                            dependents[t] += 1;
+                        }
+                        graph[i] = targets.into_iter().map(|t| t as PackageId).collect();
+                    }
+                    graph
+                })
+                .boxed(),
+        }
+    }
+}
+
 impl Repository {
     pub fn random_repo_with_size(
         pkg_count: usize,
@@ -430,11 +526,366 @@ impl Repository {
                         })
                         .collect_vec();
                     pkg_strategies.prop_map(move |packages| {
-                        (Repository { packages }, required_installs.clone())
+                        (Repository::new(packages), required_installs.clone())
                     })
                 })
             })
     }
+
+    /// A strategy that generates repositories of `pkg_count` packages whose dependency
+    /// graph has the given shape, together with requirements on every package no other
+    /// package depends on. Every version of a package depends on all of its successors in
+    /// the graph, with random ranges generated by [`Range::shrinking_centered`].
+    pub fn random_repo_with_topology(
+        pkg_count: usize,
+        max_ver: Version,
+        topology: Topology,
+    ) -> impl Strategy<Value = (Repository, RequirementSet)> {
+        (
+            vec(1..=max_ver, pkg_count),
+            topology.random_graph(pkg_count),
+        )
+            .prop_flat_map(move |(max_versions, graph)| {
+                let mut has_dependents = vec![false; pkg_count];
+                for &t in graph.iter().flatten() {
+                    has_dependents[t as usize] = true;
+                }
+                let requirements = RequirementSet::from_deps(
+                    (0..pkg_count)
+                        .filter(|&pid| !has_dependents[pid])
+                        .map(|pid| Requirement::any_version(pid as PackageId))
+                        .collect(),
+                );
+                let pkg_strategies = graph
+                    .iter()
+                    .enumerate()
+                    .map(|(pid, targets)| {
+                        let ver_strategies = (0..max_versions[pid])
+                            .map(|_| {
+                                targets
+                                    .iter()
+                                    .map(|&t| {
+                                        let max_ver = max_versions[t as usize];
+                                        let center = (max_ver / 2) + 1;
+                                        Requirement::shrinking_centered(t, center, max_ver, None)
+                                    })
+                                    .collect_vec()
+                                    .prop_map(|dependencies| PackageVer {
+                                        requirements: RequirementSet::from_deps(dependencies),
+                                    })
+                            })
+                            .collect_vec();
+                        let id = pid as PackageId;
+                        ver_strategies.prop_map(move |versions| Package { id, versions })
+                    })
+                    .collect_vec();
+                pkg_strategies
+                    .prop_map(move |packages| (Repository::new(packages), requirements.clone()))
+            })
+    }
+
+    /// A strategy that generates repositories of `pkg_count` packages together with
+    /// requirements that are guaranteed to be unsatisfiable. A chain of `depth + 1` randomly
+    /// chosen packages is planted: every version of a package in the chain depends on the
+    /// next package, and the last package conflicts with the first one, which is required.
+    /// The other packages are completely random.
+    ///
+    /// The chain is returned in order, every package of it takes part in any unsat core.
+    pub fn random_unsat_repo(
+        pkg_count: usize,
+        depth: usize,
+        max_ver: Version,
+    ) -> impl Strategy<Value = (Repository, RequirementSet, Vec<PackageId>)> {
+        assert!(
+            depth < pkg_count,
+            "Invalid depth: the chain needs more packages than there are"
+        );
+        (
+            vec(1..=max_ver, pkg_count),
+            Just((0..pkg_count as PackageId).collect_vec()).prop_shuffle(),
+        )
+            .prop_flat_map(move |(max_versions, order)| {
+                let max_versions = Rc::new(max_versions);
+                let chain = order[..=depth].to_vec();
+                let pkg_strategies = (0..pkg_count as PackageId)
+                    .map(|pid| match chain.iter().position(|&p| p == pid) {
+                        Some(i) => {
+                            let requirements = match chain.get(i + 1) {
+                                Some(&next) => {
+                                    RequirementSet::from_dep(Requirement::any_version(next))
+                                }
+                                None => {
+                                    RequirementSet::from_antidep(Requirement::any_version(chain[0]))
+                                }
+                            };
+                            let versions = (0..max_versions[pid as usize])
+                                .map(|_| PackageVer {
+                                    requirements: requirements.clone(),
+                                })
+                                .collect();
+                            Just(Package { id: pid, versions }).boxed()
+                        }
+                        None => Package::random_package(max_versions.clone(), pid).boxed(),
+                    })
+                    .collect_vec();
+                let requirements = RequirementSet::from_dep(Requirement::any_version(chain[0]));
+                pkg_strategies.prop_map(move |packages| {
+                    (
+                        Repository::new(packages),
+                        requirements.clone(),
+                        chain.clone(),
+                    )
+                })
+            })
+    }
+}
+
+/// A repository together with requirements on it, generated and shrunk as a unit.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ResolutionProblem {
+    pub repository: Repository,
+    pub requirements: RequirementSet,
+}
+
+/// The ways a problem is simplified, tried in this order since removing whole packages
+/// shrinks the problem the most.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+enum Shrink {
+    RemovePackage(PackageId),
+    RemoveNewestVersion(PackageId),
+    RemoveToplevel(usize),
+    RemoveRequirement(PackageId, usize, usize),
+    NarrowToplevel(usize),
+    NarrowRequirement(PackageId, usize, usize),
+}
+
+/// The requirements of a set, dependencies numbered first.
+fn requirement_mut(reqs: &mut RequirementSet, index: usize) -> Option<&mut Requirement> {
+    let deps = reqs.dependencies.len();
+    if index < deps {
+        reqs.dependencies.get_mut(index)
+    } else {
+        reqs.conflicts.get_mut(index - deps)
+    }
+}
+
+fn remove_requirement(reqs: &mut RequirementSet, index: usize) {
+    let deps = reqs.dependencies.len();
+    if index < deps {
+        reqs.dependencies.remove(index);
+    } else {
+        reqs.conflicts.remove(index - deps);
+    }
+}
+
+/// A narrower version of a requirement: a single range instead of several, a point
+/// instead of an interval. None if it is as narrow as it gets.
+fn narrowed(req: &Requirement) -> Option<Requirement> {
+    let ranges = req.versions.as_vec();
+    let range = match &ranges[..] {
+        [_, _, ..] => ranges[0].clone(),
+        [Range::Interval { lower, .. }] => Range::point(*lower),
+        _ => return None,
+    };
+    Some(Requirement::new(req.package, vec1![range]))
+}
+
+impl ResolutionProblem {
+    /// Apply `f` to every requirement, dropping the requirements it maps to None.
+    fn map_requirements(&mut self, mut f: impl FnMut(&Requirement) -> Option<Requirement>) {
+        let mut map = |reqs: &mut RequirementSet| {
+            reqs.dependencies = reqs.dependencies.iter().filter_map(&mut f).collect();
+            reqs.conflicts = reqs.conflicts.iter().filter_map(&mut f).collect();
+        };
+        map(&mut self.requirements);
+        for package in self.repository.packages_mut() {
+            for ver in &mut package.versions {
+                map(&mut ver.requirements);
+            }
+        }
+    }
+
+    fn shrinks(&self) -> Vec<Shrink> {
+        let packages = &self.repository.packages[..];
+        let mut shrinks = packages
+            .iter()
+            .map(|package| Shrink::RemovePackage(package.id))
+            .collect_vec();
+        shrinks.extend(
+            packages
+                .iter()
+                .filter(|package| package.versions.len() > 1)
+                .map(|package| Shrink::RemoveNewestVersion(package.id)),
+        );
+        let toplevel = self.requirements.dependencies.len() + self.requirements.conflicts.len();
+        shrinks.extend((0..toplevel).map(Shrink::RemoveToplevel));
+        for package in packages {
+            for (v, ver) in package.versions.iter().enumerate() {
+                let reqs = ver.requirements.dependencies.len() + ver.requirements.conflicts.len();
+                shrinks.extend((0..reqs).map(|i| Shrink::RemoveRequirement(package.id, v, i)));
+            }
+        }
+        shrinks.extend((0..toplevel).map(Shrink::NarrowToplevel));
+        for package in packages {
+            for (v, ver) in package.versions.iter().enumerate() {
+                let reqs = ver.requirements.dependencies.len() + ver.requirements.conflicts.len();
+                shrinks.extend((0..reqs).map(|i| Shrink::NarrowRequirement(package.id, v, i)));
+            }
+        }
+        shrinks
+    }
+
+    /// The simplified problem, None if the shrink does not apply.
+    fn apply(&self, shrink: Shrink) -> Option<Self> {
+        let mut problem = self.clone();
+        match shrink {
+            // the requirements on the package are dropped and the packages after it are
+            // renumbered
+            Shrink::RemovePackage(pid) => {
+                let packages = problem.repository.packages_mut();
+                packages.remove(pid as usize);
+                for package in &mut packages[pid as usize..] {
+                    package.id -= 1;
+                }
+                problem.map_requirements(|req| match req.package {
+                    p if p == pid => None,
+                    p if p > pid => Some(Requirement::new(p - 1, req.versions.clone())),
+                    _ => Some(req.clone()),
+                });
+            }
+            // ranges reaching beyond the new newest version are cut off
+            Shrink::RemoveNewestVersion(pid) => {
+                let package = &mut problem.repository.packages_mut()[pid as usize];
+                package.versions.pop();
+                let newest = package.newest_version_number();
+                problem.map_requirements(|req| {
+                    let beyond = req.versions.iter().any(|range| match range {
+                        Range::Interval { upper, .. } => *upper > newest,
+                        Range::Point(v) => *v > newest,
+                        Range::All => false,
+                    });
+                    if req.package != pid || !beyond {
+                        return Some(req.clone());
+                    }
+                    RangeSet::from(&req.versions)
+                        .intersection(&RangeSet::interval(1, newest))
+                        .to_ranges()
+                        .map(|ranges| Requirement::new(pid, ranges))
+                });
+            }
+            Shrink::RemoveToplevel(i) => remove_requirement(&mut problem.requirements, i),
+            Shrink::RemoveRequirement(pid, v, i) => remove_requirement(
+                &mut problem.repository.packages_mut()[pid as usize].versions[v].requirements,
+                i,
+            ),
+            Shrink::NarrowToplevel(i) => {
+                let req = requirement_mut(&mut problem.requirements, i)?;
+                *req = narrowed(req)?;
+            }
+            Shrink::NarrowRequirement(pid, v, i) => {
+                let reqs =
+                    &mut problem.repository.packages_mut()[pid as usize].versions[v].requirements;
+                let req = requirement_mut(reqs, i)?;
+                *req = narrowed(req)?;
+            }
+        }
+        Some(problem)
+    }
+}
+
+/// Shrinks a problem by applying the first simplification that keeps the test failing,
+/// then starting over from the simplified problem.
+pub struct ResolutionProblemValueTree {
+    current: ResolutionProblem,
+    shrinks: Vec<Shrink>,
+    next: usize,
+    // the problem before the last simplification, with the simplifications left to try
+    previous: Option<(ResolutionProblem, Vec<Shrink>, usize)>,
+}
+
+impl ResolutionProblemValueTree {
+    fn new(problem: ResolutionProblem) -> Self {
+        Self {
+            shrinks: problem.shrinks(),
+            current: problem,
+            next: 0,
+            previous: None,
+        }
+    }
+}
+
+impl ValueTree for ResolutionProblemValueTree {
+    type Value = ResolutionProblem;
+
+    fn current(&self) -> ResolutionProblem {
+        self.current.clone()
+    }
+
+    fn simplify(&mut self) -> bool {
+        while let Some(&shrink) = self.shrinks.get(self.next) {
+            self.next += 1;
+            if let Some(simplified) = self.current.apply(shrink) {
+                let shrinks = simplified.shrinks();
+                let current = std::mem::replace(&mut self.current, simplified);
+                let shrinks = std::mem::replace(&mut self.shrinks, shrinks);
+                self.previous = Some((current, shrinks, self.next));
+                self.next = 0;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn complicate(&mut self) -> bool {
+        match self.previous.take() {
+            Some((problem, shrinks, next)) => {
+                self.current = problem;
+                self.shrinks = shrinks;
+                self.next = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Generates problems with [`Repository::random_repo_with_size`], requiring every package
+/// that is required to be installed, and shrinks them with [`ResolutionProblemValueTree`].
+#[derive(Debug)]
+pub struct ResolutionProblemStrategy(BoxedStrategy<ResolutionProblem>);
+
+impl Strategy for ResolutionProblemStrategy {
+    type Tree = ResolutionProblemValueTree;
+    type Value = ResolutionProblem;
+
+    fn new_tree(&self, runner: &mut TestRunner) -> NewTree<Self> {
+        let problem = self.0.new_tree(runner)?.current();
+        Ok(ResolutionProblemValueTree::new(problem))
+    }
+}
+
+impl Arbitrary for ResolutionProblem {
+    /// The number of packages, of packages required to be installed and the maximum
+    /// number of versions of a package.
+    type Parameters = (usize, usize, Version);
+    type Strategy = ResolutionProblemStrategy;
+
+    fn arbitrary_with(
+        (pkg_count, installed_pkg_count, max_ver): Self::Parameters,
+    ) -> Self::Strategy {
+        let problems =
+            Repository::random_repo_with_size(pkg_count, installed_pkg_count, max_ver, None)
+                .prop_map(|(repository, required_installs)| ResolutionProblem {
+                    repository,
+                    requirements: RequirementSet::from_deps(
+                        required_installs
+                            .keys()
+                            .map(|&pid| Requirement::any_version(pid))
+                            .collect(),
+                    ),
+                });
+        ResolutionProblemStrategy(problems.boxed())
+    }
 }
 
 #[cfg(test)]
@@ -463,10 +914,90 @@ mod test {
                  .iter()
                  .map(|(&pid, _)| Requirement { package: pid, versions: vec1![Range::all()]})
                  .collect_vec();
-            let requirements = RequirementSet { dependencies, conflicts: vec![] };
+            let requirements = RequirementSet { dependencies, conflicts: vec![], formula: None };
             let result = simple_solve(&repo, &requirements).unwrap();
             println!("{result:?}");
             prop_assert!(result.is_sat())
         }
     }
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            fork: false,
+            cases: 16,
+            .. ProptestConfig::default()
+        })]
+        #[test]
+        fn test_topologies(
+            (repo, requirements) in prop_oneof![
+                Just(Topology::Chain),
+                Just(Topology::Diamonds),
+                Just(Topology::Layered { layers: 4, fanout: 3 }),
+                Just(Topology::ScaleFree { edges: 2 }),
+            ]
+            .prop_flat_map(|topology| Repository::random_repo_with_topology(40, 5, topology))
+        ) {
+            prop_assert!(!requirements.dependencies.is_empty());
+            for package in repo.packages.iter() {
+                for ver in &package.versions {
+                    for req in &ver.requirements.dependencies {
+                        prop_assert!(req.package > package.id);
+                    }
+                }
+            }
+            let result = simple_solve(&repo, &requirements).unwrap();
+            println!("{result:?}");
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig {
+            fork: false,
+            cases: 32,
+            .. ProptestConfig::default()
+        })]
+        #[test]
+        fn test_unsat_repo(
+            (repo, requirements, chain) in Repository::random_unsat_repo(30, 4, 5)
+        ) {
+            let result = simple_solve(&repo, &requirements).unwrap();
+            let ResolutionResult::UnsatWithCore { core } = result else {
+                panic!("expected an unsat core, got {result:?}")
+            };
+            for pid in chain {
+                prop_assert!(core.package_reqs.contains_key(pid));
+            }
+        }
+    }
+
+    #[test]
+    fn test_problem_shrinking() {
+        use proptest::test_runner::{TestError, TestRunner};
+
+        // fails on every problem with at least 3 packages and a toplevel requirement, the
+        // minimal failing problem has exactly that
+        let mut runner = TestRunner::new(ProptestConfig {
+            fork: false,
+            failure_persistence: None,
+            ..ProptestConfig::default()
+        });
+        let result = runner.run(&ResolutionProblem::arbitrary_with((10, 3, 5)), |problem| {
+            prop_assert!(
+                problem.repository.packages.len() < 3
+                    || problem.requirements.dependencies.is_empty()
+            );
+            Ok(())
+        });
+        let Err(TestError::Fail(_, minimal)) = result else {
+            panic!("expected a failing problem, got {result:?}")
+        };
+        println!("{minimal:?}");
+        assert_eq!(minimal.repository.packages.len(), 3);
+        assert_eq!(minimal.requirements.dependencies.len(), 1);
+        for (id, package) in (0..).zip(minimal.repository.packages.iter()) {
+            assert_eq!(package.id, id);
+            assert_eq!(package.versions.len(), 1);
+            assert!(package.versions[0].requirements.dependencies.is_empty());
+        }
+    }
 }
@@ -0,0 +1,52 @@
+// Values derived from the packages of a repository, built on first use and shared with
+// the clones of the repository. They are dropped along with the other caches whenever the
+// packages change, see `Repository::packages_mut`, or whatever else they are derived
+// from does
+use std::sync::{Arc, OnceLock};
+
+pub(crate) struct Derived<T>(Arc<OnceLock<T>>);
+
+impl<T> Derived<T> {
+    pub(crate) fn get_or_init(&self, init: impl FnOnce() -> T) -> &T {
+        self.0.get_or_init(init)
+    }
+
+    pub(crate) fn is_built(&self) -> bool {
+        self.0.get().is_some()
+    }
+}
+
+impl<T> Default for Derived<T> {
+    fn default() -> Self {
+        Self(Arc::new(OnceLock::new()))
+    }
+}
+
+impl<T> Clone for Derived<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> std::fmt::Debug for Derived<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Derived")
+            .field("built", &self.is_built())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Derived;
+
+    #[test]
+    fn test_derived_shared() {
+        let derived = Derived::<u32>::default();
+        let clone = derived.clone();
+        assert!(!clone.is_built());
+        assert_eq!(*derived.get_or_init(|| 3), 3);
+        assert!(clone.is_built());
+        assert_eq!(*clone.get_or_init(|| 4), 3);
+    }
+}
@@ -0,0 +1,176 @@
+// Results of the spine described with the names and versions of an `ERepository`, so that
+// tools built on the extended layer never deal with package ids and version numbers.
+// Placeholders of missing packages have a name but no versions, and formulas are left out
+// since the extended layer has no counterpart for them
+use std::{fmt::Display, hash::Hash};
+
+use crate::internals::{
+    closure::EdgeKind,
+    types::{extended::ERepository, ConstraintSet, Plan, RangeSet, Requirement, RequirementSet},
+};
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct EPlan<'a, K, V> {
+    // in the order of the plan
+    pub installed: Vec<(&'a K, &'a V)>,
+    pub not_installed: Vec<&'a K>,
+    // the placeholders installed, see `UnknownPackages::External`
+    pub external: Vec<&'a K>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct EDescribedRequirement<'a, K, V> {
+    pub kind: EdgeKind,
+    pub package: &'a K,
+    // the versions matched, from the oldest to the newest
+    pub versions: Vec<&'a V>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ECore<'a, K, V> {
+    // the versions whose requirements take part in the conflict, with those requirements
+    pub versions: Vec<(&'a K, &'a V, Vec<EDescribedRequirement<'a, K, V>>)>,
+    // the requirements resolved for and the masks taking part in the conflict
+    pub toplevel: Vec<EDescribedRequirement<'a, K, V>>,
+}
+
+impl<K, V, R> ERepository<K, V, R>
+where
+    K: Eq + Hash + Display,
+{
+    // Packages unknown to the repository are left out
+    pub fn describe_plan(&self, plan: &Plan) -> EPlan<'_, K, V> {
+        let mut described = EPlan {
+            installed: Vec::new(),
+            not_installed: Vec::new(),
+            external: Vec::new(),
+        };
+        for &(pid, version) in plan {
+            let Some(name) = self.package_name(pid) else {
+                continue;
+            };
+            if version == 0 {
+                described.not_installed.push(name);
+            } else if let Some(v) = self.version_of(pid, version) {
+                described.installed.push((name, v));
+            } else {
+                described.external.push(name);
+            }
+        }
+        described
+    }
+
+    pub fn describe_core(&self, core: &ConstraintSet) -> ECore<'_, K, V> {
+        let mut versions = Vec::new();
+        for (pid, reqs) in core.package_reqs.iter() {
+            for (version, reqs) in reqs.iter() {
+                let (Some(name), Some(v)) = (self.package_name(pid), self.version_of(pid, version))
+                else {
+                    continue;
+                };
+                versions.push((name, v, self.describe_requirements(reqs)));
+            }
+        }
+        ECore {
+            versions,
+            toplevel: self.describe_requirements(&core.toplevel_reqs),
+        }
+    }
+
+    pub fn describe_requirements(
+        &self,
+        reqs: &RequirementSet,
+    ) -> Vec<EDescribedRequirement<'_, K, V>> {
+        let deps = reqs
+            .dependencies
+            .iter()
+            .map(|req| (EdgeKind::Dependency, req));
+        let conflicts = reqs.conflicts.iter().map(|req| (EdgeKind::Conflict, req));
+        deps.chain(conflicts)
+            .filter_map(|(kind, req)| self.describe_requirement(kind, req))
+            .collect()
+    }
+
+    fn describe_requirement(
+        &self,
+        kind: EdgeKind,
+        req: &Requirement,
+    ) -> Option<EDescribedRequirement<'_, K, V>> {
+        let package = self.package_name(req.package)?;
+        let matched = RangeSet::from(&req.versions);
+        let newest = self.repository().newest_ver_of(req.package).unwrap_or(0);
+        let versions = (1..=newest)
+            .filter(|&version| matched.contains(version))
+            .filter_map(|version| self.version_of(req.package, version))
+            .collect();
+        Some(EDescribedRequirement {
+            kind,
+            package,
+            versions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::RangeInclusive;
+
+    use crate::internals::{
+        closure::EdgeKind,
+        solver::simple_solve,
+        types::{
+            extended::{
+                EPackageBuilder, ERepositoryBuilder, ERequirement, EVersion, ViaRangeBound,
+            },
+            RequirementSet, ResolutionResult,
+        },
+    };
+
+    use super::EDescribedRequirement;
+
+    type Exact = ViaRangeBound<RangeInclusive<u64>>;
+
+    #[test]
+    fn test_describe() {
+        let mut builder = ERepositoryBuilder::<&str, u64, Exact>::new();
+        let mut app = EPackageBuilder::new("app");
+        let mut version = EVersion::new(1);
+        version.add_dependency(ERequirement::new("lib", ViaRangeBound(2..=3)));
+        app.add_version(version);
+        builder.add_package(app.build());
+        let mut lib = EPackageBuilder::new("lib");
+        for v in 1..=3 {
+            lib.add_version(EVersion::new(v));
+        }
+        builder.add_package(lib.build());
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+
+        let app = repo.translate(&ERequirement::new("app", ViaRangeBound(1..=1)));
+        let mut reqs = RequirementSet::from_dep(app.unwrap());
+        let r = simple_solve(repo.repository(), &reqs).unwrap();
+        let plan = repo.describe_plan(&r.typed_plan().unwrap().to_plan());
+        println!("{plan:?}");
+        assert!(plan.installed.contains(&(&"app", &1)));
+        assert!(plan.not_installed.is_empty() && plan.external.is_empty());
+
+        let lib = repo.translate(&ERequirement::new("lib", ViaRangeBound(2..=3)));
+        reqs.add_antidep(lib.unwrap());
+        let ResolutionResult::UnsatWithCore { core } =
+            simple_solve(repo.repository(), &reqs).unwrap()
+        else {
+            panic!("expected an unsat core")
+        };
+        let core = repo.describe_core(&core);
+        println!("{core:?}");
+        let lib_2_3 = |kind| EDescribedRequirement {
+            kind,
+            package: &"lib",
+            versions: vec![&2, &3],
+        };
+        assert_eq!(
+            core.versions,
+            vec![(&"app", &1, vec![lib_2_3(EdgeKind::Dependency)])]
+        );
+        assert!(core.toplevel.contains(&lib_2_3(EdgeKind::Conflict)));
+    }
+}
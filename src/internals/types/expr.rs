@@ -5,6 +5,7 @@ use std::cmp::Ordering;
 use std::fmt::{self, Formatter};
 
 use bumpalo::Bump;
+use rkyv::{Archive, Deserialize, Serialize};
 
 use crate::internals::types::*;
 
@@ -26,7 +27,7 @@ where
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+#[derive(Eq, PartialEq, Debug, Copy, Clone, Hash, Archive, Serialize, Deserialize)]
 pub enum AtomicExpr {
     VerEq { pid: PackageId, version: Version },
     VerLE { pid: PackageId, version: Version },
@@ -55,6 +56,12 @@ impl AtomicExpr {
     pub fn ver_ge(pid: PackageId, version: Version) -> AtomicExpr {
         AtomicExpr::VerGE { pid, version }
     }
+
+    pub fn pid(&self) -> PackageId {
+        match self {
+            Self::VerEq { pid, .. } | Self::VerLE { pid, .. } | Self::VerGE { pid, .. } => *pid,
+        }
+    }
 }
 
 #[derive(Eq, PartialEq, Clone)]
@@ -111,6 +118,146 @@ impl Expr<'_> {
     pub fn top<'a>() -> Expr<'a> {
         Expr::Top
     }
+
+    // Fold the expression bottom-up
+    pub fn fold<F: ExprFold>(&self, folder: &mut F) -> F::Output {
+        match self {
+            Expr::Atom(a) => folder.atom(a),
+            Expr::Not(e) => {
+                let e = e.fold(folder);
+                folder.not(e)
+            }
+            Expr::And(l, r) => {
+                let (l, r) = (l.fold(folder), r.fold(folder));
+                folder.and(l, r)
+            }
+            Expr::Or(l, r) => {
+                let (l, r) = (l.fold(folder), r.fold(folder));
+                folder.or(l, r)
+            }
+            Expr::Implies(l, r) => {
+                let (l, r) = (l.fold(folder), r.fold(folder));
+                folder.implies(l, r)
+            }
+            Expr::Bot => folder.bot(),
+            Expr::Top => folder.top(),
+        }
+    }
+
+    // The atoms of the expression, from left to right
+    pub fn atoms(&self) -> Vec<AtomicExpr> {
+        struct Atoms;
+        impl ExprFold for Atoms {
+            type Output = Vec<AtomicExpr>;
+            fn atom(&mut self, atom: &AtomicExpr) -> Vec<AtomicExpr> {
+                vec![*atom]
+            }
+            fn not(&mut self, e: Vec<AtomicExpr>) -> Vec<AtomicExpr> {
+                e
+            }
+            fn and(&mut self, mut l: Vec<AtomicExpr>, r: Vec<AtomicExpr>) -> Vec<AtomicExpr> {
+                l.extend(r);
+                l
+            }
+            fn or(&mut self, l: Vec<AtomicExpr>, r: Vec<AtomicExpr>) -> Vec<AtomicExpr> {
+                self.and(l, r)
+            }
+            fn implies(&mut self, l: Vec<AtomicExpr>, r: Vec<AtomicExpr>) -> Vec<AtomicExpr> {
+                self.and(l, r)
+            }
+            fn bot(&mut self) -> Vec<AtomicExpr> {
+                Vec::new()
+            }
+            fn top(&mut self) -> Vec<AtomicExpr> {
+                Vec::new()
+            }
+        }
+        self.fold(&mut Atoms)
+    }
+
+    pub fn to_owned_expr(&self) -> OwnedExpr {
+        struct ToOwned;
+        impl ExprFold for ToOwned {
+            type Output = OwnedExpr;
+            fn atom(&mut self, atom: &AtomicExpr) -> OwnedExpr {
+                OwnedExpr::Atom(*atom)
+            }
+            fn not(&mut self, e: OwnedExpr) -> OwnedExpr {
+                OwnedExpr::Not(Box::new(e))
+            }
+            fn and(&mut self, l: OwnedExpr, r: OwnedExpr) -> OwnedExpr {
+                OwnedExpr::And(Box::new(l), Box::new(r))
+            }
+            fn or(&mut self, l: OwnedExpr, r: OwnedExpr) -> OwnedExpr {
+                OwnedExpr::Or(Box::new(l), Box::new(r))
+            }
+            fn implies(&mut self, l: OwnedExpr, r: OwnedExpr) -> OwnedExpr {
+                OwnedExpr::Implies(Box::new(l), Box::new(r))
+            }
+            fn bot(&mut self) -> OwnedExpr {
+                OwnedExpr::Bot
+            }
+            fn top(&mut self) -> OwnedExpr {
+                OwnedExpr::Top
+            }
+        }
+        self.fold(&mut ToOwned)
+    }
+}
+
+// A bottom-up fold over expressions, every method receives the results for the
+// subexpressions of the node
+pub trait ExprFold {
+    type Output;
+    fn atom(&mut self, atom: &AtomicExpr) -> Self::Output;
+    fn not(&mut self, e: Self::Output) -> Self::Output;
+    fn and(&mut self, l: Self::Output, r: Self::Output) -> Self::Output;
+    fn or(&mut self, l: Self::Output, r: Self::Output) -> Self::Output;
+    fn implies(&mut self, l: Self::Output, r: Self::Output) -> Self::Output;
+    fn bot(&mut self) -> Self::Output;
+    fn top(&mut self) -> Self::Output;
+}
+
+// An expression owning its subexpressions instead of borrowing them from an allocator,
+// for keeping or serializing generated constraints
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(bound(serialize = "__S: rkyv::ser::Serializer"))]
+pub enum OwnedExpr {
+    Atom(AtomicExpr),
+    Not(#[omit_bounds] Box<OwnedExpr>),
+    And(#[omit_bounds] Box<OwnedExpr>, #[omit_bounds] Box<OwnedExpr>),
+    Or(#[omit_bounds] Box<OwnedExpr>, #[omit_bounds] Box<OwnedExpr>),
+    Implies(#[omit_bounds] Box<OwnedExpr>, #[omit_bounds] Box<OwnedExpr>),
+    Bot,
+    Top,
+}
+
+impl OwnedExpr {
+    // Allocate the expression in an allocator
+    pub fn to_expr<'a>(&self, b: &'a Bump) -> Expr<'a> {
+        match self {
+            OwnedExpr::Atom(a) => Expr::Atom(*a),
+            OwnedExpr::Not(e) => Expr::Not(b.alloc(e.to_expr(b))),
+            OwnedExpr::And(l, r) => Expr::and(b, l.to_expr(b), r.to_expr(b)),
+            OwnedExpr::Or(l, r) => Expr::or(b, l.to_expr(b), r.to_expr(b)),
+            OwnedExpr::Implies(l, r) => Expr::implies(b, l.to_expr(b), r.to_expr(b)),
+            OwnedExpr::Bot => Expr::Bot,
+            OwnedExpr::Top => Expr::Top,
+        }
+    }
+}
+
+impl From<&Expr<'_>> for OwnedExpr {
+    fn from(expr: &Expr<'_>) -> Self {
+        expr.to_owned_expr()
+    }
+}
+
+impl Display for OwnedExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let b = Bump::new();
+        write!(f, "{}", self.to_expr(&b))
+    }
 }
 
 // "chaining" two posets together
@@ -230,7 +377,7 @@ mod test {
 
     use crate::internals::types::expr::ViaDisplayPrec;
 
-    use super::{AtomicExpr, Expr};
+    use super::{AtomicExpr, Expr, OwnedExpr};
 
     #[test]
     fn test_pretty_printing() {
@@ -255,4 +402,23 @@ mod test {
         );
         println!("{}", ViaDisplayPrec(&expr7));
     }
+
+    #[test]
+    fn test_owned_expr() {
+        let b = Bump::new();
+        let a1 = Expr::Atom(AtomicExpr::ver_eq(1, 1));
+        let a2 = Expr::Atom(AtomicExpr::ver_ge(2, 3));
+        let expr = Expr::implies(&b, a1.clone(), Expr::not(&b, Expr::or(&b, a2, a1)));
+        let owned = expr.to_owned_expr();
+        println!("{owned}");
+        assert_eq!(owned.to_expr(&b), expr);
+        assert_eq!(
+            expr.atoms(),
+            vec![
+                AtomicExpr::ver_eq(1, 1),
+                AtomicExpr::ver_ge(2, 3),
+                AtomicExpr::ver_eq(1, 1)
+            ]
+        );
+    }
 }
@@ -1,15 +1,58 @@
 use crate::{
-    Package, PackageId, PackageVer, Range, Repository, Requirement, RequirementSet, Version,
+    internals::types::{InvalidInputSnafu, Vec1},
+    Package, PackageId, PackageVer, Range, Repository, Requirement, RequirementSet,
+    ResolutionError, Version,
 };
 use indexmap::IndexMap;
 use rkyv::{Archive, Deserialize, Serialize};
 
+use std::fmt::{self, Debug, Display, Formatter};
 use std::hash::Hash;
-use std::ops::RangeBounds;
+use std::ops::{
+    Bound, RangeBounds, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive,
+};
+use std::sync::Arc;
+
+// The versions matched by a set, numbered like the keys of the map they are matched in
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub enum RangeMatch {
+    Matched(Vec1<Range>),
+    // no version matches, a dependency can never be satisfied
+    Empty,
+    // every version matches
+    All,
+}
+
+impl RangeMatch {
+    // None if no version matches
+    pub fn into_ranges(self) -> Option<Vec1<Range>> {
+        match self {
+            RangeMatch::Matched(ranges) => Some(ranges),
+            RangeMatch::Empty => None,
+            RangeMatch::All => Some(Vec1::new(Range::all())),
+        }
+    }
+}
 
 pub trait SetOf<T> {
     fn contains(&self, t: &T) -> bool;
 
+    fn match_ranges<V>(&self, map: &IndexMap<T, V>) -> RangeMatch {
+        let ranges = self.to_ranges(map);
+        match ranges.as_slice() {
+            [] => RangeMatch::Empty,
+            [Range::Point(1)] if map.len() == 1 => RangeMatch::All,
+            [Range::Interval { lower: 1, upper }] if *upper == map.len() as Version => {
+                RangeMatch::All
+            }
+            _ => RangeMatch::Matched(
+                ranges
+                    .try_into()
+                    .expect("Impossible: the ranges are not empty"),
+            ),
+        }
+    }
+
     #[allow(clippy::collapsible_else_if)]
     fn to_ranges<V>(&self, map: &IndexMap<T, V>) -> Vec<Range> {
         let mut ranges = Vec::new();
@@ -88,6 +131,113 @@ impl<T> SetOf<T> for ViaFunPtr<T> {
     }
 }
 
+// The sets are displayed in the syntax of `named`, e.g. `>=1, <3`, so that the
+// requirements of build errors read like the requirements that were given
+fn fmt_bounds<T: Display + PartialEq>(
+    start: Bound<&T>,
+    end: Bound<&T>,
+    f: &mut Formatter<'_>,
+) -> fmt::Result {
+    match (start, end) {
+        (Bound::Included(lower), Bound::Included(upper)) if lower == upper => {
+            return write!(f, "={lower}")
+        }
+        (Bound::Unbounded, Bound::Unbounded) => return write!(f, "*"),
+        _ => {}
+    }
+    match start {
+        Bound::Included(lower) => write!(f, ">={lower}")?,
+        Bound::Excluded(lower) => write!(f, ">{lower}")?,
+        Bound::Unbounded => {}
+    }
+    if !matches!(start, Bound::Unbounded) && !matches!(end, Bound::Unbounded) {
+        write!(f, ", ")?;
+    }
+    match end {
+        Bound::Included(upper) => write!(f, "<={upper}"),
+        Bound::Excluded(upper) => write!(f, "<{upper}"),
+        Bound::Unbounded => Ok(()),
+    }
+}
+
+macro_rules! display_range {
+    ($($range:ty),*) => {
+        $(
+            impl<T: Display + PartialEq> Display for ViaRangeBound<$range> {
+                fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                    fmt_bounds(self.0.start_bound(), self.0.end_bound(), f)
+                }
+            }
+        )*
+    };
+}
+
+display_range!(
+    std::ops::Range<T>,
+    RangeInclusive<T>,
+    RangeFrom<T>,
+    RangeTo<T>,
+    RangeToInclusive<T>,
+    (Bound<T>, Bound<T>)
+);
+
+impl Display for ViaRangeBound<RangeFull> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "*")
+    }
+}
+
+fn fmt_joined<R: Display>(sets: &[R], separator: &str, f: &mut Formatter<'_>) -> fmt::Result {
+    for (i, set) in sets.iter().enumerate() {
+        if i != 0 {
+            write!(f, "{separator}")?;
+        }
+        write!(f, "{set}")?;
+    }
+    Ok(())
+}
+
+impl<R: Display> Display for Union<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "∅");
+        }
+        fmt_joined(&self.0, " | ", f)
+    }
+}
+
+impl<R: Display> Display for Intersection<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "*");
+        }
+        fmt_joined(&self.0, ", ", f)
+    }
+}
+
+impl<T> Display for ViaFunPtr<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "<predicate>")
+    }
+}
+
+// A set of versions extended to the aliases of its members, see
+// `ERepositoryBuilder::add_alias`
+struct Aliased<'a, V, S> {
+    set: &'a S,
+    aliases: &'a [(V, V)],
+}
+
+impl<V: Eq, S: SetOf<V>> SetOf<V> for Aliased<'_, V, S> {
+    fn contains(&self, v: &V) -> bool {
+        self.set.contains(v)
+            || self
+                .aliases
+                .iter()
+                .any(|(alias, target)| target == v && self.set.contains(alias))
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum RepositoryBuildError<K, V, R> {
     UnknownPackage {
@@ -100,17 +250,353 @@ pub enum RepositoryBuildError<K, V, R> {
         version: V,
         requirement: ERequirement<K, R>,
     },
+    // a version for the target platform depends on a package none of whose matching
+    // versions is for the target platform
+    PlatformMismatch {
+        source: K,
+        version: V,
+        requirement: ERequirement<K, R>,
+        platform: String,
+    },
+    // an alias or an epoch of a version the package does not have
+    UnknownVersion {
+        package: K,
+        version: V,
+    },
+    // a version numbered twice, by an alias which is also a version, by aliases of two
+    // versions or by two different epochs
+    ConflictingVersion {
+        package: K,
+        version: V,
+    },
+}
+
+impl<K: Display, V: Debug, R: Display> Display for RepositoryBuildError<K, V, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryBuildError::UnknownPackage {
+                source,
+                version,
+                unknown,
+            } => write!(f, "{source} {version:?}: unknown package `{unknown}`"),
+            RepositoryBuildError::IllformedRequirement {
+                source,
+                version,
+                requirement,
+            } => write!(
+                f,
+                "{source} {version:?}: no version satisfies {requirement}"
+            ),
+            RepositoryBuildError::PlatformMismatch {
+                source,
+                version,
+                requirement,
+                platform,
+            } => write!(
+                f,
+                "{source} {version:?}: no version satisfying {requirement} is for {platform}"
+            ),
+            RepositoryBuildError::UnknownVersion { package, version } => {
+                write!(f, "{package} has no version {version:?}")
+            }
+            RepositoryBuildError::ConflictingVersion { package, version } => {
+                write!(f, "{package}: {version:?} is given two version numbers")
+            }
+        }
+    }
+}
+
+// What a requirement is needed for. Resolutions for some kinds only keep the requirements
+// of those kinds, see `ERepository::repository_for`
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash, Default, Archive, Serialize, Deserialize)]
+pub enum DepKind {
+    #[default]
+    Runtime,
+    Build,
+    Test,
+}
+
+impl DepKind {
+    pub const ALL: [DepKind; 3] = [DepKind::Runtime, DepKind::Build, DepKind::Test];
+}
+
+// How requirements on packages missing from the repository are built, e.g. requirements
+// on packages deleted from an index. See `ERepositoryBuilder::unknown_packages`
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Hash, Default, Archive, Serialize, Deserialize)]
+pub enum UnknownPackages {
+    // fail with `RepositoryBuildError::UnknownPackage`
+    #[default]
+    Reject,
+    // leave the requirements out, `ERepository::missing_references` lists them
+    Drop,
+    // require a placeholder package whose only version is masked, so that the packages
+    // requiring it cannot be installed and the mask shows up in unsat cores
+    Unsatisfiable,
+    // require a placeholder package with a single version, standing for a package
+    // provided outside of the repository
+    External,
+}
+
+// The numbering of the spine of an `ERepository`, for processes interpreting its plans on
+// their own, e.g. frontends in other languages. See `ERepository::id_map`
+#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+pub struct IdMap<K, V> {
+    // ordered by id, the placeholders of missing packages come last
+    pub packages: Vec<PackageIds<K, V>>,
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+pub struct PackageIds<K, V> {
+    pub name: K,
+    pub id: PackageId,
+    // version n at index n - 1, placeholders have none
+    pub versions: Vec<V>,
+    // aliases with the number of their version, see `ERepositoryBuilder::add_alias`
+    pub aliases: Vec<(V, Version)>,
+    // stands for a missing package, see `UnknownPackages`
+    pub placeholder: bool,
+}
+
+impl<K, V> IdMap<K, V> {
+    pub fn name(&self, id: PackageId) -> Option<&K> {
+        Some(&self.packages.get(id as usize)?.name)
+    }
+
+    pub fn version(&self, id: PackageId, version: Version) -> Option<&V> {
+        let index = (version as usize).checked_sub(1)?;
+        self.packages.get(id as usize)?.versions.get(index)
+    }
 }
 
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 pub struct ERepository<K, V, R> {
-    packages: IndexMap<K, EPackage<K, V, R>>,
+    // shared with the clones of the repository, as are the packages of the spine
+    packages: Arc<IndexMap<K, EPackage<K, V, R>>>,
     spine: Repository,
+    platform: Option<String>,
+    unknown: UnknownPackages,
+    warn_empty: bool,
+    // the packages required but missing, with their placeholders numbered after the
+    // packages of the repository, None if the requirements are dropped
+    missing: IndexMap<K, Option<PackageId>>,
+}
+
+impl<K, V, R> ERepository<K, V, R>
+where
+    K: Eq + Hash + Display,
+{
+    // The repository the packages are translated to, package ids are the indices of the
+    // names in insertion order
+    pub fn repository(&self) -> &Repository {
+        &self.spine
+    }
+
+    // Missing packages have the id of their placeholder if they have one
+    pub fn package_id(&self, name: &K) -> Option<PackageId> {
+        match self.packages.get_index_of(name) {
+            Some(id) => Some(id as PackageId),
+            None => self.missing.get(name).copied().flatten(),
+        }
+    }
+
+    // The name of a package of the spine, placeholders have the name of the package they
+    // stand for
+    pub fn package_name(&self, id: PackageId) -> Option<&K> {
+        match self.packages.get_index(id as usize) {
+            Some((name, _)) => Some(name),
+            None => self
+                .missing
+                .iter()
+                .find(|(_, placeholder)| **placeholder == Some(id))
+                .map(|(name, _)| name),
+        }
+    }
+
+    // The numbering of the packages and of their versions in the spine
+    pub fn id_map(&self) -> IdMap<K, V>
+    where
+        K: Clone,
+        V: Clone + Eq + Hash,
+    {
+        let mut packages = Vec::with_capacity(self.spine.packages.len());
+        for (id, (name, package)) in self.packages.iter().enumerate() {
+            packages.push(PackageIds {
+                name: name.clone(),
+                id: id as PackageId,
+                versions: package.versions.keys().cloned().collect(),
+                aliases: package
+                    .aliases
+                    .iter()
+                    .filter_map(|(alias, target)| {
+                        let index = package.versions.get_index_of(target)?;
+                        Some((alias.clone(), index as Version + 1))
+                    })
+                    .collect(),
+                placeholder: false,
+            });
+        }
+        for (name, placeholder) in &self.missing {
+            if let Some(id) = *placeholder {
+                packages.push(PackageIds {
+                    name: name.clone(),
+                    id,
+                    versions: Vec::new(),
+                    aliases: Vec::new(),
+                    placeholder: true,
+                });
+            }
+        }
+        IdMap { packages }
+    }
+
+    // The version with a number of the spine, None for the versions of placeholders
+    pub fn version_of(&self, id: PackageId, version: Version) -> Option<&V> {
+        let (_, package) = self.packages.get_index(id as usize)?;
+        let index = (version as usize).checked_sub(1)?;
+        package.versions.get_index(index).map(|(v, _)| v)
+    }
+
+    // The requirements on packages missing from the repository, as the package and the
+    // version requiring them and the missing package
+    pub fn missing_references(&self) -> Vec<(&K, &V, &K)> {
+        let mut references = Vec::new();
+        for (name, package) in self.packages.iter() {
+            for (v, version) in &package.versions {
+                for req in version.requirements() {
+                    if self.missing.contains_key(&req.package) {
+                        references.push((name, v, &req.package));
+                    }
+                }
+            }
+        }
+        references
+    }
+
+    // Translate a requirement on a named package, failing on unknown packages and on
+    // requirements no version satisfies
+    pub fn translate<S: SetOf<V>>(
+        &self,
+        requirement: &ERequirement<K, S>,
+    ) -> Result<Requirement, ResolutionError>
+    where
+        V: Eq,
+    {
+        requirement.translate(&*self.packages).map_err(|e| {
+            let message = match e {
+                Ok(name) => format!("unknown package `{name}`"),
+                Err(req) => format!("no version of `{}` satisfies the requirement", req.package),
+            };
+            InvalidInputSnafu { message }.build()
+        })
+    }
+
+    // The repository with only the requirements of some kinds, e.g. the build and runtime
+    // requirements for a build environment. `repository` keeps the requirements of every
+    // kind, the masks and the numbering of the versions are the same in both
+    pub fn repository_for(&self, kinds: &[DepKind]) -> Repository
+    where
+        V: Eq,
+        R: SetOf<V>,
+    {
+        let mut spine = self.spine.clone();
+        for (package, epackage) in spine.packages_mut().iter_mut().zip(self.packages.values()) {
+            for (version, eversion) in package.versions.iter_mut().zip(epackage.versions.values()) {
+                *version = eversion
+                    .translate(&*self.packages, &self.missing, kinds, self.warn_empty)
+                    .unwrap_or_else(|_| {
+                        panic!("Impossible: requirements failed to translate after building")
+                    });
+            }
+        }
+        spine
+    }
+
+    // The requirements matching no version of the package they are on, as the package and
+    // the version requiring them and the requirement. Those are only left in a repository
+    // built with `ERepositoryBuilder::warn_on_empty`
+    pub fn empty_requirements(&self) -> Vec<(&K, &V, &ERequirement<K, R>)>
+    where
+        V: Eq,
+        R: SetOf<V>,
+    {
+        let mut empty = Vec::new();
+        for (name, package) in self.packages.iter() {
+            for (v, version) in &package.versions {
+                for req in version.requirements() {
+                    if let Err(Err(req)) = req.translate_in(&*self.packages, &self.missing) {
+                        empty.push((name, v, req));
+                    }
+                }
+            }
+        }
+        empty
+    }
+
+    // A builder with the packages of the repository, to add and replace packages. Building
+    // it again only translates the packages added or replaced and the packages requiring
+    // them, the others keep their translation in the spine
+    pub fn into_builder(self) -> ERepositoryBuilder<K, V, R> {
+        // the placeholders are numbered again, and the packages requiring missing packages
+        // are translated again in case the packages are added
+        let mut spine = self.spine;
+        spine.packages_mut().truncate(self.packages.len());
+        let amended = self
+            .packages
+            .values()
+            .enumerate()
+            .filter(|(_, package)| {
+                package
+                    .versions
+                    .values()
+                    .flat_map(EVersion::requirements)
+                    .any(|req| self.missing.contains_key(&req.package))
+            })
+            .map(|(i, _)| i)
+            .collect();
+        ERepositoryBuilder {
+            packages: Arc::unwrap_or_clone(self.packages),
+            platform: self.platform,
+            aliases: Vec::new(),
+            epochs: Vec::new(),
+            unknown: self.unknown,
+            warn_empty: self.warn_empty,
+            base: Some(spine),
+            amended,
+        }
+    }
+
+    // The number of a version in the spine, an alias has the number of its version
+    pub fn version_number(&self, name: &K, version: &V) -> Option<Version>
+    where
+        V: Eq + Hash,
+    {
+        let package = self.packages.get(name)?;
+        let version = package
+            .aliases
+            .iter()
+            .find(|(alias, _)| alias == version)
+            .map_or(version, |(_, target)| target);
+        let index = package.versions.get_index_of(version)?;
+        Some(index as Version + 1)
+    }
 }
 
 #[derive(Debug, Clone, Archive, Serialize, Deserialize)]
 pub struct ERepositoryBuilder<K, V, R> {
     packages: IndexMap<K, EPackage<K, V, R>>,
+    // versions for other platforms are masked in the spine, see `target_platform`
+    platform: Option<String>,
+    // package, alias and aliased version
+    aliases: Vec<(K, V, V)>,
+    epochs: Vec<(K, V, u64)>,
+    unknown: UnknownPackages,
+    // requirements matching no version are kept out of the spine instead of failing, see
+    // `warn_on_empty`
+    warn_empty: bool,
+    // the spine of the repository the builder was made from, see `ERepository::into_builder`
+    base: Option<Repository>,
+    // indices of the packages replaced or remapped since
+    amended: Vec<usize>,
 }
 
 impl<K, V, R> ERepositoryBuilder<K, V, R>
@@ -119,30 +605,142 @@ where
     V: Clone + Hash,
     R: SetOf<V>,
 {
-    pub fn build(
-        Self { packages }: Self,
-    ) -> Result<ERepository<K, V, R>, RepositoryBuildError<K, V, R>>
+    // Build the repository, failing on the first error
+    pub fn build(builder: Self) -> Result<ERepository<K, V, R>, RepositoryBuildError<K, V, R>>
+    where
+        V: Ord,
+        R: Clone,
+    {
+        Self::build_collecting(builder).map_err(|mut errors| errors.swap_remove(0))
+    }
+
+    // Build the repository, reporting every bad requirement at once so that an index can
+    // be fixed in one pass. Errors in the aliases and epochs are reported alone, since the
+    // versions cannot be numbered without them
+    pub fn build_collecting(
+        Self {
+            mut packages,
+            platform,
+            aliases,
+            epochs,
+            unknown,
+            warn_empty,
+            base,
+            mut amended,
+        }: Self,
+    ) -> Result<ERepository<K, V, R>, Vec<RepositoryBuildError<K, V, R>>>
     where
+        V: Ord,
         R: Clone,
     {
+        Self::remap_versions(&mut packages, aliases, epochs, &mut amended)
+            .map_err(|error| vec![error])?;
+
+        let mut missing = IndexMap::new();
+        if unknown != UnknownPackages::Reject {
+            for package in packages.values() {
+                for req in package.versions.values().flat_map(EVersion::requirements) {
+                    if !packages.contains_key(&req.package) && !missing.contains_key(&req.package) {
+                        let placeholder = (packages.len() + missing.len()) as PackageId;
+                        let placeholder = (unknown != UnknownPackages::Drop).then_some(placeholder);
+                        let _ = missing.insert(req.package.clone(), placeholder);
+                    }
+                }
+            }
+        }
+
+        // only the packages of the base which neither changed nor require packages that
+        // changed keep their translation
+        let (mut base, base_masks) = match base {
+            Some(spine) => {
+                let masks = spine.masks().to_vec();
+                let packages = Arc::unwrap_or_clone(spine.packages);
+                (packages.into_iter().map(Some).collect(), masks)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        let mut changed = vec![false; packages.len()];
+        for i in amended.into_iter().chain(base.len()..packages.len()) {
+            changed[i] = true;
+        }
+        let requires_changed = |package: &EPackage<K, V, R>| {
+            package
+                .versions
+                .values()
+                .flat_map(EVersion::requirements)
+                .any(|req| {
+                    packages
+                        .get_index_of(&req.package)
+                        .is_some_and(|j| changed[j])
+                })
+        };
+        let stale = packages
+            .values()
+            .enumerate()
+            .map(|(i, package)| changed[i] || requires_changed(package))
+            .collect::<Vec<_>>();
+
         let mut pkgs = Vec::with_capacity(packages.len());
+        let mut masked = Vec::new();
+        let mut errors = Vec::new();
 
         for (i, (name, package)) in packages.iter().enumerate() {
+            // the masks of the packages reused are kept as well
+            if let Some(pkg) = base.get_mut(i).filter(|_| !stale[i]).and_then(Option::take) {
+                pkgs.push(pkg);
+                continue;
+            }
             let mut versions = Vec::with_capacity(package.versions.len());
 
-            for (v, version) in &package.versions {
-                versions.push(version.translate(&packages).map_err(|e| match e {
-                    Ok(k) => RepositoryBuildError::UnknownPackage {
-                        source: name.clone(),
-                        version: v.clone(),
-                        unknown: k.clone(),
-                    },
-                    Err(r) => RepositoryBuildError::IllformedRequirement {
-                        source: name.clone(),
-                        version: v.clone(),
-                        requirement: r.clone(),
-                    },
-                })?);
+            for (j, (v, version)) in package.versions.iter().enumerate() {
+                if let Some(platform) = &platform {
+                    if !package.is_for(version, platform) {
+                        masked.push((i as PackageId, j as Version + 1));
+                    } else if let Some(requirement) = version.dependencies.iter().find(|dep| {
+                        packages
+                            .get(&dep.package)
+                            .is_some_and(|target| target.only_for_others(&dep.versions, platform))
+                    }) {
+                        errors.push(RepositoryBuildError::PlatformMismatch {
+                            source: name.clone(),
+                            version: v.clone(),
+                            requirement: requirement.clone(),
+                            platform: platform.clone(),
+                        });
+                    }
+                }
+                match version.translate(&packages, &missing, &DepKind::ALL, warn_empty) {
+                    Ok(translated) => {
+                        // a version requiring a package none of whose versions match can
+                        // never be installed
+                        if warn_empty
+                            && version.dependencies.iter().any(|dep| {
+                                matches!(dep.translate_in(&packages, &missing), Err(Err(_)))
+                            })
+                        {
+                            masked.push((i as PackageId, j as Version + 1));
+                        }
+                        versions.push(translated)
+                    }
+                    // translate the requirements one by one to find every bad one
+                    Err(_) => errors.extend(
+                        version
+                            .requirements()
+                            .filter_map(|req| req.translate_in(&packages, &missing).err())
+                            .map(|e| match e {
+                                Ok(k) => RepositoryBuildError::UnknownPackage {
+                                    source: name.clone(),
+                                    version: v.clone(),
+                                    unknown: k.clone(),
+                                },
+                                Err(r) => RepositoryBuildError::IllformedRequirement {
+                                    source: name.clone(),
+                                    version: v.clone(),
+                                    requirement: r.clone(),
+                                },
+                            }),
+                    ),
+                }
             }
 
             let pkg = Package {
@@ -152,22 +750,161 @@ where
             pkgs.push(pkg);
         }
 
-        let spine = Repository { packages: pkgs };
-        Ok(ERepository { packages, spine })
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for &id in missing.values().flatten() {
+            pkgs.push(Package {
+                id,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            });
+            if unknown == UnknownPackages::Unsatisfiable {
+                masked.push((id, 1));
+            }
+        }
+
+        let mut spine = Repository::new(pkgs);
+        for (id, version) in masked {
+            spine.mask_version(id, version);
+        }
+        for mask in base_masks {
+            if (mask.package as usize) < stale.len() && !stale[mask.package as usize] {
+                for range in mask.versions.iter() {
+                    spine.mask_range(mask.package, range.clone());
+                }
+            }
+        }
+        for (id, package) in packages.values().enumerate() {
+            if package.meta {
+                spine.mark_meta_package(id as PackageId);
+            }
+        }
+        Ok(ERepository {
+            packages: Arc::new(packages),
+            spine,
+            platform,
+            unknown,
+            warn_empty,
+            missing,
+        })
+    }
+
+    // Reorder the versions by epoch and record the aliases, before any requirement is
+    // translated. The packages remapped are added to `amended`
+    fn remap_versions(
+        packages: &mut IndexMap<K, EPackage<K, V, R>>,
+        aliases: Vec<(K, V, V)>,
+        epochs: Vec<(K, V, u64)>,
+        amended: &mut Vec<usize>,
+    ) -> Result<(), RepositoryBuildError<K, V, R>>
+    where
+        V: Ord,
+    {
+        for (name, version, epoch) in epochs {
+            let Some((i, _, package)) = packages
+                .get_full_mut(&name)
+                .filter(|(_, _, package)| package.versions.contains_key(&version))
+            else {
+                return Err(RepositoryBuildError::UnknownVersion {
+                    package: name,
+                    version,
+                });
+            };
+            match package.epochs.iter().find(|(v, _)| *v == version) {
+                Some(&(_, previous)) if previous != epoch => {
+                    return Err(RepositoryBuildError::ConflictingVersion {
+                        package: name,
+                        version,
+                    })
+                }
+                Some(_) => {}
+                None => {
+                    package.epochs.push((version, epoch));
+                    amended.push(i);
+                }
+            }
+        }
+        amended.sort_unstable();
+        amended.dedup();
+        for &i in amended.iter() {
+            let (_, package) = packages
+                .get_index_mut(i)
+                .expect("Impossible: amended packages exist");
+            if !package.epochs.is_empty() {
+                let epochs = &package.epochs;
+                let epoch = |v: &V| epochs.iter().find(|(e, _)| e == v).map_or(0, |&(_, e)| e);
+                package
+                    .versions
+                    .sort_by(|v1, _, v2, _| (epoch(v1), v1).cmp(&(epoch(v2), v2)));
+            }
+        }
+
+        for (name, alias, target) in aliases {
+            let Some((i, _, package)) = packages
+                .get_full_mut(&name)
+                .filter(|(_, _, package)| package.versions.contains_key(&target))
+            else {
+                return Err(RepositoryBuildError::UnknownVersion {
+                    package: name,
+                    version: target,
+                });
+            };
+            let previous = package.aliases.iter().find(|(a, _)| *a == alias);
+            if package.versions.contains_key(&alias) || previous.is_some_and(|(_, t)| *t != target)
+            {
+                return Err(RepositoryBuildError::ConflictingVersion {
+                    package: name,
+                    version: alias,
+                });
+            }
+            if previous.is_none() {
+                package.aliases.push((alias, target));
+                amended.push(i);
+            }
+        }
+        Ok(())
     }
 
     pub fn new() -> Self {
         ERepositoryBuilder {
             packages: IndexMap::new(),
+            platform: None,
+            aliases: Vec::new(),
+            epochs: Vec::new(),
+            unknown: UnknownPackages::Reject,
+            warn_empty: false,
+            base: None,
+            amended: Vec::new(),
         }
     }
 
     pub fn with_capacity(n: usize) -> Self {
         ERepositoryBuilder {
             packages: IndexMap::with_capacity(n),
+            platform: None,
+            aliases: Vec::new(),
+            epochs: Vec::new(),
+            unknown: UnknownPackages::Reject,
+            warn_empty: false,
+            base: None,
+            amended: Vec::new(),
         }
     }
 
+    // Resolve for a platform: versions tagged for other platforms are masked, keeping
+    // the version numbers of the spine the same on every platform
+    pub fn target_platform(&mut self, platform: impl Into<String>) {
+        let platform = Some(platform.into());
+        if platform != self.platform {
+            // every package is translated again
+            self.base = None;
+        }
+        self.platform = platform;
+    }
+
     pub fn add_package(&mut self, package: EPackage<K, V, R>) -> bool {
         if !(self.packages.contains_key(&package.name)) {
             let _ = self.packages.insert(package.name.clone(), package);
@@ -176,6 +913,95 @@ where
             false
         }
     }
+
+    // Choose how requirements on packages missing from the repository are built, they are
+    // rejected by default
+    pub fn unknown_packages(&mut self, mode: UnknownPackages) {
+        if mode != self.unknown {
+            self.base = None;
+        }
+        self.unknown = mode;
+    }
+
+    // Build requirements matching no version instead of failing with
+    // `RepositoryBuildError::IllformedRequirement`: the versions depending on them are
+    // masked and conflicts with them are left out. `ERepository::empty_requirements` lists
+    // them
+    pub fn warn_on_empty(&mut self, warn: bool) {
+        if warn != self.warn_empty {
+            self.base = None;
+        }
+        self.warn_empty = warn;
+    }
+
+    // Add a package, replacing the package with the same name if there is one. The package
+    // keeps the number of the package it replaces, along with its aliases and epochs of
+    // the versions still there
+    pub fn replace_package(&mut self, mut package: EPackage<K, V, R>) -> Option<EPackage<K, V, R>>
+    where
+        V: Eq,
+    {
+        let Some((i, _, previous)) = self.packages.get_full_mut(&package.name) else {
+            let _ = self.packages.insert(package.name.clone(), package);
+            return None;
+        };
+        package.aliases = previous
+            .aliases
+            .iter()
+            .filter(|(alias, target)| {
+                !package.versions.contains_key(alias) && package.versions.contains_key(target)
+            })
+            .cloned()
+            .collect();
+        package.epochs = previous
+            .epochs
+            .iter()
+            .filter(|(version, _)| package.versions.contains_key(version))
+            .cloned()
+            .collect();
+        self.amended.push(i);
+        Some(std::mem::replace(previous, package))
+    }
+
+    // Add the packages of another builder, e.g. of another shard or of a newer snapshot,
+    // replacing the packages with the same names. The platform of the other builder wins
+    // if it has one
+    pub fn merge(&mut self, other: Self)
+    where
+        V: Eq,
+    {
+        for package in other.packages.into_values() {
+            let _ = self.replace_package(package);
+        }
+        self.aliases.extend(other.aliases);
+        self.epochs.extend(other.epochs);
+        if let Some(platform) = other.platform {
+            self.target_platform(platform);
+        }
+    }
+
+    // Define a group: a meta-package with a single version requiring the members
+    pub fn add_group(&mut self, name: K, version: V, members: Vec<ERequirement<K, R>>) -> bool
+    where
+        V: Ord,
+    {
+        let mut package = EPackageBuilder::new(name);
+        package.set_meta(true);
+        package.add_version(EVersion::from(version, members, Vec::new()));
+        self.add_package(package.build())
+    }
+
+    // Declare another name of a version of a package, e.g. `1.0` for `1.0.0`. The alias
+    // gets the number of the version, requirements matching the alias match the version
+    pub fn add_alias(&mut self, package: K, alias: V, target: V) {
+        self.aliases.push((package, alias, target))
+    }
+
+    // Override the order of a version: versions are ordered by epoch first, 0 unless set,
+    // so that e.g. a release renumbered backwards stays newer than the releases before it
+    pub fn set_epoch(&mut self, package: K, version: V, epoch: u64) {
+        self.epochs.push((package, version, epoch))
+    }
 }
 
 impl<K, V, R> Default for ERepositoryBuilder<K, V, R>
@@ -193,12 +1019,60 @@ where
 pub struct EPackage<K, V, R> {
     name: K,
     versions: IndexMap<V, EVersion<K, V, R>>,
+    // platforms of every version, on top of the platforms of the versions
+    platforms: Vec<String>,
+    // only carries requirements, see `Repository::mark_meta_package`
+    meta: bool,
+    // alias and aliased version, see `ERepositoryBuilder::add_alias`
+    aliases: Vec<(V, V)>,
+    // see `ERepositoryBuilder::set_epoch`
+    epochs: Vec<(V, u64)>,
+}
+
+// A list of platform tags, no tags standing for every platform
+fn matches_platform(tags: &[String], platform: &str) -> bool {
+    tags.is_empty() || tags.iter().any(|tag| tag == platform)
+}
+
+impl<K, V, R> EPackage<K, V, R> {
+    pub fn platforms(&self) -> &[String] {
+        &self.platforms
+    }
+
+    pub fn is_meta(&self) -> bool {
+        self.meta
+    }
+
+    // Whether a version of the package is for a platform, according to the tags of both
+    fn is_for(&self, version: &EVersion<K, V, R>, platform: &str) -> bool {
+        matches_platform(&self.platforms, platform)
+            && matches_platform(&version.platforms, platform)
+    }
+}
+
+impl<K, V: Eq, R: SetOf<V>> EPackage<K, V, R> {
+    // Whether some versions match but none of them is for the platform. Unknown packages
+    // and requirements without any matching version are reported by the translation
+    fn only_for_others(&self, versions: &R, platform: &str) -> bool {
+        let versions = Aliased {
+            set: versions,
+            aliases: &self.aliases,
+        };
+        let mut matching = self
+            .versions
+            .iter()
+            .filter(|(v, _)| versions.contains(v))
+            .peekable();
+        matching.peek().is_some() && !matching.any(|(_, version)| self.is_for(version, platform))
+    }
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
 pub struct EPackageBuilder<K, V, R> {
     name: K,
     versions: Vec<EVersion<K, V, R>>,
+    platforms: Vec<String>,
+    meta: bool,
 }
 
 impl<K, V, R> EPackageBuilder<K, V, R>
@@ -209,6 +1083,8 @@ where
         EPackageBuilder {
             name,
             versions: Vec::new(),
+            platforms: Vec::new(),
+            meta: false,
         }
     }
 
@@ -216,6 +1092,8 @@ where
         EPackageBuilder {
             name,
             versions: Vec::with_capacity(n),
+            platforms: Vec::new(),
+            meta: false,
         }
     }
 
@@ -223,6 +1101,15 @@ where
         self.versions.push(version)
     }
 
+    // Restrict the package to a platform, a package with several tags is for any of them
+    pub fn add_platform(&mut self, platform: impl Into<String>) {
+        self.platforms.push(platform.into())
+    }
+
+    pub fn set_meta(&mut self, meta: bool) {
+        self.meta = meta
+    }
+
     pub fn build(mut self) -> EPackage<K, V, R> {
         let mut versions = IndexMap::with_capacity(self.versions.len());
 
@@ -237,6 +1124,10 @@ where
         EPackage {
             name: self.name,
             versions,
+            platforms: self.platforms,
+            meta: self.meta,
+            aliases: Vec::new(),
+            epochs: Vec::new(),
         }
     }
 }
@@ -246,6 +1137,8 @@ pub struct EVersion<K, V, R> {
     version: V,
     dependencies: Vec<ERequirement<K, R>>,
     conflicts: Vec<ERequirement<K, R>>,
+    // the platforms the version is for, every platform if empty
+    platforms: Vec<String>,
 }
 
 impl<K, V, R> EVersion<K, V, R>
@@ -258,6 +1151,7 @@ where
             version,
             dependencies: Vec::new(),
             conflicts: Vec::new(),
+            platforms: Vec::new(),
         }
     }
 
@@ -270,6 +1164,7 @@ where
             version,
             dependencies,
             conflicts,
+            platforms: Vec::new(),
         }
     }
 
@@ -278,6 +1173,7 @@ where
             version,
             dependencies: Vec::with_capacity(n),
             conflicts: Vec::with_capacity(n),
+            platforms: Vec::new(),
         }
     }
 
@@ -289,34 +1185,69 @@ where
         self.conflicts.push(requirement)
     }
 
+    pub fn add_platform(&mut self, platform: impl Into<String>) {
+        self.platforms.push(platform.into())
+    }
+
+    pub fn platforms(&self) -> &[String] {
+        &self.platforms
+    }
+
+    pub fn is_for(&self, platform: &str) -> bool {
+        matches_platform(&self.platforms, platform)
+    }
+
     fn translate(
         &self,
         map: &IndexMap<K, EPackage<K, V, R>>,
-    ) -> Result<PackageVer, Result<&K, &ERequirement<K, R>>> {
+        missing: &IndexMap<K, Option<PackageId>>,
+        kinds: &[DepKind],
+        skip_empty: bool,
+    ) -> Result<PackageVer, Result<&K, &ERequirement<K, R>>>
+    where
+        V: Eq,
+    {
         let mut dependencies = Vec::with_capacity(self.dependencies.len());
         let mut conflicts = Vec::with_capacity(self.conflicts.len());
+        let selected = |req: &&ERequirement<K, R>| kinds.contains(&req.kind);
 
-        for dep in self.dependencies.iter() {
-            dependencies.push(dep.translate(map)?)
+        // requirements matching no version are left out if skipped
+        for dep in self.dependencies.iter().filter(selected) {
+            match dep.translate_in(map, missing) {
+                Err(Err(_)) if skip_empty => {}
+                r => dependencies.extend(r?),
+            }
         }
 
-        for antidep in self.conflicts.iter() {
-            conflicts.push(antidep.translate(map)?)
+        for antidep in self.conflicts.iter().filter(selected) {
+            match antidep.translate_in(map, missing) {
+                Err(Err(_)) if skip_empty => {}
+                r => conflicts.extend(r?),
+            }
         }
 
         Ok(PackageVer {
             requirements: RequirementSet {
                 dependencies,
                 conflicts,
+                formula: None,
             },
         })
     }
 }
 
+impl<K, V, R> EVersion<K, V, R> {
+    // The dependencies and the conflicts
+    fn requirements(&self) -> impl Iterator<Item = &ERequirement<K, R>> {
+        self.dependencies.iter().chain(&self.conflicts)
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
 pub struct ERequirement<K, R> {
     package: K,
     versions: R,
+    kind: DepKind,
 }
 
 impl<K, R> ERequirement<K, R>
@@ -324,25 +1255,434 @@ where
     K: Eq + Hash,
 {
     pub fn new(package: K, versions: R) -> Self {
-        ERequirement { package, versions }
+        ERequirement {
+            package,
+            versions,
+            kind: DepKind::Runtime,
+        }
+    }
+
+    pub fn with_kind(self, kind: DepKind) -> Self {
+        ERequirement { kind, ..self }
+    }
+
+    pub fn kind(&self) -> DepKind {
+        self.kind
     }
 
-    fn translate<V>(
+    pub fn package(&self) -> &K {
+        &self.package
+    }
+
+    pub fn versions(&self) -> &R {
+        &self.versions
+    }
+
+    fn translate<V, S>(
         &self,
-        map: &IndexMap<K, EPackage<K, V, R>>,
+        map: &IndexMap<K, EPackage<K, V, S>>,
     ) -> Result<Requirement, Result<&K, &Self>>
     where
+        V: Eq,
         R: SetOf<V>,
     {
         let (id, _, package) = map.get_full(&self.package).ok_or(Ok(&self.package))?;
-        let ranges = self
-            .versions
-            .to_ranges(&package.versions)
-            .try_into()
-            .map_err(|_| Err(self))?;
+        let versions = Aliased {
+            set: &self.versions,
+            aliases: &package.aliases,
+        };
+        let ranges = versions
+            .match_ranges(&package.versions)
+            .into_ranges()
+            .ok_or(Err(self))?;
         Ok(Requirement {
             package: id as PackageId,
             versions: ranges,
         })
     }
+
+    // Requirements on missing packages translate to requirements on their placeholders,
+    // None if they are dropped
+    fn translate_in<V, S>(
+        &self,
+        map: &IndexMap<K, EPackage<K, V, S>>,
+        missing: &IndexMap<K, Option<PackageId>>,
+    ) -> Result<Option<Requirement>, Result<&K, &Self>>
+    where
+        V: Eq,
+        R: SetOf<V>,
+    {
+        match missing.get(&self.package) {
+            Some(placeholder) => Ok(placeholder.map(Requirement::any_version)),
+            None => self.translate(map).map(Some),
+        }
+    }
+}
+
+impl<K: Display, R: Display> Display for ERequirement<K, R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "`{} {}`", self.package, self.versions)?;
+        if self.kind != DepKind::Runtime {
+            write!(f, " ({:?})", self.kind)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::ops::{RangeFull, RangeInclusive};
+
+    use indexmap::IndexMap;
+
+    use crate::internals::{
+        solver::simple_solve,
+        types::{vec1, PackageSource, Range, Repository, Requirement, RequirementSet},
+    };
+
+    use super::{
+        DepKind, EPackageBuilder, ERepository, ERepositoryBuilder, ERequirement, EVersion, IdMap,
+        RangeMatch, RepositoryBuildError, SetOf, Union, UnknownPackages, ViaFunPtr, ViaRangeBound,
+    };
+
+    type Any = ViaRangeBound<RangeFull>;
+
+    #[test]
+    fn test_platforms() {
+        let builder = |platform: &str| {
+            let mut builder = ERepositoryBuilder::<&str, u64, Any>::new();
+            let mut app = EPackageBuilder::new("app");
+            let mut version = EVersion::<&str, u64, Any>::new(1);
+            version.add_dependency(ERequirement::new("libc", ViaRangeBound(..)));
+            app.add_version(version);
+            builder.add_package(app.build());
+            let mut libc = EPackageBuilder::new("libc");
+            for (v, tag) in [(1, "linux"), (2, "windows")] {
+                let mut version = EVersion::<&str, u64, Any>::new(v);
+                version.add_platform(tag);
+                libc.add_version(version);
+            }
+            builder.add_package(libc.build());
+            builder.target_platform(platform);
+            ERepositoryBuilder::build(builder)
+        };
+
+        let repo = builder("linux").unwrap();
+        let app = repo.translate(&ERequirement::new("app", ViaRangeBound(..)));
+        let reqs = RequirementSet::from_dep(app.unwrap());
+        let r = simple_solve(repo.repository(), &reqs).unwrap();
+        println!("{r:?}");
+        assert_eq!(r.typed_plan().unwrap().installed_version(1), Some(1));
+        assert!(repo.repository().is_masked(1, 2));
+
+        let Err(RepositoryBuildError::PlatformMismatch {
+            source, platform, ..
+        }) = builder("macos")
+        else {
+            panic!("expected a platform mismatch")
+        };
+        assert_eq!((source, platform.as_str()), ("app", "macos"));
+    }
+
+    #[test]
+    fn test_dep_kinds() {
+        let mut builder = ERepositoryBuilder::<&str, u64, Any>::new();
+        let mut app = EPackageBuilder::new("app");
+        let mut version = EVersion::<&str, u64, Any>::new(1);
+        version.add_dependency(ERequirement::new("libc", ViaRangeBound(..)));
+        version
+            .add_dependency(ERequirement::new("cc", ViaRangeBound(..)).with_kind(DepKind::Build));
+        app.add_version(version);
+        builder.add_package(app.build());
+        for name in ["libc", "cc"] {
+            let mut package = EPackageBuilder::new(name);
+            package.add_version(EVersion::<&str, u64, Any>::new(1));
+            builder.add_package(package.build());
+        }
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+
+        let deps = |repo: &Repository| {
+            let reqs = &repo.packages[0].versions[0].requirements.dependencies;
+            reqs.iter().map(|req| req.package).collect::<Vec<_>>()
+        };
+        assert_eq!(deps(repo.repository()), vec![1, 2]);
+        assert_eq!(deps(&repo.repository_for(&[DepKind::Runtime])), vec![1]);
+        assert_eq!(
+            deps(&repo.repository_for(&[DepKind::Runtime, DepKind::Build])),
+            vec![1, 2]
+        );
+        assert!(deps(&repo.repository_for(&[DepKind::Test])).is_empty());
+    }
+
+    #[test]
+    fn test_groups() {
+        let mut builder = ERepositoryBuilder::<&str, u64, Any>::new();
+        for name in ["server", "client"] {
+            let mut package = EPackageBuilder::new(name);
+            package.add_version(EVersion::<&str, u64, Any>::new(1));
+            builder.add_package(package.build());
+        }
+        let members = ["server", "client"].map(|name| ERequirement::new(name, ViaRangeBound(..)));
+        assert!(builder.add_group("web", 1, members.to_vec()));
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+
+        let web = repo.translate(&ERequirement::new("web", ViaRangeBound(..)));
+        let reqs = RequirementSet::from_dep(web.unwrap());
+        let r = simple_solve(repo.repository(), &reqs).unwrap();
+        println!("{r:?}");
+        let plan = r.typed_plan().unwrap();
+        assert_eq!(plan.installed().count(), 3);
+        let meta = (0..3).filter(|&id| repo.repository().is_meta_package(id));
+        assert_eq!(meta.collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn test_version_remapping() {
+        type Exact = ViaRangeBound<RangeInclusive<u64>>;
+        let mut builder = ERepositoryBuilder::<&str, u64, Exact>::new();
+        let mut lib = EPackageBuilder::new("lib");
+        for v in [1, 2, 3] {
+            lib.add_version(EVersion::<&str, u64, Exact>::new(v));
+        }
+        builder.add_package(lib.build());
+        // 1 was released after 3, and 10 is another name of 2
+        builder.set_epoch("lib", 1, 1);
+        builder.add_alias("lib", 10, 2);
+
+        let repo = ERepositoryBuilder::build(builder.clone()).unwrap();
+        let numbers = [1, 2, 3, 10].map(|v| repo.version_number(&"lib", &v).unwrap());
+        assert_eq!(numbers, [3, 1, 2, 1]);
+        let req = repo.translate(&ERequirement::new("lib", ViaRangeBound(10..=10)));
+        assert_eq!(req.unwrap(), Requirement::single_version(0, 1));
+
+        let mut aliasing_version = builder.clone();
+        aliasing_version.add_alias("lib", 3, 2);
+        let mut aliasing_twice = builder.clone();
+        aliasing_twice.add_alias("lib", 10, 3);
+        let mut two_epochs = builder.clone();
+        two_epochs.set_epoch("lib", 1, 2);
+        for builder in [aliasing_version, aliasing_twice, two_epochs] {
+            let r = ERepositoryBuilder::build(builder);
+            assert!(matches!(
+                r,
+                Err(RepositoryBuildError::ConflictingVersion { .. })
+            ));
+        }
+        builder.add_alias("lib", 20, 4);
+        let Err(RepositoryBuildError::UnknownVersion { version, .. }) =
+            ERepositoryBuilder::build(builder)
+        else {
+            panic!("expected an unknown version")
+        };
+        assert_eq!(version, 4);
+    }
+
+    #[test]
+    fn test_incremental_builds() {
+        let package = |name, versions: &[u64], deps: &[&'static str]| {
+            let mut package = EPackageBuilder::new(name);
+            for &v in versions {
+                let mut version = EVersion::<&str, u64, Any>::new(v);
+                for &dep in deps {
+                    version.add_dependency(ERequirement::new(dep, ViaRangeBound(..)));
+                }
+                package.add_version(version);
+            }
+            package.build()
+        };
+        let mut builder = ERepositoryBuilder::new();
+        builder.add_package(package("app", &[1], &["lib"]));
+        builder.add_package(package("lib", &[1, 2], &[]));
+        builder.add_package(package("docs", &[1], &[]));
+        builder.add_alias("lib", 20, 2);
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+
+        let mut update = ERepositoryBuilder::new();
+        update.add_package(package("lib", &[1, 2, 3], &[]));
+        update.add_package(package("tool", &[1], &["app"]));
+        let mut builder = repo.clone().into_builder();
+        builder.merge(update);
+        let updated = ERepositoryBuilder::build(builder).unwrap();
+        println!("{:?}", updated.repository());
+
+        let spine = updated.repository();
+        assert_eq!(updated.package_id(&"tool"), Some(3));
+        assert_eq!(spine.packages[1].versions.len(), 3);
+        assert_eq!(
+            spine.packages[0].versions[0].requirements.dependencies,
+            vec![Requirement::any_version(1)]
+        );
+        assert_eq!(spine.packages[2], repo.repository().packages[2]);
+        assert_eq!(updated.version_number(&"lib", &20), Some(2));
+    }
+
+    #[test]
+    fn test_collected_errors() {
+        let mut builder =
+            ERepositoryBuilder::<&str, u64, ViaRangeBound<RangeInclusive<u64>>>::new();
+        let mut app = EPackageBuilder::new("app");
+        for v in [1, 2] {
+            let mut version = EVersion::new(v);
+            version.add_dependency(ERequirement::new("lib", ViaRangeBound(1..=1)));
+            version.add_dependency(ERequirement::new("lib", ViaRangeBound(5..=6)));
+            version.add_conflict(ERequirement::new("gone", ViaRangeBound(1..=1)));
+            app.add_version(version);
+        }
+        builder.add_package(app.build());
+        let mut lib = EPackageBuilder::new("lib");
+        lib.add_version(EVersion::new(1));
+        builder.add_package(lib.build());
+
+        let errors = ERepositoryBuilder::build_collecting(builder.clone()).unwrap_err();
+        for error in &errors {
+            println!("{error}");
+        }
+        assert_eq!(errors.len(), 4);
+        assert_eq!(
+            errors[0].to_string(),
+            "app 1: no version satisfies `lib >=5, <=6`"
+        );
+        assert_eq!(
+            ERequirement::new(
+                "lib",
+                Union(vec![ViaRangeBound(3..=3), ViaRangeBound(5..=6)])
+            )
+            .to_string(),
+            "`lib =3 | >=5, <=6`"
+        );
+        assert_eq!(errors[3].to_string(), "app 2: unknown package `gone`");
+        assert_eq!(ERepositoryBuilder::build(builder).unwrap_err(), errors[0]);
+    }
+
+    #[test]
+    fn test_unknown_packages() {
+        let build = |mode| {
+            let mut builder = ERepositoryBuilder::<&str, u64, Any>::new();
+            let mut app = EPackageBuilder::new("app");
+            let mut version = EVersion::<&str, u64, Any>::new(1);
+            version.add_dependency(ERequirement::new("lib", ViaRangeBound(..)));
+            version.add_dependency(ERequirement::new("gone", ViaRangeBound(..)));
+            app.add_version(version);
+            builder.add_package(app.build());
+            let mut lib = EPackageBuilder::new("lib");
+            lib.add_version(EVersion::new(1));
+            builder.add_package(lib.build());
+            builder.unknown_packages(mode);
+            ERepositoryBuilder::build(builder)
+        };
+        let solve = |repo: &ERepository<&str, u64, Any>| {
+            let app = repo.translate(&ERequirement::new("app", ViaRangeBound(..)));
+            simple_solve(repo.repository(), &RequirementSet::from_dep(app.unwrap())).unwrap()
+        };
+
+        assert!(matches!(
+            build(UnknownPackages::Reject),
+            Err(RepositoryBuildError::UnknownPackage { .. })
+        ));
+
+        let repo = build(UnknownPackages::Drop).unwrap();
+        assert_eq!(repo.missing_references(), vec![(&"app", &1, &"gone")]);
+        assert_eq!(repo.package_id(&"gone"), None);
+        assert!(solve(&repo).is_sat());
+
+        let repo = build(UnknownPackages::Unsatisfiable).unwrap();
+        assert_eq!(repo.package_id(&"gone"), Some(2));
+        assert!(repo.repository().is_masked(2, 1));
+        assert!(!solve(&repo).is_sat());
+
+        let repo = build(UnknownPackages::External).unwrap();
+        let r = solve(&repo);
+        println!("{r:?}");
+        assert!(r.typed_plan().unwrap().is_installed(2));
+    }
+
+    #[test]
+    fn test_range_matches() {
+        let map = (1..=5).map(|v| (v, ())).collect::<IndexMap<u64, ()>>();
+        let ranges = |set: fn(&u64) -> bool| {
+            let set = ViaFunPtr(set);
+            (set.to_ranges(&map), set.match_ranges(&map))
+        };
+
+        let (first, _) = ranges(|&v| v == 1);
+        assert_eq!(first, vec![Range::point(1)]);
+        let (last, _) = ranges(|&v| v == 5);
+        assert_eq!(last, vec![Range::point(5)]);
+        let (ends, matched) = ranges(|&v| v != 3);
+        assert_eq!(
+            ends,
+            vec![
+                Range::interval_unchecked(1, 2),
+                Range::interval_unchecked(4, 5)
+            ]
+        );
+        assert_eq!(
+            matched,
+            RangeMatch::Matched(vec1![
+                Range::interval_unchecked(1, 2),
+                Range::interval_unchecked(4, 5)
+            ])
+        );
+        assert_eq!(ranges(|&v| v % 2 == 1).0.len(), 3);
+        assert_eq!(ranges(|_| true).1, RangeMatch::All);
+        assert_eq!(ranges(|_| false), (vec![], RangeMatch::Empty));
+
+        let single = [(7, ())].into_iter().collect::<IndexMap<u64, ()>>();
+        assert_eq!(ViaRangeBound(7..).match_ranges(&single), RangeMatch::All);
+        assert_eq!(ViaRangeBound(..7).match_ranges(&single), RangeMatch::Empty);
+        assert_eq!(RangeMatch::All.into_ranges(), Some(vec1![Range::all()]));
+    }
+
+    #[test]
+    fn test_empty_requirements() {
+        let mut builder =
+            ERepositoryBuilder::<&str, u64, ViaRangeBound<RangeInclusive<u64>>>::new();
+        let mut app = EPackageBuilder::new("app");
+        for v in [1, 2] {
+            let mut version = EVersion::new(v);
+            version.add_dependency(ERequirement::new("lib", ViaRangeBound(v..=v)));
+            version.add_conflict(ERequirement::new("lib", ViaRangeBound(5..=6)));
+            app.add_version(version);
+        }
+        builder.add_package(app.build());
+        let mut lib = EPackageBuilder::new("lib");
+        lib.add_version(EVersion::new(1));
+        builder.add_package(lib.build());
+        assert!(ERepositoryBuilder::build(builder.clone()).is_err());
+
+        builder.warn_on_empty(true);
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+        let empty = repo.empty_requirements();
+        println!("{empty:?}");
+        assert_eq!(empty.len(), 3);
+        assert!(repo.repository().is_masked(0, 2));
+        assert!(!repo.repository().is_masked(0, 1));
+        let conflicts = &repo.repository().packages[0].versions[0]
+            .requirements
+            .conflicts;
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_id_map() {
+        let mut builder = ERepositoryBuilder::<&str, u64, Any>::new();
+        let mut app = EPackageBuilder::new("app");
+        let mut version = EVersion::<&str, u64, Any>::new(3);
+        version.add_dependency(ERequirement::new("gone", ViaRangeBound(..)));
+        app.add_version(version);
+        app.add_version(EVersion::new(1));
+        builder.add_package(app.build());
+        builder.add_alias("app", 30, 3);
+        builder.unknown_packages(UnknownPackages::External);
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+
+        let ids = repo.id_map();
+        println!("{ids:?}");
+        assert_eq!(ids.packages.len(), 2);
+        assert_eq!(ids.packages[0].versions, vec![1, 3]);
+        assert_eq!(ids.packages[0].aliases, vec![(30, 2)]);
+        assert_eq!((ids.name(1), ids.version(1, 1)), (Some(&"gone"), None));
+        assert!(ids.packages[1].placeholder);
+        assert_eq!(ids.version(0, 2), Some(&3));
+    }
 }
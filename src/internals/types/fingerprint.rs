@@ -0,0 +1,234 @@
+// Content-addressed fingerprints, stable across platforms, compiler versions and runs,
+// so they can be stored in lockfiles or shared between machines as cache keys. The order
+// of the requirements of a version and of the ranges of a requirement does not change
+// the fingerprint, neither do duplicate requirements, while the order of the packages and
+// of their versions does since it determines the ids and version numbers
+use crate::internals::types::*;
+
+// 64 bit FNV-1a over little endian words
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StableHasher(u64);
+
+impl StableHasher {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub(crate) fn write_u64(&mut self, x: u64) {
+        for byte in x.to_le_bytes() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(crate) fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+fn hash_requirements<'a>(hasher: &mut StableHasher, reqs: impl Iterator<Item = &'a Requirement>) {
+    let mut hashes = reqs.map(Requirement::fingerprint).collect::<Vec<_>>();
+    hashes.sort_unstable();
+    hashes.dedup();
+    hasher.write_u64(hashes.len() as u64);
+    for hash in hashes {
+        hasher.write_u64(hash);
+    }
+}
+
+impl Requirement {
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.package as u64);
+        for range in RangeSet::from(&self.versions).iter() {
+            let (lower, upper) = match range {
+                Range::Interval { lower, upper } => (lower, upper),
+                Range::Point(v) => (v, v),
+                Range::All => (1, Version::MAX),
+            };
+            hasher.write_u64(lower);
+            hasher.write_u64(upper);
+        }
+        hasher.finish()
+    }
+}
+
+impl RequirementSet {
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        hash_requirements(&mut hasher, self.dependencies.iter());
+        hash_requirements(&mut hasher, self.conflicts.iter());
+        // sets without a formula keep the fingerprints they had before formulas existed
+        if let Some(formula) = &self.formula {
+            hasher.write_u64(formula.fingerprint());
+        }
+        hasher.finish()
+    }
+}
+
+impl ReqExpr {
+    // Unlike the requirements of a set, the operands of a formula are hashed in order
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        match self {
+            ReqExpr::Req(req) => {
+                hasher.write_u64(0);
+                hasher.write_u64(req.fingerprint());
+            }
+            ReqExpr::Not(expr) => {
+                hasher.write_u64(1);
+                hasher.write_u64(expr.fingerprint());
+            }
+            ReqExpr::And(exprs) | ReqExpr::Or(exprs) => {
+                hasher.write_u64(if matches!(self, ReqExpr::And(_)) {
+                    2
+                } else {
+                    3
+                });
+                hasher.write_u64(exprs.len() as u64);
+                for expr in exprs {
+                    hasher.write_u64(expr.fingerprint());
+                }
+            }
+        }
+        hasher.finish()
+    }
+}
+
+impl Package {
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::new();
+        hasher.write_u64(self.id as u64);
+        hasher.write_u64(self.versions.len() as u64);
+        for ver in &self.versions {
+            hasher.write_u64(ver.requirements.fingerprint());
+        }
+        hasher.finish()
+    }
+}
+
+fn hash_ids(hasher: &mut StableHasher, ids: &[PackageId]) {
+    let mut ids = ids.to_vec();
+    ids.sort_unstable();
+    ids.dedup();
+    hasher.write_u64(ids.len() as u64);
+    for pid in ids {
+        hasher.write_u64(pid as u64);
+    }
+}
+
+// The fingerprint of a repository from the fingerprints of its packages
+pub(crate) fn combine_fingerprints(
+    packages: impl Iterator<Item = u64>,
+    masks: &[Requirement],
+    essential: &[PackageId],
+    preferences: &[(PackageId, Vec<Version>)],
+    meta_packages: &[PackageId],
+) -> u64 {
+    let mut hasher = StableHasher::new();
+    let mut count = 0;
+    for package in packages {
+        hasher.write_u64(package);
+        count += 1;
+    }
+    hasher.write_u64(count);
+    hash_requirements(&mut hasher, masks.iter());
+    // repositories without essential packages keep the fingerprints they had before
+    if !essential.is_empty() {
+        hash_ids(&mut hasher, essential);
+    }
+    // so do repositories without preferences and meta-packages, each is tagged so that
+    // one is not mistaken for the other
+    if !preferences.is_empty() {
+        let mut preferences = preferences.iter().collect::<Vec<_>>();
+        preferences.sort_unstable_by_key(|(pid, _)| *pid);
+        hasher.write_u64(0);
+        hasher.write_u64(preferences.len() as u64);
+        for (pid, versions) in preferences {
+            hasher.write_u64(*pid as u64);
+            hasher.write_u64(versions.len() as u64);
+            for &v in versions {
+                hasher.write_u64(v);
+            }
+        }
+    }
+    if !meta_packages.is_empty() {
+        hasher.write_u64(1);
+        hash_ids(&mut hasher, meta_packages);
+    }
+    hasher.finish()
+}
+
+impl Repository {
+    // A hash over the packages, the masked versions, the essential packages, the
+    // preferred versions and the meta-packages, all of which change the plans found.
+    // Computed once and kept until one of them is edited
+    pub fn fingerprint(&self) -> u64 {
+        *self.fingerprint.get_or_init(|| {
+            combine_fingerprints(
+                self.packages.iter().map(Package::fingerprint),
+                self.masks(),
+                &self.essential_packages,
+                self.preferences(),
+                self.meta_packages(),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{
+        vec1, Package, PackageVer, Range, Repository, Requirement, RequirementSet,
+    };
+
+    #[test]
+    fn test_fingerprint() {
+        let package = |reqs: RequirementSet| Package {
+            id: 0,
+            versions: vec![PackageVer { requirements: reqs }],
+        };
+        let reqs = RequirementSet::from_deps(vec![
+            Requirement::any_version(1),
+            Requirement::new(2, vec1![Range::point(3), Range::interval_unchecked(1, 2)]),
+        ]);
+        // the same requirements in another order, with the ranges merged
+        let reordered = RequirementSet::from_deps(vec![
+            Requirement::new(2, vec1![Range::interval_unchecked(1, 3)]),
+            Requirement::any_version(1),
+            Requirement::any_version(1),
+        ]);
+        let repo = Repository::new(vec![package(reqs.clone())]);
+        println!("{:x}", repo.fingerprint());
+        assert_eq!(reqs.fingerprint(), reordered.fingerprint());
+        assert_eq!(
+            repo.fingerprint(),
+            Repository::new(vec![package(reordered)]).fingerprint()
+        );
+
+        // dependencies and conflicts are told apart
+        let conflicts = RequirementSet::from_antideps(reqs.dependencies.clone());
+        assert_ne!(reqs.fingerprint(), conflicts.fingerprint());
+
+        let mut masked = repo.clone();
+        masked.mask_version(0, 1);
+        assert_ne!(repo.fingerprint(), masked.fingerprint());
+
+        let mut preferring = repo.clone();
+        preferring.prefer(0, vec![1]);
+        assert_ne!(repo.fingerprint(), preferring.fingerprint());
+        let mut meta = repo.clone();
+        meta.mark_meta_package(0);
+        assert_ne!(repo.fingerprint(), meta.fingerprint());
+        assert_ne!(preferring.fingerprint(), meta.fingerprint());
+
+        // the fingerprint kept is dropped by edits of the packages
+        let mut edited = repo.clone();
+        edited.packages_mut()[0].versions.clear();
+        assert_ne!(repo.fingerprint(), edited.fingerprint());
+        assert_eq!(
+            edited.fingerprint(),
+            Repository::new(edited.packages.to_vec()).fingerprint()
+        );
+    }
+}
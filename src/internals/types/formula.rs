@@ -0,0 +1,325 @@
+// Requirements combined by arbitrary boolean formulas, like the dependency formulas of
+// opam, e.g. `pkg1 ∧ (pkg2 in [1, 3] ∨ ¬pkg4)`. A formula is encoded into a single `Expr`
+// whose atoms are the encodings of its requirements, so it is translated to z3 like any
+// other constraint and can be read back from the expressions of an unsat core
+use std::fmt::{self, Display, Formatter};
+
+use bumpalo::Bump;
+use rkyv::{Archive, Deserialize, Serialize};
+
+use crate::internals::{constraints::AsConstraints, types::*};
+
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(bound(serialize = "__S: rkyv::ser::ScratchSpace + rkyv::ser::Serializer"))]
+#[archive(check_bytes)]
+#[archive_attr(check_bytes(
+    bound = "__C: rkyv::validation::ArchiveContext, <__C as rkyv::Fallible>::Error: std::error::Error"
+))]
+pub enum ReqExpr {
+    Req(Requirement),
+    Not(#[omit_bounds] Box<ReqExpr>),
+    // the empty conjunction always holds
+    And(#[omit_bounds] Vec<ReqExpr>),
+    // the empty disjunction never holds
+    Or(#[omit_bounds] Vec<ReqExpr>),
+}
+
+impl From<Requirement> for ReqExpr {
+    fn from(req: Requirement) -> Self {
+        ReqExpr::Req(req)
+    }
+}
+
+impl ReqExpr {
+    pub fn req(req: Requirement) -> Self {
+        ReqExpr::Req(req)
+    }
+
+    pub fn not(expr: ReqExpr) -> Self {
+        match expr {
+            ReqExpr::Not(inner) => *inner,
+            _ => ReqExpr::Not(Box::new(expr)),
+        }
+    }
+
+    // Nested conjunctions are flattened, a single operand is returned as is
+    pub fn and(exprs: impl IntoIterator<Item = ReqExpr>) -> Self {
+        let mut operands = Vec::new();
+        for expr in exprs {
+            match expr {
+                ReqExpr::And(inner) => operands.extend(inner),
+                _ => operands.push(expr),
+            }
+        }
+        if operands.len() == 1 {
+            operands.pop().unwrap()
+        } else {
+            ReqExpr::And(operands)
+        }
+    }
+
+    // The same for disjunctions
+    pub fn or(exprs: impl IntoIterator<Item = ReqExpr>) -> Self {
+        let mut operands = Vec::new();
+        for expr in exprs {
+            match expr {
+                ReqExpr::Or(inner) => operands.extend(inner),
+                _ => operands.push(expr),
+            }
+        }
+        if operands.len() == 1 {
+            operands.pop().unwrap()
+        } else {
+            ReqExpr::Or(operands)
+        }
+    }
+
+    // The requirements of the formula, from left to right
+    pub fn requirements(&self) -> Vec<&Requirement> {
+        match self {
+            ReqExpr::Req(req) => vec![req],
+            ReqExpr::Not(expr) => expr.requirements(),
+            ReqExpr::And(exprs) | ReqExpr::Or(exprs) => {
+                exprs.iter().flat_map(ReqExpr::requirements).collect()
+            }
+        }
+    }
+
+    pub fn into_requirements(self) -> Vec<Requirement> {
+        match self {
+            ReqExpr::Req(req) => vec![req],
+            ReqExpr::Not(expr) => expr.into_requirements(),
+            ReqExpr::And(exprs) | ReqExpr::Or(exprs) => exprs
+                .into_iter()
+                .flat_map(ReqExpr::into_requirements)
+                .collect(),
+        }
+    }
+
+    // The formula with every requirement replaced, None as soon as a requirement is
+    // replaced by None
+    pub fn map_requirements(
+        &self,
+        f: &mut impl FnMut(&Requirement) -> Option<Requirement>,
+    ) -> Option<ReqExpr> {
+        Some(match self {
+            ReqExpr::Req(req) => ReqExpr::Req(f(req)?),
+            ReqExpr::Not(expr) => ReqExpr::Not(Box::new(expr.map_requirements(f)?)),
+            ReqExpr::And(exprs) => ReqExpr::And(
+                exprs
+                    .iter()
+                    .map(|expr| expr.map_requirements(f))
+                    .collect::<Option<_>>()?,
+            ),
+            ReqExpr::Or(exprs) => ReqExpr::Or(
+                exprs
+                    .iter()
+                    .map(|expr| expr.map_requirements(f))
+                    .collect::<Option<_>>()?,
+            ),
+        })
+    }
+
+    pub fn to_expr<'b>(&self, b: &'b Bump) -> Expr<'b> {
+        match self {
+            ReqExpr::Req(req) => {
+                // a requirement generates exactly one constraint
+                let mut expr = Expr::top();
+                req.add_symbolic_constraints(b, |sym_expr| expr = sym_expr);
+                expr
+            }
+            ReqExpr::Not(expr) => Expr::not(b, expr.to_expr(b)),
+            ReqExpr::And(exprs) => exprs
+                .iter()
+                .map(|expr| expr.to_expr(b))
+                .reduce(|l, r| Expr::and(b, l, r))
+                .unwrap_or(Expr::Top),
+            ReqExpr::Or(exprs) => exprs
+                .iter()
+                .map(|expr| expr.to_expr(b))
+                .reduce(|l, r| Expr::or(b, l, r))
+                .unwrap_or(Expr::Bot),
+        }
+    }
+
+    // Read a formula back from its encoding, None if the expression is not the encoding of
+    // a formula. The ranges of the requirements may come back merged or regrouped
+    pub fn from_expr(expr: &Expr<'_>) -> Option<ReqExpr> {
+        if let Some(req) = requirement_of(expr) {
+            return Some(ReqExpr::Req(req));
+        }
+        Some(match expr {
+            Expr::Atom(AtomicExpr::VerEq { pid, version: 0 }) => {
+                ReqExpr::not(ReqExpr::Req(Requirement::any_version(*pid)))
+            }
+            Expr::Atom(_) => return None,
+            Expr::Not(e) => ReqExpr::not(Self::from_expr(e)?),
+            Expr::And(l, r) => ReqExpr::and([Self::from_expr(l)?, Self::from_expr(r)?]),
+            Expr::Or(l, r) => ReqExpr::or([Self::from_expr(l)?, Self::from_expr(r)?]),
+            Expr::Implies(l, r) => {
+                ReqExpr::or([ReqExpr::not(Self::from_expr(l)?), Self::from_expr(r)?])
+            }
+            Expr::Top => ReqExpr::And(Vec::new()),
+            Expr::Bot => ReqExpr::Or(Vec::new()),
+        })
+    }
+}
+
+// The requirement encoded by an expression, None if the expression does not encode a
+// single requirement
+pub(crate) fn requirement_of(expr: &Expr<'_>) -> Option<Requirement> {
+    fn ranges(expr: &Expr<'_>, acc: &mut Vec<Range>) -> Option<PackageId> {
+        match expr {
+            Expr::Atom(AtomicExpr::VerEq { pid, version }) if *version != 0 => {
+                acc.push(Range::point(*version));
+                Some(*pid)
+            }
+            Expr::And(
+                Expr::Atom(AtomicExpr::VerGE {
+                    pid,
+                    version: lower,
+                }),
+                Expr::Atom(AtomicExpr::VerLE {
+                    pid: pid2,
+                    version: upper,
+                }),
+            ) if pid == pid2 => {
+                acc.push(Range::interval(*lower, *upper)?);
+                Some(*pid)
+            }
+            Expr::Or(l, r) => {
+                let pid = ranges(l, acc)?;
+                (ranges(r, acc)? == pid).then_some(pid)
+            }
+            _ => None,
+        }
+    }
+
+    if let Expr::Not(Expr::Atom(AtomicExpr::VerEq { pid, version: 0 })) = expr {
+        return Some(Requirement::any_version(*pid));
+    }
+    let mut acc = Vec::new();
+    let pid = ranges(expr, &mut acc)?;
+    Some(Requirement::new(pid, Vec1::try_from(acc).ok()?))
+}
+
+// The requirement a conflict is encoded from, the encoding being the negation of the
+// encoding of the requirement
+pub(crate) fn conflict_of(expr: &Expr<'_>) -> Option<Requirement> {
+    match expr {
+        Expr::Atom(AtomicExpr::VerEq { pid, version: 0 }) => Some(Requirement::any_version(*pid)),
+        Expr::Not(e) => requirement_of(e),
+        _ => None,
+    }
+}
+
+impl ReqExpr {
+    fn fmt_operand(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReqExpr::And(exprs) | ReqExpr::Or(exprs) if exprs.len() > 1 => write!(f, "({self})"),
+            _ => write!(f, "{self}"),
+        }
+    }
+}
+
+impl Display for ReqExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let (exprs, op, empty) = match self {
+            ReqExpr::Req(req) => return write!(f, "{req}"),
+            ReqExpr::Not(expr) => {
+                write!(f, "¬")?;
+                return expr.fmt_operand(f);
+            }
+            ReqExpr::And(exprs) => (exprs, " ∧ ", "⊤"),
+            ReqExpr::Or(exprs) => (exprs, " ∨ ", "⊥"),
+        };
+        if exprs.is_empty() {
+            return write!(f, "{empty}");
+        }
+        for (i, expr) in exprs.iter().enumerate() {
+            if i != 0 {
+                write!(f, "{op}")?;
+            }
+            expr.fmt_operand(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bumpalo::Bump;
+
+    use crate::internals::{
+        solver::simple_solve,
+        types::{
+            vec1, Package, PackageVer, Range, Repository, Requirement, RequirementSet,
+            ResolutionResult,
+        },
+    };
+
+    use super::ReqExpr;
+
+    #[test]
+    fn test_formula_round_trip() {
+        let b = Bump::new();
+        let formula = ReqExpr::and([
+            ReqExpr::req(Requirement::any_version(1)),
+            ReqExpr::or([
+                ReqExpr::req(Requirement::new(
+                    2,
+                    vec1![Range::interval_unchecked(1, 3), Range::point(5)],
+                )),
+                ReqExpr::not(ReqExpr::req(Requirement::any_version(4))),
+            ]),
+        ]);
+        println!("{formula}");
+        assert_eq!(formula.to_string(), "pkg1 ∧ (pkg2 in [1, 3] | {5} ∨ ¬pkg4)");
+        assert_eq!(
+            formula
+                .requirements()
+                .iter()
+                .map(|req| req.package)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 4]
+        );
+
+        let expr = formula.to_expr(&b);
+        let decoded = ReqExpr::from_expr(&expr).unwrap();
+        assert_eq!(decoded.to_expr(&b), expr);
+        assert_eq!(ReqExpr::and([]).to_string(), "⊤");
+    }
+
+    #[test]
+    fn test_formula_requirements() {
+        let repo = Repository::new(
+            (0..3)
+                .map(|id| Package {
+                    id,
+                    versions: vec![PackageVer {
+                        requirements: Default::default(),
+                    }],
+                })
+                .collect(),
+        );
+        let pkg = |pid| ReqExpr::req(Requirement::any_version(pid));
+        // either 0 or 1 but not both, and 2 only along with 1
+        let mut reqs = RequirementSet::from_formula(ReqExpr::and([
+            ReqExpr::or([pkg(0), pkg(1)]),
+            ReqExpr::not(ReqExpr::and([pkg(0), pkg(1)])),
+        ]));
+        reqs.add_dep(Requirement::any_version(2));
+        reqs.add_formula(ReqExpr::or([ReqExpr::not(pkg(2)), pkg(1)]));
+        let r = simple_solve(&repo, &reqs).unwrap();
+        println!("{r:?}");
+        let plan = r.typed_plan().unwrap();
+        assert!(!plan.is_installed(0) && plan.is_installed(1));
+
+        reqs.add_antidep(Requirement::any_version(1));
+        let ResolutionResult::UnsatWithCore { core } = simple_solve(&repo, &reqs).unwrap() else {
+            panic!("expected an unsat core")
+        };
+        println!("{core:?}");
+        assert!(core.toplevel_reqs.formula.is_some());
+    }
+}
@@ -0,0 +1,395 @@
+// JSON rendering of results, for tools wrapping the library. The schema is stable:
+//
+//   result      = { "status": "sat", "optimal": bool, "plans": [plan],
+//                   "objective": objective | null }
+//               | { "status": "unsat", "core": core | null }
+//   pareto      = { "status": "sat", "frontier": [{ "plan": plan, "values": [int] }] }
+//               | result of the unsatisfiable resolution
+//   plan        = [{ "package": int, "version": int }], sorted by package, only
+//                 listing installed packages
+//   objective   = { "distance_from_newest": int, "installed": int }, only reported by
+//                 the optimizers
+//   core        = { "packages": [{ "package": int, "version": int,
+//                                  "requirements": requirements }],
+//                   "toplevel": requirements, "toplevel_indices": [int],
+//                   "restrictions": [string] }
+//   requirements = { "dependencies": [requirement], "conflicts": [requirement],
+//                    "formula": string | null }, the formula in the syntax of its Display
+//   requirement = { "package": int, "versions": [{ "lower": int, "upper": int | null }] }
+//   id_map      = { "packages": [{ "name": string, "id": int, "versions": [string],
+//                                  "aliases": [{ "alias": string, "version": int }],
+//                                  "placeholder": bool }] }, version n of a package
+//                 being at index n - 1 of its versions
+//
+//   repository  = { "packages": [[requirements]], "masks": [requirement],
+//                   "preferences": [{ "package": int, "versions": [int] }],
+//                   "meta_packages": [int], "essential": [int] }, the requirements of
+//                 version n of package i being the set at index n - 1 of the i-th list
+//
+// where an upper bound of null means every version from the lower bound on, and only
+// goes with a lower bound of 1. Repositories are written by `Repository::to_json` and
+// read back by `Repository::from_json`, which also takes requirement sets and masks as
+// strings in the syntax of `RequirementSet` and `Requirement`, and leaves out missing
+// fields but the packages
+use std::fmt::Display;
+
+use serde_json::{json, Value};
+
+use crate::internals::types::{extended::IdMap, *};
+
+fn range_to_json(range: &Range) -> Value {
+    match range {
+        Range::Interval { lower, upper } => json!({ "lower": lower, "upper": upper }),
+        Range::Point(v) => json!({ "lower": v, "upper": v }),
+        Range::All => json!({ "lower": 1, "upper": null }),
+    }
+}
+
+fn requirement_to_json(req: &Requirement) -> Value {
+    json!({
+        "package": req.package,
+        "versions": req.versions.iter().map(range_to_json).collect::<Vec<_>>(),
+    })
+}
+
+fn range_from_json(value: &Value) -> Result<Range, ResolutionError> {
+    let lower = uint_of(&value["lower"], "a lower bound")?;
+    match &value["upper"] {
+        Value::Null if lower == 1 => Ok(Range::All),
+        Value::Null => Err(invalid("expected an upper bound")),
+        upper => Range::interval(lower, uint_of(upper, "an upper bound")?)
+            .ok_or_else(|| invalid(format!("empty range [{lower}, {upper}]"))),
+    }
+}
+
+fn requirement_from_json(value: &Value) -> Result<Requirement, ResolutionError> {
+    if let Value::String(req) = value {
+        return req.parse();
+    }
+    let package = uint_of(&value["package"], "a package")? as PackageId;
+    let mut versions = list_of(&value["versions"], "versions")?
+        .iter()
+        .map(range_from_json);
+    let first = versions
+        .next()
+        .ok_or_else(|| invalid(format!("no versions of package {package}")))??;
+    let mut ranges = Vec1::new(first);
+    for range in versions {
+        ranges.push(range?);
+    }
+    Ok(Requirement::new(package, ranges))
+}
+
+fn requirements_from_json(value: &Value) -> Result<RequirementSet, ResolutionError> {
+    if let Value::String(reqs) = value {
+        return reqs.parse();
+    }
+    let list = |key: &str| {
+        list_of(&value[key], key)?
+            .iter()
+            .map(requirement_from_json)
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok(RequirementSet {
+        dependencies: list("dependencies")?,
+        conflicts: list("conflicts")?,
+        formula: match &value["formula"] {
+            Value::Null => None,
+            formula => Some(string_of(formula, "a formula")?.parse()?),
+        },
+    })
+}
+
+fn plan_to_json(plan: &Plan) -> Value {
+    let mut plan = plan.installed();
+    plan.sort_unstable();
+    Value::Array(
+        plan.into_iter()
+            .map(|(pid, v)| json!({ "package": pid, "version": v }))
+            .collect(),
+    )
+}
+
+fn invalid(message: impl Into<String>) -> ResolutionError {
+    InvalidInputSnafu {
+        message: message.into(),
+    }
+    .build()
+}
+
+fn string_of(value: &Value, what: &str) -> Result<&str, ResolutionError> {
+    value
+        .as_str()
+        .ok_or_else(|| invalid(format!("expected {what} as a string")))
+}
+
+fn uint_of(value: &Value, what: &str) -> Result<u64, ResolutionError> {
+    value
+        .as_u64()
+        .ok_or_else(|| invalid(format!("expected {what} as an integer")))
+}
+
+// a missing list is an empty one
+fn list_of<'v>(value: &'v Value, what: &str) -> Result<&'v [Value], ResolutionError> {
+    match value {
+        Value::Null => Ok(&[]),
+        Value::Array(values) => Ok(values),
+        _ => Err(invalid(format!("expected a list of `{what}`"))),
+    }
+}
+
+impl Repository {
+    pub fn from_json(value: &Value) -> Result<Self, ResolutionError> {
+        let packages = value["packages"]
+            .as_array()
+            .ok_or_else(|| invalid("expected a list of `packages`"))?
+            .iter()
+            .enumerate()
+            .map(|(id, versions)| {
+                let versions = versions
+                    .as_array()
+                    .ok_or_else(|| invalid(format!("expected the versions of package {id}")))?
+                    .iter()
+                    .map(|reqs| {
+                        Ok(PackageVer {
+                            requirements: requirements_from_json(reqs)?,
+                        })
+                    })
+                    .collect::<Result<_, ResolutionError>>()?;
+                Ok(Package {
+                    id: id as PackageId,
+                    versions,
+                })
+            })
+            .collect::<Result<Vec<_>, ResolutionError>>()?;
+        let mut repo = Repository::new(packages);
+        for mask in list_of(&value["masks"], "masks")? {
+            let mask = requirement_from_json(mask)?;
+            for range in mask.versions.iter() {
+                repo.mask_range(mask.package, range.clone());
+            }
+        }
+        for preference in list_of(&value["preferences"], "preferences")? {
+            let versions = list_of(&preference["versions"], "versions")?
+                .iter()
+                .map(|v| uint_of(v, "a version"))
+                .collect::<Result<_, _>>()?;
+            repo.prefer(
+                uint_of(&preference["package"], "a package")? as PackageId,
+                versions,
+            );
+        }
+        for id in list_of(&value["meta_packages"], "meta_packages")? {
+            repo.mark_meta_package(uint_of(id, "a package")? as PackageId);
+        }
+        for id in list_of(&value["essential"], "essential")? {
+            repo.mark_essential(uint_of(id, "a package")? as PackageId);
+        }
+        Ok(repo)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let packages = self.packages.iter().map(|package| {
+            package
+                .versions
+                .iter()
+                .map(|ver| ver.requirements.to_json())
+                .collect::<Vec<_>>()
+        });
+        let preferences = self
+            .preferences()
+            .iter()
+            .map(|(id, versions)| json!({ "package": id, "versions": versions }));
+        json!({
+            "packages": packages.collect::<Vec<_>>(),
+            "masks": self.masks().iter().map(requirement_to_json).collect::<Vec<_>>(),
+            "preferences": preferences.collect::<Vec<_>>(),
+            "meta_packages": self.meta_packages(),
+            "essential": self.essential_packages(),
+        })
+    }
+}
+
+impl RequirementSet {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "dependencies": self.dependencies.iter().map(requirement_to_json).collect::<Vec<_>>(),
+            "conflicts": self.conflicts.iter().map(requirement_to_json).collect::<Vec<_>>(),
+            "formula": self.formula.as_ref().map(ToString::to_string),
+        })
+    }
+}
+
+impl ConstraintSet {
+    pub fn to_json(&self) -> Value {
+        let mut packages = Vec::new();
+        for (pid, versions) in self.package_reqs.iter() {
+            for (v, reqs) in versions.iter() {
+                packages.push((pid, v, reqs));
+            }
+        }
+        json!({
+            "packages": packages
+                .into_iter()
+                .map(|(pid, v, reqs)| {
+                    json!({ "package": pid, "version": v, "requirements": reqs.to_json() })
+                })
+                .collect::<Vec<_>>(),
+            "toplevel": self.toplevel_reqs.to_json(),
+            "toplevel_indices": self.toplevel_indices,
+            "restrictions": self.restrictions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl ResolutionResult {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Sat {
+                plans,
+                objective,
+                optimal,
+            } => json!({
+                "status": "sat",
+                "optimal": optimal,
+                "plans": plans.iter().map(plan_to_json).collect::<Vec<_>>(),
+                "objective": objective.map(|objective| json!({
+                    "distance_from_newest": objective.distance_from_newest,
+                    "installed": objective.installed,
+                })),
+            }),
+            Self::Unsat => json!({ "status": "unsat", "core": null }),
+            Self::UnsatWithCore { core } => json!({ "status": "unsat", "core": core.to_json() }),
+        }
+    }
+}
+
+impl ParetoResult {
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::Unsat(result) => result.to_json(),
+            Self::Frontier(points) => json!({
+                "status": "sat",
+                "frontier": points
+                    .as_vec()
+                    .iter()
+                    .map(|point| {
+                        json!({ "plan": plan_to_json(&point.plan), "values": point.values })
+                    })
+                    .collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+impl<K: Display, V: Display> IdMap<K, V> {
+    pub fn to_json(&self) -> Value {
+        let alias = |(alias, version): &(V, Version)| {
+            let alias = alias.to_string();
+            json!({ "alias": alias, "version": version })
+        };
+        let packages = self.packages.iter().map(|package| {
+            json!({
+                "name": package.name.to_string(),
+                "id": package.id,
+                "versions": package.versions.iter().map(ToString::to_string).collect::<Vec<_>>(),
+                "aliases": package.aliases.iter().map(alias).collect::<Vec<_>>(),
+                "placeholder": package.placeholder,
+            })
+        });
+        json!({ "packages": packages.collect::<Vec<_>>() })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::internals::{
+        solver::simple_solve,
+        types::{
+            extended::{IdMap, PackageIds},
+            Package, PackageVer, Repository, Requirement, RequirementSet,
+        },
+    };
+
+    #[test]
+    fn test_json() {
+        let repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_dep(Requirement::any_version(1)),
+                }],
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: Default::default(),
+                }],
+            },
+        ]);
+        let reqs = RequirementSet::from_dep(Requirement::any_version(0));
+        let r = simple_solve(&repo, &reqs).unwrap();
+        println!("{}", r.to_json());
+        assert_eq!(
+            r.to_json(),
+            json!({
+                "status": "sat",
+                "plans": [[{ "package": 0, "version": 1 }, { "package": 1, "version": 1 }]],
+                "objective": null,
+            })
+        );
+
+        let reqs = RequirementSet {
+            dependencies: vec![Requirement::any_version(1)],
+            conflicts: vec![Requirement::any_version(1)],
+            formula: None,
+        };
+        let r = simple_solve(&repo, &reqs).unwrap();
+        let json = r.to_json();
+        println!("{json}");
+        assert_eq!(json["status"], "unsat");
+        assert_eq!(
+            json["core"]["toplevel"]["dependencies"][0],
+            json!({ "package": 1, "versions": [{ "lower": 1, "upper": null }] })
+        );
+
+        let parsed = Repository::from_json(&json!({ "packages": [["pkg1"], [""]] })).unwrap();
+        assert_eq!(parsed.fingerprint(), repo.fingerprint());
+        assert!(Repository::from_json(&json!({ "packages": [["pkg1 in"]] })).is_err());
+
+        // what is written is read back
+        let mut repo = Repository::from_json(&json!({
+            "packages": [["pkg1; !pkg2 in [2, 3]", "(pkg1 ∨ ¬pkg2)"], ["", ""], ["", "", ""]],
+            "masks": ["pkg2 in {1}"],
+        }))
+        .unwrap();
+        repo.prefer(1, vec![1, 2]);
+        repo.mark_meta_package(0);
+        repo.mark_essential(2);
+        let json = repo.to_json();
+        println!("{json}");
+        assert_eq!(Repository::from_json(&json).unwrap(), repo);
+    }
+
+    #[test]
+    fn test_id_map_json() {
+        let ids = IdMap {
+            packages: vec![PackageIds {
+                name: "app",
+                id: 0,
+                versions: vec!["1.0", "2.0"],
+                aliases: vec![("2", 2)],
+                placeholder: false,
+            }],
+        };
+        let json = ids.to_json();
+        println!("{json}");
+        assert_eq!(json["packages"][0]["versions"], json!(["1.0", "2.0"]));
+        assert_eq!(
+            json["packages"][0]["aliases"],
+            json!([{ "alias": "2", "version": 2 }])
+        );
+    }
+}
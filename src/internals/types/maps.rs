@@ -0,0 +1,190 @@
+// Maps keyed by package ids and by version numbers. They wrap `IntMap` so that the public
+// API does not depend on a particular version of intmap, and iterate in the order of
+// their keys so that results and renderings are deterministic
+use intmap::IntMap;
+
+use crate::internals::types::{PackageId, Version};
+
+macro_rules! id_map {
+    ($name:ident, $key:ty) => {
+        // the keys are kept sorted next to the map, so that iterating needs no sorting
+        #[derive(Eq, PartialEq, Debug, Clone)]
+        pub struct $name<T> {
+            map: IntMap<T>,
+            keys: Vec<$key>,
+        }
+
+        impl<T> $name<T> {
+            pub fn new() -> Self {
+                Self {
+                    map: IntMap::new(),
+                    keys: Vec::new(),
+                }
+            }
+
+            // conversions from the keys to the keys of the `IntMap`, the identity for some
+            #[allow(clippy::unnecessary_cast)]
+            fn raw(key: $key) -> u64 {
+                key as u64
+            }
+
+            pub fn len(&self) -> usize {
+                self.keys.len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.keys.is_empty()
+            }
+
+            pub fn get(&self, key: $key) -> Option<&T> {
+                self.map.get(Self::raw(key))
+            }
+
+            pub fn get_mut(&mut self, key: $key) -> Option<&mut T> {
+                self.map.get_mut(Self::raw(key))
+            }
+
+            pub fn contains_key(&self, key: $key) -> bool {
+                self.map.contains_key(Self::raw(key))
+            }
+
+            // Returns the value previously stored under the key
+            pub fn insert(&mut self, key: $key, value: T) -> Option<T> {
+                let previous = self.map.insert(Self::raw(key), value);
+                if previous.is_none() {
+                    // keys are usually inserted in increasing order
+                    match self.keys.last() {
+                        Some(&last) if last > key => {
+                            let i = self.keys.partition_point(|&k| k < key);
+                            self.keys.insert(i, key);
+                        }
+                        _ => self.keys.push(key),
+                    }
+                }
+                previous
+            }
+
+            pub fn remove(&mut self, key: $key) -> Option<T> {
+                let value = self.map.remove(Self::raw(key))?;
+                let i = self.keys.partition_point(|&k| k < key);
+                self.keys.remove(i);
+                Some(value)
+            }
+
+            pub fn get_or_insert_with(&mut self, key: $key, f: impl FnOnce() -> T) -> &mut T {
+                if !self.contains_key(key) {
+                    self.insert(key, f());
+                }
+                self.map
+                    .get_mut(Self::raw(key))
+                    .expect("Impossible: entry was just inserted")
+            }
+
+            // The keys in increasing order
+            pub fn keys(&self) -> Vec<$key> {
+                self.keys.clone()
+            }
+
+            // The entries in increasing order of their keys
+            pub fn iter(&self) -> impl Iterator<Item = ($key, &T)> + '_ {
+                self.keys.iter().map(|&k| {
+                    let v = self
+                        .map
+                        .get(Self::raw(k))
+                        .expect("Impossible: every key is in the map");
+                    (k, v)
+                })
+            }
+
+            pub fn values(&self) -> impl Iterator<Item = &T> + '_ {
+                self.iter().map(|(_, v)| v)
+            }
+        }
+
+        impl<T> Default for $name<T> {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<T> IntoIterator for $name<T> {
+            type Item = ($key, T);
+            type IntoIter = std::vec::IntoIter<($key, T)>;
+
+            // in increasing order of the keys
+            fn into_iter(mut self) -> Self::IntoIter {
+                self.keys
+                    .iter()
+                    .map(|&k| {
+                        let v = self
+                            .map
+                            .remove(Self::raw(k))
+                            .expect("Impossible: every key is in the map");
+                        (k, v)
+                    })
+                    .collect::<Vec<_>>()
+                    .into_iter()
+            }
+        }
+
+        impl<T> FromIterator<($key, T)> for $name<T> {
+            fn from_iter<I: IntoIterator<Item = ($key, T)>>(iter: I) -> Self {
+                let mut map = Self::new();
+                map.extend(iter);
+                map
+            }
+        }
+
+        impl<T> Extend<($key, T)> for $name<T> {
+            fn extend<I: IntoIterator<Item = ($key, T)>>(&mut self, iter: I) {
+                for (k, v) in iter {
+                    self.insert(k, v);
+                }
+            }
+        }
+
+        // serialized as a map from the keys to the values, in increasing order
+        #[cfg(feature = "serde")]
+        impl<T: serde::Serialize> serde::Serialize for $name<T> {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_map(self.iter())
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for $name<T> {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let entries = std::collections::BTreeMap::<$key, T>::deserialize(deserializer)?;
+                Ok(entries.into_iter().collect())
+            }
+        }
+    };
+}
+
+id_map!(PackageMap, PackageId);
+id_map!(VersionMap, Version);
+
+#[cfg(test)]
+mod test {
+    use super::{PackageMap, VersionMap};
+
+    #[test]
+    fn test_id_maps() {
+        let mut map = [(3, "c"), (1, "a")].into_iter().collect::<PackageMap<_>>();
+        assert_eq!(map.insert(2, "b"), None);
+        assert_eq!(map.insert(2, "B"), Some("b"));
+        *map.get_or_insert_with(4, || "d") = "D";
+        assert_eq!(map.keys(), vec![1, 2, 3, 4]);
+        assert_eq!(
+            map.clone().into_iter().collect::<Vec<_>>(),
+            vec![(1, "a"), (2, "B"), (3, "c"), (4, "D")]
+        );
+        assert_eq!(map.remove(1), Some("a"));
+        assert!(!map.contains_key(1));
+        assert_eq!(map.len(), 3);
+
+        let mut versions = VersionMap::new();
+        versions.insert(u64::MAX, ());
+        assert_eq!(versions.iter().next(), Some((u64::MAX, &())));
+    }
+}
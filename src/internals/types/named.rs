@@ -0,0 +1,190 @@
+// Parsing of human-written requirements on named packages, e.g. `foo >=1.2, <2.0` or
+// `conflict: bar ^3`, for tools built on `ERepository`. A requirement is a package name
+// followed by comma separated comparisons that all have to hold:
+//
+//   >=v  >v  <=v  <v  =v  v     comparisons with a version, a bare version is exact
+//   ^v  ~v                      compatible versions, from `v` up to the bounds given by
+//                               `VersionSyntax::caret_upper` and `tilde_upper`
+//   *                           every version, the same as no comparison
+//
+// A `conflict:` prefix turns the requirement into a conflict. Versions are parsed through
+// `VersionSyntax`, so any version scheme with a total order can be used
+use std::ops::Bound;
+
+use crate::internals::{
+    closure::EdgeKind,
+    types::{
+        extended::{ERepository, ERequirement, Intersection, ViaRangeBound},
+        InvalidInputSnafu, RequirementSet, ResolutionError,
+    },
+};
+
+pub trait VersionSyntax: Ord + Clone + Sized {
+    fn parse_version(s: &str) -> Option<Self>;
+
+    // The smallest version no longer compatible with `self` in the sense of `^`, None if
+    // the version scheme has no such notion
+    fn caret_upper(&self) -> Option<Self> {
+        None
+    }
+
+    // The same for `~`
+    fn tilde_upper(&self) -> Option<Self> {
+        None
+    }
+}
+
+impl VersionSyntax for u64 {
+    fn parse_version(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+pub type VersionBounds<V> = Intersection<ViaRangeBound<(Bound<V>, Bound<V>)>>;
+
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct NamedRequirement<V> {
+    pub kind: EdgeKind,
+    pub requirement: ERequirement<String, VersionBounds<V>>,
+}
+
+fn invalid(message: String) -> ResolutionError {
+    InvalidInputSnafu { message }.build()
+}
+
+// longer operators first, so that `>=` is not taken for `>`
+const OPERATORS: [&str; 8] = [">=", "<=", "==", ">", "<", "=", "^", "~"];
+
+fn parse_comparison<V: VersionSyntax>(s: &str) -> Result<(Bound<V>, Bound<V>), ResolutionError> {
+    let (op, version) = OPERATORS
+        .iter()
+        .find_map(|&op| s.strip_prefix(op).map(|version| (op, version)))
+        .unwrap_or(("=", s));
+    let v = V::parse_version(version.trim())
+        .ok_or_else(|| invalid(format!("malformed version in `{s}`")))?;
+    let upper = |bound: Option<V>| {
+        bound.ok_or_else(|| invalid(format!("`{op}` is not supported by the version scheme")))
+    };
+    Ok(match op {
+        ">=" => (Bound::Included(v), Bound::Unbounded),
+        ">" => (Bound::Excluded(v), Bound::Unbounded),
+        "<=" => (Bound::Unbounded, Bound::Included(v)),
+        "<" => (Bound::Unbounded, Bound::Excluded(v)),
+        "^" => {
+            let upper = upper(v.caret_upper())?;
+            (Bound::Included(v), Bound::Excluded(upper))
+        }
+        "~" => {
+            let upper = upper(v.tilde_upper())?;
+            (Bound::Included(v), Bound::Excluded(upper))
+        }
+        _ => (Bound::Included(v.clone()), Bound::Included(v)),
+    })
+}
+
+pub fn parse_named_requirement<V: VersionSyntax>(
+    s: &str,
+) -> Result<NamedRequirement<V>, ResolutionError> {
+    let s = s.trim();
+    let (kind, rest) = match s.strip_prefix("conflict:") {
+        Some(rest) => (EdgeKind::Conflict, rest.trim_start()),
+        None => (EdgeKind::Dependency, s),
+    };
+    let end = rest
+        .find(|c: char| c.is_whitespace() || "<>=^~*,".contains(c))
+        .unwrap_or(rest.len());
+    let (name, comparisons) = rest.split_at(end);
+    if name.is_empty() {
+        return Err(invalid(format!("missing package name in `{s}`")));
+    }
+    let bounds = comparisons
+        .split(',')
+        .map(str::trim)
+        .filter(|comparison| !comparison.is_empty() && *comparison != "*")
+        .map(|comparison| parse_comparison(comparison).map(ViaRangeBound))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(NamedRequirement {
+        kind,
+        requirement: ERequirement::new(name.to_string(), Intersection(bounds)),
+    })
+}
+
+impl<V: VersionSyntax, R> ERepository<String, V, R> {
+    // Parse and translate requirements, e.g. those given on the command line
+    pub fn parse_requirements<'a>(
+        &self,
+        requirements: impl IntoIterator<Item = &'a str>,
+    ) -> Result<RequirementSet, ResolutionError> {
+        let mut reqs = RequirementSet::default();
+        for s in requirements {
+            let NamedRequirement { kind, requirement } = parse_named_requirement::<V>(s)?;
+            let requirement = self.translate(&requirement)?;
+            match kind {
+                EdgeKind::Dependency => reqs.add_dep(requirement),
+                EdgeKind::Conflict => reqs.add_antidep(requirement),
+            }
+        }
+        Ok(reqs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        closure::EdgeKind,
+        types::{
+            extended::{EPackageBuilder, ERepositoryBuilder, EVersion},
+            vec1, Range, Requirement,
+        },
+    };
+
+    use super::{parse_named_requirement, VersionBounds, VersionSyntax};
+
+    #[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone)]
+    struct Semver(u64, u64);
+
+    impl VersionSyntax for Semver {
+        fn parse_version(s: &str) -> Option<Self> {
+            let (major, minor) = s.split_once('.').unwrap_or((s, "0"));
+            Some(Semver(major.parse().ok()?, minor.parse().ok()?))
+        }
+
+        fn caret_upper(&self) -> Option<Self> {
+            Some(Semver(self.0 + 1, 0))
+        }
+    }
+
+    #[test]
+    fn test_named_requirements() {
+        let mut builder = ERepositoryBuilder::<String, Semver, VersionBounds<Semver>>::new();
+        for (name, versions) in [
+            ("foo", vec!["1.0", "1.5", "2.0"]),
+            ("bar", vec!["2", "3.0", "3.1", "4"]),
+        ] {
+            let mut package = EPackageBuilder::new(name.to_string());
+            for v in versions {
+                package.add_version(EVersion::new(Semver::parse_version(v).unwrap()));
+            }
+            builder.add_package(package.build());
+        }
+        let repo = ERepositoryBuilder::build(builder).unwrap();
+
+        let req = parse_named_requirement::<Semver>("conflict: bar ^3").unwrap();
+        assert_eq!(req.kind, EdgeKind::Conflict);
+        assert_eq!(req.requirement.package(), "bar");
+
+        let reqs = repo
+            .parse_requirements(["foo >=1.2, <2.0", "conflict: bar ^3"])
+            .unwrap();
+        println!("{reqs:?}");
+        assert_eq!(reqs.dependencies, vec![Requirement::single_version(0, 2)]);
+        assert_eq!(
+            reqs.conflicts,
+            vec![Requirement::new(1, vec1![Range::interval_unchecked(2, 3)])]
+        );
+        assert!(repo.parse_requirements(["foo>=2"]).is_ok());
+        assert!(repo.parse_requirements(["baz"]).is_err());
+        assert!(repo.parse_requirements(["foo >=3"]).is_err());
+        assert!(parse_named_requirement::<u64>("foo ^1").is_err());
+    }
+}
@@ -0,0 +1,237 @@
+// Classification of the entries of a plan. Plans store "not installed" as version 0, the
+// helpers here and `TypedPlan` spell that out so that consumers do not have to know about
+// the sentinel
+use crate::internals::types::{PackageId, PackageMap, Plan, Version};
+
+#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone, Copy)]
+pub enum PlanEntry {
+    Installed {
+        package: PackageId,
+        version: Version,
+    },
+    NotInstalled {
+        package: PackageId,
+    },
+}
+
+impl PlanEntry {
+    pub fn new(package: PackageId, version: Version) -> Self {
+        if version == 0 {
+            Self::NotInstalled { package }
+        } else {
+            Self::Installed { package, version }
+        }
+    }
+
+    pub fn package(&self) -> PackageId {
+        match *self {
+            Self::Installed { package, .. } | Self::NotInstalled { package } => package,
+        }
+    }
+
+    // None if the package is not installed
+    pub fn version(&self) -> Option<Version> {
+        match *self {
+            Self::Installed { version, .. } => Some(version),
+            Self::NotInstalled { .. } => None,
+        }
+    }
+
+    pub fn is_installed(&self) -> bool {
+        matches!(self, Self::Installed { .. })
+    }
+}
+
+impl From<(PackageId, Version)> for PlanEntry {
+    fn from((package, version): (PackageId, Version)) -> Self {
+        Self::new(package, version)
+    }
+}
+
+impl From<PlanEntry> for (PackageId, Version) {
+    fn from(entry: PlanEntry) -> Self {
+        (entry.package(), entry.version().unwrap_or(0))
+    }
+}
+
+// Implemented for plans, the results keep the order of the plan
+pub trait PlanExt {
+    fn entries(&self) -> Vec<PlanEntry>;
+
+    // The installed packages with their versions
+    fn installed(&self) -> Plan;
+
+    fn not_installed(&self) -> Vec<PackageId>;
+
+    // The installed and the not installed packages
+    fn installation_status(&self) -> (Vec<PackageId>, Vec<PackageId>);
+}
+
+impl PlanExt for [(PackageId, Version)] {
+    fn entries(&self) -> Vec<PlanEntry> {
+        self.iter().map(|&entry| entry.into()).collect()
+    }
+
+    fn installed(&self) -> Plan {
+        self.iter().copied().filter(|&(_, v)| v != 0).collect()
+    }
+
+    fn not_installed(&self) -> Vec<PackageId> {
+        self.iter()
+            .filter(|&&(_, v)| v == 0)
+            .map(|&(pid, _)| pid)
+            .collect()
+    }
+
+    fn installation_status(&self) -> (Vec<PackageId>, Vec<PackageId>) {
+        let installed = self.installed().into_iter().map(|(pid, _)| pid).collect();
+        (installed, self.not_installed())
+    }
+}
+
+// A plan keyed by package where not installed packages have no version. Packages outside
+// of the plan, e.g. outside of the dependency closure, are absent altogether
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct TypedPlan(PackageMap<Option<Version>>);
+
+impl TypedPlan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // None if the package is not part of the plan
+    pub fn get(&self, package: PackageId) -> Option<PlanEntry> {
+        let version = *self.0.get(package)?;
+        Some(match version {
+            Some(version) => PlanEntry::Installed { package, version },
+            None => PlanEntry::NotInstalled { package },
+        })
+    }
+
+    pub fn installed_version(&self, package: PackageId) -> Option<Version> {
+        self.0.get(package).copied().flatten()
+    }
+
+    pub fn is_installed(&self, package: PackageId) -> bool {
+        self.installed_version(package).is_some()
+    }
+
+    // Returns the entry previously stored for the package
+    pub fn set(&mut self, entry: PlanEntry) -> Option<PlanEntry> {
+        let package = entry.package();
+        let previous = self.get(package);
+        self.0.insert(package, entry.version());
+        previous
+    }
+
+    // The entries in increasing order of the packages
+    pub fn entries(&self) -> impl Iterator<Item = PlanEntry> + '_ {
+        self.0.iter().map(|(package, version)| match *version {
+            Some(version) => PlanEntry::Installed { package, version },
+            None => PlanEntry::NotInstalled { package },
+        })
+    }
+
+    pub fn installed(&self) -> impl Iterator<Item = (PackageId, Version)> + '_ {
+        self.0
+            .iter()
+            .filter_map(|(package, version)| Some((package, (*version)?)))
+    }
+
+    pub fn not_installed(&self) -> impl Iterator<Item = PackageId> + '_ {
+        self.0
+            .iter()
+            .filter(|(_, version)| version.is_none())
+            .map(|(package, _)| package)
+    }
+
+    // The plan in the untyped representation, sorted by package
+    pub fn to_plan(&self) -> Plan {
+        self.entries().map(|entry| entry.into()).collect()
+    }
+}
+
+impl FromIterator<PlanEntry> for TypedPlan {
+    fn from_iter<I: IntoIterator<Item = PlanEntry>>(iter: I) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|entry| (entry.package(), entry.version()))
+                .collect(),
+        )
+    }
+}
+
+impl From<&[(PackageId, Version)]> for TypedPlan {
+    fn from(plan: &[(PackageId, Version)]) -> Self {
+        plan.iter().map(|&entry| PlanEntry::from(entry)).collect()
+    }
+}
+
+impl From<&Plan> for TypedPlan {
+    fn from(plan: &Plan) -> Self {
+        plan.as_slice().into()
+    }
+}
+
+impl From<TypedPlan> for Plan {
+    fn from(plan: TypedPlan) -> Self {
+        plan.to_plan()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PlanEntry, PlanExt, TypedPlan};
+
+    #[test]
+    fn test_plan_classification() {
+        let plan = vec![(2, 3), (0, 0), (1, 1)];
+        assert_eq!(plan.installed(), vec![(2, 3), (1, 1)]);
+        assert_eq!(plan.not_installed(), vec![0]);
+        assert_eq!(plan.installation_status(), (vec![2, 1], vec![0]));
+        assert_eq!(
+            plan.entries(),
+            vec![
+                PlanEntry::Installed {
+                    package: 2,
+                    version: 3
+                },
+                PlanEntry::NotInstalled { package: 0 },
+                PlanEntry::Installed {
+                    package: 1,
+                    version: 1
+                },
+            ]
+        );
+        let entry = PlanEntry::new(0, 0);
+        assert!(!entry.is_installed());
+        assert_eq!(entry.version(), None);
+        assert_eq!(<(u32, u64)>::from(entry), (0, 0));
+    }
+
+    #[test]
+    fn test_typed_plan() {
+        let plan = vec![(2, 3), (0, 0), (1, 1)];
+        let mut typed = TypedPlan::from(&plan);
+        assert_eq!(typed.len(), 3);
+        assert_eq!(typed.installed_version(2), Some(3));
+        assert_eq!(typed.installed_version(0), None);
+        assert_eq!(typed.get(0), Some(PlanEntry::NotInstalled { package: 0 }));
+        assert_eq!(typed.get(5), None);
+        assert_eq!(typed.installed().collect::<Vec<_>>(), vec![(1, 1), (2, 3)]);
+        assert_eq!(typed.not_installed().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(typed.to_plan(), vec![(0, 0), (1, 1), (2, 3)]);
+
+        assert_eq!(typed.set(PlanEntry::new(1, 0)), Some(PlanEntry::new(1, 1)));
+        assert!(!typed.is_installed(1));
+        println!("{typed:?}");
+    }
+}
@@ -0,0 +1,248 @@
+// Sets of versions represented as sorted, disjoint and non-adjacent intervals. Versions
+// start from 1, so the complement is taken relative to [1, newest]
+use std::{cmp::Ordering, fmt::Display};
+
+use crate::internals::{
+    types::{Range, Vec1, Version},
+    utils::{merge_insert, Interval},
+};
+
+#[derive(Eq, PartialEq, Debug, Clone, Default, Hash)]
+pub struct RangeSet {
+    intervals: Vec<Interval>,
+}
+
+impl RangeSet {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    // Every (installed) version
+    pub fn all() -> Self {
+        Self::interval(1, Version::MAX)
+    }
+
+    pub fn point(v: Version) -> Self {
+        Self::interval(v, v)
+    }
+
+    pub fn interval(lower: Version, upper: Version) -> Self {
+        let intervals = if lower <= upper {
+            vec![(lower, upper)]
+        } else {
+            Vec::new()
+        };
+        Self { intervals }
+    }
+
+    pub fn from_range(range: &Range) -> Self {
+        match range {
+            Range::Interval { lower, upper } => Self::interval(*lower, *upper),
+            Range::Point(v) => Self::point(*v),
+            Range::All => Self::all(),
+        }
+    }
+
+    pub fn from_ranges<'a>(ranges: impl IntoIterator<Item = &'a Range>) -> Self {
+        ranges.into_iter().fold(Self::empty(), |set, range| {
+            set.union(&Self::from_range(range))
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    // The versions within some ranges that exist in a package with versions [1, newest]
+    pub fn clamped(ranges: &Vec1<Range>, newest: Version) -> Self {
+        Self::from(ranges).intersection(&Self::interval(1, newest))
+    }
+
+    pub fn lowest(&self) -> Option<Version> {
+        self.intervals.first().map(|&(lower, _)| lower)
+    }
+
+    pub fn highest(&self) -> Option<Version> {
+        self.intervals.last().map(|&(_, upper)| upper)
+    }
+
+    pub fn contains(&self, v: Version) -> bool {
+        self.intervals
+            .binary_search_by(|&(lower, upper)| {
+                if upper < v {
+                    Ordering::Less
+                } else if lower > v {
+                    Ordering::Greater
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    // Whether every version in `other` is in this set
+    pub fn is_superset(&self, other: &RangeSet) -> bool {
+        other.difference(self).is_empty()
+    }
+
+    pub fn is_subset(&self, other: &RangeSet) -> bool {
+        other.is_superset(self)
+    }
+
+    // The number of versions in the set, saturating at Version::MAX
+    pub fn len(&self) -> Version {
+        self.intervals.iter().fold(0, |n: Version, (l, u)| {
+            n.saturating_add(u - l).saturating_add(1)
+        })
+    }
+
+    pub fn union(&self, other: &RangeSet) -> RangeSet {
+        let intervals = other
+            .intervals
+            .iter()
+            .fold(self.intervals.clone(), |iset, &i| merge_insert(iset, i));
+        Self { intervals }
+    }
+
+    pub fn intersection(&self, other: &RangeSet) -> RangeSet {
+        let mut intervals = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.intervals.len() && j < other.intervals.len() {
+            let (l1, u1) = self.intervals[i];
+            let (l2, u2) = other.intervals[j];
+            let (lower, upper) = (l1.max(l2), u1.min(u2));
+            if lower <= upper {
+                intervals.push((lower, upper));
+            }
+            if u1 < u2 {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Self { intervals }
+    }
+
+    // The versions in [1, newest] not in the set
+    pub fn complement(&self, newest: Version) -> RangeSet {
+        let mut intervals = Vec::new();
+        let mut next = 1;
+        for &(lower, upper) in &self.intervals {
+            if lower > newest {
+                break;
+            }
+            if lower > next {
+                intervals.push((next, lower - 1));
+            }
+            next = match upper.checked_add(1) {
+                Some(next) => next,
+                None => return Self { intervals },
+            };
+        }
+        if next <= newest {
+            intervals.push((next, newest));
+        }
+        Self { intervals }
+    }
+
+    pub fn difference(&self, other: &RangeSet) -> RangeSet {
+        let newest = self.intervals.last().map_or(0, |&(_, upper)| upper);
+        self.intersection(&other.complement(newest))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Range> + '_ {
+        self.intervals.iter().map(|&(lower, upper)| {
+            if lower == upper {
+                Range::Point(lower)
+            } else {
+                Range::Interval { lower, upper }
+            }
+        })
+    }
+
+    // The set as ranges, None if it is empty
+    pub fn to_ranges(&self) -> Option<Vec1<Range>> {
+        if *self == Self::all() {
+            Some(Vec1::new(Range::All))
+        } else {
+            Vec1::try_from(self.iter().collect::<Vec<_>>()).ok()
+        }
+    }
+}
+
+impl From<&Vec1<Range>> for RangeSet {
+    fn from(ranges: &Vec1<Range>) -> Self {
+        Self::from_ranges(ranges.iter())
+    }
+}
+
+impl From<Range> for RangeSet {
+    fn from(range: Range) -> Self {
+        Self::from_range(&range)
+    }
+}
+
+impl TryFrom<RangeSet> for Vec1<Range> {
+    type Error = ();
+
+    fn try_from(set: RangeSet) -> Result<Self, Self::Error> {
+        set.to_ranges().ok_or(())
+    }
+}
+
+impl Display for RangeSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(ranges) = self.to_ranges() else {
+            return write!(f, "∅");
+        };
+        for (i, range) in ranges.iter().enumerate() {
+            if i != 0 {
+                write!(f, " ∪ ")?;
+            }
+            write!(f, "{range}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{Range, Vec1};
+    use crate::vec1;
+
+    use super::RangeSet;
+
+    #[test]
+    fn test_range_set_algebra() {
+        let a = RangeSet::from(&vec1![Range::interval_unchecked(1, 3), Range::point(7)]);
+        let b = RangeSet::interval(3, 5);
+        println!("{a} | {b}");
+
+        assert_eq!(
+            a.union(&b),
+            RangeSet::interval(1, 5).union(&RangeSet::point(7))
+        );
+        assert_eq!(a.intersection(&b), RangeSet::point(3));
+        assert_eq!(
+            a.complement(8),
+            RangeSet::interval(4, 6).union(&RangeSet::point(8))
+        );
+        assert_eq!(
+            a.difference(&b),
+            RangeSet::interval(1, 2).union(&RangeSet::point(7))
+        );
+        assert!(a.contains(2) && a.contains(7) && !a.contains(5));
+        assert!(RangeSet::interval(1, 2).is_subset(&a));
+        assert!(!b.is_subset(&a));
+        assert!(a.intersection(&RangeSet::interval(4, 6)).is_empty());
+        assert_eq!(a.len(), 4);
+
+        assert_eq!(RangeSet::all().complement(10), RangeSet::empty());
+        assert_eq!(RangeSet::all().to_ranges(), Some(vec1![Range::All]));
+        assert_eq!(
+            Vec1::try_from(a),
+            Ok(vec1![Range::interval_unchecked(1, 3), Range::point(7)])
+        );
+        assert_eq!(Vec1::<Range>::try_from(RangeSet::empty()), Err(()));
+    }
+}
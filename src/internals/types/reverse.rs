@@ -0,0 +1,181 @@
+// Reverse dependencies of repositories, for impact analysis before removing or yanking a
+// package. Repositories build the index on the first query and share it with their
+// clones until the packages are edited
+use intmap::IntMap;
+
+use crate::internals::{closure::EdgeKind, types::*};
+
+// Version `version` of package `package` depends on or conflicts with `versions` of the
+// queried package
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ReverseDep {
+    pub package: PackageId,
+    pub version: Version,
+    pub kind: EdgeKind,
+    pub versions: RangeSet,
+}
+
+// The reverse dependencies of every package of a repository. `Repository::reverse_deps`
+// builds one on the first query, an index built with `build` is a snapshot that editing
+// the repository afterwards does not update
+#[derive(Clone)]
+pub struct ReverseIndex(IntMap<Vec<ReverseDep>>);
+
+impl std::fmt::Debug for ReverseIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReverseIndex")
+            .field("packages", &self.0.len())
+            .finish()
+    }
+}
+
+impl ReverseIndex {
+    pub fn build(repo: &Repository) -> Self {
+        let mut index = IntMap::<Vec<ReverseDep>>::new();
+        for package in repo.packages.iter() {
+            for (version, ver) in (1..).zip(&package.versions) {
+                let reqs = ver
+                    .requirements
+                    .dependencies
+                    .iter()
+                    .map(|req| (req, EdgeKind::Dependency))
+                    .chain(
+                        ver.requirements
+                            .conflicts
+                            .iter()
+                            .map(|req| (req, EdgeKind::Conflict)),
+                    );
+                for (req, kind) in reqs {
+                    let versions = RangeSet::from(&req.versions);
+                    if !index.contains_key(req.package as u64) {
+                        index.insert(req.package as u64, Vec::new());
+                    }
+                    let entries = index
+                        .get_mut(req.package as u64)
+                        .expect("Impossible: entry was just inserted");
+                    // the requirements of a version on the same package are pushed one
+                    // after the other, so they are gathered into the last entry
+                    match entries.last_mut() {
+                        Some(dep)
+                            if dep.package == package.id
+                                && dep.version == version
+                                && dep.kind == kind =>
+                        {
+                            dep.versions = dep.versions.union(&versions)
+                        }
+                        _ => entries.push(ReverseDep {
+                            package: package.id,
+                            version,
+                            kind,
+                            versions,
+                        }),
+                    }
+                }
+            }
+        }
+        Self(index)
+    }
+
+    // The versions of packages depending on or conflicting with some version of a
+    // package, ordered by package and version
+    pub fn reverse_deps(&self, pid: PackageId) -> &[ReverseDep] {
+        self.0.get(pid as u64).map_or(&[], |deps| &deps[..])
+    }
+
+    // The versions of packages depending on or conflicting with some version of a
+    // package within a range, e.g. the versions about to be yanked
+    pub fn reverse_deps_in_range(&self, pid: PackageId, range: &Range) -> Vec<&ReverseDep> {
+        let range = RangeSet::from_range(range);
+        self.reverse_deps(pid)
+            .iter()
+            .filter(|dep| !dep.versions.intersection(&range).is_empty())
+            .collect()
+    }
+}
+
+impl Repository {
+    // The reverse dependencies of a package, from an index built on the first query and
+    // dropped when the packages are edited through `packages_mut`
+    pub fn reverse_deps(&self, pid: PackageId) -> &[ReverseDep] {
+        self.reverse_index().reverse_deps(pid)
+    }
+
+    pub fn reverse_deps_in_range(&self, pid: PackageId, range: &Range) -> Vec<&ReverseDep> {
+        self.reverse_index().reverse_deps_in_range(pid, range)
+    }
+
+    fn reverse_index(&self) -> &ReverseIndex {
+        self.reverse_index.get_or_init(|| ReverseIndex::build(self))
+    }
+
+    // Drop the indices built from the packages, see `packages_mut`
+    pub(super) fn invalidate_caches(&mut self) {
+        self.reverse_index = Default::default();
+        self.normalized = Default::default();
+        self.fingerprint = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::{
+        closure::EdgeKind,
+        types::{Package, PackageVer, Range, Repository, Requirement, RequirementSet},
+    };
+
+    use super::ReverseIndex;
+
+    #[test]
+    fn test_reverse_deps() {
+        // version 1 of package 0 requires version 1 of package 2, version 2 requires
+        // version 2, package 1 conflicts with every version of package 2
+        let mut repo = Repository::new(vec![
+            Package {
+                id: 0,
+                versions: (1..=2)
+                    .map(|v| PackageVer {
+                        requirements: RequirementSet::from_dep(Requirement::single_version(2, v)),
+                    })
+                    .collect(),
+            },
+            Package {
+                id: 1,
+                versions: vec![PackageVer {
+                    requirements: RequirementSet::from_antidep(Requirement::any_version(2)),
+                }],
+            },
+            Package {
+                id: 2,
+                versions: (1..=2)
+                    .map(|_| PackageVer {
+                        requirements: Default::default(),
+                    })
+                    .collect(),
+            },
+        ]);
+        let deps = repo.reverse_deps(2);
+        println!("{deps:?}");
+        assert_eq!(deps.len(), 3);
+        assert_eq!(deps[2].kind, EdgeKind::Conflict);
+        assert!(repo.reverse_deps(0).is_empty());
+
+        let yanked = repo.reverse_deps_in_range(2, &Range::point(2));
+        assert_eq!(
+            yanked
+                .iter()
+                .map(|dep| (dep.package, dep.version))
+                .collect::<Vec<_>>(),
+            vec![(0, 2), (1, 1)]
+        );
+
+        // the requirements of a version on the same package are gathered, editing the
+        // packages drops the index of the repository but not a snapshot
+        let index = ReverseIndex::build(&repo);
+        let conflicts = &mut repo.packages_mut()[1].versions[0].requirements;
+        conflicts.add_antidep(Requirement::single_version(2, 1));
+        assert_eq!(repo.reverse_deps(2).len(), 3);
+        repo.packages_mut()[1].versions[0].requirements = Default::default();
+        assert_eq!(repo.reverse_deps(2).len(), 2);
+        assert_eq!(index.reverse_deps(2).len(), 3);
+    }
+}
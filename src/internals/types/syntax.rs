@@ -0,0 +1,297 @@
+// A textual syntax of requirements for config files and test fixtures:
+//
+//   pkg3                        every version of package 3
+//   pkg3 in [1, 4] | {7} | *    versions 1 to 4, version 7, every version
+//   !pkg5 in {2}                a conflict, only allowed in requirement sets
+//   pkg1; !pkg5                 a requirement set, dependencies and conflicts separated
+//                               by semicolons
+//   pkg1 ∧ (pkg2 ∨ ¬pkg4)       a formula, `∧` binding tighter than `∨`, `⊤` and `⊥`
+//                               being the empty conjunction and disjunction
+//   pkg1; (pkg2 ∨ pkg3)         a requirement set with a formula, written last
+//
+// Displaying and parsing round-trip, with conflicts written after the dependencies. The
+// only formulas that come back different are conjunctions and disjunctions of a single
+// operand, which come back as the operand
+use std::{fmt::Display, str::FromStr};
+
+use crate::internals::types::*;
+
+fn invalid(message: String) -> ResolutionError {
+    InvalidInputSnafu { message }.build()
+}
+
+fn parse_version(s: &str) -> Result<Version, ResolutionError> {
+    s.trim()
+        .parse()
+        .map_err(|_| invalid(format!("malformed version `{}`", s.trim())))
+}
+
+fn parse_range(s: &str) -> Result<Range, ResolutionError> {
+    let s = s.trim();
+    if s == "*" {
+        Ok(Range::All)
+    } else if let Some(v) = s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+        Ok(Range::point(parse_version(v)?))
+    } else if let Some((lower, upper)) = s
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .and_then(|s| s.split_once(','))
+    {
+        Range::interval(parse_version(lower)?, parse_version(upper)?)
+            .ok_or_else(|| invalid(format!("empty interval `{s}`")))
+    } else {
+        Err(invalid(format!("malformed range `{s}`")))
+    }
+}
+
+struct RangeSyntax<'a>(&'a Range);
+
+impl Display for RangeSyntax<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Range::All => write!(f, "*"),
+            range => write!(f, "{range}"),
+        }
+    }
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pkg{}", self.package)?;
+        if self.versions.as_vec() != &[Range::All] {
+            write!(f, " in ")?;
+            for (i, range) in self.versions.iter().enumerate() {
+                if i != 0 {
+                    write!(f, " | ")?;
+                }
+                write!(f, "{}", RangeSyntax(range))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = ResolutionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let rest = s
+            .strip_prefix("pkg")
+            .ok_or_else(|| invalid(format!("requirement `{s}` does not start with `pkg`")))?;
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let package = rest[..digits]
+            .parse()
+            .map_err(|_| invalid(format!("malformed package id in `{s}`")))?;
+        let rest = rest[digits..].trim();
+        if rest.is_empty() {
+            return Ok(Requirement::any_version(package));
+        }
+        let ranges = rest
+            .strip_prefix("in")
+            .filter(|ranges| ranges.starts_with(char::is_whitespace))
+            .ok_or_else(|| invalid(format!("expected `in` after the package in `{s}`")))?
+            .split('|')
+            .map(parse_range)
+            .collect::<Result<Vec<_>, _>>()?;
+        let versions =
+            Vec1::try_from(ranges).map_err(|_| invalid(format!("no ranges in `{s}`")))?;
+        Ok(Requirement::new(package, versions))
+    }
+}
+
+// the symbols of formulas, none of which appear in requirements
+const FORMULA_SYMBOLS: [char; 7] = ['∧', '∨', '¬', '(', ')', '⊤', '⊥'];
+
+fn is_formula(s: &str) -> bool {
+    s.contains(|c| FORMULA_SYMBOLS.contains(&c))
+}
+
+// A recursive descent parser over what is left of the input
+struct FormulaParser<'s> {
+    rest: &'s str,
+}
+
+impl FormulaParser<'_> {
+    fn eat(&mut self, symbol: char) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(symbol) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The operands of an operator, a single operand being returned as is
+    fn operands(
+        &mut self,
+        op: char,
+        operand: fn(&mut Self) -> Result<ReqExpr, ResolutionError>,
+        node: fn(Vec<ReqExpr>) -> ReqExpr,
+    ) -> Result<ReqExpr, ResolutionError> {
+        let mut operands = vec![operand(self)?];
+        while self.eat(op) {
+            operands.push(operand(self)?);
+        }
+        Ok(if operands.len() == 1 {
+            operands.pop().unwrap()
+        } else {
+            node(operands)
+        })
+    }
+
+    fn or(&mut self) -> Result<ReqExpr, ResolutionError> {
+        self.operands('∨', Self::and, ReqExpr::Or)
+    }
+
+    fn and(&mut self) -> Result<ReqExpr, ResolutionError> {
+        self.operands('∧', Self::unary, ReqExpr::And)
+    }
+
+    fn unary(&mut self) -> Result<ReqExpr, ResolutionError> {
+        if self.eat('¬') {
+            Ok(ReqExpr::Not(Box::new(self.unary()?)))
+        } else if self.eat('⊤') {
+            Ok(ReqExpr::And(Vec::new()))
+        } else if self.eat('⊥') {
+            Ok(ReqExpr::Or(Vec::new()))
+        } else if self.eat('(') {
+            let expr = self.or()?;
+            if !self.eat(')') {
+                return Err(invalid(format!(
+                    "unclosed parenthesis before `{}`",
+                    self.rest
+                )));
+            }
+            Ok(expr)
+        } else {
+            let end = self
+                .rest
+                .find(|c| FORMULA_SYMBOLS.contains(&c))
+                .unwrap_or(self.rest.len());
+            let (req, rest) = self.rest.split_at(end);
+            self.rest = rest;
+            Ok(ReqExpr::Req(req.parse()?))
+        }
+    }
+}
+
+impl FromStr for ReqExpr {
+    type Err = ResolutionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = FormulaParser { rest: s };
+        let expr = parser.or()?;
+        match parser.rest.trim() {
+            "" => Ok(expr),
+            rest => Err(invalid(format!(
+                "unexpected `{rest}` in formula `{}`",
+                s.trim()
+            ))),
+        }
+    }
+}
+
+impl Display for RequirementSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reqs = self
+            .dependencies
+            .iter()
+            .map(|req| ("", req))
+            .chain(self.conflicts.iter().map(|req| ("!", req)));
+        for (i, (prefix, req)) in reqs.enumerate() {
+            if i != 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{prefix}{req}")?;
+        }
+        if let Some(formula) = &self.formula {
+            if !self.dependencies.is_empty() || !self.conflicts.is_empty() {
+                write!(f, "; ")?;
+            }
+            // a formula of a single requirement would be read back as a dependency
+            let formula = formula.to_string();
+            if is_formula(&formula) {
+                write!(f, "{formula}")?;
+            } else {
+                write!(f, "({formula})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for RequirementSet {
+    type Err = ResolutionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut reqs = RequirementSet::default();
+        for req in s.split(';').map(str::trim).filter(|req| !req.is_empty()) {
+            if is_formula(req) {
+                reqs.add_formula(req.parse()?);
+                continue;
+            }
+            match req.strip_prefix('!') {
+                Some(antidep) => reqs.add_antidep(antidep.parse()?),
+                None => reqs.add_dep(req.parse()?),
+            }
+        }
+        Ok(reqs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::internals::types::{vec1, Range, ReqExpr, Requirement, RequirementSet};
+
+    #[test]
+    fn test_requirement_syntax() {
+        let req = "pkg3 in [1, 4] | {7}".parse::<Requirement>().unwrap();
+        assert_eq!(
+            req,
+            Requirement::new(3, vec1![Range::interval_unchecked(1, 4), Range::point(7)])
+        );
+        assert_eq!(req.to_string(), "pkg3 in [1, 4] | {7}");
+        assert_eq!(
+            "pkg2".parse::<Requirement>().unwrap(),
+            Requirement::any_version(2)
+        );
+        assert!("pkg2 in [4, 1]".parse::<Requirement>().is_err());
+        assert!("pkg2 in{1}".parse::<Requirement>().is_err());
+        assert!("!pkg2".parse::<Requirement>().is_err());
+
+        let reqs = "pkg1 in [1,2]; !pkg5; !pkg6 in * | {2}"
+            .parse::<RequirementSet>()
+            .unwrap();
+        println!("{reqs}");
+        assert_eq!(reqs.dependencies.len(), 1);
+        assert_eq!(reqs.conflicts.len(), 2);
+        assert_eq!(reqs.to_string().parse::<RequirementSet>().unwrap(), reqs);
+        assert_eq!(
+            "".parse::<RequirementSet>().unwrap(),
+            RequirementSet::default()
+        );
+
+        let formula = "pkg1 ∧ (pkg2 in [1, 3] | {5} ∨ ¬¬pkg4) ∧ (⊤ ∨ ⊥)"
+            .parse::<ReqExpr>()
+            .unwrap();
+        println!("{formula}");
+        assert_eq!(formula.to_string().parse::<ReqExpr>().unwrap(), formula);
+        assert_eq!(formula.requirements().len(), 3);
+        assert!("pkg1 ∧ (pkg2".parse::<ReqExpr>().is_err());
+        assert!("pkg1 pkg2".parse::<ReqExpr>().is_err());
+
+        let mut reqs = "pkg1; !pkg2; pkg3 ∨ pkg4"
+            .parse::<RequirementSet>()
+            .unwrap();
+        assert!(reqs.formula.is_some());
+        assert_eq!(reqs.to_string().parse::<RequirementSet>().unwrap(), reqs);
+        reqs.formula = Some(ReqExpr::req(Requirement::any_version(5)));
+        assert_eq!(reqs.to_string(), "pkg1; !pkg2; (pkg5)");
+        assert_eq!(reqs.to_string().parse::<RequirementSet>().unwrap(), reqs);
+    }
+}
@@ -1,6 +1,9 @@
+use std::ops::{Deref, DerefMut};
+
 use rkyv::{Archive, Deserialize, Serialize};
 
-#[derive(Eq, PartialEq, Debug, Clone, Archive, Serialize, Deserialize)]
+#[derive(Eq, PartialEq, Debug, Clone, Hash, Archive, Serialize, Deserialize)]
+#[archive(check_bytes)]
 #[repr(transparent)]
 pub struct Vec1<T>(Vec<T>);
 
@@ -26,6 +29,46 @@ impl<T> Vec1<T> {
     pub fn as_vec(&self) -> &Vec<T> {
         &self.0
     }
+
+    // never fails, unlike the slice versions
+    pub fn first(&self) -> &T {
+        &self.0[0]
+    }
+
+    pub fn last(&self) -> &T {
+        &self.0[self.0.len() - 1]
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Vec1<U> {
+        Vec1(self.0.into_iter().map(f).collect())
+    }
+}
+
+// Slice methods such as `iter` and `len`, the length can't change through a slice
+impl<T> Deref for Vec1<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Vec1<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        &mut self.0
+    }
+}
+
+impl<T> AsRef<[T]> for Vec1<T> {
+    fn as_ref(&self) -> &[T] {
+        &self.0
+    }
+}
+
+impl<T> Extend<T> for Vec1<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
 }
 
 impl<T> IntoIterator for Vec1<T> {
@@ -37,11 +80,35 @@ impl<T> IntoIterator for Vec1<T> {
     }
 }
 
+impl<'a, T> IntoIterator for &'a Vec1<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+// The first element and the rest
+impl<T> From<(T, Vec<T>)> for Vec1<T> {
+    fn from((first, mut rest): (T, Vec<T>)) -> Self {
+        let mut v = Vec1::new(first);
+        v.append(&mut rest);
+        v
+    }
+}
+
+impl<T> From<Vec1<T>> for Vec<T> {
+    fn from(v: Vec1<T>) -> Self {
+        v.0
+    }
+}
+
 impl<T> TryFrom<Vec<T>> for Vec1<T> {
     type Error = ();
 
     fn try_from(value: Vec<T>) -> Result<Self, Self::Error> {
-        if value.len() == 0 {
+        if value.is_empty() {
             Err(())
         } else {
             Ok(Vec1(value))
@@ -66,3 +133,22 @@ macro_rules! vec1 {
 }
 
 pub use vec1;
+
+#[cfg(test)]
+mod test {
+    use super::Vec1;
+
+    #[test]
+    fn test_vec1_api() {
+        let mut v = Vec1::from((1, vec![2, 3]));
+        v.extend([4]);
+        assert_eq!(v.len(), 4);
+        assert_eq!((*v.first(), *v.last()), (1, 4));
+        assert_eq!(v.iter().sum::<i32>(), 10);
+        assert_eq!(&v[1..3], &[2, 3]);
+        let doubled = v.clone().map(|x| x * 2);
+        assert_eq!(Vec::from(doubled), vec![2, 4, 6, 8]);
+        assert_eq!((&v).into_iter().count(), 4);
+        assert!(Vec1::<i32>::try_from(Vec::new()).is_err());
+    }
+}
@@ -3,7 +3,8 @@ pub mod z3;
 use std::cmp::Ordering;
 
 pub use colors::{blue_text, green_text, red_text};
-pub use interval_merging::merge_and_sort_ranges;
+pub use interval_merging::{merge_and_sort_ranges, merge_insert, Interval};
+pub use text::{format_ranges, parse_requirement};
 pub use z3::*;
 
 // Utilities for merging intervals
@@ -19,7 +20,7 @@ mod interval_merging {
     pub type ISet = Vec<Interval>;
 
     fn less_no_overlap(a: Interval, b: Interval) -> bool {
-        (a.1 + 1) < b.0
+        a.1.saturating_add(1) < b.0
     }
 
     fn greater_no_overlap(a: Interval, b: Interval) -> bool {
@@ -113,6 +114,43 @@ pub fn iter_max_map<T, V: Ord, W>(
     v
 }
 
+// The textual notation of requirements shared by cache entries and test cases: a
+// package id followed by comma separated points, intervals or `*`, e.g. `1 2,4-5,*`
+mod text {
+    use crate::internals::types::*;
+
+    pub fn format_ranges(ranges: &Vec1<Range>) -> String {
+        ranges
+            .as_vec()
+            .iter()
+            .map(|range| match range {
+                Range::Interval { lower, upper } => format!("{lower}-{upper}"),
+                Range::Point(v) => v.to_string(),
+                Range::All => "*".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn parse_requirement(pid: &str, ranges: &str) -> Option<Requirement> {
+        let ranges = ranges
+            .split(',')
+            .map(|range| match range.split_once('-') {
+                _ if range == "*" => Some(Range::All),
+                Some((lower, upper)) => Some(Range::Interval {
+                    lower: lower.parse().ok()?,
+                    upper: upper.parse().ok()?,
+                }),
+                None => Some(Range::Point(range.parse().ok()?)),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Requirement::new(
+            pid.parse().ok()?,
+            Vec1::try_from(ranges).ok()?,
+        ))
+    }
+}
+
 // Colors for terminal displaying
 mod colors {
     use termcolor::{Color, ColorSpec};
@@ -1,23 +1,9 @@
-use crate::internals::types::*;
-use z3::ast::{Ast, Bool, Int};
-use z3::SatResult::Sat;
-use z3::{set_global_param, Config, Context, Model, Params, Solver};
-
-pub fn set_global_params() {
-    set_global_param("unsat_core", "true");
-    set_global_param("parallel.enable", "true");
-    set_global_param("sat.core.minimize", "true");
-    set_global_param("sat.threads", "12");
-    set_global_param("smt.core.minimize", "true");
-    set_global_param("smt.threads", "12");
-}
-
-pub fn default_params(ctx: &Context) -> Params<'_> {
-    let mut p = Params::new(ctx);
-    p.set_bool("unsat_core", true);
-    p.set_bool("core.minimize", true);
-    p
-}
+use crate::internals::{encoding::Encoder, types::*};
+use std::ops::ControlFlow;
+use std::time::Instant;
+use z3::ast::{Ast, Int};
+use z3::SatResult::{Sat, Unknown, Unsat};
+use z3::{Config, Context, Model, Solver, Statistics, StatisticsValue};
 
 pub fn default_config() -> Config {
     let mut cfg = Config::new();
@@ -39,13 +25,14 @@ pub fn sgn<'a>(ctx: &'a Context, a: Int<'a>) -> Int<'a> {
 
 // the expression representing the taxicab distance of all installed from the newest versions,
 // useful as an optimization metric
-pub fn distance_from_newest(
-    ctx: &Context,
+pub fn distance_from_newest<'c>(
+    encoder: &Encoder<'c>,
     iter: impl Iterator<Item = (PackageId, Version)>,
-) -> Int {
+) -> Int<'c> {
+    let ctx = encoder.ctx();
     let mut expr = zero(ctx);
     for (pid, max_ver) in iter {
-        let pkg_ver = Int::new_const(ctx, pid);
+        let pkg_ver = encoder.version(pid);
         expr += pkg_ver
             ._eq(&zero(ctx))
             .ite(&zero(ctx), &(Int::from_u64(ctx, max_ver) - pkg_ver));
@@ -54,14 +41,91 @@ pub fn distance_from_newest(
 }
 
 // the expression representing the number of packages installed, useful as an optimization metric
-pub fn installed_packages(ctx: &Context, pids: impl Iterator<Item = PackageId>) -> Int {
+pub fn installed_packages<'c>(
+    encoder: &Encoder<'c>,
+    pids: impl Iterator<Item = PackageId>,
+) -> Int<'c> {
+    let ctx = encoder.ctx();
+    let mut expr = zero(ctx);
+    for pid in pids {
+        expr += sgn(ctx, encoder.version(pid));
+    }
+    expr.simplify()
+}
+
+// the expression representing the total cost of the installed versions, costs[pid][v - 1] being
+// the cost of version v of package pid. Packages without costs are free
+pub fn total_cost<'c>(
+    encoder: &Encoder<'c>,
+    pids: impl Iterator<Item = PackageId>,
+    costs: &PackageMap<Vec<u64>>,
+) -> Int<'c> {
+    let ctx = encoder.ctx();
     let mut expr = zero(ctx);
     for pid in pids {
-        expr += sgn(ctx, Int::new_const(ctx, pid));
+        if let Some(version_costs) = costs.get(pid) {
+            let mut cost = zero(ctx);
+            for (i, c) in version_costs.iter().enumerate() {
+                cost = encoder
+                    .atom(&AtomicExpr::ver_eq(pid, i as Version + 1))
+                    .ite(&Int::from_u64(ctx, *c), &cost);
+            }
+            expr += cost;
+        }
+    }
+    expr.simplify()
+}
+
+// the expression representing the number of packages installed at a version lower than the
+// given one, not being installed counts as a downgrade
+pub fn downgrades<'c>(
+    encoder: &Encoder<'c>,
+    current: impl Iterator<Item = (PackageId, Version)>,
+) -> Int<'c> {
+    let ctx = encoder.ctx();
+    let mut expr = zero(ctx);
+    for (pid, version) in current {
+        if version != 0 {
+            expr += encoder
+                .atom(&AtomicExpr::ver_ge(pid, version))
+                .ite(&zero(ctx), &Int::from_u64(ctx, 1));
+        }
+    }
+    expr.simplify()
+}
+
+// the expression representing the number of packages installed at a version other than the
+// given one, versions of 0 counting packages that get installed at all
+pub fn changes<'c>(
+    encoder: &Encoder<'c>,
+    current: impl Iterator<Item = (PackageId, Version)>,
+) -> Int<'c> {
+    let ctx = encoder.ctx();
+    let mut expr = zero(ctx);
+    for (pid, version) in current {
+        let unchanged = if version == 0 {
+            encoder.not_installed(pid)
+        } else {
+            encoder.atom(&AtomicExpr::ver_eq(pid, version))
+        };
+        expr += unchanged.ite(&zero(ctx), &Int::from_u64(ctx, 1));
     }
     expr.simplify()
 }
 
+pub fn collect_statistics(statistics: &Statistics, stats: &mut SolveStats) {
+    stats.z3 = statistics
+        .entries()
+        .map(|entry| {
+            let value = match entry.value {
+                StatisticsValue::UInt(v) => StatValue::UInt(v),
+                StatisticsValue::Double(v) => StatValue::Double(v),
+            };
+            (entry.key, value)
+        })
+        .collect();
+}
+
 pub fn eval_int_expr_in_model(model: &Model, expr: &Int) -> u64 {
     let eval_result = model
         .eval(expr, false)
@@ -71,12 +135,16 @@ pub fn eval_int_expr_in_model(model: &Model, expr: &Int) -> u64 {
         .unwrap_or_else(|| panic!("Impossible: failed to convert eval result {eval_result} to u64"))
 }
 
-// enumerate all models.
+// enumerate the models, stopping after `max_models` models, once the deadline has passed,
+// when z3 cannot decide whether there are more models or when the continuation breaks.
+// returns whether all the models have been enumerated
 pub fn enumerate_models<'a, T: Ast<'a>>(
     solver: &'a Solver,
     vars: impl Iterator<Item = T> + Clone,
-    mut cont: impl FnMut(Model<'a>),
-) {
+    max_models: Option<usize>,
+    deadline: Option<Instant>,
+    cont: impl FnMut(Model<'a>) -> ControlFlow<()>,
+) -> bool {
     fn block_var<'a, T: Ast<'a>>(solver: &'a Solver, model: &Model<'a>, var: &T) {
         let assertion = var
             ._eq(&model.eval(var, false).unwrap_or_else(|| {
@@ -112,84 +180,76 @@ pub fn enumerate_models<'a, T: Ast<'a>>(
     // we add an assertion blocking the second variable, tries to find a new model, then we fix the second
     // variable, repeat the enumeration step for the first variable, and so on to enumerate the scecond variable,
     // after that we backtrack to the third variable, and fourth... until all the variable has been enumerated.
-    fn go<'a, T: Ast<'a>>(
+    struct State<F> {
+        cont: F,
+        remaining: Option<usize>,
+        deadline: Option<Instant>,
+    }
+
+    impl<F> State<F> {
+        fn exhausted(&self) -> bool {
+            self.remaining == Some(0) || self.deadline.is_some_and(|d| Instant::now() >= d)
+        }
+    }
+
+    fn go<'a, T: Ast<'a>, F: FnMut(Model<'a>) -> ControlFlow<()>>(
         solver: &'a Solver,
-        cont: &mut impl FnMut(Model<'a>),
+        state: &mut State<F>,
         mut vars: impl Iterator<Item = T> + Clone,
-    ) {
+    ) -> ControlFlow<()> {
+        if state.exhausted() {
+            return ControlFlow::Break(());
+        }
         if let Some(var) = vars.next() {
             solver.push();
-            while solver.check() == Sat {
-                let model = get_model(solver);
-                solver.push();
-                fix_var(solver, &model, &var);
-                go(solver, cont, vars.clone());
-                solver.pop(1);
-                block_var(solver, &model, &var);
+            let mut res = ControlFlow::Continue(());
+            while res.is_continue() {
+                match solver.check() {
+                    Sat => {
+                        let model = get_model(solver);
+                        solver.push();
+                        fix_var(solver, &model, &var);
+                        res = go(solver, state, vars.clone());
+                        solver.pop(1);
+                        block_var(solver, &model, &var);
+                    }
+                    Unsat => break,
+                    Unknown => res = ControlFlow::Break(()),
+                }
             }
             solver.pop(1);
-        } else if solver.check() == Sat {
-            cont(get_model(solver));
-        }
-    }
-    go(solver, &mut cont, vars);
-}
-
-pub fn installation_status(
-    ctx: &Context,
-    model: &Model,
-    closure: impl Iterator<Item = PackageId>,
-) -> (Vec<PackageId>, Vec<PackageId>) {
-    let mut not_installed = Vec::new();
-    let mut installed = Vec::new();
-    for pid in closure {
-        let p = Int::new_const(ctx, pid);
-        if let Some(interp) = model.get_const_interp(&p) {
-            if let Some(v) = interp.as_u64() {
-                if v != 0 {
-                    installed.push(pid);
-                    continue;
+            res
+        } else {
+            match solver.check() {
+                Sat => {
+                    if let Some(remaining) = &mut state.remaining {
+                        *remaining -= 1;
+                    }
+                    (state.cont)(get_model(solver))
                 }
+                Unsat => ControlFlow::Continue(()),
+                Unknown => ControlFlow::Break(()),
             }
         }
-        not_installed.push(pid);
     }
-    (installed, not_installed)
-}
 
-pub fn fix_installed_pkgs(ctx: &Context, solver: &Solver, not_installed: &Vec<PackageId>) {
-    for pid in not_installed {
-        solver.assert(&Int::new_const(ctx, *pid)._eq(&zero(ctx)));
-    }
-}
-
-pub fn block_le_solutions(
-    ctx: &Context,
-    solver: &Solver,
-    model: &Model,
-    installed: &Vec<PackageId>,
-) {
-    let mut e = Bool::from_bool(ctx, true);
-    for pid in installed {
-        let p = Int::new_const(ctx, *pid);
-        let v = eval_int_expr_in_model(model, &p);
-        let v_expr = Int::from_u64(ctx, v);
-        e &= p._eq(&v_expr);
-        solver.assert(&p.ge(&v_expr));
-    }
-    e = e.not().simplify();
-    solver.assert(&e);
+    let mut state = State {
+        cont,
+        remaining: max_models,
+        deadline,
+    };
+    go(solver, &mut state, vars).is_continue()
 }
 
 #[cfg(test)]
 mod test {
-    use super::{default_config, set_global_params};
+    use super::{default_config, enumerate_models};
+    use std::ops::ControlFlow;
     use z3::ast::{Ast, Bool, Int};
     use z3::{Context, Goal, Solver, Tactic};
 
     #[test]
     fn test_build_context() {
-        set_global_params();
         let cfg = default_config();
         let ctx = Context::new(&cfg);
         let solver = Solver::new(&ctx);
@@ -230,4 +290,38 @@ mod test {
             println!("o: {i}")
         }
     }
+
+    #[test]
+    fn test_enumeration_limits() {
+        let ctx = Context::new(&default_config());
+        let solver = Solver::new(&ctx);
+        let v = Int::new_const(&ctx, 0);
+        solver.assert(&v.ge(&Int::from_u64(&ctx, 1)));
+        solver.assert(&v.le(&Int::from_u64(&ctx, 5)));
+        let vars = [v];
+
+        let mut count = 0;
+        let complete = enumerate_models(&solver, vars.clone().into_iter(), None, None, |_| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        assert!(complete);
+        assert_eq!(count, 5);
+
+        let mut count = 0;
+        let complete = enumerate_models(&solver, vars.clone().into_iter(), Some(3), None, |_| {
+            count += 1;
+            ControlFlow::Continue(())
+        });
+        assert!(!complete);
+        assert_eq!(count, 3);
+
+        let mut count = 0;
+        let complete = enumerate_models(&solver, vars.clone().into_iter(), None, None, |_| {
+            count += 1;
+            ControlFlow::Break(())
+        });
+        assert!(!complete);
+        assert_eq!(count, 1);
+    }
 }
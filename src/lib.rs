@@ -1,18 +1,137 @@
-#![forbid(unsafe_code)]
+#![cfg_attr(
+    not(any(feature = "ffi", feature = "python", feature = "shards")),
+    forbid(unsafe_code)
+)]
+#![cfg_attr(
+    any(feature = "ffi", feature = "python", feature = "shards"),
+    deny(unsafe_code)
+)]
 
 mod internals;
 
 pub use internals::{
+    // strong dependencies and impossible combinations
+    analysis::{impossible_combinations, strong_deps, DependencyAnalyzer},
+    // audit trails of resolutions
+    audit::{Audit, AuditHook, AuditRecord, ResolutionKind},
+    // batch resolution
+    batch::{solve_batch, solve_batch_parallel, solve_batch_with_options},
+    // closure computation
+    closure::{
+        closure, compute_closure, compute_orphans, flatten_requirements, restricted_closure,
+        Closure, ClosureOptions, EdgeKind, EncodedClosure, ParentEdge,
+    },
+    // solver configuration
+    config::SolverConfig,
+    // on-demand loading of package metadata
+    constraints::{load_closure, Origin},
+    // import of CUDF documents
+    cudf::CudfDocument,
+    // incremental repository edits
+    editor::{RepositoryChange, RepositoryEditor},
+    // constraint encodings
+    encoding::{Encoder, Encoding},
+    // drill-down into conflicts
+    explorer::{ConflictExplorer, CoreAssertion},
+    // constraint export
+    export::{
+        dot, export_dimacs, export_opb, export_smtlib, mermaid_core, mermaid_plan, write_dimacs,
+        write_dot, write_mermaid_core, write_mermaid_plan, write_opb, write_smtlib, DotGraph,
+        Literal, LiteralMap, PbObjective,
+    },
+    // synthetic benchmark instances
+    generate,
+    // local search towards newer versions
+    improve::{improve_plan, improve_plan_with_options},
+    // optimization metrics
+    metrics,
+    // multi-objective optimization
+    objective::{Objective, Priority},
+    // resolution options
+    options::{CancelToken, DowngradePolicy, Limits, OptimizeOptions, SolveOptions, TieBreaker},
+    // package policies
+    policy::Policy,
+    // pools of solvers for services
+    pool::{ContextPool, PooledSolver},
+    // repositories with precomputed constraints
+    prepared::PreparedRepository,
+    // pruning of unusable versions
+    preprocess::{preprocess, Preprocessed},
+    // plain and colored rendering
+    render::{render, to_ascii, RenderOptions},
+    // repair of broken installations
+    repair::{find_violations, repair, Change, Repair, Violation},
+    // runtime selection of the resolution strategy
+    resolver::{MinimalResolver, NewestResolver, PreferredResolver, Resolver, SimpleResolver},
+    // screening of versions that can never be installed
+    screen::{screen_repo, Screening},
+    // reusable resolution sessions
+    session::SolverSession,
+    // lazy enumeration of solutions
+    solutions::{count_solutions, sample_solutions, SolutionCount, SolutionIter},
     // resolution functions
     solver::{
-        optimize_minimal, optimize_newest, parallel_optimize_minimal, parallel_optimize_newest,
-        simple_solve,
+        optimize_cheapest, optimize_minimal, optimize_newest, optimize_pareto,
+        optimize_with_metrics, optimize_with_objective, optimize_with_options,
+        optimize_with_progress, optimize_with_stats, parallel_optimize_minimal,
+        parallel_optimize_newest, simple_solve, simple_solve_with_options,
+        simple_solve_with_progress, simple_solve_with_stats,
+    },
+    // asynchronous resolution
+    task::{optimize_async, solve_async, SolveFuture},
+    // reproducible test cases
+    testing::{load_corpus, repository_from_requirements, Expected, TestCase},
+    // tracking of the assertions of unsat cores
+    tracking::AssertionRegistry,
+    // packages with names and arbitrary version schemes
+    types::{
+        describe::{ECore, EDescribedRequirement, EPlan},
+        extended::{
+            DepKind, EPackage, EPackageBuilder, ERepository, ERepositoryBuilder, ERequirement,
+            EVersion, IdMap, Intersection, PackageIds, RangeMatch, RepositoryBuildError, SetOf,
+            Union, UnknownPackages, ViaRangeBound,
+        },
+        named::{parse_named_requirement, NamedRequirement, VersionBounds, VersionSyntax},
     },
     // type definitions
     types::{
-        ConstraintSet, Package, PackageId, PackageVer, Plan, Range, Repository, Requirement,
-        RequirementSet, ResolutionError, ResolutionResult, Vec1, Version,
+        AtomicExpr, ConstraintSet, Expr, ExprFold, Goal, LabeledRequirementSet, ObjectiveValues,
+        OwnedExpr, Package, PackageId, PackageMap, PackageSource, PackageVer, ParetoPoint,
+        ParetoResult, PartialRepository, Plan, PlanEntry, PlanExt, Progress, Range, RangeSet,
+        Repository, ReqExpr, Requirement, RequirementSet, ResolutionError, ResolutionResult,
+        Restriction, ReverseDep, ReverseIndex, SolveStats, StatValue, TypedPlan, Vec1, Version,
+        VersionMap,
     },
 };
 
-pub use intmap::IntMap;
+// proptest strategies
+#[cfg(feature = "arbitrary")]
+pub use internals::types::{ResolutionProblem, Topology};
+
+// differential testing of resolution backends
+#[cfg(feature = "arbitrary")]
+pub use internals::differential::{check_equivalence, encoding_backends, Backend, Disagreement};
+
+// benchmark instances and timing harness
+#[cfg(feature = "bench-internals")]
+pub use internals::bench;
+
+// C bindings
+#[cfg(feature = "ffi")]
+pub use internals::ffi;
+
+// Python bindings
+#[cfg(feature = "python")]
+pub use internals::python;
+
+// resolution daemon
+#[cfg(feature = "service")]
+pub use internals::service::ResolverService;
+
+// memoization of resolutions
+#[cfg(feature = "cache")]
+pub use internals::cache::{CacheStore, DirectoryStore, MemoryStore, SolveCache};
+
+// sharded repositories mapped on demand
+#[cfg(feature = "shards")]
+pub use internals::shard::{write_shards, ShardEntry, ShardIndex, ShardedRepository};